@@ -0,0 +1,26 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+//! Every script that parses has to either lower into plans or be rejected with an error, and
+//! what the formatter writes for it has to parse back into the same statements.
+
+#![no_main]
+
+use kuiperdb_engine::plan::QueryPlan;
+use kuiperdb_lang::format::format_query;
+use kuiperdb_lang::parser::parse_query;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|source: &str| {
+    let Ok(ast) = parse_query(source) else {
+        return;
+    };
+
+    let _ = QueryPlan::from_script(&ast);
+
+    if let Ok(text) = format_query(&ast) {
+        let parsed = parse_query(&text).unwrap_or_else(|e| panic!("{}\n{}", e, text));
+        assert_eq!(parsed, ast, "{}", text);
+    }
+});
@@ -35,16 +35,59 @@ pub enum Error {
     #[error("The key being inserted already exists")]
     TxKeyAlreadyExists,
 
+    /// The transaction has no savepoint to roll back to
+    #[error("There is no savepoint to roll back to")]
+    TxNoSavepoint,
+
+    /// Another transaction wrote the same keys first. The transaction can be run again, see
+    /// [`crate::storage::kv::Store::run_in_transaction`]
+    #[error("The transaction conflicted with a concurrent transaction")]
+    TxConflict,
+
+    /// A lock held by another transaction wasn't released in time
+    #[error("Timed out waiting for a lock held by another transaction")]
+    TxLockTimeout,
+
+    /// Waiting for the lock would deadlock with another transaction
+    #[error("The transaction was stopped to break a deadlock with another transaction")]
+    TxDeadlock,
+
     #[error("{0}")]
     Parse(String),
 
     #[error("{0}")]
     Value(String),
+
+    /// The configuration could not be read or has an invalid setting
+    #[error("There was a problem with the configuration: {0}")]
+    Config(String),
+
+    /// There was a problem reading or writing a file
+    #[error("There was a problem with a file: {0}")]
+    Io(String),
+}
+
+impl Error {
+    /// Whether the failed transaction can succeed when it is run again
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Error::TxConflict | Error::TxDeadlock)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::Io(e.to_string())
+    }
 }
 
 impl From<rocksdb::Error> for Error {
     fn from(e: rocksdb::Error) -> Error {
-        Error::Tx(e.to_string())
+        match e.kind() {
+            rocksdb::ErrorKind::TimedOut => Error::TxLockTimeout,
+            rocksdb::ErrorKind::Busy if e.to_string().contains("Deadlock") => Error::TxDeadlock,
+            rocksdb::ErrorKind::Busy | rocksdb::ErrorKind::TryAgain => Error::TxConflict,
+            _ => Error::Tx(e.to_string()),
+        }
     }
 }
 
@@ -53,3 +96,9 @@ impl From<regex::Error> for Error {
         Error::Value(err.to_string())
     }
 }
+
+impl<T> From<std::sync::PoisonError<T>> for Error {
+    fn from(e: std::sync::PoisonError<T>) -> Error {
+        Error::Tx(e.to_string())
+    }
+}
@@ -2,12 +2,231 @@
 // (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
 //--------------------------------------------------------------------------
 
+use crate::error::Error;
+use std::future::Future;
+use std::ops::Range;
+use std::time::Duration;
+
 /// The key part of a key-value pair. An alias for [`Vec<u8>`].
 pub type Key = Vec<u8>;
 
 /// The value part of a key-value pair. An alias for [`Vec<u8>`].
 pub type Val = Vec<u8>;
 
+/// A key-value store with transactions, the storage the engine runs on. Implemented by
+/// [`crate::storage::rocksdb::Datastore`], and by [`crate::storage::memory::Datastore`] for
+/// tests and databases that don't outlive the process.
+pub trait Store: Send + Sync + 'static {
+    type Transaction: Transaction;
+
+    /// Start a new transaction, at the store's isolation level
+    fn transaction(
+        &self,
+        write: bool,
+    ) -> impl Future<Output = Result<Self::Transaction, Error>> + Send {
+        self.transaction_with(write, self.isolation())
+    }
+
+    /// Start a new transaction at an isolation level
+    fn transaction_with(
+        &self,
+        write: bool,
+        isolation: IsolationLevel,
+    ) -> impl Future<Output = Result<Self::Transaction, Error>> + Send;
+
+    /// The isolation level of the transactions started with [`Store::transaction`]
+    fn isolation(&self) -> IsolationLevel {
+        IsolationLevel::default()
+    }
+
+    /// How [`Store::run_in_transaction`] retries failed transactions
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
+    /// Runs `f` in a new transaction and commits it. When `f` or the commit fail with a
+    /// retryable error, such as a conflict with a concurrent transaction, the transaction is
+    /// rolled back and `f` runs again in a new one, after backing off on the tokio timer.
+    fn run_in_transaction<F, T>(
+        &self,
+        write: bool,
+        mut f: F,
+    ) -> impl Future<Output = Result<T, Error>>
+    where
+        F: AsyncFnMut(&mut Self::Transaction) -> Result<T, Error>,
+    {
+        async move {
+            let policy = self.retry_policy();
+            let mut attempt = 1;
+
+            loop {
+                let mut txn = self.transaction(write).await?;
+                let result = match f(&mut txn).await {
+                    Ok(value) if txn.is_completed() => Ok(value),
+                    Ok(value) if !write => txn.rollback().await.map(|_| value),
+                    Ok(value) => txn.commit().await.map(|_| value),
+                    Err(e) => {
+                        // The error is returned even when the rollback fails too, it is what
+                        // the caller needs to see
+                        if !txn.is_completed() {
+                            if let Err(rollback) = txn.rollback().await {
+                                log::warn!("Couldn't roll back a failed transaction: {}", rollback);
+                            }
+                        }
+                        Err(e)
+                    }
+                };
+
+                match result {
+                    Err(e) if e.is_retryable() && attempt < policy.attempts => {
+                        tokio::time::sleep(policy.backoff(attempt)).await;
+                        attempt += 1;
+                    }
+                    result => return result,
+                }
+            }
+        }
+    }
+}
+
+/// What a transaction sees of the transactions committed while it runs. At every level a
+/// transaction sees its own writes, and fails to commit when another transaction committed one
+/// of the keys it writes, or read for update, after it started.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IsolationLevel {
+    /// Every read, of single keys and of ranges, sees the store as it was when the transaction
+    /// started. Two transactions can still each read what the other writes and both commit,
+    /// the write skew anomaly.
+    #[default]
+    Snapshot,
+
+    /// Every read sees the latest committed value of its keys, so reading the same key twice
+    /// can return different values, and a range read can miss keys committed while a cursor
+    /// walks it.
+    ReadCommitted,
+
+    /// Reads see the snapshot, and every key and range read is recorded. A commit fails when
+    /// another transaction committed a change to any of them after this one started, so
+    /// committed transactions behave as if they ran one at a time.
+    Serializable,
+}
+
+/// How often a transaction that failed with a retryable error is run, and how long to wait
+/// before running it again. The waits double from `backoff` up to `max_backoff`, each with up
+/// to half of it added at random, so that the transactions that conflicted don't all retry at
+/// once.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The number of times the transaction is run, including the first
+    pub attempts: u32,
+
+    /// The wait after the first attempt
+    pub backoff: Duration,
+
+    /// The longest wait
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 5,
+            backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The wait after an attempt failed
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        use std::hash::{BuildHasher, Hasher};
+
+        let wait = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff);
+        let jitter = std::collections::hash_map::RandomState::new()
+            .build_hasher()
+            .finish();
+
+        wait + wait.mul_f64((jitter % 1024) as f64 / 2048.0)
+    }
+}
+
+/// A transaction of a [`Store`]. Reads see the store as its [`IsolationLevel`] allows, along
+/// with the transaction's own writes, which the others only see once it is committed.
+pub trait Transaction: Send {
+    /// A cursor over a range of keys, see [`Transaction::cursor`]
+    type Cursor<'a>: Iterator<Item = Result<(Key, Val), Error>> + Send
+    where
+        Self: 'a;
+
+    /// Check to see if txn is completed, this could mean either commited or rolled back
+    fn is_completed(&self) -> bool;
+
+    /// Discard all transaction operations
+    fn rollback(&mut self) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Commit transaction
+    fn commit(&mut self) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Check if a key exists
+    fn key_exists<K>(&mut self, key: K) -> impl Future<Output = Result<bool, Error>> + Send
+    where
+        K: Into<Key> + Send;
+
+    /// Fetch a key from the database
+    fn get<K>(&mut self, key: K) -> impl Future<Output = Result<Option<Val>, Error>> + Send
+    where
+        K: Into<Key> + Send;
+
+    /// Fetch a key and lock it until the transaction completes. With pessimistic transactions
+    /// other writers of the key wait for the lock, with optimistic ones this transaction fails
+    /// to commit if the key was written by another transaction after it started. Keys can be
+    /// locked while a cursor borrows the transaction, as they are read from it.
+    fn get_for_update<K>(&self, key: K) -> impl Future<Output = Result<Option<Val>, Error>> + Send
+    where
+        K: Into<Key> + Send;
+
+    /// Insert or update a key in the database
+    fn upsert<K, V>(&mut self, key: K, val: V) -> impl Future<Output = Result<(), Error>> + Send
+    where
+        K: Into<Key> + Send,
+        V: Into<Val> + Send;
+
+    /// Insert a key if it doesn't exist in the database
+    fn insert<K, V>(&mut self, key: K, val: V) -> impl Future<Output = Result<(), Error>> + Send
+    where
+        K: Into<Key> + Send,
+        V: Into<Val> + Send;
+
+    /// Delete a key
+    fn delete<K>(&mut self, key: K) -> impl Future<Output = Result<(), Error>> + Send
+    where
+        K: Into<Key> + Send;
+
+    /// Marks the state of the transaction, so the writes that follow can be undone with
+    /// [`Transaction::rollback_to_savepoint`]. Savepoints nest, each one is set on top of the
+    /// previous ones.
+    fn savepoint(&mut self) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Undoes the writes since the last savepoint and removes it, the transaction stays open.
+    /// Keys read for update since then are no longer locked.
+    fn rollback_to_savepoint(&mut self) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Opens a cursor over the keys of the range, as the transaction's isolation level reads
+    /// them. Keys and values are read as the cursor is advanced, not up front.
+    ///
+    /// At every isolation level the cursor sees the transaction's own inserts, updates and
+    /// deletes, the writes can't change while it is open since it borrows the transaction.
+    fn cursor(
+        &self,
+        range: ScanRange,
+    ) -> impl Future<Output = Result<Self::Cursor<'_>, Error>> + Send;
+}
+
 /// This trait appends an element to a collection, and allows chaining
 pub(super) trait Add<T> {
     fn add(self, v: T) -> Self;
@@ -20,16 +239,166 @@ impl Add<u8> for Vec<u8> {
     }
 }
 
-/// This trait converts a collection of key-value pairs into the desired type
-pub(super) trait Convert<T> {
-    fn convert(self) -> T;
+/// The keys a cursor visits, and the order it visits them in. Ranges are built with
+/// [`ScanRange::all`], [`ScanRange::prefix`] or [`ScanRange::between`], and narrowed with
+/// [`ScanRange::after`] to resume a scan where a previous cursor stopped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanRange {
+    /// The first key of the range, unbounded when `None`
+    pub start: Option<Key>,
+
+    /// The key the range ends before, unbounded when `None`
+    pub end: Option<Key>,
+
+    /// Whether the keys are visited from the end of the range
+    pub reverse: bool,
+}
+
+impl ScanRange {
+    /// Every key
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Every key starting with the prefix
+    pub fn prefix<K: Into<Key>>(prefix: K) -> Self {
+        let start: Key = prefix.into();
+        let end = prefix_end(&start);
+
+        ScanRange {
+            start: Some(start),
+            end,
+            reverse: false,
+        }
+    }
+
+    /// The keys from `start`, up to but not including `end`
+    pub fn between<K: Into<Key>>(range: Range<K>) -> Self {
+        ScanRange {
+            start: Some(range.start.into()),
+            end: Some(range.end.into()),
+            reverse: false,
+        }
+    }
+
+    /// The single key
+    pub fn key<K: Into<Key>>(key: K) -> Self {
+        let start: Key = key.into();
+        let end = start.clone().add(0);
+
+        ScanRange {
+            start: Some(start),
+            end: Some(end),
+            reverse: false,
+        }
+    }
+
+    /// Visits the keys from the end of the range
+    pub fn reverse(mut self) -> Self {
+        self.reverse = true;
+        self
+    }
+
+    /// Skips the keys up to and including `key`, or from it onwards when the range is
+    /// reversed, so the scan continues past the last key it returned.
+    pub fn after<K: Into<Key>>(mut self, key: K) -> Self {
+        let key: Key = key.into();
+
+        if self.reverse {
+            if self.end.as_ref().is_none_or(|end| key < *end) {
+                self.end = Some(key);
+            }
+        } else {
+            // The smallest key after `key` is the key with a zero byte appended
+            let next = key.add(0);
+            if self.start.as_ref().is_none_or(|start| next > *start) {
+                self.start = Some(next);
+            }
+        }
+
+        self
+    }
+
+    /// Whether the key is part of the range
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.start
+            .as_ref()
+            .is_none_or(|start| key >= start.as_slice())
+            && self.end.as_ref().is_none_or(|end| key < end.as_slice())
+    }
+}
+
+/// The first key after every key starting with the prefix, `None` when there is no such key
+/// because the prefix is empty or all `0xff`.
+fn prefix_end(prefix: &[u8]) -> Option<Key> {
+    let mut end = prefix.to_vec();
+
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+
+    None
 }
 
-impl<T> Convert<Vec<T>> for Vec<(Key, Val)>
-where
-    T: From<Val>,
-{
-    fn convert(self) -> Vec<T> {
-        self.into_iter().map(|(_, v)| v.into()).collect()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_backoff() {
+        let policy = RetryPolicy {
+            attempts: 10,
+            backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(100),
+        };
+
+        for (attempt, wait) in [(1, 10), (2, 20), (3, 40), (4, 80), (5, 100), (40, 100)] {
+            let wait = Duration::from_millis(wait);
+            let backoff = policy.backoff(attempt);
+            assert!(
+                backoff >= wait && backoff <= wait + wait / 2,
+                "{:?}",
+                backoff
+            );
+        }
+    }
+
+    #[test]
+    fn prefix_ranges() {
+        let range = ScanRange::prefix(b"ab".to_vec());
+        assert_eq!(range.end, Some(b"ac".to_vec()));
+        assert!(range.contains(b"ab"));
+        assert!(range.contains(b"ab\xff\xff"));
+        assert!(!range.contains(b"ac"));
+        assert!(!range.contains(b"aa\xff"));
+
+        assert_eq!(
+            ScanRange::prefix(b"a\xff".to_vec()).end,
+            Some(b"b".to_vec())
+        );
+        assert_eq!(ScanRange::prefix(b"\xff\xff".to_vec()).end, None);
+        assert_eq!(ScanRange::prefix(Vec::new()).end, None);
+    }
+
+    #[test]
+    fn resuming_ranges() {
+        let range = ScanRange::prefix(b"a".to_vec()).after(b"a1".to_vec());
+        assert!(!range.contains(b"a1"));
+        assert!(range.contains(b"a1\x00"));
+        assert!(range.contains(b"a2"));
+
+        let range = ScanRange::prefix(b"a".to_vec())
+            .reverse()
+            .after(b"a1".to_vec());
+        assert!(range.contains(b"a0"));
+        assert!(!range.contains(b"a1"));
+        assert!(!range.contains(b"a2"));
+
+        // A key before the range doesn't widen it
+        let range = ScanRange::between(b"b".to_vec()..b"c".to_vec()).after(b"a".to_vec());
+        assert_eq!(range.start, Some(b"b".to_vec()));
     }
 }
@@ -0,0 +1,766 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+//! An in-memory datastore, for tests and for databases that don't need to outlive the process.
+//!
+//! Every key of the B+tree holds the versions of its value, each tagged with the commit that
+//! wrote it. A transaction reads the versions committed before it started, and buffers its own
+//! writes until it commits. Like the optimistic rocksdb transactions, a commit fails when one
+//! of its keys, or of the keys it read for update, was committed by another transaction after
+//! it started.
+//!
+//! Read committed transactions read the newest version of every key instead, and serializable
+//! ones record the ranges they read, their commit fails when a key of one of them has a
+//! version newer than the transaction.
+
+mod btree;
+
+use self::btree::{BTree, Iter};
+use super::kv::{self, IsolationLevel, Key, ScanRange, Val};
+use crate::error::Error;
+use std::cmp::Ordering;
+use std::collections::btree_map;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// The versions of a value, oldest first. `None` marks a deleted key.
+type Versions = Vec<(u64, Option<Val>)>;
+
+/// The writes of a transaction that aren't committed yet, `None` deletes the key
+type Writes = BTreeMap<Key, Option<Val>>;
+
+#[derive(Clone, Default)]
+pub struct Datastore {
+    tree: Arc<RwLock<BTree<Versions>>>,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    // The version of the last commit
+    committed: u64,
+
+    // The number of open transactions reading each version
+    readers: BTreeMap<u64, usize>,
+}
+
+pub struct Transaction {
+    // The writes that aren't committed yet, `None` deletes the key
+    writes: Writes,
+
+    // The keys read for update, which must not change before the transaction commits
+    locked: Mutex<BTreeSet<Key>>,
+
+    // The writes and locked keys as they were at each savepoint
+    savepoints: Vec<(Writes, BTreeSet<Key>)>,
+
+    // The version the transaction reads
+    version: u64,
+
+    // What the transaction sees of the commits made while it runs
+    isolation: IsolationLevel,
+
+    // The ranges read by a serializable transaction, checked for changes when it commits
+    reads: Mutex<Vec<ScanRange>>,
+
+    // Has the transaction completed.
+    completed: bool,
+
+    // Is the transaction ReadWrite, true, or ReadOnly, false.
+    rw: bool,
+
+    // This is the datastore that the transaction is tied to
+    ds: Datastore,
+}
+
+impl Datastore {
+    /// Open a new, empty database
+    pub fn new() -> Datastore {
+        Datastore::default()
+    }
+}
+
+impl kv::Store for Datastore {
+    type Transaction = Transaction;
+
+    async fn transaction_with(
+        &self,
+        write: bool,
+        isolation: IsolationLevel,
+    ) -> Result<Transaction, Error> {
+        let mut state = self.state.lock()?;
+        let version = state.committed;
+        *state.readers.entry(version).or_default() += 1;
+
+        Ok(Transaction {
+            writes: BTreeMap::new(),
+            locked: Mutex::new(BTreeSet::new()),
+            savepoints: Vec::new(),
+            version,
+            isolation,
+            reads: Mutex::new(Vec::new()),
+            completed: false,
+            rw: write,
+            ds: self.clone(),
+        })
+    }
+}
+
+/// The value of a key as of a version.
+fn visible(versions: &Versions, version: u64) -> Option<Val> {
+    versions
+        .iter()
+        .rev()
+        .find(|(v, _)| *v <= version)
+        .and_then(|(_, val)| val.clone())
+}
+
+impl Transaction {
+    /// Check to see if the transaction can be read from, and written to when `write` is set
+    fn check(&self, write: bool) -> Result<(), Error> {
+        // Check to see if transaction is closed
+        if self.completed {
+            return Err(Error::TxFinished);
+        }
+
+        // Check to see if transaction is writable
+        if write && !self.rw {
+            return Err(Error::TxReadonly);
+        }
+
+        Ok(())
+    }
+
+    /// Reads a key, the transaction's own writes first
+    fn read(&self, key: &[u8]) -> Result<Option<Val>, Error> {
+        if let Some(val) = self.writes.get(key) {
+            return Ok(val.clone());
+        }
+
+        self.track(ScanRange::key(key))?;
+        match self.ds.tree.read()?.get(key) {
+            Some(versions) => Ok(visible(versions, self.read_version())),
+            None => Ok(None),
+        }
+    }
+
+    /// The version reads see, the newest one when reading committed data
+    fn read_version(&self) -> u64 {
+        match self.isolation {
+            IsolationLevel::ReadCommitted => u64::MAX,
+            _ => self.version,
+        }
+    }
+
+    /// Records a range read by a serializable transaction
+    fn track(&self, range: ScanRange) -> Result<(), Error> {
+        if self.isolation == IsolationLevel::Serializable {
+            self.reads.lock()?.push(range);
+        }
+
+        Ok(())
+    }
+
+    /// Marks the transaction as done, the versions it was reading can be pruned
+    fn finish(&mut self) -> Result<(), Error> {
+        self.completed = true;
+        self.writes.clear();
+        self.locked.lock()?.clear();
+        self.savepoints.clear();
+        self.reads.lock()?.clear();
+        let mut state = self.ds.state.lock()?;
+        release(&mut state, self.version);
+
+        Ok(())
+    }
+}
+
+/// Removes a reader of a version.
+fn release(state: &mut State, version: u64) {
+    if let Some(readers) = state.readers.get_mut(&version) {
+        *readers -= 1;
+        if *readers == 0 {
+            state.readers.remove(&version);
+        }
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if !self.completed {
+            let _ = self.finish();
+        }
+    }
+}
+
+impl kv::Transaction for Transaction {
+    type Cursor<'a> = Cursor<'a>;
+
+    fn is_completed(&self) -> bool {
+        self.completed
+    }
+
+    async fn rollback(&mut self) -> Result<(), Error> {
+        self.check(false)?;
+        self.finish()
+    }
+
+    async fn commit(&mut self) -> Result<(), Error> {
+        self.check(true)?;
+
+        // Mark this transaction as done
+        self.completed = true;
+        let writes = std::mem::take(&mut self.writes);
+        let locked = std::mem::take(&mut *self.locked.lock()?);
+        let reads = std::mem::take(&mut *self.reads.lock()?);
+        let mut state = self.ds.state.lock()?;
+        release(&mut state, self.version);
+
+        let mut tree = self.ds.tree.write()?;
+        let changed = |versions: &Versions| versions.last().is_some_and(|(v, _)| *v > self.version);
+
+        // Fail when another transaction committed one of the keys since this one started
+        for key in locked.iter().filter(|key| !writes.contains_key(*key)) {
+            if let Some(versions) = tree.get(key) {
+                if changed(versions) {
+                    return Err(Error::TxConflict);
+                }
+            }
+        }
+
+        // Fail when another transaction committed a key of a range a serializable transaction
+        // read, including keys that didn't exist when it was read
+        for range in &reads {
+            for (_, versions) in tree.range(range) {
+                if changed(&versions) {
+                    return Err(Error::TxConflict);
+                }
+            }
+        }
+
+        let mut written = Vec::with_capacity(writes.len());
+        for (key, val) in writes {
+            let versions = tree.get(&key).cloned().unwrap_or_default();
+            if changed(&versions) {
+                return Err(Error::TxConflict);
+            }
+            written.push((key, val, versions));
+        }
+
+        state.committed += 1;
+        let version = state.committed;
+
+        // Only the newest version the oldest reader sees, and the ones after it, are still read
+        let oldest = state.readers.keys().next().copied().unwrap_or(version);
+
+        for (key, val, mut versions) in written {
+            versions.push((version, val));
+
+            let keep = versions
+                .iter()
+                .rposition(|(v, _)| *v <= oldest)
+                .unwrap_or(0);
+            versions.drain(..keep);
+
+            match versions.as_slice() {
+                [(_, None)] => tree.delete(&key),
+                _ => tree.set(&key, versions),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn key_exists<K>(&mut self, key: K) -> Result<bool, Error>
+    where
+        K: Into<Key> + Send,
+    {
+        self.check(false)?;
+        Ok(self.read(&key.into())?.is_some())
+    }
+
+    async fn get<K>(&mut self, key: K) -> Result<Option<Val>, Error>
+    where
+        K: Into<Key> + Send,
+    {
+        self.check(false)?;
+        self.read(&key.into())
+    }
+
+    async fn get_for_update<K>(&self, key: K) -> Result<Option<Val>, Error>
+    where
+        K: Into<Key> + Send,
+    {
+        self.check(false)?;
+
+        let key = key.into();
+        let val = self.read(&key)?;
+        if self.rw {
+            self.locked.lock()?.insert(key);
+        }
+
+        Ok(val)
+    }
+
+    async fn upsert<K, V>(&mut self, key: K, val: V) -> Result<(), Error>
+    where
+        K: Into<Key> + Send,
+        V: Into<Val> + Send,
+    {
+        self.check(true)?;
+        self.writes.insert(key.into(), Some(val.into()));
+        Ok(())
+    }
+
+    async fn insert<K, V>(&mut self, key: K, val: V) -> Result<(), Error>
+    where
+        K: Into<Key> + Send,
+        V: Into<Val> + Send,
+    {
+        self.check(true)?;
+
+        let key = key.into();
+        if self.read(&key)?.is_some() {
+            return Err(Error::TxKeyAlreadyExists);
+        }
+
+        self.writes.insert(key, Some(val.into()));
+        Ok(())
+    }
+
+    async fn delete<K>(&mut self, key: K) -> Result<(), Error>
+    where
+        K: Into<Key> + Send,
+    {
+        self.check(true)?;
+        self.writes.insert(key.into(), None);
+        Ok(())
+    }
+
+    async fn savepoint(&mut self) -> Result<(), Error> {
+        self.check(false)?;
+        self.savepoints
+            .push((self.writes.clone(), self.locked.lock()?.clone()));
+        Ok(())
+    }
+
+    async fn rollback_to_savepoint(&mut self) -> Result<(), Error> {
+        self.check(false)?;
+        let (writes, locked) = self.savepoints.pop().ok_or(Error::TxNoSavepoint)?;
+        self.writes = writes;
+        *self.locked.lock()? = locked;
+        Ok(())
+    }
+
+    async fn cursor(&self, range: ScanRange) -> Result<Cursor<'_>, Error> {
+        self.check(false)?;
+
+        let start = range
+            .start
+            .clone()
+            .map_or(Bound::Unbounded, Bound::Included);
+        let end = range.end.clone().map_or(Bound::Unbounded, Bound::Excluded);
+        let writes = match (&range.start, &range.end) {
+            // An empty range, which `BTreeMap::range` doesn't accept
+            (Some(start), Some(end)) if start >= end => self.writes.range(..Vec::new()),
+            _ => self.writes.range((start, end)),
+        };
+        self.track(range.clone())?;
+
+        Ok(Cursor {
+            stored: Iter::new(self.ds.tree.clone(), range.clone()),
+            writes,
+            next_stored: None,
+            next_write: None,
+            version: self.read_version(),
+            reverse: range.reverse,
+            primed: false,
+            finished: false,
+        })
+    }
+}
+
+/// A cursor over a range of keys of a transaction, returned by [`kv::Transaction::cursor`].
+/// It merges the keys of the tree, as of the version the transaction reads, with the
+/// transaction's own writes, reading the tree one key at a time as it is advanced.
+pub struct Cursor<'a> {
+    stored: Iter<Versions>,
+    writes: btree_map::Range<'a, Key, Option<Val>>,
+
+    // The next key of the tree, with its versions, and the next write, read ahead of the one
+    // the cursor returns so the two can be merged in order
+    next_stored: Option<(Key, Versions)>,
+    next_write: Option<(&'a Key, &'a Option<Val>)>,
+
+    version: u64,
+    reverse: bool,
+    primed: bool,
+    finished: bool,
+}
+
+impl Cursor<'_> {
+    fn advance_stored(&mut self) -> Result<(), Error> {
+        let next = match self.reverse {
+            true => self.stored.next_back(),
+            false => self.stored.next(),
+        };
+        self.next_stored = next.transpose()?;
+        Ok(())
+    }
+
+    fn advance_write(&mut self) {
+        self.next_write = match self.reverse {
+            true => self.writes.next_back(),
+            false => self.writes.next(),
+        };
+    }
+
+    /// next() with error handling.
+    fn try_next(&mut self) -> Result<Option<(Key, Val)>, Error> {
+        if !self.primed {
+            self.primed = true;
+            self.advance_stored()?;
+            self.advance_write();
+        }
+
+        loop {
+            // Take whichever key comes first in the cursor's direction, a write replaces the
+            // stored value of the same key
+            let order = match (&self.next_stored, &self.next_write) {
+                (None, None) => return Ok(None),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((stored, _)), Some((write, _))) => match self.reverse {
+                    true => write.cmp(&stored),
+                    false => stored.cmp(write),
+                },
+            };
+
+            if order != Ordering::Greater {
+                let (key, versions) = self.next_stored.take().unwrap();
+                self.advance_stored()?;
+
+                if order == Ordering::Less {
+                    if let Some(val) = visible(&versions, self.version) {
+                        return Ok(Some((key, val)));
+                    }
+                    continue;
+                }
+            }
+
+            let (key, val) = self.next_write.take().unwrap();
+            self.advance_write();
+
+            if let Some(val) = val {
+                return Ok(Some((key.clone(), val.clone())));
+            }
+        }
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = Result<(Key, Val), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        match self.try_next() {
+            Ok(Some(item)) => Some(Ok(item)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::kv::{Store as _, Transaction as _};
+    use futures::executor::block_on;
+
+    fn keys(cursor: Cursor) -> Vec<String> {
+        cursor
+            .map(|item| String::from_utf8(item.unwrap().0).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn cursors() {
+        block_on(async {
+            let ds = Datastore::new();
+
+            let mut txn = ds.transaction(true).await.unwrap();
+            for key in ["a1", "a2", "a3", "b1"] {
+                txn.insert(key, key).await.unwrap();
+            }
+            txn.commit().await.unwrap();
+
+            let mut txn = ds.transaction(true).await.unwrap();
+            txn.upsert("a0", "a0").await.unwrap();
+            txn.upsert("a25", "a25").await.unwrap();
+            txn.upsert("a2", "changed").await.unwrap();
+            txn.delete("a3").await.unwrap();
+
+            let prefix = ScanRange::prefix("a");
+            assert_eq!(
+                keys(txn.cursor(prefix.clone()).await.unwrap()),
+                ["a0", "a1", "a2", "a25"]
+            );
+            assert_eq!(
+                keys(txn.cursor(prefix.clone().reverse()).await.unwrap()),
+                ["a25", "a2", "a1", "a0"]
+            );
+            assert_eq!(
+                keys(txn.cursor(prefix.clone().after("a2")).await.unwrap()),
+                ["a25"]
+            );
+            assert_eq!(
+                keys(
+                    txn.cursor(ScanRange::between(b"b".to_vec()..b"a".to_vec()))
+                        .await
+                        .unwrap()
+                ),
+                Vec::<String>::new()
+            );
+            assert_eq!(txn.get("a2").await.unwrap(), Some(b"changed".to_vec()));
+        });
+    }
+
+    #[test]
+    fn snapshots() {
+        block_on(async {
+            let ds = Datastore::new();
+
+            let mut txn = ds.transaction(true).await.unwrap();
+            txn.insert("a", "1").await.unwrap();
+            txn.insert("b", "1").await.unwrap();
+            txn.commit().await.unwrap();
+
+            // Readers keep seeing the versions from when they started
+            let reader = ds.transaction(false).await.unwrap();
+            let mut txn = ds.transaction(true).await.unwrap();
+            txn.upsert("a", "2").await.unwrap();
+            txn.delete("b").await.unwrap();
+            txn.commit().await.unwrap();
+
+            assert_eq!(
+                keys(reader.cursor(ScanRange::all()).await.unwrap()),
+                ["a", "b"]
+            );
+            let mut reader = reader;
+            assert_eq!(reader.get("a").await.unwrap(), Some(b"1".to_vec()));
+            reader.rollback().await.unwrap();
+
+            let mut txn = ds.transaction(false).await.unwrap();
+            assert_eq!(txn.get("a").await.unwrap(), Some(b"2".to_vec()));
+            assert!(!txn.key_exists("b").await.unwrap());
+            assert!(matches!(txn.commit().await, Err(Error::TxReadonly)));
+            txn.rollback().await.unwrap();
+            assert!(matches!(txn.get("a").await, Err(Error::TxFinished)));
+
+            // Deleted keys are removed by the next commit once nothing reads their old versions
+            assert!(ds.state.lock().unwrap().readers.is_empty());
+            let mut txn = ds.transaction(true).await.unwrap();
+            txn.delete("a").await.unwrap();
+            txn.commit().await.unwrap();
+            assert!(ds.tree.read().unwrap().get(b"a").is_none());
+        });
+    }
+
+    #[test]
+    fn conflicts() {
+        block_on(async {
+            let ds = Datastore::new();
+
+            let mut first = ds.transaction(true).await.unwrap();
+            let mut second = ds.transaction(true).await.unwrap();
+            first.insert("a", "1").await.unwrap();
+            second.insert("a", "2").await.unwrap();
+            first.commit().await.unwrap();
+            assert!(matches!(second.commit().await, Err(Error::TxConflict)));
+            assert!(second.is_completed());
+
+            let mut txn = ds.transaction(true).await.unwrap();
+            assert!(matches!(
+                txn.insert("a", "3").await,
+                Err(Error::TxKeyAlreadyExists)
+            ));
+            assert_eq!(txn.get("a").await.unwrap(), Some(b"1".to_vec()));
+
+            // Keys read for update can't change before the reader commits, others can
+            let mut first = ds.transaction(true).await.unwrap();
+            let mut second = ds.transaction(true).await.unwrap();
+            assert_eq!(
+                first.get_for_update("a").await.unwrap(),
+                Some(b"1".to_vec())
+            );
+            first.get("b").await.unwrap();
+            first.upsert("c", "1").await.unwrap();
+            second.upsert("a", "2").await.unwrap();
+            second.upsert("b", "2").await.unwrap();
+            second.commit().await.unwrap();
+            assert!(matches!(first.commit().await, Err(Error::TxConflict)));
+        });
+    }
+
+    #[test]
+    fn isolation_levels() {
+        block_on(async {
+            let ds = Datastore::new();
+
+            let mut txn = ds.transaction(true).await.unwrap();
+            txn.insert("a", "1").await.unwrap();
+            txn.insert("b", "1").await.unwrap();
+            txn.commit().await.unwrap();
+
+            // Read committed transactions see commits made after they started
+            let mut snapshot = ds.transaction(false).await.unwrap();
+            let mut committed = ds
+                .transaction_with(false, IsolationLevel::ReadCommitted)
+                .await
+                .unwrap();
+            let mut txn = ds.transaction(true).await.unwrap();
+            txn.upsert("a", "2").await.unwrap();
+            txn.insert("c", "1").await.unwrap();
+            txn.commit().await.unwrap();
+
+            assert_eq!(snapshot.get("a").await.unwrap(), Some(b"1".to_vec()));
+            assert_eq!(
+                keys(snapshot.cursor(ScanRange::all()).await.unwrap()),
+                ["a", "b"]
+            );
+            assert_eq!(committed.get("a").await.unwrap(), Some(b"2".to_vec()));
+            assert_eq!(
+                keys(committed.cursor(ScanRange::all()).await.unwrap()),
+                ["a", "b", "c"]
+            );
+
+            // Snapshot transactions that write what the other read both commit, serializable
+            // ones don't
+            for (isolation, val, conflicts) in [
+                (IsolationLevel::Snapshot, "3", false),
+                (IsolationLevel::Serializable, "4", true),
+            ] {
+                let mut first = ds.transaction_with(true, isolation).await.unwrap();
+                let mut second = ds.transaction_with(true, isolation).await.unwrap();
+                first.get("a").await.unwrap();
+                second.get("b").await.unwrap();
+                first.upsert("b", val).await.unwrap();
+                second.upsert("a", val).await.unwrap();
+                first.commit().await.unwrap();
+                assert_eq!(second.commit().await.is_err(), conflicts);
+            }
+
+            // Keys committed into a range a serializable transaction read conflict with it
+            let mut txn = ds
+                .transaction_with(true, IsolationLevel::Serializable)
+                .await
+                .unwrap();
+            assert_eq!(
+                keys(txn.cursor(ScanRange::prefix("d")).await.unwrap()).len(),
+                0
+            );
+            txn.upsert("e", "1").await.unwrap();
+            let mut other = ds.transaction(true).await.unwrap();
+            other.insert("d1", "1").await.unwrap();
+            other.insert("f", "1").await.unwrap();
+            other.commit().await.unwrap();
+            assert!(matches!(txn.commit().await, Err(Error::TxConflict)));
+
+            // Commits outside of the ranges it read don't
+            let mut txn = ds
+                .transaction_with(true, IsolationLevel::Serializable)
+                .await
+                .unwrap();
+            assert_eq!(
+                keys(txn.cursor(ScanRange::prefix("d")).await.unwrap()),
+                ["d1"]
+            );
+            txn.upsert("e", "1").await.unwrap();
+            let mut other = ds.transaction(true).await.unwrap();
+            other.upsert("f", "2").await.unwrap();
+            other.commit().await.unwrap();
+            txn.commit().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn savepoints() {
+        block_on(async {
+            let ds = Datastore::new();
+
+            let mut txn = ds.transaction(true).await.unwrap();
+            txn.upsert("a", "1").await.unwrap();
+            txn.savepoint().await.unwrap();
+            txn.upsert("a", "2").await.unwrap();
+            txn.upsert("b", "2").await.unwrap();
+            txn.savepoint().await.unwrap();
+            txn.delete("a").await.unwrap();
+
+            txn.rollback_to_savepoint().await.unwrap();
+            assert_eq!(txn.get("a").await.unwrap(), Some(b"2".to_vec()));
+            txn.rollback_to_savepoint().await.unwrap();
+            assert_eq!(keys(txn.cursor(ScanRange::all()).await.unwrap()), ["a"]);
+            assert_eq!(txn.get("a").await.unwrap(), Some(b"1".to_vec()));
+            assert!(matches!(
+                txn.rollback_to_savepoint().await,
+                Err(Error::TxNoSavepoint)
+            ));
+            txn.commit().await.unwrap();
+
+            let mut txn = ds.transaction(false).await.unwrap();
+            assert_eq!(txn.get("a").await.unwrap(), Some(b"1".to_vec()));
+            assert_eq!(txn.get("b").await.unwrap(), None);
+        });
+    }
+
+    #[tokio::test]
+    async fn retries() {
+        let ds = Datastore::new();
+
+        // The first attempt conflicts with a concurrent increment and runs again
+        let mut attempts = 0;
+        let count = ds
+            .run_in_transaction(true, async |txn| {
+                attempts += 1;
+                let count = txn.get("count").await?.map_or(0, |val| val[0]);
+
+                if attempts == 1 {
+                    let mut other = ds.transaction(true).await?;
+                    other.upsert("count", vec![10]).await?;
+                    other.commit().await?;
+                }
+
+                txn.upsert("count", vec![count + 1]).await?;
+                Ok(count + 1)
+            })
+            .await
+            .unwrap();
+        assert_eq!((attempts, count), (2, 11));
+
+        // Other errors are returned at once, and the writes rolled back
+        let mut attempts = 0;
+        let result: Result<(), Error> = ds
+            .run_in_transaction(true, async |txn| {
+                attempts += 1;
+                txn.upsert("count", vec![0]).await?;
+                Err(Error::TxConditionNotMet)
+            })
+            .await;
+        assert!(matches!(result, Err(Error::TxConditionNotMet)));
+        assert_eq!(attempts, 1);
+
+        let mut txn = ds.transaction(false).await.unwrap();
+        assert_eq!(txn.get("count").await.unwrap(), Some(vec![11]));
+    }
+}
@@ -0,0 +1,210 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+//! Online copies of a rocksdb datastore, taken while it serves reads and writes.
+//!
+//! A checkpoint is a full copy in a new directory, made of hard links to the table files where
+//! the file system allows, that opens as a database of its own. Backups are incremental, each
+//! one only copies the table files the earlier backups don't have. They are kept in the backup
+//! directory of the config until the retention policy purges them, and can be verified and
+//! restored into a new directory.
+//!
+//! The rocksdb bindings only offer checkpoints and backups of optimistic databases.
+
+use super::{Datastore, DatastoreConfig, Db};
+use crate::error::Error;
+use rocksdb::backup::{BackupEngine, BackupEngineOptions, RestoreOptions};
+use rocksdb::checkpoint::Checkpoint;
+use rocksdb::{Env, OptimisticTransactionDB};
+use std::path::Path;
+
+/// A backup kept in the backup directory
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupInfo {
+    /// Identifies the backup, every backup gets a larger id than the ones before it
+    pub id: u32,
+
+    /// When the backup was taken, in seconds since the Unix epoch
+    pub timestamp: i64,
+
+    /// The size of the backup's files in bytes, including the ones it shares with others
+    pub size: u64,
+
+    /// The number of files of the backup
+    pub files: u32,
+}
+
+impl Datastore {
+    /// Writes a checkpoint of the database to a directory, which must not exist yet
+    pub fn checkpoint(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let checkpoint = Checkpoint::new(self.db.optimistic()?).map_err(io)?;
+        checkpoint.create_checkpoint(path).map_err(io)
+    }
+
+    /// Takes a backup into the backup directory, then purges the oldest backups beyond the
+    /// number the config keeps. Returns the new backup.
+    pub fn backup(&self) -> Result<BackupInfo, Error> {
+        let db = self.db.optimistic()?;
+        let _backups = self.backups.lock()?;
+
+        // The memtables are flushed first, so the backup has the writes that aren't in a
+        // table file yet even when the write ahead log is disabled
+        let mut engine = open_engine(&self.config)?;
+        engine.create_new_backup_flush(db, true).map_err(io)?;
+        if self.config.backup_retention > 0 {
+            engine
+                .purge_old_backups(self.config.backup_retention)
+                .map_err(io)?;
+        }
+
+        infos(&engine)
+            .pop()
+            .ok_or_else(|| Error::Io(String::from("The backup wasn't recorded")))
+    }
+
+    /// The backups in the backup directory, oldest first
+    pub fn backups(&self) -> Result<Vec<BackupInfo>, Error> {
+        let _backups = self.backups.lock()?;
+        Ok(infos(&open_engine(&self.config)?))
+    }
+
+    /// Checks that the files of a backup exist with the sizes they were written with
+    pub fn verify_backup(&self, id: u32) -> Result<(), Error> {
+        let _backups = self.backups.lock()?;
+        open_engine(&self.config)?.verify_backup(id).map_err(io)
+    }
+
+    /// Restores a backup from the backup directory of a config, the latest one when `id` is
+    /// `None`, into a directory a database can then be opened from. The directory must not be
+    /// the one of an open database.
+    pub fn restore(
+        config: &DatastoreConfig,
+        id: Option<u32>,
+        path: impl AsRef<Path>,
+    ) -> Result<(), Error> {
+        let path = path.as_ref();
+        let mut engine = open_engine(config)?;
+        let options = RestoreOptions::default();
+
+        match id {
+            Some(id) => engine.restore_from_backup(path, path, &options, id),
+            None => engine.restore_from_latest_backup(path, path, &options),
+        }
+        .map_err(io)
+    }
+}
+
+impl Db {
+    /// The optimistic database, the only kind the bindings can checkpoint and back up
+    fn optimistic(&self) -> Result<&OptimisticTransactionDB, Error> {
+        match self {
+            Db::Optimistic(db) => Ok(db),
+            Db::Pessimistic(_) => Err(Error::Io(String::from(
+                "Checkpoints and backups are only supported for optimistic databases",
+            ))),
+        }
+    }
+}
+
+fn open_engine(config: &DatastoreConfig) -> Result<BackupEngine, Error> {
+    let options = BackupEngineOptions::new(&config.backup_path).map_err(io)?;
+    BackupEngine::open(&options, &Env::new().map_err(io)?).map_err(io)
+}
+
+fn infos(engine: &BackupEngine) -> Vec<BackupInfo> {
+    let mut infos: Vec<BackupInfo> = engine
+        .get_backup_info()
+        .into_iter()
+        .map(|info| BackupInfo {
+            id: info.backup_id,
+            timestamp: info.timestamp,
+            size: info.size,
+            files: info.num_files,
+        })
+        .collect();
+    infos.sort_by_key(|info| info.id);
+
+    infos
+}
+
+/// Backups fail on the files they read and write, not on transactions
+fn io(e: rocksdb::Error) -> Error {
+    Error::Io(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::kv::{Store as _, Transaction as _};
+    use crate::storage::rocksdb::TransactionMode;
+    use futures::executor::block_on;
+
+    fn value(ds: &Datastore, key: &str) -> Option<Vec<u8>> {
+        block_on(async {
+            let mut txn = ds.transaction(false).await.unwrap();
+            txn.get(key).await.unwrap()
+        })
+    }
+
+    fn put(ds: &Datastore, key: &str) {
+        block_on(async {
+            let mut txn = ds.transaction(true).await.unwrap();
+            txn.upsert(key, "1").await.unwrap();
+            txn.commit().await.unwrap();
+        })
+    }
+
+    fn open(path: &Path) -> Datastore {
+        block_on(Datastore::new(path.to_str().unwrap())).unwrap()
+    }
+
+    #[test]
+    fn checkpoints_and_backups() {
+        let dir = std::env::temp_dir().join(format!("kuiperdb-backup-{}", uuid::Uuid::new_v4()));
+        let config = DatastoreConfig {
+            backup_path: dir.join("backups").to_str().unwrap().to_owned(),
+            backup_retention: 2,
+            ..DatastoreConfig::with_path(dir.join("db").to_str().unwrap())
+        };
+        let ds = block_on(Datastore::open(config.clone())).unwrap();
+
+        // A checkpoint opens as a database of its own
+        put(&ds, "a");
+        ds.checkpoint(dir.join("checkpoint")).unwrap();
+        put(&ds, "b");
+        let checkpoint = open(&dir.join("checkpoint"));
+        assert!(value(&checkpoint, "a").is_some());
+        assert!(value(&checkpoint, "b").is_none());
+        assert!(ds.checkpoint(dir.join("checkpoint")).is_err());
+
+        // Only the newest backups are kept
+        let first = ds.backup().unwrap();
+        put(&ds, "c");
+        let second = ds.backup().unwrap();
+        put(&ds, "d");
+        let third = ds.backup().unwrap();
+        assert!(first.id < second.id && second.id < third.id);
+        assert_eq!(ds.backups().unwrap(), [second.clone(), third]);
+        ds.verify_backup(second.id).unwrap();
+        assert!(ds.verify_backup(first.id).is_err());
+
+        Datastore::restore(&config, Some(second.id), dir.join("second")).unwrap();
+        let restored = open(&dir.join("second"));
+        assert!(value(&restored, "c").is_some());
+        assert!(value(&restored, "d").is_none());
+
+        Datastore::restore(&config, None, dir.join("latest")).unwrap();
+        assert!(value(&open(&dir.join("latest")), "d").is_some());
+
+        // Pessimistic databases can't be copied
+        let pessimistic = block_on(Datastore::open(DatastoreConfig {
+            transactions: TransactionMode::Pessimistic,
+            ..DatastoreConfig::with_path(dir.join("pessimistic").to_str().unwrap())
+        }))
+        .unwrap();
+        assert!(matches!(pessimistic.backup(), Err(Error::Io(_))));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,507 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+//! The settings a rocksdb datastore is opened with.
+//!
+//! A config is read from a TOML file, and every setting can then be overridden by a
+//! `KUIPERDB_` environment variable named after it, e.g. `KUIPERDB_BLOCK_CACHE_SIZE`:
+//!
+//! ```toml
+//! path = "dbs/vuln_db"
+//! compression = ["none", "none", "lz4", "lz4", "zstd"]
+//! block_cache_size = 268435456
+//! bloom_filter_bits = 10.0
+//! prefix_extractor = true
+//! wal = "sync"
+//! max_open_files = 1024
+//! transactions = "pessimistic"
+//! lock_timeout_ms = 500
+//! deadlock_detect = true
+//! isolation = "serializable"
+//! retry_attempts = 5
+//! retry_backoff_ms = 10
+//! retry_max_backoff_ms = 1000
+//! backup_path = "dbs/backups"
+//! backup_retention = 7
+//! admin_commands = true
+//! checkpoint_path = "dbs/checkpoints"
+//! ```
+
+use crate::error::Error;
+use crate::schema::information_schema::PADDING;
+use crate::storage::kv::{IsolationLevel, RetryPolicy, ScanRange};
+use rocksdb::{
+    BlockBasedOptions, Cache, DBCompressionType, OptimisticTransactionOptions, SliceTransform,
+    TransactionDBOptions, TransactionOptions, WriteOptions,
+};
+use std::path::Path;
+use std::time::Duration;
+
+/// The file a config is read from when `KUIPERDB_CONFIG` isn't set
+pub const DEFAULT_CONFIG_FILE: &str = "kuiperdb.toml";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatastoreConfig {
+    /// The directory of the database, created if it doesn't exist
+    pub path: String,
+
+    /// The compression of each level, starting at level 0. The last entry is used for the levels
+    /// after it.
+    pub compression: Vec<Compression>,
+
+    /// The size of the block cache in bytes, rocksdb's default cache is used when 0
+    pub block_cache_size: usize,
+
+    /// The bits per key of the bloom filters, no filters are built when not set
+    pub bloom_filter_bits: Option<f64>,
+
+    /// Extract the `schema::collection::` prefix of keys, so scans of one collection can use
+    /// the prefix blooms and skip the files of other collections
+    pub prefix_extractor: bool,
+
+    /// How commits are written to the write ahead log
+    pub wal: WalPolicy,
+
+    /// The number of files rocksdb keeps open, -1 keeps every file open
+    pub max_open_files: i32,
+
+    /// How transactions that write the same keys are kept apart
+    pub transactions: TransactionMode,
+
+    /// How long a pessimistic transaction waits for a lock before failing, in milliseconds. A
+    /// negative timeout waits forever.
+    pub lock_timeout_ms: i64,
+
+    /// Fail a pessimistic transaction as soon as its wait for a lock would deadlock, instead
+    /// of when the wait times out
+    pub deadlock_detect: bool,
+
+    /// The isolation level of transactions: `snapshot`, `read_committed` or `serializable`
+    pub isolation: IsolationLevel,
+
+    /// The number of times a transaction that conflicted with another one is run, including
+    /// the first
+    pub retry_attempts: u32,
+
+    /// How long to wait before running a conflicting transaction again, in milliseconds. The
+    /// wait doubles with every attempt.
+    pub retry_backoff_ms: u64,
+
+    /// The longest wait before running a conflicting transaction again, in milliseconds
+    pub retry_max_backoff_ms: u64,
+
+    /// The directory backups are kept in, created if it doesn't exist
+    pub backup_path: String,
+
+    /// The number of backups kept, the oldest ones are purged after each backup. Every backup
+    /// is kept when 0.
+    pub backup_retention: usize,
+
+    /// Whether the gateway runs admin commands such as `.backup`, its clients aren't
+    /// authenticated so they are off by default
+    pub admin_commands: bool,
+
+    /// The directory the checkpoints and restores of admin commands are written under, they
+    /// can't name a directory outside of it
+    pub checkpoint_path: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Snappy,
+    Zlib,
+    Bz2,
+    Lz4,
+    Lz4hc,
+    Zstd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WalPolicy {
+    /// Commits are written to the log and the log is synced to disk before a commit returns
+    Sync,
+
+    /// Commits are written to the log, which the operating system flushes to disk later. A
+    /// crash of the machine, but not of the process, can lose the last commits.
+    Async,
+
+    /// Commits are only written to the memtables, a crash loses everything not yet flushed
+    Disabled,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionMode {
+    /// Transactions don't lock, a commit fails when another transaction committed one of its
+    /// keys after it started. Suits workloads where transactions rarely touch the same keys.
+    Optimistic,
+
+    /// Writes, and reads for update, lock their keys until the transaction completes, other
+    /// transactions wait for the locks. Suits hot keys such as counters, which would keep
+    /// failing to commit.
+    Pessimistic,
+}
+
+impl Default for DatastoreConfig {
+    fn default() -> Self {
+        DatastoreConfig {
+            path: String::from("dbs/vuln_db"),
+            compression: vec![Compression::None],
+            block_cache_size: 0,
+            bloom_filter_bits: None,
+            prefix_extractor: false,
+            wal: WalPolicy::Async,
+            max_open_files: -1,
+            transactions: TransactionMode::Optimistic,
+            lock_timeout_ms: 1000,
+            deadlock_detect: true,
+            isolation: IsolationLevel::Snapshot,
+            retry_attempts: 5,
+            retry_backoff_ms: 10,
+            retry_max_backoff_ms: 1000,
+            backup_path: String::from("dbs/backups"),
+            backup_retention: 7,
+            admin_commands: false,
+            checkpoint_path: String::from("dbs/checkpoints"),
+        }
+    }
+}
+
+impl DatastoreConfig {
+    /// The default config for a database at a path
+    pub fn with_path(path: &str) -> DatastoreConfig {
+        DatastoreConfig {
+            path: path.to_owned(),
+            ..DatastoreConfig::default()
+        }
+    }
+
+    /// Reads the config of the binaries: the file named by `KUIPERDB_CONFIG`, or
+    /// `kuiperdb.toml` when it exists, with the `KUIPERDB_` variables applied on top.
+    pub fn load() -> Result<DatastoreConfig, Error> {
+        let mut config = match std::env::var("KUIPERDB_CONFIG") {
+            Ok(file) => DatastoreConfig::from_file(file)?,
+            Err(_) if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                DatastoreConfig::from_file(DEFAULT_CONFIG_FILE)?
+            }
+            Err(_) => DatastoreConfig::default(),
+        };
+
+        config.apply_env(std::env::vars())?;
+        Ok(config)
+    }
+
+    /// Reads a config from a TOML file, settings it doesn't have keep their defaults
+    pub fn from_file(file: impl AsRef<Path>) -> Result<DatastoreConfig, Error> {
+        let file = file.as_ref();
+        let text = std::fs::read_to_string(file)
+            .map_err(|e| Error::Config(format!("Can't read {}: {}", file.display(), e)))?;
+
+        DatastoreConfig::from_toml(&text)
+    }
+
+    /// Parses a config from TOML text, settings it doesn't have keep their defaults
+    pub fn from_toml(text: &str) -> Result<DatastoreConfig, Error> {
+        toml::from_str(text).map_err(|e| Error::Config(e.to_string()))
+    }
+
+    /// Overrides settings from `KUIPERDB_` variables, other variables are ignored.
+    /// Compression levels are separated by commas, e.g. `KUIPERDB_COMPRESSION=none,lz4,zstd`.
+    pub fn apply_env(
+        &mut self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<(), Error> {
+        for (name, value) in vars {
+            let setting = match name.strip_prefix("KUIPERDB_") {
+                Some(setting) => setting.to_lowercase(),
+                None => continue,
+            };
+
+            let value = value.trim();
+            match setting.as_str() {
+                "config" => {}
+                "path" => self.path = value.to_owned(),
+                "compression" => {
+                    self.compression = value
+                        .split(',')
+                        .map(|level| parse_name(&name, level))
+                        .collect::<Result<_, _>>()?
+                }
+                "block_cache_size" => self.block_cache_size = parse(&name, value)?,
+                "bloom_filter_bits" if value.is_empty() => self.bloom_filter_bits = None,
+                "bloom_filter_bits" => self.bloom_filter_bits = Some(parse(&name, value)?),
+                "prefix_extractor" => self.prefix_extractor = parse(&name, value)?,
+                "wal" => self.wal = parse_name(&name, value)?,
+                "max_open_files" => self.max_open_files = parse(&name, value)?,
+                "transactions" => self.transactions = parse_name(&name, value)?,
+                "lock_timeout_ms" => self.lock_timeout_ms = parse(&name, value)?,
+                "deadlock_detect" => self.deadlock_detect = parse(&name, value)?,
+                "isolation" => self.isolation = parse_name(&name, value)?,
+                "retry_attempts" => self.retry_attempts = parse(&name, value)?,
+                "retry_backoff_ms" => self.retry_backoff_ms = parse(&name, value)?,
+                "retry_max_backoff_ms" => self.retry_max_backoff_ms = parse(&name, value)?,
+                "backup_path" => self.backup_path = value.to_owned(),
+                "backup_retention" => self.backup_retention = parse(&name, value)?,
+                "admin_commands" => self.admin_commands = parse(&name, value)?,
+                "checkpoint_path" => self.checkpoint_path = value.to_owned(),
+                _ => return Err(Error::Config(format!("Unknown setting {}", name))),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// How conflicting transactions are retried
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            attempts: self.retry_attempts,
+            backoff: Duration::from_millis(self.retry_backoff_ms),
+            max_backoff: Duration::from_millis(self.retry_max_backoff_ms),
+        }
+    }
+
+    /// The options the database and its column families are opened with
+    pub(super) fn options(&self) -> rocksdb::Options {
+        let mut options = rocksdb::Options::default();
+        options.set_error_if_exists(false);
+        options.create_if_missing(true);
+        options.create_missing_column_families(true);
+        options.set_max_open_files(self.max_open_files);
+
+        match self.compression.as_slice() {
+            [] => {}
+            [compression] => options.set_compression_type((*compression).into()),
+            levels => {
+                let levels: Vec<DBCompressionType> = levels.iter().map(|&c| c.into()).collect();
+                options.set_compression_per_level(&levels);
+            }
+        }
+
+        let mut table_options = BlockBasedOptions::default();
+        if self.block_cache_size > 0 {
+            table_options.set_block_cache(&Cache::new_lru_cache(self.block_cache_size));
+        }
+        if let Some(bits) = self.bloom_filter_bits {
+            table_options.set_bloom_filter(bits, false);
+        }
+        options.set_block_based_table_factory(&table_options);
+
+        if self.prefix_extractor {
+            options.set_prefix_extractor(SliceTransform::create(
+                "kuiperdb.collection",
+                collection_prefix,
+                Some(has_collection_prefix),
+            ));
+            if self.bloom_filter_bits.is_some() {
+                options.set_memtable_prefix_bloom_ratio(0.1);
+            }
+        }
+
+        options
+    }
+
+    /// The options commits are written with
+    pub(super) fn write_options(&self) -> WriteOptions {
+        let mut write_options = WriteOptions::default();
+        match self.wal {
+            WalPolicy::Sync => write_options.set_sync(true),
+            WalPolicy::Async => {}
+            WalPolicy::Disabled => write_options.disable_wal(true),
+        }
+
+        write_options
+    }
+
+    /// The options a pessimistic database is opened with
+    pub(super) fn transaction_db_options(&self) -> TransactionDBOptions {
+        let mut options = TransactionDBOptions::default();
+        options.set_txn_lock_timeout(self.lock_timeout_ms);
+        options.set_default_lock_timeout(self.lock_timeout_ms);
+        options
+    }
+
+    /// The options of an optimistic transaction, it reads from a snapshot
+    pub(super) fn optimistic_options(&self) -> OptimisticTransactionOptions {
+        let mut options = OptimisticTransactionOptions::default();
+        options.set_snapshot(true);
+        options
+    }
+
+    /// The options of a pessimistic transaction, it reads from a snapshot
+    pub(super) fn pessimistic_options(&self) -> TransactionOptions {
+        let mut options = TransactionOptions::default();
+        options.set_snapshot(true);
+        options.set_lock_timeout(self.lock_timeout_ms);
+        options.set_deadlock_detect(self.deadlock_detect);
+        options
+    }
+}
+
+fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::Config(format!("Invalid value for {}: {}", name, value)))
+}
+
+/// Parses the name of a variant the same way it is read from a file
+fn parse_name<T: serde::de::DeserializeOwned>(name: &str, value: &str) -> Result<T, Error> {
+    toml::Value::String(value.trim().to_lowercase())
+        .try_into()
+        .map_err(|_| Error::Config(format!("Invalid value for {}: {}", name, value)))
+}
+
+impl From<Compression> for DBCompressionType {
+    fn from(compression: Compression) -> DBCompressionType {
+        match compression {
+            Compression::None => DBCompressionType::None,
+            Compression::Snappy => DBCompressionType::Snappy,
+            Compression::Zlib => DBCompressionType::Zlib,
+            Compression::Bz2 => DBCompressionType::Bz2,
+            Compression::Lz4 => DBCompressionType::Lz4,
+            Compression::Lz4hc => DBCompressionType::Lz4hc,
+            Compression::Zstd => DBCompressionType::Zstd,
+        }
+    }
+}
+
+/// The length of the `schema::collection::` prefix of a key
+fn collection_prefix_len(key: &[u8]) -> Option<usize> {
+    let mut separators = key
+        .windows(PADDING.len())
+        .enumerate()
+        .filter(|(_, window)| *window == PADDING)
+        .map(|(i, _)| i + PADDING.len());
+
+    separators.next()?;
+    separators.next()
+}
+
+/// The `schema::collection::` prefix of a key, the whole key when it doesn't have one
+pub(super) fn collection_prefix(key: &[u8]) -> &[u8] {
+    &key[..collection_prefix_len(key).unwrap_or(key.len())]
+}
+
+fn has_collection_prefix(key: &[u8]) -> bool {
+    collection_prefix_len(key).is_some()
+}
+
+/// Can a range be scanned in prefix mode, which only sees the keys with the prefix of the key
+/// it seeks to. Only forward ranges inside of a single collection can.
+pub(super) fn within_collection(range: &ScanRange) -> bool {
+    let (start, end) = match (&range.start, &range.end) {
+        (Some(start), Some(end)) if !range.reverse && has_collection_prefix(start) => (start, end),
+        _ => return false,
+    };
+
+    ScanRange::prefix(collection_prefix(start))
+        .end
+        .is_some_and(|collection_end| *end <= collection_end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn toml_and_env() {
+        let mut config = DatastoreConfig::from_toml(
+            "path = \"/var/lib/kuiperdb\"\n\
+             compression = [\"none\", \"lz4\", \"zstd\"]\n\
+             bloom_filter_bits = 10.0\n\
+             wal = \"sync\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            DatastoreConfig {
+                path: String::from("/var/lib/kuiperdb"),
+                compression: vec![Compression::None, Compression::Lz4, Compression::Zstd],
+                bloom_filter_bits: Some(10.0),
+                wal: WalPolicy::Sync,
+                ..DatastoreConfig::default()
+            }
+        );
+
+        config
+            .apply_env(vars(&[
+                ("HOME", "/root"),
+                ("KUIPERDB_COMPRESSION", "Snappy, zstd"),
+                ("KUIPERDB_BLOCK_CACHE_SIZE", "1048576"),
+                ("KUIPERDB_BLOOM_FILTER_BITS", ""),
+                ("KUIPERDB_PREFIX_EXTRACTOR", "true"),
+                ("KUIPERDB_WAL", "DISABLED"),
+                ("KUIPERDB_TRANSACTIONS", "Pessimistic"),
+                ("KUIPERDB_LOCK_TIMEOUT_MS", "250"),
+                ("KUIPERDB_ISOLATION", "Read_Committed"),
+                ("KUIPERDB_RETRY_ATTEMPTS", "3"),
+                ("KUIPERDB_BACKUP_RETENTION", "30"),
+                ("KUIPERDB_ADMIN_COMMANDS", "true"),
+            ]))
+            .unwrap();
+        assert_eq!(
+            config,
+            DatastoreConfig {
+                path: String::from("/var/lib/kuiperdb"),
+                compression: vec![Compression::Snappy, Compression::Zstd],
+                block_cache_size: 1048576,
+                bloom_filter_bits: None,
+                prefix_extractor: true,
+                wal: WalPolicy::Disabled,
+                transactions: TransactionMode::Pessimistic,
+                lock_timeout_ms: 250,
+                isolation: IsolationLevel::ReadCommitted,
+                retry_attempts: 3,
+                backup_retention: 30,
+                admin_commands: true,
+                ..DatastoreConfig::default()
+            }
+        );
+
+        for invalid in [
+            vec![("KUIPERDB_WAL", "sometimes")],
+            vec![("KUIPERDB_MAX_OPEN_FILES", "many")],
+            vec![("KUIPERDB_ISOLATION", "repeatable_read")],
+            vec![("KUIPERDB_CACHE", "1")],
+        ] {
+            assert!(matches!(
+                config.clone().apply_env(vars(&invalid)),
+                Err(Error::Config(_))
+            ));
+        }
+        assert!(matches!(
+            DatastoreConfig::from_toml("paths = \"x\""),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn collection_prefixes() {
+        assert_eq!(
+            collection_prefix(b"default::events::\x01::\x02"),
+            b"default::events::"
+        );
+        assert_eq!(collection_prefix(b"default::events"), b"default::events");
+        assert!(has_collection_prefix(b"default::events::"));
+        assert!(!has_collection_prefix(b"default::events:"));
+
+        let collection = ScanRange::prefix("default::events::");
+        assert!(within_collection(&collection));
+        assert!(within_collection(
+            &collection.clone().after("default::events::\x01")
+        ));
+        assert!(!within_collection(&collection.reverse()));
+        assert!(!within_collection(&ScanRange::prefix("default::")));
+        assert!(!within_collection(&ScanRange::all()));
+    }
+}
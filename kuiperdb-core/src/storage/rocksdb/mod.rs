@@ -2,27 +2,226 @@
 // (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
 //--------------------------------------------------------------------------
 
-use super::kv::Key;
-use super::kv::Val;
+mod backup;
+mod config;
+
+pub use self::backup::BackupInfo;
+pub use self::config::{
+    Compression, DatastoreConfig, TransactionMode, WalPolicy, DEFAULT_CONFIG_FILE,
+};
+
+use super::kv::{self, IsolationLevel, Key, ScanRange, Val};
 use crate::error::Error;
-use futures::lock::Mutex;
 use rocksdb::{
-    OptimisticTransactionDB, OptimisticTransactionOptions, ReadOptions, WriteOptions, DB,
+    BoundColumnFamily, DBAccess, DBRawIteratorWithThreadMode, OptimisticTransactionDB, ReadOptions,
+    TransactionDB, DB,
 };
-use std::ops::Neg;
-use std::ops::Range;
+use std::future::{self, Future};
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+
+/// Runs the same code on the optimistic and the pessimistic variant of a rocksdb type.
+macro_rules! dispatch {
+    ($value:expr, $kind:ident, $inner:ident => $body:expr) => {
+        match $value {
+            $kind::Optimistic($inner) => $body,
+            $kind::Pessimistic($inner) => $body,
+        }
+    };
+}
 
 #[derive(Clone)]
 pub struct Datastore {
-    db: Pin<Arc<OptimisticTransactionDB>>,
+    db: Pin<Arc<Db>>,
     indexes: Vec<String>,
+    config: Arc<DatastoreConfig>,
+
+    // Serializable transactions validate their reads and commit while holding it for writing,
+    // so no other transaction commits in between. The others commit holding it for reading.
+    commits: Arc<RwLock<()>>,
+
+    // Backups are taken, listed and verified one at a time
+    backups: Arc<std::sync::Mutex<()>>,
+}
+
+/// The database, opened for the transaction mode of the config.
+enum Db {
+    Optimistic(OptimisticTransactionDB),
+    Pessimistic(TransactionDB),
+}
+
+impl Db {
+    fn cf_handle(&self, name: &str) -> Option<Arc<BoundColumnFamily<'_>>> {
+        dispatch!(self, Db, db => db.cf_handle(name))
+    }
+
+    /// Whether a key of the range, in the default column family or an index, was committed
+    /// after the snapshot of the read options
+    fn changed_since(
+        &self,
+        idx: Option<&str>,
+        range: &ScanRange,
+        snapshot: ReadOptions,
+    ) -> Result<bool, Error> {
+        let bounded = |mut read_options: ReadOptions| {
+            if let Some(start) = &range.start {
+                read_options.set_iterate_lower_bound(start.clone());
+            }
+            if let Some(end) = &range.end {
+                read_options.set_iterate_upper_bound(end.clone());
+            }
+            read_options.set_total_order_seek(true);
+            read_options
+        };
+        let cf = idx.and_then(|idx| self.cf_handle(idx));
+
+        dispatch!(self, Db, db => {
+            let iterator = |read_options| match &cf {
+                Some(cf) => db.raw_iterator_cf_opt(cf, read_options),
+                None => db.raw_iterator_opt(read_options),
+            };
+            differ(
+                iterator(bounded(snapshot)),
+                iterator(bounded(ReadOptions::default())),
+                range,
+            )
+        })
+    }
+}
+
+/// Whether two iterators over a range visit different keys or values
+fn differ<'a, D: DBAccess>(
+    mut before: DBRawIteratorWithThreadMode<'a, D>,
+    mut after: DBRawIteratorWithThreadMode<'a, D>,
+    range: &ScanRange,
+) -> Result<bool, Error> {
+    for iterator in [&mut before, &mut after] {
+        match &range.start {
+            Some(start) => iterator.seek(start),
+            None => iterator.seek_to_first(),
+        }
+    }
+
+    let within = |iterator: &DBRawIteratorWithThreadMode<'a, D>| {
+        iterator.key().is_some_and(|k| range.contains(k))
+    };
+
+    loop {
+        match (within(&before), within(&after)) {
+            (false, false) => break,
+            (true, true) if before.item() == after.item() => {
+                before.next();
+                after.next();
+            }
+            _ => {
+                before.status()?;
+                after.status()?;
+                return Ok(true);
+            }
+        }
+    }
+
+    before.status()?;
+    after.status()?;
+    Ok(false)
+}
+
+/// A transaction of either kind of database.
+enum Txn {
+    Optimistic(rocksdb::Transaction<'static, OptimisticTransactionDB>),
+    Pessimistic(rocksdb::Transaction<'static, TransactionDB>),
+}
+
+impl Txn {
+    /// Read options that read from the transaction's snapshot
+    fn snapshot_read_options(&self) -> ReadOptions {
+        let mut read_options = ReadOptions::default();
+        dispatch!(self, Txn, txn => read_options.set_snapshot(&txn.snapshot()));
+        read_options
+    }
+
+    /// Read options that read what the isolation level sees, the snapshot, or the latest
+    /// commits when reading committed data
+    fn read_options(&self, isolation: IsolationLevel) -> ReadOptions {
+        match isolation {
+            IsolationLevel::ReadCommitted => ReadOptions::default(),
+            _ => self.snapshot_read_options(),
+        }
+    }
+
+    fn get_opt<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        read_options: &ReadOptions,
+    ) -> Result<Option<Val>, Error> {
+        Ok(dispatch!(self, Txn, txn => txn.get_opt(key, read_options))?)
+    }
+
+    fn get_for_update_opt<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        read_options: &ReadOptions,
+    ) -> Result<Option<Val>, Error> {
+        Ok(dispatch!(self, Txn, txn => txn.get_for_update_opt(key, true, read_options))?)
+    }
+
+    fn put(&self, key: Key, val: Val) -> Result<(), Error> {
+        Ok(dispatch!(self, Txn, txn => txn.put(key, val))?)
+    }
+
+    fn put_cf(&self, cf: &Arc<BoundColumnFamily>, key: Key, val: Val) -> Result<(), Error> {
+        Ok(dispatch!(self, Txn, txn => txn.put_cf(cf, key, val))?)
+    }
+
+    fn delete(&self, key: Key) -> Result<(), Error> {
+        Ok(dispatch!(self, Txn, txn => txn.delete(key))?)
+    }
+
+    fn commit(self) -> Result<(), Error> {
+        Ok(dispatch!(self, Txn, txn => txn.commit())?)
+    }
+
+    fn set_savepoint(&self) {
+        dispatch!(self, Txn, txn => txn.set_savepoint())
+    }
+
+    fn rollback_to_savepoint(&self) -> Result<(), Error> {
+        dispatch!(self, Txn, txn => txn.rollback_to_savepoint()).map_err(|e| match e.kind() {
+            rocksdb::ErrorKind::NotFound => Error::TxNoSavepoint,
+            _ => e.into(),
+        })
+    }
+
+    fn rollback(&self) -> Result<(), Error> {
+        Ok(dispatch!(self, Txn, txn => txn.rollback())?)
+    }
+
+    fn raw_iterator(
+        &self,
+        cf: Option<&Arc<BoundColumnFamily>>,
+        read_options: ReadOptions,
+    ) -> RawIterator<'_> {
+        match (self, cf) {
+            (Txn::Optimistic(txn), Some(cf)) => {
+                RawIterator::Optimistic(txn.raw_iterator_cf_opt(cf, read_options))
+            }
+            (Txn::Optimistic(txn), None) => {
+                RawIterator::Optimistic(txn.raw_iterator_opt(read_options))
+            }
+            (Txn::Pessimistic(txn), Some(cf)) => {
+                RawIterator::Pessimistic(txn.raw_iterator_cf_opt(cf, read_options))
+            }
+            (Txn::Pessimistic(txn), None) => {
+                RawIterator::Pessimistic(txn.raw_iterator_opt(read_options))
+            }
+        }
+    }
 }
 
 pub struct Transaction {
-    // The datastore transaction
-    txn: Arc<Mutex<Option<rocksdb::Transaction<'static, OptimisticTransactionDB>>>>,
+    // The datastore transaction, taken when it is committed or rolled back. Writes need the
+    // transaction mutably, so they can't happen while a cursor borrows it.
+    txn: Option<Txn>,
 
     // Has the transaction completed.
     completed: bool,
@@ -30,33 +229,37 @@ pub struct Transaction {
     // Is the transaction ReadWrite, true, or ReadOnly, false.
     rw: bool,
 
-    // The read options regarding the transaction Snapshot
-    _snapshot_read_options: ReadOptions,
+    // What the transaction sees of the commits made while it runs
+    isolation: IsolationLevel,
+
+    // The read options of the isolation level, with the transaction's snapshot unless it reads
+    // committed data
+    _read_options: ReadOptions,
+
+    // The ranges read by a serializable transaction, with the index they were read from,
+    // checked for changes when it commits
+    reads: std::sync::Mutex<Vec<(Option<String>, ScanRange)>>,
 
     // the above 'static transaction points here in order to keep
     // the memory alive - to make sure that this is dropped last,
     // it must be declared last
-    _db: Pin<Arc<OptimisticTransactionDB>>,
+    _db: Pin<Arc<Db>>,
 
     // This is the datastore that the db is tied to
     _ds: Pin<Arc<Datastore>>,
 }
 
 impl Datastore {
-    /// Open a new database
+    /// Open a new database with the default config
     pub async fn new(path: &str) -> Result<Datastore, Error> {
-        let mut options = rocksdb::Options::default();
-        options.set_compression_type(rocksdb::DBCompressionType::None);
-        options.set_error_if_exists(false);
-        options.create_if_missing(true);
-        options.create_missing_column_families(true);
-
-        // Disable WAL for specific write operations
-        // options.set_atomic_flush(true);
-        // let mut write_options = WriteOptions::default();
-        // write_options.disable_wal(true);
-
-        let mut indexes = DB::list_cf(&options, path.clone()).unwrap_or(vec![]);
+        Datastore::open(DatastoreConfig::with_path(path)).await
+    }
+
+    /// Open a database with the settings of a config
+    pub async fn open(config: DatastoreConfig) -> Result<Datastore, Error> {
+        let options = config.options();
+
+        let mut indexes = DB::list_cf(&options, &config.path).unwrap_or(vec![]);
         let cfs = indexes.clone();
 
         let default_index = indexes.iter().position(|x| *x == "default");
@@ -65,27 +268,49 @@ impl Datastore {
             indexes.remove(default_index.unwrap());
         }
 
+        let db = match config.transactions {
+            TransactionMode::Optimistic => Db::Optimistic(OptimisticTransactionDB::open_cf(
+                &options,
+                &config.path,
+                cfs,
+            )?),
+            TransactionMode::Pessimistic => Db::Pessimistic(TransactionDB::open_cf(
+                &options,
+                &config.transaction_db_options(),
+                &config.path,
+                cfs,
+            )?),
+        };
+
         Ok(Datastore {
-            db: Arc::pin(OptimisticTransactionDB::open_cf(&options, path, cfs)?),
+            db: Arc::pin(db),
             indexes: indexes,
+            config: Arc::new(config),
+            commits: Arc::new(RwLock::new(())),
+            backups: Arc::new(std::sync::Mutex::new(())),
         })
     }
 
+    /// The config the database was opened with
+    pub fn config(&self) -> &DatastoreConfig {
+        &self.config
+    }
+
     fn index_exists(&self, idx_name: &str) -> bool {
         self.db.cf_handle(idx_name).is_some()
     }
 
     pub fn add_index(&mut self, idx_name: &str) {
         if !self.index_exists(idx_name) {
-            let options = rocksdb::Options::default();
+            let options = self.config.options();
             self.indexes.push(idx_name.to_owned());
-            self.db.create_cf(idx_name, &options).unwrap();
+            dispatch!(&*self.db, Db, db => db.create_cf(idx_name, &options)).unwrap();
         }
     }
 
     pub fn drop_index(&mut self, idx_name: &str) {
         if self.index_exists(idx_name) {
-            self.db.drop_cf(idx_name).unwrap();
+            dispatch!(&*self.db, Db, db => db.drop_cf(idx_name)).unwrap();
 
             let index_to_remove = self.indexes.iter().position(|x| *x == idx_name);
 
@@ -94,73 +319,105 @@ impl Datastore {
             }
         }
     }
+}
 
-    /// Start a new transaction
-    pub async fn transaction(&self, write: bool) -> Result<Transaction, Error> {
-        // snapshot options
-        let mut transaction_options = OptimisticTransactionOptions::default();
-        transaction_options.set_snapshot(true);
+impl kv::Store for Datastore {
+    type Transaction = Transaction;
 
-        let write_options = WriteOptions::default();
-        // When atomic flush is enabled, disable wal
-        // write_options.disable_wal(true);
+    async fn transaction_with(
+        &self,
+        write: bool,
+        isolation: IsolationLevel,
+    ) -> Result<Transaction, Error> {
+        let write_options = self.config.write_options();
 
         // Create a new transaction
-        let txn = self
-            .db
-            .transaction_opt(&write_options, &transaction_options);
-
+        //
         // The database reference must always outlive
         // the transaction. If it doesn't then this
         // is undefined behaviour. This unsafe block
         // ensures that the transaction reference is
         // static, but will cause a crash if the
         // datastore is dropped prematurely.
-        let txn = unsafe {
-            std::mem::transmute::<
-                rocksdb::Transaction<'_, OptimisticTransactionDB>,
-                rocksdb::Transaction<'static, OptimisticTransactionDB>,
-            >(txn)
+        let txn = match &*self.db {
+            Db::Optimistic(db) => Txn::Optimistic(unsafe {
+                std::mem::transmute::<
+                    rocksdb::Transaction<'_, OptimisticTransactionDB>,
+                    rocksdb::Transaction<'static, OptimisticTransactionDB>,
+                >(
+                    db.transaction_opt(&write_options, &self.config.optimistic_options())
+                )
+            }),
+            Db::Pessimistic(db) => Txn::Pessimistic(unsafe {
+                std::mem::transmute::<
+                    rocksdb::Transaction<'_, TransactionDB>,
+                    rocksdb::Transaction<'static, TransactionDB>,
+                >(
+                    db.transaction_opt(&write_options, &self.config.pessimistic_options())
+                )
+            }),
         };
 
-        let mut snapshot_read_options = ReadOptions::default();
-        snapshot_read_options.set_snapshot(&txn.snapshot());
+        let read_options = txn.read_options(isolation);
 
         // Return the transaction
         Ok(Transaction {
             completed: false,
             rw: write,
-            txn: Arc::new(Mutex::new(Some(txn))),
-            _snapshot_read_options: snapshot_read_options,
+            txn: Some(txn),
+            isolation,
+            _read_options: read_options,
+            reads: std::sync::Mutex::new(Vec::new()),
             _db: self.db.clone(),
             _ds: Arc::pin(self.clone()),
         })
     }
+
+    fn isolation(&self) -> IsolationLevel {
+        self.config.isolation
+    }
+
+    fn retry_policy(&self) -> kv::RetryPolicy {
+        self.config.retry_policy()
+    }
 }
 
 impl Transaction {
-    /** Check to see if txn is completed, this could mean either commited or rolled back */
-    pub fn is_completed(&self) -> bool {
-        self.completed
+    /// Records a range read by a serializable transaction
+    fn track(&self, idx: Option<&str>, range: ScanRange) -> Result<(), Error> {
+        if self.isolation == IsolationLevel::Serializable {
+            self.reads.lock()?.push((idx.map(str::to_owned), range));
+        }
+
+        Ok(())
     }
 
-    /** Discard all transaction operations */
-    pub async fn rollback(&mut self) -> Result<(), Error> {
+    /// Locks a key until the transaction completes and gets it
+    fn lock_key(&self, key: Key) -> Result<Option<Val>, Error> {
         // Check to see if transaction is closed
         if self.completed {
             return Err(Error::TxFinished);
         }
 
-        // Mark this transaction as completed
-        self.completed = true;
+        self.track(None, ScanRange::key(key.clone()))?;
+        self.txn
+            .as_ref()
+            .unwrap()
+            .get_for_update_opt(key, &self._read_options)
+    }
 
-        // Rollback this transaction
-        match self.txn.lock().await.take() {
-            Some(txn) => txn.rollback()?,
-            None => unreachable!(),
-        };
+    /// Fails with a conflict when a range the transaction read was changed by a commit after
+    /// its snapshot
+    fn validate_reads(&self, txn: &Txn) -> Result<(), Error> {
+        for (idx, range) in self.reads.lock()?.iter() {
+            if self
+                ._db
+                .changed_since(idx.as_deref(), range, txn.snapshot_read_options())?
+            {
+                return Err(Error::TxConflict);
+            }
+        }
 
-        // Continue
         Ok(())
     }
 
@@ -189,19 +446,23 @@ impl Transaction {
         let key = key.into();
         let val = val.into();
 
-        self.txn
-            .lock()
-            .await
-            .as_ref()
-            .unwrap()
-            .put_cf(&cf, key, val)?;
+        self.txn.as_ref().unwrap().put_cf(&cf, key, val)?;
 
         // Continue
         Ok(())
     }
 
-    /** Commit transaction */
-    pub async fn commit(&mut self) -> Result<(), Error> {
+    /// Insert a key if it doesn't exist in the database
+    pub async fn insert_checked<K, V>(
+        &mut self,
+        key: K,
+        val: V,
+        chk: Option<V>,
+    ) -> Result<(), Error>
+    where
+        K: Into<Key>,
+        V: Into<Val>,
+    {
         // Check to see if transaction is closed
         if self.completed {
             return Err(Error::TxFinished);
@@ -212,12 +473,100 @@ impl Transaction {
             return Err(Error::TxReadonly);
         }
 
-        // Mark this transaction as done
+        // Get the transaction
+        let txn = self.txn.as_ref().unwrap();
+
+        // Get the arguments
+        let key = key.into();
+        let val = val.into();
+        let chk = chk.map(Into::into);
+
+        // Set the key if valid
+        self.track(None, ScanRange::key(key.clone()))?;
+        match (txn.get_opt(&key, &self._read_options)?, chk) {
+            (Some(v), Some(w)) if v == w => txn.put(key, val)?,
+            (None, None) => txn.put(key, val)?,
+            _ => return Err(Error::TxConditionNotMet),
+        };
+
+        // Return result
+        Ok(())
+    }
+
+    /// Opens a cursor over the keys of an index, like [`kv::Transaction::cursor`].
+    pub async fn index_cursor(&self, idx: &str, range: ScanRange) -> Result<Cursor<'_>, Error> {
+        if !self._ds.index_exists(idx) {
+            return Err(Error::Tx(format!("`{}` index does not exist.", idx)));
+        }
+
+        self.open_cursor(Some(idx), range)
+    }
+
+    fn open_cursor(&self, idx: Option<&str>, range: ScanRange) -> Result<Cursor<'_>, Error> {
+        // Check to see if transaction is closed
+        let txn = match &self.txn {
+            Some(txn) if !self.completed => txn,
+            _ => return Err(Error::TxFinished),
+        };
+
+        // Set the ReadOptions of the isolation level, the bounds let rocksdb skip the keys
+        // outside of the range
+        self.track(idx, range.clone())?;
+        let mut read_options = txn.read_options(self.isolation);
+        if let Some(start) = &range.start {
+            read_options.set_iterate_lower_bound(start.clone());
+        }
+        if let Some(end) = &range.end {
+            read_options.set_iterate_upper_bound(end.clone());
+        }
+        if self._ds.config.prefix_extractor {
+            read_options.set_total_order_seek(!config::within_collection(&range));
+        }
+
+        // Create the iterator
+        let cf = idx.map(|idx| self._db.cf_handle(idx).unwrap());
+        let mut iterator = txn.raw_iterator(cf.as_ref(), read_options);
+
+        // Prime the iterator on the first key of the range
+        match (&range.start, &range.end, range.reverse) {
+            (Some(start), _, false) => iterator.seek(start),
+            (None, _, false) => iterator.seek_to_first(),
+            (_, Some(end), true) => {
+                iterator.seek_for_prev(end);
+                if iterator.key() == Some(end.as_slice()) {
+                    iterator.prev();
+                }
+            }
+            (_, None, true) => iterator.seek_to_last(),
+        }
+
+        Ok(Cursor {
+            iterator,
+            range,
+            finished: false,
+        })
+    }
+}
+
+impl kv::Transaction for Transaction {
+    type Cursor<'a> = Cursor<'a>;
+
+    fn is_completed(&self) -> bool {
+        self.completed
+    }
+
+    async fn rollback(&mut self) -> Result<(), Error> {
+        // Check to see if transaction is closed
+        if self.completed {
+            return Err(Error::TxFinished);
+        }
+
+        // Mark this transaction as completed
         self.completed = true;
 
-        // Cancel this transaction
-        match self.txn.lock().await.take() {
-            Some(txn) => txn.commit()?,
+        // Rollback this transaction
+        match self.txn.take() {
+            Some(txn) => txn.rollback()?,
             None => unreachable!(),
         };
 
@@ -225,10 +574,43 @@ impl Transaction {
         Ok(())
     }
 
-    /// Check if a key exists
-    pub async fn key_exists<K>(&mut self, key: K) -> Result<bool, Error>
+    async fn commit(&mut self) -> Result<(), Error> {
+        // Check to see if transaction is closed
+        if self.completed {
+            return Err(Error::TxFinished);
+        }
+
+        // Check to see if transaction is writable
+        if !self.rw {
+            return Err(Error::TxReadonly);
+        }
+
+        // Mark this transaction as done
+        self.completed = true;
+
+        // Commit this transaction, once its reads are known to be unchanged when it is
+        // serializable
+        let txn = self.txn.take().unwrap();
+        match self.isolation {
+            IsolationLevel::Serializable => {
+                let _exclusive = self._ds.commits.write()?;
+                self.validate_reads(&txn)?;
+                txn.commit()?;
+            }
+            _ => {
+                let _shared = self._ds.commits.read()?;
+                txn.commit()?;
+            }
+        }
+        self.reads.lock()?.clear();
+
+        // Continue
+        Ok(())
+    }
+
+    async fn key_exists<K>(&mut self, key: K) -> Result<bool, Error>
     where
-        K: Into<Key>,
+        K: Into<Key> + Send,
     {
         // Check to see if transaction is closed
         if self.completed {
@@ -236,23 +618,22 @@ impl Transaction {
         }
 
         // Check the key
+        let key = key.into();
+        self.track(None, ScanRange::key(key.clone()))?;
         let res = self
             .txn
-            .lock()
-            .await
             .as_ref()
             .unwrap()
-            .get_opt(key.into(), &self._snapshot_read_options)?
+            .get_opt(key, &self._read_options)?
             .is_some();
 
         // Return result
         Ok(res)
     }
 
-    /// Fetch a key from the database
-    pub async fn get<K>(&mut self, key: K) -> Result<Option<Val>, Error>
+    async fn get<K>(&mut self, key: K) -> Result<Option<Val>, Error>
     where
-        K: Into<Key>,
+        K: Into<Key> + Send,
     {
         // Check to see if transaction is closed
         if self.completed {
@@ -260,23 +641,31 @@ impl Transaction {
         }
 
         // Get the key
+        let key = key.into();
+        self.track(None, ScanRange::key(key.clone()))?;
         let res = self
             .txn
-            .lock()
-            .await
             .as_ref()
             .unwrap()
-            .get_opt(key.into(), &self._snapshot_read_options)?;
+            .get_opt(key, &self._read_options)?;
 
         // Return result
         Ok(res)
     }
 
-    /// Insert or update a key in the database
-    pub async fn upsert<K, V>(&mut self, key: K, val: V) -> Result<(), Error>
+    fn get_for_update<K>(&self, key: K) -> impl Future<Output = Result<Option<Val>, Error>> + Send
     where
-        K: Into<Key>,
-        V: Into<Val>,
+        K: Into<Key> + Send,
+    {
+        // Locked before the future, since the rocksdb transaction can't be shared between
+        // threads
+        future::ready(self.lock_key(key.into()))
+    }
+
+    async fn upsert<K, V>(&mut self, key: K, val: V) -> Result<(), Error>
+    where
+        K: Into<Key> + Send,
+        V: Into<Val> + Send,
     {
         // Check to see if transaction is closed
         if self.completed {
@@ -289,22 +678,16 @@ impl Transaction {
         }
 
         // Set the key
-        self.txn
-            .lock()
-            .await
-            .as_ref()
-            .unwrap()
-            .put(key.into(), val.into())?;
+        self.txn.as_ref().unwrap().put(key.into(), val.into())?;
 
         // Return result
         Ok(())
     }
 
-    /// Insert a key if it doesn't exist in the database
-    pub async fn insert<K, V>(&mut self, key: K, val: V) -> Result<(), Error>
+    async fn insert<K, V>(&mut self, key: K, val: V) -> Result<(), Error>
     where
-        K: Into<Key>,
-        V: Into<Val>,
+        K: Into<Key> + Send,
+        V: Into<Val> + Send,
     {
         // Check to see if transaction is closed
         if self.completed {
@@ -317,15 +700,15 @@ impl Transaction {
         }
 
         // Get the transaction
-        let txn = self.txn.lock().await;
-        let txn = txn.as_ref().unwrap();
+        let txn = self.txn.as_ref().unwrap();
 
         // Get the arguments
         let key = key.into();
         let val = val.into();
 
         // Set the key if empty
-        match txn.get_opt(&key, &self._snapshot_read_options)? {
+        self.track(None, ScanRange::key(key.clone()))?;
+        match txn.get_opt(&key, &self._read_options)? {
             None => txn.put(key, val)?,
             _ => return Err(Error::TxKeyAlreadyExists),
         };
@@ -334,16 +717,9 @@ impl Transaction {
         Ok(())
     }
 
-    /// Insert a key if it doesn't exist in the database
-    pub async fn insert_checked<K, V>(
-        &mut self,
-        key: K,
-        val: V,
-        chk: Option<V>,
-    ) -> Result<(), Error>
+    async fn delete<K>(&mut self, key: K) -> Result<(), Error>
     where
-        K: Into<Key>,
-        V: Into<Val>,
+        K: Into<Key> + Send,
     {
         // Check to see if transaction is closed
         if self.completed {
@@ -355,319 +731,522 @@ impl Transaction {
             return Err(Error::TxReadonly);
         }
 
-        // Get the transaction
-        let txn = self.txn.lock().await;
-        let txn = txn.as_ref().unwrap();
-
-        // Get the arguments
-        let key = key.into();
-        let val = val.into();
-        let chk = chk.map(Into::into);
-
-        // Set the key if valid
-        match (txn.get_opt(&key, &self._snapshot_read_options)?, chk) {
-            (Some(v), Some(w)) if v == w => txn.put(key, val)?,
-            (None, None) => txn.put(key, val)?,
-            _ => return Err(Error::TxConditionNotMet),
-        };
+        // Remove the key
+        self.txn.as_ref().unwrap().delete(key.into())?;
 
         // Return result
         Ok(())
     }
 
-    /// Delete a key
-    pub async fn delete<K>(&mut self, key: K) -> Result<(), Error>
-    where
-        K: Into<Key>,
-    {
+    async fn savepoint(&mut self) -> Result<(), Error> {
         // Check to see if transaction is closed
         if self.completed {
             return Err(Error::TxFinished);
         }
 
-        // Check to see if transaction is writable
-        if !self.rw {
-            return Err(Error::TxReadonly);
-        }
-
-        // Remove the key
-        self.txn.lock().await.as_ref().unwrap().delete(key.into())?;
+        // Mark the writes so far
+        self.txn.as_ref().unwrap().set_savepoint();
 
-        // Return result
+        // Continue
         Ok(())
     }
 
-    pub async fn scan<K>(
-        &mut self,
-        limit: u32,
-        after: Option<Vec<u8>>,
-    ) -> Result<Vec<(Key, Val)>, Error>
-    where
-        K: Into<Key>,
-    {
+    async fn rollback_to_savepoint(&mut self) -> Result<(), Error> {
         // Check to see if transaction is closed
         if self.completed {
             return Err(Error::TxFinished);
         }
 
-        // Get the transaction
-        let txn = self.txn.lock().await;
-        let txn = txn.as_ref().unwrap();
+        // Undo the writes since the savepoint
+        self.txn.as_ref().unwrap().rollback_to_savepoint()?;
 
-        // Create result set
-        let mut res = vec![];
+        // Continue
+        Ok(())
+    }
 
-        // Set the ReadOptions with the snapshot
-        let mut read_options = ReadOptions::default();
-        read_options.set_snapshot(&txn.snapshot());
+    fn cursor(&self, range: ScanRange) -> impl Future<Output = Result<Cursor<'_>, Error>> + Send {
+        // Opened before the future, which then only holds the cursor, since the rocksdb
+        // transaction can't be shared between threads
+        future::ready(self.open_cursor(None, range))
+    }
+}
 
-        // Create the iterator
-        let mut iterator = txn.raw_iterator_opt(read_options);
+/// A cursor over a range of keys of a transaction, returned by [`kv::Transaction::cursor`].
+/// It holds an iterator on the transaction's snapshot and reads every key and value as it is
+/// advanced, so a scan can stop early without loading the rest of the range.
+pub struct Cursor<'a> {
+    iterator: RawIterator<'a>,
+    range: ScanRange,
+    finished: bool,
+}
 
-        // Prime the iterator
-        if after == Option::None {
-            iterator.seek_to_first();
-        } else {
-            iterator.seek(after.unwrap());
+/// An iterator of either kind of transaction.
+enum RawIterator<'a> {
+    Optimistic(
+        DBRawIteratorWithThreadMode<'a, rocksdb::Transaction<'static, OptimisticTransactionDB>>,
+    ),
+    Pessimistic(DBRawIteratorWithThreadMode<'a, rocksdb::Transaction<'static, TransactionDB>>),
+}
 
-            // Advance past the key ...
-            if iterator.valid() {
-                iterator.next();
-            }
-        }
+impl RawIterator<'_> {
+    fn seek(&mut self, key: &[u8]) {
+        dispatch!(self, RawIterator, iterator => iterator.seek(key))
+    }
 
-        // Scan the keys in the iterator
-        while iterator.valid() {
-            // Check the scan limit
-            if res.len() < limit as usize {
-                // Get the key and value
-                let (k, v) = (iterator.key(), iterator.value());
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        dispatch!(self, RawIterator, iterator => iterator.seek_for_prev(key))
+    }
 
-                // Check the key and value
-                if let (Some(k), Some(v)) = (k, v) {
-                    res.push((k.to_vec(), v.to_vec()));
-                    iterator.next();
-                    continue;
-                }
-            }
+    fn seek_to_first(&mut self) {
+        dispatch!(self, RawIterator, iterator => iterator.seek_to_first())
+    }
 
-            // Exit
-            break;
-        }
+    fn seek_to_last(&mut self) {
+        dispatch!(self, RawIterator, iterator => iterator.seek_to_last())
+    }
 
-        // Return result
-        Ok(res)
+    fn next(&mut self) {
+        dispatch!(self, RawIterator, iterator => iterator.next())
     }
 
-    pub async fn scan_range<K>(&mut self, rng: Range<K>, limit: u32) -> Result<Vec<Key>, Error>
-    where
-        K: Into<Key>,
-    {
-        // Check to see if transaction is closed
-        if self.completed {
-            return Err(Error::TxFinished);
-        }
+    fn prev(&mut self) {
+        dispatch!(self, RawIterator, iterator => iterator.prev())
+    }
 
-        // Get the transaction
-        let txn = self.txn.lock().await;
-        let txn = txn.as_ref().unwrap();
+    fn key(&self) -> Option<&[u8]> {
+        dispatch!(self, RawIterator, iterator => iterator.key())
+    }
 
-        // Convert the range to bytes
-        let rng: Range<Key> = Range {
-            start: rng.start.into(),
-            end: rng.end.into(),
-        };
+    fn item(&self) -> Option<(&[u8], &[u8])> {
+        dispatch!(self, RawIterator, iterator => iterator.item())
+    }
 
-        // Create result set
-        let mut res = vec![];
+    fn status(&self) -> Result<(), rocksdb::Error> {
+        dispatch!(self, RawIterator, iterator => iterator.status())
+    }
+}
 
-        // Set the key range
-        let beg = rng.start.as_slice();
-        let end = rng.end.as_slice();
+impl Iterator for Cursor<'_> {
+    type Item = Result<(Key, Val), Error>;
 
-        // Set the ReadOptions with the snapshot
-        let mut read_options = ReadOptions::default();
-        read_options.set_snapshot(&txn.snapshot());
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
 
-        // Create the iterator
-        let mut iter = txn.raw_iterator_opt(read_options);
-
-        // Seek to the start key
-        iter.seek(&rng.start);
-
-        // Scan the keys in the iterator
-        while iter.valid() {
-            // Check the scan limit
-            if res.len() < limit as usize {
-                // Get the key and value
-                let k = iter.key();
-
-                // Check the key and value
-                if let Some(k) = k {
-                    if k >= beg && k < end {
-                        res.push(k.to_vec());
-                        iter.next();
-                        continue;
-                    }
+        // The bounds are checked again, rocksdb doesn't apply them to the transaction's own
+        // writes
+        match self.iterator.item() {
+            Some((k, v)) if self.range.contains(k) => {
+                let item = (k.to_vec(), v.to_vec());
+                match self.range.reverse {
+                    true => self.iterator.prev(),
+                    false => self.iterator.next(),
                 }
+                Some(Ok(item))
+            }
+            _ => {
+                self.finished = true;
+                self.iterator.status().err().map(|e| Err(e.into()))
             }
-
-            // Exit
-            break;
         }
-
-        // Return result
-        Ok(res)
     }
+}
 
-    /// Retrieve a range of keys from the databases based on a certain collection prefix
-    pub async fn scan_collection_index<K>(
-        &mut self,
-        collection_prefix: Vec<u8>,
-        column: &str,
-        limit: u32,
-        after: Option<Vec<u8>>,
-    ) -> Result<Vec<(Key, Val)>, Error>
-    where
-        K: Into<Key>,
-    {
-        // Check to see if transaction is closed
-        if self.completed {
-            return Err(Error::TxFinished);
-        }
-
-        // Get the transaction
-        let txn = self.txn.lock().await;
-        let txn = txn.as_ref().unwrap();
-
-        // Create result set
-        let mut res = vec![];
-
-        // Set the ReadOptions with the snapshot
-        let mut read_options = ReadOptions::default();
-        read_options.set_snapshot(&txn.snapshot());
-
-        read_options.set_prefix_same_as_start(true);
-        read_options.set_iterate_upper_bound(collection_prefix);
-        // read_options.set_iterate_range(rocksdb::PrefixRange(collection_prefix));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::kv::{Store as _, Transaction as _};
+    use futures::executor::block_on;
 
-        // Grab the column family handle ...
-        let cf_handle = self
-            ._db
-            .cf_handle(column)
-            .expect("Column family not found.");
+    fn keys(cursor: Cursor) -> Vec<String> {
+        cursor
+            .map(|item| String::from_utf8(item.unwrap().0).unwrap())
+            .collect()
+    }
 
-        // Create the iterator
-        let mut iterator = txn.raw_iterator_cf_opt(&cf_handle, read_options);
+    #[test]
+    fn cursors() {
+        let path = std::env::temp_dir().join(format!("kuiperdb-cursor-{}", uuid::Uuid::new_v4()));
 
-        // Prime the iterator
-        if after == Option::None {
-            iterator.seek_to_first();
-        } else {
-            iterator.seek(after.unwrap());
+        block_on(async {
+            let ds = Datastore::new(path.to_str().unwrap()).await.unwrap();
 
-            // Advance past the key ...
-            if iterator.valid() {
-                iterator.next();
+            let mut txn = ds.transaction(true).await.unwrap();
+            for key in ["a1", "a2", "a3", "b1"] {
+                txn.insert(key, key).await.unwrap();
             }
-        }
+            txn.commit().await.unwrap();
+
+            let mut txn = ds.transaction(true).await.unwrap();
+            txn.upsert("a25", "a25").await.unwrap();
+            txn.delete("a3").await.unwrap();
+
+            let prefix = ScanRange::prefix("a");
+            assert_eq!(
+                keys(txn.cursor(prefix.clone()).await.unwrap()),
+                ["a1", "a2", "a25"]
+            );
+            assert_eq!(
+                keys(txn.cursor(prefix.clone().reverse()).await.unwrap()),
+                ["a25", "a2", "a1"]
+            );
+            assert_eq!(
+                keys(txn.cursor(prefix.clone().after("a2")).await.unwrap()),
+                ["a25"]
+            );
+            assert_eq!(
+                keys(txn.cursor(prefix.reverse().after("a25")).await.unwrap()),
+                ["a2", "a1"]
+            );
+            assert_eq!(
+                keys(txn.cursor(ScanRange::between("a2".."b1")).await.unwrap()),
+                ["a2", "a25"]
+            );
+            assert_eq!(
+                keys(txn.cursor(ScanRange::all().reverse()).await.unwrap()),
+                ["b1", "a25", "a2", "a1"]
+            );
+
+            // The cursor reads lazily, stopping early leaves the rest of the range unread
+            let mut cursor = txn.cursor(ScanRange::all()).await.unwrap();
+            assert_eq!(cursor.next().unwrap().unwrap().0, b"a1");
+            drop(cursor);
+
+            txn.rollback().await.unwrap();
+            assert_eq!(
+                txn.cursor(ScanRange::all()).await.err(),
+                Some(Error::TxFinished)
+            );
+        });
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
 
-        // Scan the keys in the iterator
-        while iterator.valid() {
-            // Check the scan limit
-            if res.len() < limit as usize {
-                // Get the key and value
-                let (k, v) = (iterator.key(), iterator.value());
+    #[test]
+    fn configured() {
+        let path = std::env::temp_dir().join(format!("kuiperdb-config-{}", uuid::Uuid::new_v4()));
+        let config = DatastoreConfig {
+            path: path.to_str().unwrap().to_owned(),
+            compression: vec![Compression::None, Compression::Lz4, Compression::Zstd],
+            block_cache_size: 1 << 20,
+            bloom_filter_bits: Some(10.0),
+            prefix_extractor: true,
+            wal: WalPolicy::Sync,
+            max_open_files: 64,
+            ..DatastoreConfig::default()
+        };
 
-                // Check the key and value
-                if let (Some(k), Some(v)) = (k, v) {
-                    res.push((k.to_vec(), v.to_vec()));
-                    iterator.next();
-                    continue;
-                }
+        block_on(async {
+            let ds = Datastore::open(config.clone()).await.unwrap();
+            assert_eq!(ds.config(), &config);
+
+            let mut txn = ds.transaction(true).await.unwrap();
+            for key in ["s::a::1", "s::a::2", "s::ab::1", "s::b::1", "t::a::1"] {
+                txn.insert(key, key).await.unwrap();
             }
+            txn.commit().await.unwrap();
+
+            // Scans within a collection use the prefix, the others read in key order
+            let txn = ds.transaction(false).await.unwrap();
+            assert_eq!(
+                keys(txn.cursor(ScanRange::prefix("s::a::")).await.unwrap()),
+                ["s::a::1", "s::a::2"]
+            );
+            assert_eq!(
+                keys(txn.cursor(ScanRange::prefix("s::")).await.unwrap()),
+                ["s::a::1", "s::a::2", "s::ab::1", "s::b::1"]
+            );
+            assert_eq!(
+                keys(
+                    txn.cursor(ScanRange::prefix("s::a::").reverse())
+                        .await
+                        .unwrap()
+                ),
+                ["s::a::2", "s::a::1"]
+            );
+            assert_eq!(keys(txn.cursor(ScanRange::all()).await.unwrap()).len(), 5);
+        });
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
 
-            // Exit
-            break;
-        }
+    #[test]
+    fn pessimistic() {
+        let path = std::env::temp_dir().join(format!("kuiperdb-locks-{}", uuid::Uuid::new_v4()));
+        let config = DatastoreConfig {
+            lock_timeout_ms: 50,
+            ..pessimistic_config(&path)
+        };
 
-        // Return result
-        Ok(res)
+        block_on(async {
+            let ds = Datastore::open(config).await.unwrap();
+
+            let mut txn = ds.transaction(true).await.unwrap();
+            txn.insert("a", "1").await.unwrap();
+            txn.insert("b", "1").await.unwrap();
+            txn.commit().await.unwrap();
+
+            // A locked key can't be locked or written by others until the lock is released
+            let mut first = ds.transaction(true).await.unwrap();
+            let mut second = ds.transaction(true).await.unwrap();
+            assert_eq!(
+                first.get_for_update("a").await.unwrap(),
+                Some(b"1".to_vec())
+            );
+            assert_eq!(second.get_for_update("a").await, Err(Error::TxLockTimeout));
+            assert_eq!(second.upsert("a", "3").await, Err(Error::TxLockTimeout));
+            assert_eq!(second.get("a").await.unwrap(), Some(b"1".to_vec()));
+            first.upsert("a", "2").await.unwrap();
+            first.commit().await.unwrap();
+            second.rollback().await.unwrap();
+
+            let mut txn = ds.transaction(true).await.unwrap();
+            assert_eq!(txn.get_for_update("a").await.unwrap(), Some(b"2".to_vec()));
+            txn.rollback().await.unwrap();
+        });
+
+        // Transactions waiting on each other's locks are stopped instead of timing out
+        let ds = block_on(Datastore::open(DatastoreConfig {
+            lock_timeout_ms: 5000,
+            ..pessimistic_config(&path)
+        }))
+        .unwrap();
+        let mut first = block_on(ds.transaction(true)).unwrap();
+        let mut second = block_on(ds.transaction(true)).unwrap();
+        block_on(first.get_for_update("a")).unwrap();
+        block_on(second.get_for_update("b")).unwrap();
+
+        let waiting = std::thread::spawn(move || {
+            let locked = block_on(first.get_for_update("b"));
+            block_on(first.rollback()).unwrap();
+            locked
+        });
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert_eq!(block_on(second.get_for_update("a")), Err(Error::TxDeadlock));
+        block_on(second.rollback()).unwrap();
+        assert!(waiting.join().unwrap().is_ok());
+
+        drop(ds);
+        std::fs::remove_dir_all(path).unwrap();
     }
 
-    /// Retrieve a range of keys from the databases based on a certain collection prefix
-    pub async fn scan_collection<K>(
-        &mut self,
-        collection_prefix: Vec<u8>,
-        limit: Option<usize>,
-        after: Option<Vec<u8>>,
-    ) -> Result<Vec<(Key, Val)>, Error>
-    where
-        K: Into<Key>,
-    {
-        // Check to see if transaction is closed
-        if self.completed {
-            return Err(Error::TxFinished);
+    fn pessimistic_config(path: &std::path::Path) -> DatastoreConfig {
+        DatastoreConfig {
+            transactions: TransactionMode::Pessimistic,
+            ..DatastoreConfig::with_path(path.to_str().unwrap())
         }
+    }
 
-        // Get the transaction
-        let txn = self.txn.lock().await;
-        let txn = txn.as_ref().unwrap();
-
-        // Create result set
-        let mut res = vec![];
-
-        // Set the ReadOptions with the snapshot
-        let mut read_options = ReadOptions::default();
-        read_options.set_snapshot(&txn.snapshot());
-
-        read_options.set_prefix_same_as_start(true);
-        // read_options.set_iterate_upper_bound(collection_prefix);
-        read_options.set_iterate_range(rocksdb::PrefixRange(collection_prefix));
-
-        // Create the iterator
-        let mut iterator = txn.raw_iterator_opt(read_options);
-        // let iterator = self._db.iterator(IteratorMode::Start);
-
-        // Prime the iterator
-        iterator.seek_to_first();
-
-        // Seek to location, if needed
-        if after != Option::None {
-            iterator.seek(after.unwrap());
-        }
+    #[test]
+    fn savepoints() {
+        let path =
+            std::env::temp_dir().join(format!("kuiperdb-savepoints-{}", uuid::Uuid::new_v4()));
+
+        block_on(async {
+            let ds = Datastore::open(pessimistic_config(&path)).await.unwrap();
+
+            let mut txn = ds.transaction(true).await.unwrap();
+            txn.upsert("a", "1").await.unwrap();
+            txn.savepoint().await.unwrap();
+            txn.upsert("a", "2").await.unwrap();
+            txn.get_for_update("b").await.unwrap();
+            txn.rollback_to_savepoint().await.unwrap();
+            assert_eq!(txn.get("a").await.unwrap(), Some(b"1".to_vec()));
+            assert!(matches!(
+                txn.rollback_to_savepoint().await,
+                Err(Error::TxNoSavepoint)
+            ));
+
+            // The lock taken after the savepoint was released
+            let mut other = ds.transaction(true).await.unwrap();
+            other.upsert("b", "1").await.unwrap();
+            other.commit().await.unwrap();
+            txn.commit().await.unwrap();
+
+            let mut txn = ds.transaction(false).await.unwrap();
+            assert_eq!(txn.get("a").await.unwrap(), Some(b"1".to_vec()));
+        });
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
 
-        let no_limit = limit.is_none();
+    #[test]
+    fn isolation_levels() {
+        let path =
+            std::env::temp_dir().join(format!("kuiperdb-isolation-{}", uuid::Uuid::new_v4()));
+
+        block_on(async {
+            let ds = Datastore::new(path.to_str().unwrap()).await.unwrap();
+
+            let mut txn = ds.transaction(true).await.unwrap();
+            txn.insert("a", "1").await.unwrap();
+            txn.insert("b", "1").await.unwrap();
+            txn.commit().await.unwrap();
+
+            // Read committed transactions see commits made after they started, in point and
+            // range reads alike
+            let mut snapshot = ds.transaction(false).await.unwrap();
+            let mut committed = ds
+                .transaction_with(false, IsolationLevel::ReadCommitted)
+                .await
+                .unwrap();
+            let mut txn = ds.transaction(true).await.unwrap();
+            txn.upsert("a", "2").await.unwrap();
+            txn.insert("c", "1").await.unwrap();
+            txn.commit().await.unwrap();
+
+            assert_eq!(snapshot.get("a").await.unwrap(), Some(b"1".to_vec()));
+            assert!(!snapshot.key_exists("c").await.unwrap());
+            assert_eq!(
+                keys(snapshot.cursor(ScanRange::all()).await.unwrap()),
+                ["a", "b"]
+            );
+            assert_eq!(committed.get("a").await.unwrap(), Some(b"2".to_vec()));
+            assert!(committed.key_exists("c").await.unwrap());
+            assert_eq!(
+                keys(committed.cursor(ScanRange::all()).await.unwrap()),
+                ["a", "b", "c"]
+            );
+
+            // Snapshot transactions that write what the other read both commit, serializable
+            // ones don't
+            for (isolation, val, conflicts) in [
+                (IsolationLevel::Snapshot, "3", false),
+                (IsolationLevel::Serializable, "4", true),
+            ] {
+                let mut first = ds.transaction_with(true, isolation).await.unwrap();
+                let mut second = ds.transaction_with(true, isolation).await.unwrap();
+                first.get("a").await.unwrap();
+                second.get("b").await.unwrap();
+                first.upsert("b", val).await.unwrap();
+                second.upsert("a", val).await.unwrap();
+                first.commit().await.unwrap();
+                assert_eq!(second.commit().await.is_err(), conflicts);
+            }
 
-        // // Scan the keys in the iterator
-        while iterator.valid() {
-            // Check the scan limit
-            if no_limit || res.len() < limit.unwrap() {
-                // Get the key and value
-                let (k, v) = (iterator.key(), iterator.value());
+            // Keys committed into a range a serializable transaction read conflict with it,
+            // commits outside of it don't
+            let mut txn = ds
+                .transaction_with(true, IsolationLevel::Serializable)
+                .await
+                .unwrap();
+            assert_eq!(
+                keys(txn.cursor(ScanRange::prefix("d")).await.unwrap()).len(),
+                0
+            );
+            txn.upsert("e", "1").await.unwrap();
+            let mut other = ds.transaction(true).await.unwrap();
+            other.insert("d1", "1").await.unwrap();
+            other.commit().await.unwrap();
+            assert_eq!(txn.commit().await, Err(Error::TxConflict));
+
+            let mut txn = ds
+                .transaction_with(true, IsolationLevel::Serializable)
+                .await
+                .unwrap();
+            assert_eq!(
+                keys(txn.cursor(ScanRange::prefix("d")).await.unwrap()),
+                ["d1"]
+            );
+            txn.upsert("e", "1").await.unwrap();
+            let mut other = ds.transaction(true).await.unwrap();
+            other.upsert("f", "1").await.unwrap();
+            other.commit().await.unwrap();
+            txn.commit().await.unwrap();
+        });
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
 
-                // Check the key and value
-                if let (Some(k), Some(v)) = (k, v) {
-                    res.push((k.to_vec(), v.to_vec()));
-                    iterator.next();
-                    continue;
-                }
-            } else {
-                iterator.next();
-                iterator.valid();
-                println!("DEBUG >> HAS MORE!");
+    #[test]
+    fn own_writes() {
+        let path = std::env::temp_dir().join(format!("kuiperdb-own-{}", uuid::Uuid::new_v4()));
+
+        block_on(async {
+            let mut ds = Datastore::new(path.to_str().unwrap()).await.unwrap();
+            ds.add_index("names");
+
+            let mut txn = ds.transaction(true).await.unwrap();
+            txn.insert("a1", "1").await.unwrap();
+            txn.insert("a2", "1").await.unwrap();
+            txn.commit().await.unwrap();
+
+            // Inserts, updates and deletes are seen by the transaction's later scans, at
+            // every isolation level, and by nobody else until it commits
+            for isolation in [
+                IsolationLevel::Snapshot,
+                IsolationLevel::ReadCommitted,
+                IsolationLevel::Serializable,
+            ] {
+                let mut txn = ds.transaction_with(true, isolation).await.unwrap();
+                txn.insert("a0", "1").await.unwrap();
+                txn.upsert("a1", "2").await.unwrap();
+                txn.delete("a2").await.unwrap();
+                txn.update_index("names", "ann", "a0").await.unwrap();
+
+                let items: Vec<(Key, Val)> = txn
+                    .cursor(ScanRange::prefix("a"))
+                    .await
+                    .unwrap()
+                    .map(Result::unwrap)
+                    .collect();
+                assert_eq!(
+                    items,
+                    [
+                        (b"a0".to_vec(), b"1".to_vec()),
+                        (b"a1".to_vec(), b"2".to_vec())
+                    ]
+                );
+                assert_eq!(
+                    keys(txn.cursor(ScanRange::prefix("a").reverse()).await.unwrap()),
+                    ["a1", "a0"]
+                );
+                assert_eq!(
+                    keys(txn.index_cursor("names", ScanRange::all()).await.unwrap()),
+                    ["ann"]
+                );
+
+                let other = ds.transaction(false).await.unwrap();
+                assert_eq!(
+                    keys(other.cursor(ScanRange::prefix("a")).await.unwrap()),
+                    ["a1", "a2"]
+                );
+                assert!(
+                    keys(other.index_cursor("names", ScanRange::all()).await.unwrap()).is_empty()
+                );
+
+                txn.rollback().await.unwrap();
             }
+        });
 
-            // Exit
-            break;
-        }
-
-        // for v in iterator {
-        //     let x = v.unwrap();
-        //     res.push((x.0.to_vec(), x.1.to_vec()));
-        // }
+        std::fs::remove_dir_all(path).unwrap();
+    }
 
-        // Return result
-        Ok(res)
+    #[test]
+    fn optimistic_reads_for_update() {
+        let path = std::env::temp_dir().join(format!("kuiperdb-reads-{}", uuid::Uuid::new_v4()));
+
+        block_on(async {
+            let ds = Datastore::new(path.to_str().unwrap()).await.unwrap();
+
+            let mut txn = ds.transaction(true).await.unwrap();
+            txn.insert("a", "1").await.unwrap();
+            txn.commit().await.unwrap();
+
+            // Optimistic transactions don't wait, the reader fails to commit instead
+            let mut first = ds.transaction(true).await.unwrap();
+            let mut second = ds.transaction(true).await.unwrap();
+            assert_eq!(
+                first.get_for_update("a").await.unwrap(),
+                Some(b"1".to_vec())
+            );
+            first.upsert("b", "1").await.unwrap();
+            second.upsert("a", "2").await.unwrap();
+            second.commit().await.unwrap();
+            assert!(matches!(first.commit().await, Err(Error::TxConflict)));
+        });
+
+        std::fs::remove_dir_all(path).unwrap();
     }
 }
@@ -0,0 +1,262 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+use bson::Bson;
+use kuiperdb_core::error::{Error, Result};
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::types::expression::Expression;
+use crate::types::hash::canonical_key;
+use crate::types::ordering::compare_values;
+use crate::types::KuiperObject;
+
+/// The running state of one aggregate function call over a group.
+#[derive(Clone)]
+enum Accumulator {
+    /// Counts the documents, or the non-null values of the expression
+    Count(Option<Expression>, i64),
+    Sum(Expression, Bson),
+    Min(Expression, Bson),
+    Max(Expression, Bson),
+    Average(Expression, f64, i64),
+}
+
+impl Accumulator {
+    fn new(function: &str, mut args: Vec<Expression>) -> Result<Accumulator> {
+        let arg = args.pop();
+        Ok(match (function, arg) {
+            ("count", arg) => Accumulator::Count(arg, 0),
+            ("sum", Some(arg)) => Accumulator::Sum(arg, Bson::Null),
+            ("min", Some(arg)) => Accumulator::Min(arg, Bson::Null),
+            ("max", Some(arg)) => Accumulator::Max(arg, Bson::Null),
+            ("avg", Some(arg)) => Accumulator::Average(arg, 0.0, 0),
+            (function, _) => {
+                return Err(Error::Value(format!(
+                    "Unknown aggregate function {}()",
+                    function
+                )))
+            }
+        })
+    }
+
+    /// Adds a document to the aggregate, null values are skipped.
+    fn add(&mut self, document: &KuiperObject) -> Result<()> {
+        match self {
+            Accumulator::Count(None, count) => *count += 1,
+            Accumulator::Count(Some(expr), count) => {
+                if expr.evaluate(Some(document))? != Bson::Null {
+                    *count += 1;
+                }
+            }
+            Accumulator::Sum(expr, sum) => match expr.evaluate(Some(document))? {
+                Bson::Null => {}
+                value => {
+                    let value = match value {
+                        Bson::Int32(i) => Bson::Int64(i as i64),
+                        value => value,
+                    };
+                    *sum = match &*sum {
+                        Bson::Null => value,
+                        current => Expression::Add(
+                            Expression::Constant(current.clone()).into(),
+                            Expression::Constant(value).into(),
+                        )
+                        .evaluate(None)?,
+                    };
+                }
+            },
+            Accumulator::Min(expr, min) => {
+                Self::keep(min, expr.evaluate(Some(document))?, Ordering::Less)
+            }
+            Accumulator::Max(expr, max) => {
+                Self::keep(max, expr.evaluate(Some(document))?, Ordering::Greater)
+            }
+            Accumulator::Average(expr, sum, count) => match expr.evaluate(Some(document))? {
+                Bson::Null => {}
+                Bson::Int32(i) => {
+                    *sum += i as f64;
+                    *count += 1;
+                }
+                Bson::Int64(i) => {
+                    *sum += i as f64;
+                    *count += 1;
+                }
+                Bson::Double(f) => {
+                    *sum += f;
+                    *count += 1;
+                }
+                value => return Err(Error::Value(format!("Can't average {}", value))),
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the current value when the new one compares in the wanted direction.
+    fn keep(current: &mut Bson, value: Bson, wanted: Ordering) {
+        if value != Bson::Null
+            && (*current == Bson::Null || compare_values(&value, current) == wanted)
+        {
+            *current = value;
+        }
+    }
+
+    /// The value of the aggregate, empty sums and averages are null.
+    fn value(&self) -> Bson {
+        match self {
+            Accumulator::Count(_, count) => Bson::Int64(*count),
+            Accumulator::Sum(_, value)
+            | Accumulator::Min(_, value)
+            | Accumulator::Max(_, value) => value.clone(),
+            Accumulator::Average(_, _, 0) => Bson::Null,
+            Accumulator::Average(_, sum, count) => Bson::Double(sum / *count as f64),
+        }
+    }
+}
+
+/// Groups documents by their grouping values and computes the aggregate fields of every
+/// group. Groups are returned in the order their first document was seen, every output
+/// document holds the grouping fields followed by the aggregate fields.
+pub fn summarize(
+    documents: Vec<KuiperObject>,
+    group_by: &[(String, Expression)],
+    aggregates: &[(String, Expression)],
+) -> Result<Vec<KuiperObject>> {
+    // Every aggregate call of every field gets its own accumulator, in order
+    let calls = RefCell::new(Vec::new());
+    for (_, expr) in aggregates {
+        expr.clone().transform(
+            &|e| match e {
+                Expression::Call(name, args) if Expression::is_aggregate_function(&name) => {
+                    calls.borrow_mut().push(Accumulator::new(&name, args)?);
+                    Ok(Expression::Constant(Bson::Null))
+                }
+                e => Ok(e),
+            },
+            &|e| Ok(e),
+        )?;
+    }
+    let accumulators = calls.into_inner();
+
+    let mut index: HashMap<Vec<u8>, usize> = HashMap::new();
+    let mut groups: Vec<(Vec<Bson>, Vec<Accumulator>)> = Vec::new();
+
+    for document in documents {
+        let values = group_by
+            .iter()
+            .map(|(_, expr)| expr.evaluate(Some(&document)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let group = *index.entry(canonical_key(&values)).or_insert_with(|| {
+            groups.push((values, accumulators.clone()));
+            groups.len() - 1
+        });
+
+        for accumulator in groups[group].1.iter_mut() {
+            accumulator.add(&document)?;
+        }
+    }
+
+    // Without grouping fields there is always a single group, even without input
+    if group_by.is_empty() && groups.is_empty() {
+        groups.push((Vec::new(), accumulators));
+    }
+
+    let mut results = Vec::with_capacity(groups.len());
+    for (values, accumulators) in groups {
+        let mut document = KuiperObject::new();
+        for ((name, _), value) in group_by.iter().zip(values) {
+            document.insert(name.clone(), value);
+        }
+
+        let call = Cell::new(0);
+        for (name, expr) in aggregates {
+            let expr = expr.clone().transform(
+                &|e| match e {
+                    Expression::Call(name, _) if Expression::is_aggregate_function(&name) => {
+                        let value = accumulators[call.get()].value();
+                        call.set(call.get() + 1);
+                        Ok(Expression::Constant(value))
+                    }
+                    e => Ok(e),
+                },
+                &|e| Ok(e),
+            )?;
+            document.insert(name.clone(), expr.evaluate(None)?);
+        }
+
+        results.push(document);
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    fn field(name: &str) -> Expression {
+        Expression::Field(0, Some((None, String::from(name))))
+    }
+
+    fn call(name: &str, args: Vec<Expression>) -> Expression {
+        Expression::Call(String::from(name), args)
+    }
+
+    #[test]
+    fn aggregates_per_group() {
+        let documents = vec![
+            doc! { "user": "a", "amount": 1_i64 },
+            doc! { "user": "b", "amount": 2.5 },
+            doc! { "user": "a", "amount": 3_i64 },
+            doc! { "user": "a", "amount": Bson::Null },
+        ];
+
+        let results = summarize(
+            documents,
+            &[(String::from("user"), field("user"))],
+            &[
+                (String::from("n"), call("count", vec![])),
+                (String::from("values"), call("count", vec![field("amount")])),
+                (String::from("total"), call("sum", vec![field("amount")])),
+                (String::from("low"), call("min", vec![field("amount")])),
+                (
+                    String::from("spread"),
+                    Expression::Subtract(
+                        call("max", vec![field("amount")]).into(),
+                        call("min", vec![field("amount")]).into(),
+                    ),
+                ),
+                (String::from("mean"), call("avg", vec![field("amount")])),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            results,
+            vec![
+                doc! { "user": "a", "n": 3_i64, "values": 2_i64, "total": 4_i64, "low": 1_i64, "spread": 2_i64, "mean": 2.0 },
+                doc! { "user": "b", "n": 1_i64, "values": 1_i64, "total": 2.5, "low": 2.5, "spread": 0.0, "mean": 2.5 },
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_input_without_groups() {
+        let results = summarize(
+            Vec::new(),
+            &[],
+            &[
+                (String::from("n"), call("count", vec![])),
+                (String::from("total"), call("sum", vec![field("amount")])),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(results, vec![doc! { "n": 0_i64, "total": Bson::Null }]);
+    }
+}
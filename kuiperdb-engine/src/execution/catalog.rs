@@ -0,0 +1,91 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+use bson::Bson;
+use kuiperdb_lang::catalog::{CollectionInfo, FieldInfo, ValueType};
+
+use crate::types::KuiperObject;
+
+/// The type the query language gives a stored value.
+pub fn value_type(value: &Bson) -> ValueType {
+    match value {
+        Bson::Null | Bson::Undefined => ValueType::Null,
+        Bson::Boolean(_) => ValueType::Boolean,
+        Bson::Int32(_) | Bson::Int64(_) => ValueType::Int,
+        Bson::Double(_) | Bson::Decimal128(_) => ValueType::Decimal,
+        Bson::String(_) | Bson::Symbol(_) | Bson::JavaScriptCode(_) => ValueType::String,
+        Bson::DateTime(_) | Bson::Timestamp(_) => ValueType::Date,
+        Bson::ObjectId(_) => ValueType::ObjectId,
+        Bson::Binary(_) => ValueType::Binary,
+        Bson::Array(_) => ValueType::Array,
+        _ => ValueType::Object,
+    }
+}
+
+/// Describes a collection from a sample of its documents, with every top level field and the
+/// types it was seen with.
+pub fn describe_collection(name: &str, documents: &[KuiperObject]) -> CollectionInfo {
+    let mut fields: Vec<FieldInfo> = Vec::new();
+
+    for document in documents {
+        for (key, value) in document {
+            let ty = value_type(value);
+            match fields.iter_mut().find(|field| field.name == *key) {
+                Some(field) if !field.types.contains(&ty) => field.types.push(ty),
+                Some(_) => {}
+                None => fields.push(FieldInfo {
+                    name: key.clone(),
+                    types: vec![ty],
+                }),
+            }
+        }
+    }
+
+    for field in &mut fields {
+        field.types.sort();
+    }
+
+    CollectionInfo {
+        name: name.to_owned(),
+        fields,
+        sampled: documents.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    #[test]
+    fn collects_fields_in_order() {
+        let collection = describe_collection(
+            "events",
+            &[
+                doc! { "user": "a", "amount": 1 },
+                doc! { "user": "b", "amount": 1.5, "tags": ["x"] },
+                doc! { "user": Bson::Null, "amount": 2_i64 },
+            ],
+        );
+
+        assert_eq!(collection.sampled, 3);
+        assert_eq!(
+            collection.fields,
+            [
+                FieldInfo {
+                    name: String::from("user"),
+                    types: vec![ValueType::Null, ValueType::String],
+                },
+                FieldInfo {
+                    name: String::from("amount"),
+                    types: vec![ValueType::Int, ValueType::Decimal],
+                },
+                FieldInfo {
+                    name: String::from("tags"),
+                    types: vec![ValueType::Array],
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+use bson::{Bson, Document};
+use kuiperdb_core::error::{Error, Result};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::types::hash::{canonical_key, document_key};
+
+/// The number of files documents are partitioned into once a distinct set is spilled
+const SPILL_PARTITIONS: usize = 16;
+
+/// Hash based duplicate elimination over a set of fields. The keys seen so far are kept in
+/// memory until they reach the memory budget, after which documents with unseen keys are
+/// partitioned by hash into temporary files and deduplicated one partition at a time.
+pub struct DistinctSet {
+    fields: Vec<String>,
+    memory_budget: usize,
+    seen: HashSet<Vec<u8>>,
+    seen_bytes: usize,
+    spill: Option<Spill>,
+}
+
+struct Spill {
+    directory: PathBuf,
+    partitions: Vec<(BufWriter<File>, usize)>,
+}
+
+impl DistinctSet {
+    pub fn new(fields: Vec<String>, memory_budget: usize) -> DistinctSet {
+        DistinctSet {
+            fields,
+            memory_budget,
+            seen: HashSet::new(),
+            seen_bytes: 0,
+            spill: None,
+        }
+    }
+
+    /// Projects a document onto the distinct fields, missing fields are null. Without fields
+    /// the whole document is kept.
+    fn project(&self, document: &Document) -> Document {
+        if self.fields.is_empty() {
+            return document.clone();
+        }
+
+        let mut projected = Document::new();
+        for field in &self.fields {
+            projected.insert(
+                field.clone(),
+                document.get(field).cloned().unwrap_or(Bson::Null),
+            );
+        }
+        projected
+    }
+
+    /// The key of a projection. Whole documents are keyed with their field names, documents
+    /// with the same values under different names aren't duplicates.
+    fn key(&self, projected: &Document) -> Vec<u8> {
+        match self.fields.is_empty() {
+            true => document_key(projected),
+            false => canonical_key(projected.values()),
+        }
+    }
+
+    /// Adds a document to the set. Returns its projection if it is known to be the first
+    /// document with these values. Once spilled, undecided documents are returned by
+    /// `finish()` instead.
+    pub fn insert(&mut self, document: &Document) -> Result<Option<Document>> {
+        let projected = self.project(document);
+        let key = self.key(&projected);
+
+        if self.seen.contains(&key) {
+            return Ok(None);
+        }
+
+        if let Some(spill) = &mut self.spill {
+            spill.write(&key, &projected)?;
+            return Ok(None);
+        }
+
+        self.seen_bytes += key.len();
+        self.seen.insert(key);
+
+        if self.seen_bytes > self.memory_budget {
+            self.spill = Some(Spill::create()?);
+        }
+
+        Ok(Some(projected))
+    }
+
+    /// Deduplicates the spilled documents and returns them, every partition is loaded into
+    /// memory on its own.
+    pub fn finish(mut self) -> Result<Vec<Document>> {
+        let mut spill = match self.spill.take() {
+            Some(spill) => spill,
+            None => return Ok(Vec::new()),
+        };
+
+        // Documents returned by insert() never reach the partitions, their keys can go
+        self.seen.clear();
+
+        let mut documents = Vec::new();
+        for partition in 0..SPILL_PARTITIONS {
+            let mut seen = HashSet::new();
+            for projected in spill.read_partition(partition)? {
+                if seen.insert(self.key(&projected)) {
+                    documents.push(projected);
+                }
+            }
+        }
+
+        Ok(documents)
+    }
+}
+
+impl Spill {
+    fn create() -> Result<Spill> {
+        let directory =
+            std::env::temp_dir().join(format!("kuiperdb-distinct-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&directory)?;
+
+        let mut partitions = Vec::with_capacity(SPILL_PARTITIONS);
+        for i in 0..SPILL_PARTITIONS {
+            let file = File::create(directory.join(i.to_string()))?;
+            partitions.push((BufWriter::new(file), 0));
+        }
+
+        Ok(Spill {
+            directory,
+            partitions,
+        })
+    }
+
+    fn write(&mut self, key: &[u8], document: &Document) -> Result<()> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+
+        let (writer, count) = &mut self.partitions[hasher.finish() as usize % SPILL_PARTITIONS];
+        document
+            .to_writer(writer)
+            .map_err(|e| Error::Value(e.to_string()))?;
+        *count += 1;
+
+        Ok(())
+    }
+
+    /// Reads back all documents of a partition.
+    fn read_partition(&mut self, partition: usize) -> Result<Vec<Document>> {
+        let (writer, count) = &mut self.partitions[partition];
+        writer.flush()?;
+
+        let mut reader = BufReader::new(File::open(self.directory.join(partition.to_string()))?);
+        (0..*count)
+            .map(|_| Document::from_reader(&mut reader).map_err(|e| Error::Value(e.to_string())))
+            .collect()
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.directory);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    #[test]
+    fn numeric_types_dedupe() {
+        let mut set = DistinctSet::new(vec![String::from("a")], usize::MAX);
+
+        assert!(set.insert(&doc! { "a": 1_i32 }).unwrap().is_some());
+        assert!(set.insert(&doc! { "a": 1_i64 }).unwrap().is_none());
+        assert!(set.insert(&doc! { "a": 1.0 }).unwrap().is_none());
+        assert!(set.insert(&doc! { "a": 1.5 }).unwrap().is_some());
+    }
+
+    #[test]
+    fn projects_missing_fields_as_null() {
+        let mut set = DistinctSet::new(vec![String::from("a"), String::from("b")], usize::MAX);
+
+        assert_eq!(
+            set.insert(&doc! { "a": "x", "c": 1 }).unwrap(),
+            Some(doc! { "a": "x", "b": Bson::Null })
+        );
+        assert!(set
+            .insert(&doc! { "a": "x", "b": Bson::Null })
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn whole_documents_compare_field_names() {
+        let mut set = DistinctSet::new(Vec::new(), usize::MAX);
+
+        assert!(set.insert(&doc! { "a": 1 }).unwrap().is_some());
+        assert!(set.insert(&doc! { "b": 1 }).unwrap().is_some());
+        assert!(set.insert(&doc! { "a": 1_i64 }).unwrap().is_none());
+    }
+
+    #[test]
+    fn spills_past_memory_budget() {
+        let mut set = DistinctSet::new(vec![String::from("a")], 64);
+        let mut documents = Vec::new();
+
+        for i in 0..200 {
+            if let Some(document) = set.insert(&doc! { "a": i % 50 }).unwrap() {
+                documents.push(document);
+            }
+        }
+        documents.extend(set.finish().unwrap());
+
+        let mut values: Vec<i32> = documents
+            .iter()
+            .map(|document| document.get_i32("a").unwrap())
+            .collect();
+        values.sort();
+        assert_eq!(values, (0..50).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,92 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// A map that holds at most `capacity` entries. Inserting into a full map evicts the entry
+/// that was read or inserted least recently.
+pub struct Lru<K, V> {
+    capacity: usize,
+    entries: HashMap<K, (V, u64)>,
+
+    // The keys by when they were last used, the least recently used first
+    order: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> Lru<K, V> {
+    pub fn new(capacity: usize) -> Lru<K, V> {
+        Lru {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// The value of a key, which becomes the most recently used one
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let (value, used) = self.entries.get_mut(key)?;
+
+        self.tick += 1;
+        let key = self.order.remove(used)?;
+        self.order.insert(self.tick, key);
+        *used = self.tick;
+
+        Some(value.clone())
+    }
+
+    /// Inserts or replaces the value of a key, evicting the least recently used entry when
+    /// the map is full
+    pub fn insert(&mut self, key: K, value: V) {
+        self.tick += 1;
+        if let Some((_, used)) = self.entries.insert(key.clone(), (value, self.tick)) {
+            self.order.remove(&used);
+        }
+        self.order.insert(self.tick, key);
+
+        while self.entries.len() > self.capacity {
+            match self.order.pop_first() {
+                Some((_, oldest)) => self.entries.remove(&oldest),
+                None => break,
+            };
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut lru = Lru::new(2);
+        lru.insert("a", 1);
+        lru.insert("b", 2);
+
+        // Reading a makes b the least recently used
+        assert_eq!(lru.get(&"a"), Some(1));
+        lru.insert("c", 3);
+        assert_eq!(lru.get(&"b"), None);
+        assert_eq!(lru.get(&"a"), Some(1));
+        assert_eq!(lru.get(&"c"), Some(3));
+
+        // Replacing a value uses it too
+        lru.insert("a", 4);
+        lru.insert("d", 5);
+        assert_eq!(lru.len(), 2);
+        assert_eq!(lru.get(&"c"), None);
+        assert_eq!(lru.get(&"a"), Some(4));
+        assert_eq!(lru.get(&"d"), Some(5));
+    }
+}
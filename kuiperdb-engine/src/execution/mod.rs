@@ -5,23 +5,81 @@
 use bson::doc;
 use bson::oid::ObjectId;
 
+use kuiperdb_core::error::Error;
 use kuiperdb_core::schema::information_schema;
+use kuiperdb_core::storage::kv::{ScanRange, Store, Transaction as _};
 use kuiperdb_core::{error::Result, storage::rocksdb::Datastore};
-use kuiperdb_lang::ast::ScalarValue;
+use kuiperdb_lang::analyzer::{Analyzer, Schema, Severity};
+use kuiperdb_lang::ast::{Node, ScalarValue};
+use kuiperdb_lang::catalog::Catalog;
 use serde::Deserialize;
 use serde_derive::Serialize;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::future::Future;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-use crate::plan::CollectionScan;
+use crate::plan::{self, CollectionScan};
+use crate::types::expression::Expression;
+use crate::types::hash::{canonical_key, document_key};
+use crate::types::KuiperObject;
 
-pub struct Executor {
-    _ds: Pin<Arc<Datastore>>,
+use self::distinct::DistinctSet;
+use self::lru::Lru;
+use self::session::Session;
+
+pub mod aggregate;
+pub mod catalog;
+pub mod distinct;
+pub mod lru;
+pub mod session;
+pub mod window;
+
+/// The maximum number of prepared statements kept by an executor
+const STATEMENT_CACHE_CAPACITY: usize = 1024;
+
+/// The memory a `distinct` may use for its keys before it spills to disk
+const DISTINCT_MEMORY_BUDGET: usize = 64 * 1024 * 1024;
+
+/// The documents of a plan node, read as they are iterated
+type Documents<'a> = Box<dyn Iterator<Item = Result<KuiperObject>> + 'a>;
+
+/// Runs statements against a datastore, rocksdb unless another [`Store`] is given.
+pub struct Executor<S: Store = Datastore> {
+    _ds: Pin<Arc<S>>,
+
+    // Prepared statements keyed by their language and query text
+    statements: Mutex<Lru<(Dialect, String), Arc<PreparedStatement>>>,
+
+    // The committed collections statements are checked against, listed when the first
+    // statement is prepared and again when a statement queries one it doesn't list
+    catalog: Mutex<Option<Arc<Catalog>>>,
+}
+
+/// The language a statement is written in, both are planned into the same plan nodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Dialect {
+    /// The piped query language of kuiperdb-lang
+    Klang,
+    Sql,
+}
+
+/// A script that has been parsed and planned once, and can be executed many times with
+/// different parameters. Holds one plan for every query in the script.
+pub struct PreparedStatement {
+    pub text: String,
+    pub ast: Vec<Node>,
+    pub plans: Vec<plan::QueryPlan>,
+
+    /// The fields of the documents every plan produces, with the types inferred for them.
+    pub schemas: Vec<Schema>,
 }
 
 pub struct QueryPlan {
     pub collection: String,
     pub schema: String,
+    pub filter: Option<Expression>,
 }
 pub struct ExecutionContext {
     pub parameters: Vec<(String, ScalarValue)>,
@@ -37,11 +95,691 @@ pub struct Record {
     pub body: Vec<u8>,
 }
 
-impl Executor {
-    pub fn new(datastore: Datastore) -> Executor {
+impl<S: Store> Executor<S> {
+    pub fn new(datastore: S) -> Executor<S> {
         return Executor {
-            _ds: Pin::new(Arc::new(datastore)),
+            _ds: Arc::pin(datastore),
+            statements: Mutex::new(Lru::new(STATEMENT_CACHE_CAPACITY)),
+            catalog: Mutex::new(None),
+        };
+    }
+
+    /// Parses and plans a query, reusing the cached statement if the same text was
+    /// prepared before.
+    pub async fn prepare(&self, text: &str) -> Result<Arc<PreparedStatement>> {
+        self.prepare_dialect(text, Dialect::Klang).await
+    }
+
+    /// Parses and plans a statement written in the given language.
+    pub async fn prepare_dialect(
+        &self,
+        text: &str,
+        dialect: Dialect,
+    ) -> Result<Arc<PreparedStatement>> {
+        self.prepare_in_session(text, dialect, &mut Session::new())
+            .await
+    }
+
+    /// Parses and plans a statement to run in a session. Besides the committed collections, it
+    /// can query the ones created by the session's open transaction.
+    pub async fn prepare_in_session(
+        &self,
+        text: &str,
+        dialect: Dialect,
+        session: &mut Session<S>,
+    ) -> Result<Arc<PreparedStatement>> {
+        let key = (dialect, text.to_owned());
+        if let Some(statement) = self.statements.lock().unwrap().get(&key) {
+            return Ok(statement);
+        }
+
+        let ast = match dialect {
+            Dialect::Klang => kuiperdb_lang::parser::parse_query(text)
+                .map_err(|err| Error::Parse(err.to_string()))?,
+            Dialect::Sql => {
+                kuiperdb_lang::sql::parse_sql(text).map_err(|err| Error::Parse(err.to_string()))?
+            }
+        };
+
+        // The cached catalog misses the collections committed since it was listed, by this
+        // executor or by another one on the same store, so it is listed again before a
+        // statement is rejected
+        let cached = self.catalog.lock().unwrap().clone();
+        let listed = cached.is_none();
+        let mut catalog = match cached {
+            Some(catalog) => catalog,
+            None => self.list_catalog().await?,
+        };
+        let mut schemas = Self::analyze(text, &ast, Some(&catalog));
+        if schemas.is_err() && !listed {
+            catalog = self.list_catalog().await?;
+            schemas = Self::analyze(text, &ast, Some(&catalog));
+        }
+
+        // The collections the session's transaction created are only known to the session, so
+        // a statement that needs them isn't cached for the others
+        let mut shared = true;
+        if let (Err(_), Some(txn)) = (&schemas, session.transaction()) {
+            let mut catalog = (*catalog).clone();
+            for collection in Self::collections(txn).await? {
+                if catalog.collection(&collection).is_none() {
+                    catalog
+                        .collections
+                        .push(catalog::describe_collection(&collection, &[]));
+                }
+            }
+            schemas = Self::analyze(text, &ast, Some(&catalog));
+            shared = false;
+        }
+
+        let schemas = schemas?;
+        let plans = plan::QueryPlan::from_script(&ast)?;
+
+        let statement = Arc::new(PreparedStatement {
+            text: text.to_owned(),
+            ast,
+            plans,
+            schemas,
+        });
+
+        if shared {
+            self.statements
+                .lock()
+                .unwrap()
+                .insert(key, statement.clone());
+        }
+
+        Ok(statement)
+    }
+
+    /// Lists the committed collections statements are checked against, and caches them. Their
+    /// documents aren't sampled, a sample could miss the types other documents give the same
+    /// fields.
+    async fn list_catalog(&self) -> Result<Arc<Catalog>> {
+        let catalog = Arc::new(self.catalog_snapshot(0).await?);
+        *self.catalog.lock().unwrap() = Some(catalog.clone());
+
+        Ok(catalog)
+    }
+
+    /// Checks a script before it is planned, so mistakes such as querying a collection that
+    /// doesn't exist or comparing a string to a number are reported with their position rather
+    /// than failing for every document. Nothing is known about the fields of the stored
+    /// documents, only the fields the queries compute have types.
+    fn analyze(text: &str, ast: &[Node], catalog: Option<&Catalog>) -> Result<Vec<Schema>> {
+        let mut analyzer = Analyzer::new(catalog);
+        let schemas = ast
+            .iter()
+            .filter_map(|statement| analyzer.statement(statement))
+            .collect();
+
+        let errors: Vec<String> = analyzer
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+            .map(|diagnostic| {
+                // Statements translated from SQL have no positions
+                if diagnostic.span.end == 0 {
+                    return diagnostic.message.clone();
+                }
+                let (line, column) = diagnostic.span.line_column(text);
+                format!("{} at line {}, column {}", diagnostic.message, line, column)
+            })
+            .collect();
+
+        if !errors.is_empty() {
+            return Err(Error::Parse(errors.join("\n")));
+        }
+
+        Ok(schemas)
+    }
+
+    /// Executes every statement of a prepared statement in order, binding its parameters from
+    /// the context. Returns one result per statement. A transaction the script begins must
+    /// also be ended by it, otherwise it is rolled back.
+    pub async fn execute_prepared(
+        &self,
+        statement: &PreparedStatement,
+        context: &ExecutionContext,
+    ) -> Result<Vec<QueryResult>> {
+        let mut session = Session::new();
+        let results = self
+            .execute_in_session(statement, context, &mut session)
+            .await?;
+
+        if session.transaction().is_some() {
+            session.abort().await?;
+            return Err(Error::Tx(String::from(
+                "The script didn't commit its transaction, it was rolled back",
+            )));
+        }
+
+        Ok(results)
+    }
+
+    /// Executes every statement of a prepared statement in order in a session. While the
+    /// session has a transaction open, the statements run in it rather than in their own.
+    pub async fn execute_in_session(
+        &self,
+        statement: &PreparedStatement,
+        context: &ExecutionContext,
+        session: &mut Session<S>,
+    ) -> Result<Vec<QueryResult>> {
+        let mut results = Vec::with_capacity(statement.plans.len());
+
+        for plan in &statement.plans {
+            let documents = match (&plan.0, session.transaction()) {
+                (plan::Node::Transaction(control), _) => {
+                    session.execute(&self._ds, control).await?;
+                    Vec::new()
+                }
+                (node, Some(txn)) => {
+                    let node = node.clone().bind(&context.parameters)?;
+                    self.execute_in(node, txn).await?
+                }
+                (_, None) => self.execute_plan(plan, context).await?.records,
+            };
+
+            results.push(QueryResult { records: documents });
+        }
+
+        Ok(results)
+    }
+
+    /// Executes a single plan in its own transaction, binding its parameters from the context.
+    pub async fn execute_plan(
+        &self,
+        plan: &plan::QueryPlan,
+        context: &ExecutionContext,
+    ) -> Result<QueryResult> {
+        let node = plan.0.clone().bind(&context.parameters)?;
+
+        // The statement runs in one transaction, its reads see the same snapshot and its
+        // writes are committed together. Writes that conflict with a concurrent statement
+        // are run again.
+        let records = match node.writes() {
+            true => {
+                self._ds
+                    .run_in_transaction(true, async |txn| self.execute_in(node.clone(), txn).await)
+                    .await?
+            }
+            false => {
+                let mut txn = self._ds.transaction(false).await?;
+                let records = self.execute_in(node, &mut txn).await;
+                txn.rollback().await?;
+                records?
+            }
+        };
+
+        Ok(QueryResult { records })
+    }
+
+    /// Executes a bound plan in a transaction
+    async fn execute_in(
+        &self,
+        node: plan::Node,
+        txn: &mut S::Transaction,
+    ) -> Result<Vec<bson::Bson>> {
+        let node = self.resolve_subqueries(node, txn).await?;
+        let documents = self.execute_node(&node, txn).await?;
+
+        Ok(documents.into_iter().map(bson::Bson::Document).collect())
+    }
+
+    /// Runs the subqueries used inside expressions and replaces them with their results. They
+    /// can't refer to the outer documents, so every subquery only runs once.
+    fn resolve_subqueries<'a>(
+        &'a self,
+        node: plan::Node,
+        txn: &'a mut S::Transaction,
+    ) -> Pin<Box<dyn Future<Output = Result<plan::Node>> + 'a>> {
+        Box::pin(async move {
+            // Subqueries are collected and then replaced by walking the plan in the same order
+            let subqueries = RefCell::new(Vec::new());
+            let node = node.transform_expressions(&|expr| {
+                expr.transform(
+                    &|e| {
+                        if let Expression::InSubquery(_, subquery)
+                        | Expression::ScalarSubquery(subquery) = &e
+                        {
+                            subqueries.borrow_mut().push((**subquery).clone());
+                        }
+                        Ok(e)
+                    },
+                    &|e| Ok(e),
+                )
+            })?;
+
+            let mut results = Vec::new();
+            for subquery in subqueries.into_inner() {
+                let subquery = self.resolve_subqueries(subquery, txn).await?;
+                results.push(Self::first_fields(self.execute_node(&subquery, txn).await?));
+            }
+
+            let next = Cell::new(0);
+            node.transform_expressions(&|expr| {
+                expr.transform(
+                    &|e| match e {
+                        Expression::InSubquery(expr, _) => {
+                            let values = &results[next.replace(next.get() + 1)];
+                            Ok(Expression::In(
+                                expr,
+                                values.iter().cloned().map(Expression::Constant).collect(),
+                            ))
+                        }
+                        Expression::ScalarSubquery(_) => {
+                            let values = &results[next.replace(next.get() + 1)];
+                            Ok(Expression::Constant(
+                                values.first().cloned().unwrap_or(bson::Bson::Null),
+                            ))
+                        }
+                        e => Ok(e),
+                    },
+                    &|e| Ok(e),
+                )
+            })
+        })
+    }
+
+    /// The value of the first field of every document, documents without fields are skipped.
+    fn first_fields(documents: Vec<KuiperObject>) -> Vec<bson::Bson> {
+        documents
+            .into_iter()
+            .filter_map(|document| document.into_iter().next().map(|(_, value)| value))
+            .collect()
+    }
+
+    /// Executes a plan node in a transaction, its parameters must already be bound.
+    async fn execute_node(
+        &self,
+        node: &plan::Node,
+        txn: &mut S::Transaction,
+    ) -> Result<Vec<KuiperObject>> {
+        match node {
+            plan::Node::Insert {
+                schema,
+                collection,
+                rows,
+            } => {
+                self.register_collection(collection, txn).await?;
+
+                for row in rows {
+                    let mut document = KuiperObject::new();
+                    for (name, expr) in row {
+                        document.insert(name.clone(), expr.evaluate(None)?);
+                    }
+
+                    // Documents without an _id are given one, it leads the document
+                    if !document.contains_key("_id") {
+                        let mut with_id = doc! { "_id": ObjectId::new() };
+                        with_id.extend(document);
+                        document = with_id;
+                    }
+
+                    txn.insert(
+                        Self::document_storage_key(schema, collection, &document)?,
+                        bson::to_vec(&document).map_err(|e| Error::Value(e.to_string()))?,
+                    )
+                    .await?;
+                }
+
+                Ok(vec![doc! { "count": rows.len() as i64 }])
+            }
+            plan::Node::Update {
+                source,
+                schema,
+                collection,
+                updates,
+            } => {
+                let documents = self.read_node(source, txn).await?;
+
+                for document in &documents {
+                    let key = Self::document_storage_key(schema, collection, document)?;
+                    let document = Self::apply_updates(document, updates)?;
+
+                    txn.upsert(
+                        key,
+                        bson::to_vec(&document).map_err(|e| Error::Value(e.to_string()))?,
+                    )
+                    .await?;
+                }
+
+                Ok(vec![doc! { "count": documents.len() as i64 }])
+            }
+            plan::Node::Delete {
+                source,
+                schema,
+                collection,
+            } => {
+                let documents = self.read_node(source, txn).await?;
+
+                for document in &documents {
+                    txn.delete(Self::document_storage_key(schema, collection, document)?)
+                        .await?;
+                }
+
+                Ok(vec![doc! { "count": documents.len() as i64 }])
+            }
+            plan::Node::Transaction(_) => Err(Error::Parse(String::from(
+                "Transaction statements can only run in a session",
+            ))),
+            node => self.read_node(node, txn).await,
+        }
+    }
+
+    /// Reads every document of a query node.
+    async fn read_node(
+        &self,
+        node: &plan::Node,
+        txn: &S::Transaction,
+    ) -> Result<Vec<KuiperObject>> {
+        self.stream(node, txn).await?.collect()
+    }
+
+    /// Opens the documents of a query node. They are read from the cursors as the iterator is
+    /// advanced, so a `take` stops reading once it has its documents. Nodes that need all of
+    /// their input, such as `summarize`, read it when they are opened.
+    fn stream<'a>(
+        &'a self,
+        node: &'a plan::Node,
+        txn: &'a S::Transaction,
+    ) -> Pin<Box<dyn Future<Output = Result<Documents<'a>>> + 'a>> {
+        Box::pin(async move {
+            Ok(match node {
+                plan::Node::CollectionScan(scan) => Self::scan_collection(scan, txn).await?,
+                plan::Node::Filter { source, predicate } => {
+                    let filter = Some(predicate.clone());
+
+                    Self::filter_documents(self.stream(source, txn).await?, move |document| {
+                        Self::matches_filter(&filter, document)
+                    })
+                }
+                plan::Node::SemiJoin {
+                    source,
+                    expr,
+                    subquery,
+                    negated,
+                } => {
+                    let subquery = self.stream(subquery, txn).await?.collect::<Result<_>>()?;
+                    let keys: HashSet<Vec<u8>> = Self::first_fields(subquery)
+                        .iter()
+                        .map(|value| canonical_key([value]))
+                        .collect();
+
+                    Self::filter_documents(self.stream(source, txn).await?, move |document| {
+                        // A null never matches, whether or not the join is negated
+                        let value = expr.evaluate(Some(document))?;
+                        Ok(value != bson::Bson::Null
+                            && keys.contains(&canonical_key([&value])) != *negated)
+                    })
+                }
+                plan::Node::Project { source, fields } => {
+                    Self::map_documents(self.stream(source, txn).await?, move |document| {
+                        let mut projected = KuiperObject::new();
+                        for (name, expr) in fields {
+                            projected.insert(name.clone(), expr.evaluate(Some(&document))?);
+                        }
+                        Ok(projected)
+                    })
+                }
+                plan::Node::ProjectAway { source, fields } => {
+                    Self::map_documents(self.stream(source, txn).await?, move |mut document| {
+                        for field in fields {
+                            document.remove(field);
+                        }
+                        Ok(document)
+                    })
+                }
+                plan::Node::Distinct { source, fields } => {
+                    // The first occurrences are produced as they are read, unless the keys
+                    // spilled to disk, then the rest once the input is exhausted
+                    let mut set = Some(DistinctSet::new(fields.clone(), DISTINCT_MEMORY_BUDGET));
+                    let documents = self.stream(source, txn).await?;
+
+                    Box::new(documents.map(Some).chain(std::iter::once(None)).flat_map(
+                        move |document| {
+                            match (document, &mut set) {
+                                (Some(document), Some(set)) => document
+                                    .and_then(|document| set.insert(&document))
+                                    .transpose()
+                                    .into_iter()
+                                    .collect(),
+                                (_, set) => match set.take().map(DistinctSet::finish) {
+                                    Some(Ok(rest)) => rest.into_iter().map(Ok).collect(),
+                                    Some(Err(e)) => vec![Err(e)],
+                                    None => Vec::new(),
+                                },
+                            }
+                        },
+                    ))
+                }
+                plan::Node::Serialize {
+                    source,
+                    partition_by,
+                    order_by,
+                } => Self::computed(window::serialize(
+                    self.stream(source, txn).await?.collect::<Result<_>>()?,
+                    partition_by,
+                    order_by,
+                )?),
+                plan::Node::Extend { source, fields } => {
+                    Self::map_documents(self.stream(source, txn).await?, move |mut document| {
+                        for (name, expr) in fields {
+                            let value = expr.evaluate(Some(&document))?;
+                            document.insert(name.clone(), value);
+                        }
+                        Ok(document)
+                    })
+                }
+                plan::Node::Window {
+                    source,
+                    partition_by,
+                    fields,
+                } => Self::computed(window::extend_windows(
+                    self.stream(source, txn).await?.collect::<Result<_>>()?,
+                    partition_by,
+                    fields,
+                )?),
+                plan::Node::Union {
+                    inputs,
+                    with_source,
+                } => {
+                    // Every input is opened first, then they are read one after another
+                    let mut streams = Vec::new();
+                    let mut collections: Option<Vec<String>> = None;
+
+                    for input in inputs {
+                        match input {
+                            // A wildcard scans every matching collection of the catalog
+                            plan::Node::CollectionScan(scan) if scan.is_pattern() => {
+                                if collections.is_none() {
+                                    collections = Some(Self::collections(txn).await?);
+                                }
+
+                                for collection in collections.iter().flatten() {
+                                    if scan.matches(collection) {
+                                        let scan = CollectionScan {
+                                            collection: collection.clone(),
+                                            ..scan.clone()
+                                        };
+                                        let documents = Self::scan_collection(&scan, txn).await?;
+                                        streams.push((Some(collection.clone()), documents));
+                                    }
+                                }
+                            }
+                            input => {
+                                let source = input.source_name().map(str::to_owned);
+                                streams.push((source, self.stream(input, txn).await?));
+                            }
+                        }
+                    }
+
+                    Box::new(streams.into_iter().flat_map(move |(source, documents)| {
+                        documents.map(move |document| {
+                            let mut document = document?;
+                            if let (Some(field), Some(source)) = (with_source, &source) {
+                                document.insert(field.clone(), source.clone());
+                            }
+                            Ok(document)
+                        })
+                    }))
+                }
+                plan::Node::Intersect { left, right } => {
+                    let right =
+                        Self::document_set(self.stream(right, txn).await?.collect::<Result<_>>()?);
+
+                    Self::filter_documents(self.stream(left, txn).await?, move |document| {
+                        Ok(right.contains(&document_key(document)))
+                    })
+                }
+                plan::Node::Except { left, right } => {
+                    let right =
+                        Self::document_set(self.stream(right, txn).await?.collect::<Result<_>>()?);
+
+                    Self::filter_documents(self.stream(left, txn).await?, move |document| {
+                        Ok(!right.contains(&document_key(document)))
+                    })
+                }
+                plan::Node::Take { source, limit } => Box::new(
+                    self.stream(source, txn)
+                        .await?
+                        .take(usize::try_from(*limit).unwrap_or(usize::MAX)),
+                ),
+                plan::Node::Aggregate {
+                    source,
+                    group_by,
+                    aggregates,
+                } => Self::computed(aggregate::summarize(
+                    self.stream(source, txn).await?.collect::<Result<_>>()?,
+                    group_by,
+                    aggregates,
+                )?),
+                plan::Node::Lock {
+                    source,
+                    schema,
+                    collection,
+                } => {
+                    // Every document is locked as soon as it is read, so a writer of a document
+                    // read earlier waits for the transaction instead of conflicting with it.
+                    // Locking is awaited, so the documents are read before they are returned.
+                    let mut documents = Vec::new();
+                    for document in self.stream(source, txn).await? {
+                        let document = document?;
+                        txn.get_for_update(Self::document_storage_key(
+                            schema, collection, &document,
+                        )?)
+                        .await?;
+                        documents.push(Ok(document));
+                    }
+                    Box::new(documents.into_iter())
+                }
+                plan::Node::Insert { .. }
+                | plan::Node::Update { .. }
+                | plan::Node::Delete { .. }
+                | plan::Node::Transaction(_) => {
+                    return Err(Error::Parse(String::from(
+                        "Only queries can be the source of documents",
+                    )))
+                }
+            })
+        })
+    }
+
+    /// Lists the names of all collections registered in the catalog.
+    pub async fn list_collections(&self) -> Result<Vec<String>> {
+        let mut txn = self._ds.transaction(false).await?;
+        let collections = Self::collections(&txn).await;
+        txn.rollback().await?;
+
+        collections
+    }
+
+    /// Lists the collections registered in the catalog as of the transaction's snapshot.
+    async fn collections(txn: &S::Transaction) -> Result<Vec<String>> {
+        let scan = CollectionScan {
+            schema: String::from("information_schema"),
+            collection: String::from("table"),
+            alias: None,
+            expr: None,
         };
+        let documents = Self::scan_collection(&scan, txn)
+            .await?
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut collections: Vec<String> = documents
+            .iter()
+            .filter_map(|document| document.get_str("collection").ok())
+            .map(str::to_owned)
+            .collect();
+        collections.sort();
+        collections.dedup();
+
+        Ok(collections)
+    }
+
+    /// Describes every collection from its first `sample` documents, for checking and
+    /// completing queries without access to the data.
+    pub async fn catalog_snapshot(&self, sample: usize) -> Result<Catalog> {
+        let mut collections = Vec::new();
+
+        for collection in self.list_collections().await? {
+            let mut txn = self._ds.transaction(true).await?;
+            let prefix =
+                Self::generate_collection_prefix(String::from("default"), collection.clone());
+            let documents = txn
+                .cursor(ScanRange::prefix(prefix))
+                .await?
+                .take(sample)
+                .map(|record| Self::read_document(&record?.1))
+                .collect::<Result<Vec<KuiperObject>>>()?;
+            txn.commit().await?;
+
+            collections.push(catalog::describe_collection(&collection, &documents));
+        }
+
+        Ok(Catalog { collections })
+    }
+
+    /// Builds a set of the canonical encoding of each document, so numbers of different
+    /// types still compare equal.
+    fn document_set(documents: Vec<KuiperObject>) -> HashSet<Vec<u8>> {
+        documents
+            .into_iter()
+            .map(|document| document_key(&document))
+            .collect()
+    }
+
+    /// Documents that have already been computed
+    fn computed<'a>(documents: Vec<KuiperObject>) -> Documents<'a> {
+        Box::new(documents.into_iter().map(Ok))
+    }
+
+    /// Changes every document, the first error ends the documents
+    fn map_documents<'a>(
+        documents: Documents<'a>,
+        change: impl Fn(KuiperObject) -> Result<KuiperObject> + 'a,
+    ) -> Documents<'a> {
+        Box::new(documents.map(move |document| document.and_then(&change)))
+    }
+
+    /// Keeps the documents a predicate holds for
+    fn filter_documents<'a>(
+        documents: Documents<'a>,
+        keep: impl Fn(&KuiperObject) -> Result<bool> + 'a,
+    ) -> Documents<'a> {
+        Box::new(documents.filter_map(move |document| {
+            document
+                .and_then(|document| Ok(keep(&document)?.then_some(document)))
+                .transpose()
+        }))
+    }
+
+    /// Checks whether a document satisfies the (optional) filter expression
+    fn matches_filter(filter: &Option<Expression>, document: &KuiperObject) -> Result<bool> {
+        match filter {
+            Some(expr) => Ok(expr.evaluate(Some(document))? == bson::Bson::Boolean(true)),
+            None => Ok(true),
+        }
     }
 
     fn generate_collection_prefix(schema: String, collection: String) -> Vec<u8> {
@@ -55,26 +793,54 @@ impl Executor {
         return prefix;
     }
 
-    fn generate_collection_id_with_bytes(
-        schema: String,
-        collection: String,
-        id: Vec<u8>,
-    ) -> Vec<u8> {
-        let mut prefix = Executor::generate_collection_prefix(schema, collection);
-        prefix.extend(id);
+    /// Applies the changes of an update to a copy of the document. Every value is computed
+    /// from the document as it was before the update.
+    pub fn apply_updates(
+        document: &KuiperObject,
+        updates: &[plan::FieldUpdate],
+    ) -> Result<KuiperObject> {
+        let mut updated = document.clone();
 
-        return prefix;
+        for update in updates {
+            match update {
+                plan::FieldUpdate::Set(name, expr) => {
+                    updated.insert(name.clone(), expr.evaluate(Some(document))?);
+                }
+                plan::FieldUpdate::Unset(name) => {
+                    updated.remove(name);
+                }
+            }
+        }
+
+        Ok(updated)
+    }
+
+    /// The key a document is stored under, documents are keyed by their objectid `_id`.
+    fn document_storage_key(
+        schema: &str,
+        collection: &str,
+        document: &KuiperObject,
+    ) -> Result<Vec<u8>> {
+        match document.get("_id") {
+            Some(bson::Bson::ObjectId(id)) => Ok(Self::generate_collection_id2(
+                schema.to_owned(),
+                collection.to_owned(),
+                *id,
+            )),
+            Some(id) => Err(Error::Value(format!("_id must be an objectid, got {}", id))),
+            None => Err(Error::Value(String::from("Document has no _id"))),
+        }
     }
 
     // fn generate_collection_id(schema: String, collection: String, id: Uuid) -> Vec<u8> {
-    //     let mut prefix = Executor::generate_collection_prefix(schema, collection);
+    //     let mut prefix = Self::generate_collection_prefix(schema, collection);
     //     prefix.extend(id.as_bytes());
 
     //     return prefix;
     // }
 
     fn generate_collection_id2(schema: String, collection: String, id: ObjectId) -> Vec<u8> {
-        let mut prefix = Executor::generate_collection_prefix(schema, collection);
+        let mut prefix = Self::generate_collection_prefix(schema, collection);
         prefix.extend(id.bytes());
 
         return prefix;
@@ -129,8 +895,24 @@ impl Executor {
 
     pub async fn create_collection(&self, collection: String) -> Result<()> {
         let mut txn = self._ds.transaction(true).await?;
-        let id = ObjectId::new();
+        self.register_collection(&collection, &mut txn).await?;
+        txn.commit().await
+    }
+
+    /// Lists a collection in the information schema, unless it already is. Its row is keyed by
+    /// the collection's name, so transactions that create the same collection at once write
+    /// the same key and only one of them commits. Other statements see the collection once the
+    /// transaction commits.
+    async fn register_collection(&self, collection: &str, txn: &mut S::Transaction) -> Result<()> {
+        let collections = Self::collections(txn).await?;
+        if collections
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(collection))
+        {
+            return Ok(());
+        }
 
+        let id = ObjectId::new();
         let val = doc![
             "_id": id,
             "schema": information_schema::DEFAULT_SCHEMA,
@@ -139,47 +921,28 @@ impl Executor {
 
         // TODO: Update Unique Clustered Index 'collection'
 
-        let val_bytes = bson::to_vec(&val).unwrap();
-        txn.insert(
-            Self::generate_collection_id2(
-                String::from("information_schema"),
-                String::from("table"),
-                id,
-            ),
-            val_bytes,
-        )
-        .await?;
-        txn.commit().await?;
+        let val_bytes = bson::to_vec(&val).map_err(|e| Error::Value(e.to_string()))?;
+        let mut key = Self::generate_collection_prefix(
+            String::from("information_schema"),
+            String::from("table"),
+        );
+        key.extend(collection.to_lowercase().into_bytes());
+        txn.insert(key, val_bytes).await?;
 
-        return Ok(());
+        Ok(())
     }
 
     pub async fn execute_select(&self, plan: QueryPlan) -> Result<QueryResult> {
-        let prefix = Executor::generate_collection_prefix(plan.schema, plan.collection);
+        let prefix = Self::generate_collection_prefix(plan.schema, plan.collection);
 
-        let mut txn = self._ds.transaction(false).await?;
-        let mut last: Option<Vec<u8>> = Option::None;
+        let txn = self._ds.transaction(false).await?;
         let mut records: Vec<bson::Bson> = Vec::new();
 
-        loop {
-            let scan_result = txn
-                .scan_collection::<Vec<u8>>(prefix.clone(), Some(5000), last.clone())
-                .await?;
-
-            let count = scan_result.len();
-
-            last = Option::None;
+        for record in txn.cursor(ScanRange::prefix(prefix)).await? {
+            let doc = Self::read_document(&record?.1)?;
 
-            for raw_record in scan_result {
-                last = Some(raw_record.0);
-                let doc: bson::Bson = bson::from_slice(&raw_record.1).ok().unwrap();
-
-                // TODO: Expression Matching / Filtering ...
-                records.push(doc);
-            }
-
-            if count < 5000 {
-                break;
+            if Self::matches_filter(&plan.filter, &doc)? {
+                records.push(bson::Bson::Document(doc));
             }
         }
 
@@ -190,56 +953,43 @@ impl Executor {
         &self,
         plan: CollectionScan,
     ) -> Result<Vec<bson::Document>> {
-        let mut txn = self._ds.transaction(true).await?;
-        let prefix =
-            Executor::generate_collection_prefix(plan.schema.clone(), plan.collection.clone());
-        let mut after = Option::None;
-        let limit: usize = 10000;
+        let mut txn = self._ds.transaction(false).await?;
+        let results = Self::scan_collection(&plan, &txn)
+            .await
+            .and_then(|documents| documents.collect());
+        txn.rollback().await?;
 
-        let mut results: Vec<bson::Document> = Vec::new();
+        results
+    }
 
-        loop {
-            let records = txn
-                .scan_collection::<Vec<u8>>(prefix.clone(), Some(limit), after.clone())
-                .await?;
+    /// Scans a collection as of the transaction's snapshot, including its own writes. The
+    /// documents are read as the scan is advanced.
+    async fn scan_collection<'a>(
+        plan: &CollectionScan,
+        txn: &'a S::Transaction,
+    ) -> Result<Documents<'a>> {
+        let prefix = Self::generate_collection_prefix(plan.schema.clone(), plan.collection.clone());
+        let filter = plan.expr.clone();
 
-            let loaded_all_data = records.len() < limit;
+        // TODO: We don't need to convert the data into something else, we should just be able to scan
+        // the bson data directly for better efficiency as per original design of bson.
+        let records = txn.cursor(ScanRange::prefix(prefix)).await?;
+        let documents = Box::new(records.map(|record| Self::read_document(&record?.1)));
 
-            // TODO: We don't need to convert the data into something else, we should just be able to scan
-            // the bson data directly for better efficiency as per original design of bson.
-            let mut iter = records.iter();
+        Ok(Self::filter_documents(documents, move |document| {
+            Self::matches_filter(&filter, document)
+        }))
+    }
 
-            loop {
-                match iter.next() {
-                    Some(record) => {
-                        let document = bson::from_reader(&*record.1).unwrap();
-                        results.push(document);
-                    }
-                    None => {
-                        // Iterator has reached the end, break the loop
-                        break;
-                    }
-                }
-            }
+    pub async fn execute_count(&self, plan: CollectionScan) -> Result<u64> {
+        let mut txn = self._ds.transaction(true).await?;
+        let prefix = Self::generate_collection_prefix(plan.schema.clone(), plan.collection.clone());
 
-            if let Some(last_doc) = results.last() {
-                let id_raw = last_doc.get("_id").unwrap();
-                if let bson::Bson::Binary(binary_data) = id_raw {
-                    let id = binary_data.bytes.clone();
-                    after = Some(Self::generate_collection_id_with_bytes(
-                        plan.schema.clone(),
-                        plan.collection.clone(),
-                        id,
-                    ));
-                } else {
-                    panic!()
-                }
-            }
+        let mut results: u64 = 0;
 
-            // page chunk met ..
-            if loaded_all_data {
-                break;
-            }
+        for record in txn.cursor(ScanRange::prefix(prefix)).await? {
+            record?;
+            results += 1;
         }
 
         txn.commit().await?;
@@ -247,36 +997,435 @@ impl Executor {
         Ok(results)
     }
 
-    pub async fn execute_count(&self, plan: CollectionScan) -> Result<u64> {
-        let mut txn = self._ds.transaction(true).await?;
-        let prefix =
-            Executor::generate_collection_prefix(plan.schema.clone(), plan.collection.clone());
-        let mut after = Option::None;
-        let limit: usize = 10000;
+    /// Decodes a stored document.
+    fn read_document(bytes: &[u8]) -> Result<KuiperObject> {
+        bson::from_slice(bytes).map_err(|e| Error::Value(e.to_string()))
+    }
+}
 
-        let mut results: u64 = 0;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use kuiperdb_core::storage::memory;
+    use kuiperdb_core::storage::rocksdb::{DatastoreConfig, TransactionMode};
+    use kuiperdb_lang::catalog::ValueType;
+    use kuiperdb_lang::parser::parse_query;
 
-        loop {
-            let records = txn
-                .scan_collection::<Vec<u8>>(prefix.clone(), Some(limit), after.clone())
-                .await?;
+    fn analyze(text: &str) -> Result<Vec<Schema>> {
+        <Executor>::analyze(text, &parse_query(text).unwrap(), None)
+    }
+
+    /// Runs a script, returning the records of its last statement
+    fn execute(
+        ex: &Executor<memory::Datastore>,
+        dialect: Dialect,
+        text: &str,
+    ) -> Result<Vec<bson::Bson>> {
+        let statement = block_on(ex.prepare_dialect(text, dialect))?;
+        let context = ExecutionContext {
+            parameters: Vec::new(),
+        };
 
-            let loaded_all_data = records.len() < limit;
+        Ok(block_on(ex.execute_prepared(&statement, &context))?
+            .pop()
+            .unwrap()
+            .records)
+    }
 
-            if let Some(last_record) = records.last() {
-                after = Some(last_record.0.clone());
+    /// Stores a value that isn't a document after the documents of a collection, reading it
+    /// fails the query
+    fn corrupt(ex: &Executor<memory::Datastore>, collection: &str) {
+        let mut key = Executor::<memory::Datastore>::generate_collection_prefix(
+            String::from("default"),
+            collection.to_owned(),
+        );
+        key.extend([0xff; 12]);
+
+        block_on(async {
+            let mut txn = ex._ds.transaction(true).await.unwrap();
+            txn.insert(key, "not a document").await.unwrap();
+            txn.commit().await.unwrap();
+        })
+    }
+
+    #[test]
+    fn unknown_collections_are_rejected() {
+        let ex = Executor::new(memory::Datastore::new());
+        execute(&ex, Dialect::Sql, "INSERT INTO a (n) VALUES (1)").unwrap();
+
+        // The script is checked before any of it runs, the insert doesn't happen
+        match execute(
+            &ex,
+            Dialect::Sql,
+            "INSERT INTO a (n) VALUES (2); SELECT * FROM b",
+        ) {
+            Err(Error::Parse(message)) => assert_eq!(message, "Unknown collection b"),
+            invalid => panic!("Invalid result: {:?}", invalid),
+        }
+        match execute(&ex, Dialect::Klang, "a\n| union b") {
+            Err(Error::Parse(message)) => {
+                assert_eq!(message, "Unknown collection b at line 2, column 9")
             }
+            invalid => panic!("Invalid result: {:?}", invalid),
+        }
+        assert_eq!(execute(&ex, Dialect::Klang, "a").unwrap().len(), 1);
+
+        // Inserting creates a collection, later statements and scripts can query it
+        execute(
+            &ex,
+            Dialect::Sql,
+            "INSERT INTO b (n) VALUES (3); SELECT * FROM b",
+        )
+        .unwrap();
+        assert_eq!(
+            execute(&ex, Dialect::Klang, "a | union b").unwrap().len(),
+            2
+        );
+    }
 
-            results += records.len() as u64;
+    /// Prepares and runs an SQL script in a session, returning the records of its last statement
+    fn run(
+        ex: &Executor<memory::Datastore>,
+        session: &mut Session<memory::Datastore>,
+        text: &str,
+    ) -> Result<Vec<bson::Bson>> {
+        let statement = block_on(ex.prepare_in_session(text, Dialect::Sql, session))?;
+        let context = ExecutionContext {
+            parameters: Vec::new(),
+        };
+
+        Ok(
+            block_on(ex.execute_in_session(&statement, &context, session))?
+                .pop()
+                .unwrap()
+                .records,
+        )
+    }
+
+    #[test]
+    fn collections_are_listed_once_committed() {
+        let ds = memory::Datastore::new();
+        let ex = Executor::new(ds.clone());
+        let other = Executor::new(ds);
+        execute(&ex, Dialect::Sql, "INSERT INTO a (n) VALUES (1)").unwrap();
+
+        // The session sees the collection its transaction creates, the others don't
+        let mut session = Session::new();
+        run(&ex, &mut session, "BEGIN; INSERT INTO b (n) VALUES (1)").unwrap();
+        assert_eq!(run(&ex, &mut session, "SELECT * FROM b").unwrap().len(), 1);
+        assert!(matches!(
+            execute(&ex, Dialect::Sql, "SELECT * FROM b"),
+            Err(Error::Parse(_))
+        ));
+
+        // Rolling back doesn't leave the collection listed
+        run(&ex, &mut session, "ROLLBACK").unwrap();
+        assert!(matches!(
+            execute(&ex, Dialect::Sql, "SELECT * FROM b"),
+            Err(Error::Parse(_))
+        ));
+
+        // A collection another executor created is found once it commits
+        execute(&other, Dialect::Sql, "INSERT INTO b (n) VALUES (2)").unwrap();
+        assert_eq!(
+            execute(&ex, Dialect::Sql, "SELECT * FROM b").unwrap().len(),
+            1
+        );
+    }
 
-            // page chunk met ..
-            if loaded_all_data {
-                break;
+    #[test]
+    fn concurrent_creates_list_a_collection_once() {
+        let ex = Executor::new(memory::Datastore::new());
+        let (mut first, mut second) = (Session::new(), Session::new());
+        run(&ex, &mut first, "BEGIN; INSERT INTO c (n) VALUES (1)").unwrap();
+        run(&ex, &mut second, "BEGIN; INSERT INTO c (n) VALUES (2)").unwrap();
+
+        run(&ex, &mut first, "COMMIT").unwrap();
+        assert!(matches!(
+            run(&ex, &mut second, "COMMIT"),
+            Err(Error::TxConflict)
+        ));
+
+        let rows = block_on(async {
+            let txn = ex._ds.transaction(false).await.unwrap();
+            let prefix = Executor::<memory::Datastore>::generate_collection_prefix(
+                String::from("information_schema"),
+                String::from("table"),
+            );
+            txn.cursor(ScanRange::prefix(prefix)).await.unwrap().count()
+        });
+        assert_eq!(rows, 1);
+    }
+
+    #[test]
+    fn writers_wait_for_documents_read_for_update() {
+        let path =
+            std::env::temp_dir().join(format!("kuiperdb-for-update-{}", uuid::Uuid::new_v4()));
+        let ex = Executor::new(
+            block_on(Datastore::open(DatastoreConfig {
+                transactions: TransactionMode::Pessimistic,
+                lock_timeout_ms: 5000,
+                ..DatastoreConfig::with_path(path.to_str().unwrap())
+            }))
+            .unwrap(),
+        );
+        let context = ExecutionContext {
+            parameters: Vec::new(),
+        };
+        let execute = |dialect: Dialect, text: &str| {
+            let statement = block_on(ex.prepare_dialect(text, dialect)).unwrap();
+            block_on(ex.execute_prepared(&statement, &context))
+        };
+        execute(
+            Dialect::Sql,
+            "INSERT INTO accounts (name, balance) VALUES ('ann', 1), ('bob', 1)",
+        )
+        .unwrap();
+
+        // The session locks the document of ann without writing it
+        let mut session = Session::new();
+        let mut query = |text: &str| {
+            let statement = block_on(ex.prepare(text)).unwrap();
+            block_on(ex.execute_in_session(&statement, &context, &mut session)).unwrap();
+        };
+        query("begin; accounts | where name == \"ann\" | for update");
+
+        std::thread::scope(|scope| {
+            let writer = scope.spawn(|| execute(Dialect::Sql, "UPDATE accounts SET balance = 2"));
+
+            // The writer waits for the lock rather than failing with a conflict
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            assert!(!writer.is_finished());
+            query("commit");
+            assert_eq!(
+                writer.join().unwrap().unwrap()[0].records,
+                [bson::Bson::Document(doc! { "count": 2_i64 })]
+            );
+        });
+
+        let counted = execute(
+            Dialect::Klang,
+            "accounts | where balance == 2 | summarize n = count()",
+        )
+        .unwrap();
+        assert_eq!(
+            counted[0].records,
+            [bson::Bson::Document(doc! { "n": 2_i64 })]
+        );
+
+        drop(ex);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn take_stops_reading() {
+        let ex = Executor::new(memory::Datastore::new());
+        execute(&ex, Dialect::Sql, "INSERT INTO a (n) VALUES (1), (2), (3)").unwrap();
+        execute(&ex, Dialect::Sql, "INSERT INTO b (n) VALUES (4)").unwrap();
+        corrupt(&ex, "a");
+        corrupt(&ex, "b");
+
+        assert!(matches!(
+            execute(&ex, Dialect::Klang, "a"),
+            Err(Error::Value(_))
+        ));
+        assert_eq!(
+            execute(&ex, Dialect::Klang, "a | where n > 1 | project n | take 2").unwrap(),
+            [bson::bson!({ "n": 2_i64 }), bson::bson!({ "n": 3_i64 })]
+        );
+
+        // The inputs of a union are read one after another
+        assert_eq!(
+            execute(
+                &ex,
+                Dialect::Klang,
+                "a | take 2 | union b | take 3 | project n"
+            )
+            .unwrap(),
+            [
+                bson::bson!({ "n": 1_i64 }),
+                bson::bson!({ "n": 2_i64 }),
+                bson::bson!({ "n": 4_i64 })
+            ]
+        );
+        assert!(execute(&ex, Dialect::Klang, "a | take 2 | union b | take 4").is_err());
+    }
+
+    #[test]
+    fn prepared_statements() {
+        let ex = Executor::new(memory::Datastore::new());
+        execute(
+            &ex,
+            Dialect::Sql,
+            "INSERT INTO users (name, owner) VALUES ('a', 1), ('b', 2)",
+        )
+        .unwrap();
+        let run = |statement: &PreparedStatement, parameters: &[(&str, ScalarValue)]| {
+            let context = ExecutionContext {
+                parameters: parameters
+                    .iter()
+                    .map(|(name, value)| (String::from(*name), value.clone()))
+                    .collect(),
+            };
+            block_on(ex.execute_prepared(statement, &context))
+                .map(|mut results| results.pop().unwrap().records)
+        };
+
+        // The same text is prepared once
+        let text = "users | where name == @name | project owner";
+        let statement = block_on(ex.prepare(text)).unwrap();
+        assert!(Arc::ptr_eq(
+            &statement,
+            &block_on(ex.prepare(text)).unwrap()
+        ));
+
+        // Parameters are bound each time the statement runs
+        assert_eq!(
+            run(
+                &statement,
+                &[("name", ScalarValue::String(String::from("a")))]
+            )
+            .unwrap(),
+            [bson::bson!({ "owner": 1_i64 })]
+        );
+        assert_eq!(
+            run(
+                &statement,
+                &[("name", ScalarValue::String(String::from("b")))]
+            )
+            .unwrap(),
+            [bson::bson!({ "owner": 2_i64 })]
+        );
+        let statement =
+            block_on(ex.prepare("users | where owner == $owner | project name")).unwrap();
+        assert_eq!(
+            run(&statement, &[("owner", ScalarValue::Int(2))]).unwrap(),
+            [bson::bson!({ "name": "b" })]
+        );
+
+        match run(&statement, &[]) {
+            Err(Error::Value(message)) => {
+                assert_eq!(message, "No value was bound to parameter @owner")
             }
+            invalid => panic!("Invalid result: {:?}", invalid),
         }
 
-        txn.commit().await?;
+        // A value that looks like a query is compared as a string
+        let statement = block_on(ex.prepare(text)).unwrap();
+        let injected = ScalarValue::String(String::from("a\" or name != \""));
+        assert_eq!(run(&statement, &[("name", injected)]).unwrap(), []);
+        assert_eq!(execute(&ex, Dialect::Klang, "users").unwrap().len(), 2);
+    }
 
-        Ok(results)
+    #[test]
+    fn set_operations() {
+        let ex = Executor::new(memory::Datastore::new());
+        execute(
+            &ex,
+            Dialect::Sql,
+            "INSERT INTO events_2022 (n) VALUES (1), (2)",
+        )
+        .unwrap();
+        execute(
+            &ex,
+            Dialect::Sql,
+            "INSERT INTO events_2023 (n) VALUES (2), (3)",
+        )
+        .unwrap();
+        execute(&ex, Dialect::Sql, "INSERT INTO other (n) VALUES (4)").unwrap();
+
+        // A wildcard reads every matching collection, the source names the collection read
+        assert_eq!(
+            execute(
+                &ex,
+                Dialect::Klang,
+                "other | union withsource=src events_* | project src, n"
+            )
+            .unwrap(),
+            [
+                bson::bson!({ "src": "other", "n": 4_i64 }),
+                bson::bson!({ "src": "events_2022", "n": 1_i64 }),
+                bson::bson!({ "src": "events_2022", "n": 2_i64 }),
+                bson::bson!({ "src": "events_2023", "n": 2_i64 }),
+                bson::bson!({ "src": "events_2023", "n": 3_i64 }),
+            ]
+        );
+        assert_eq!(
+            execute(&ex, Dialect::Klang, "other | union events_* | project n")
+                .unwrap()
+                .len(),
+            5
+        );
+
+        assert_eq!(
+            execute(
+                &ex,
+                Dialect::Klang,
+                "events_2022 | project n | intersect (events_2023 | project n)"
+            )
+            .unwrap(),
+            [bson::bson!({ "n": 2_i64 })]
+        );
+        assert_eq!(
+            execute(
+                &ex,
+                Dialect::Klang,
+                "events_2022 | project n | except (events_2023 | project n)"
+            )
+            .unwrap(),
+            [bson::bson!({ "n": 1_i64 })]
+        );
+    }
+
+    #[test]
+    fn let_rebinds_names() {
+        let ex = Executor::new(memory::Datastore::new());
+        execute(&ex, Dialect::Sql, "INSERT INTO a (n) VALUES (1), (2), (3)").unwrap();
+
+        // A name bound to a scalar is bound as a scalar, not read as a collection
+        assert_eq!(
+            execute(
+                &ex,
+                Dialect::Klang,
+                "let threshold = 2; let t2 = threshold; a | where n >= t2 | project n"
+            )
+            .unwrap(),
+            [bson::bson!({ "n": 2_i64 }), bson::bson!({ "n": 3_i64 })]
+        );
+
+        // A name bound to a query is bound as the query
+        assert_eq!(
+            execute(
+                &ex,
+                Dialect::Klang,
+                "let big = a | where n > 1; let b2 = big; b2 | where n < 3 | project n"
+            )
+            .unwrap(),
+            [bson::bson!({ "n": 2_i64 })]
+        );
+    }
+
+    #[test]
+    fn analysis_reports_positions() {
+        let schemas =
+            analyze("let n = 1; events | extend s = \"a\" | where s != x and n > 0 | project s")
+                .unwrap();
+        assert_eq!(
+            schemas,
+            [Schema {
+                fields: vec![(String::from("s"), Some(ValueType::String))],
+                ..Schema::default()
+            }]
+        );
+
+        match analyze("events\n| project s = \"a\"\n| where s > 1 or amount > 1") {
+            Err(Error::Parse(message)) => assert_eq!(
+                message,
+                "Can't compare string and int at line 3, column 9\n\
+                 Unknown field amount at line 3, column 18"
+            ),
+            invalid => panic!("Invalid result: {:?}", invalid.map(|_| ())),
+        }
     }
 }
@@ -0,0 +1,325 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+//! The explicit transaction of a session. `begin` opens it, the statements that follow run
+//! in it until `commit` or `rollback`. A `begin` inside of the transaction opens a nested
+//! scope, which its own `commit` keeps and its own `rollback` undoes. Savepoints can only be
+//! rolled back to from the scope that set them.
+
+use kuiperdb_core::error::{Error, Result};
+use kuiperdb_core::storage::kv::{Store, Transaction as _};
+use kuiperdb_core::storage::rocksdb::Datastore;
+use kuiperdb_lang::ast::TransactionStmt;
+
+/// The transaction a session opened with `begin`, kept between its statements.
+pub struct Session<S: Store = Datastore> {
+    txn: Option<S::Transaction>,
+
+    // One entry for every savepoint set on the transaction, in the order they were set
+    savepoints: Vec<Savepoint>,
+}
+
+/// A savepoint set on the transaction of a session
+#[derive(Debug, PartialEq)]
+enum Savepoint {
+    /// Set by `savepoint`, rolled back to by its name
+    Named(String),
+
+    /// Set by a `begin` inside of the transaction, where its nested scope starts
+    Scope,
+
+    /// Set inside of a nested scope that was committed. Savepoints can't be removed without
+    /// rolling back to them, it stays to keep the entries in step with the transaction.
+    Released,
+}
+
+impl<S: Store> Default for Session<S> {
+    fn default() -> Self {
+        Session {
+            txn: None,
+            savepoints: Vec::new(),
+        }
+    }
+}
+
+impl<S: Store> Session<S> {
+    pub fn new() -> Self {
+        Session::default()
+    }
+
+    /// The open transaction, if `begin` was run and not yet ended
+    pub fn transaction(&mut self) -> Option<&mut S::Transaction> {
+        self.txn.as_mut()
+    }
+
+    /// Rolls back the open transaction, along with all of its scopes
+    pub async fn abort(&mut self) -> Result<()> {
+        self.savepoints.clear();
+        match self.txn.take() {
+            Some(mut txn) if !txn.is_completed() => txn.rollback().await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs a transaction statement
+    pub async fn execute(&mut self, ds: &S, statement: &TransactionStmt) -> Result<()> {
+        let txn = match (&mut self.txn, statement) {
+            (None, TransactionStmt::Begin) => {
+                self.txn = Some(ds.transaction(true).await?);
+                return Ok(());
+            }
+            (None, _) => {
+                return Err(Error::Tx(String::from(
+                    "There is no transaction, it must be started with begin",
+                )))
+            }
+            (Some(txn), _) => txn,
+        };
+
+        // Where the innermost scope starts
+        let scope = self
+            .savepoints
+            .iter()
+            .rposition(|savepoint| *savepoint == Savepoint::Scope);
+
+        match (statement, scope) {
+            (TransactionStmt::Begin, _) => {
+                txn.savepoint().await?;
+                self.savepoints.push(Savepoint::Scope);
+            }
+            (TransactionStmt::Commit, Some(start)) => {
+                for savepoint in &mut self.savepoints[start..] {
+                    *savepoint = Savepoint::Released;
+                }
+            }
+            (TransactionStmt::Commit, None) => {
+                self.savepoints.clear();
+                self.txn.take().unwrap().commit().await?;
+            }
+            (TransactionStmt::Rollback, Some(start)) => {
+                Self::rollback_to(txn, &mut self.savepoints, start).await?;
+            }
+            (TransactionStmt::Rollback, None) => self.abort().await?,
+            (TransactionStmt::Savepoint(name), _) => {
+                txn.savepoint().await?;
+                self.savepoints.push(Savepoint::Named(name.clone()));
+            }
+            (TransactionStmt::RollbackTo(name), scope) => {
+                let from = scope.map_or(0, |start| start + 1);
+                let start = self.savepoints[from..]
+                    .iter()
+                    .rposition(|savepoint| matches!(savepoint, Savepoint::Named(n) if n == name))
+                    .map(|position| from + position)
+                    .ok_or_else(|| Error::Tx(format!("There is no savepoint named {}", name)))?;
+
+                // The savepoint is kept, so it can be rolled back to again
+                Self::rollback_to(txn, &mut self.savepoints, start).await?;
+                txn.savepoint().await?;
+                self.savepoints.push(Savepoint::Named(name.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rolls the transaction back to the savepoint at `start`, removing it and every
+    /// savepoint set after it
+    async fn rollback_to(
+        txn: &mut S::Transaction,
+        savepoints: &mut Vec<Savepoint>,
+        start: usize,
+    ) -> Result<()> {
+        while savepoints.len() > start {
+            txn.rollback_to_savepoint().await?;
+            savepoints.pop();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::{Dialect, ExecutionContext, Executor};
+    use futures::executor::block_on;
+    use kuiperdb_core::storage::memory;
+
+    /// Runs klang and SQL statements, one script each, in a session
+    fn run(
+        ex: &Executor<memory::Datastore>,
+        session: &mut Session<memory::Datastore>,
+        scripts: &[(Dialect, &str)],
+    ) -> Result<()> {
+        let context = ExecutionContext {
+            parameters: Vec::new(),
+        };
+
+        for (dialect, text) in scripts {
+            let statement = block_on(ex.prepare_in_session(text, *dialect, session))?;
+            block_on(ex.execute_in_session(&statement, &context, session))?;
+        }
+
+        Ok(())
+    }
+
+    fn names(ex: &Executor<memory::Datastore>) -> Vec<String> {
+        let statement = block_on(ex.prepare("users | project name")).unwrap();
+        let context = ExecutionContext {
+            parameters: Vec::new(),
+        };
+        let mut names: Vec<String> = block_on(ex.execute_prepared(&statement, &context))
+            .unwrap()
+            .remove(0)
+            .records
+            .into_iter()
+            .map(|record| {
+                record
+                    .as_document()
+                    .unwrap()
+                    .get_str("name")
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn insert(name: &str) -> (Dialect, String) {
+        (
+            Dialect::Sql,
+            format!("INSERT INTO users (name) VALUES ('{}')", name),
+        )
+    }
+
+    #[test]
+    fn savepoints_and_scopes() {
+        let ex = Executor::new(memory::Datastore::new());
+        block_on(ex.create_collection(String::from("users"))).unwrap();
+        let mut session = Session::new();
+
+        let (ann, bob, cat, dan) = (insert("ann"), insert("bob"), insert("cat"), insert("dan"));
+        run(
+            &ex,
+            &mut session,
+            &[
+                (Dialect::Klang, "begin"),
+                (ann.0, &ann.1),
+                (Dialect::Klang, "savepoint s1"),
+                (bob.0, &bob.1),
+                (Dialect::Klang, "rollback to s1"),
+                (Dialect::Klang, "begin"),
+                (cat.0, &cat.1),
+                (Dialect::Klang, "commit"),
+                (Dialect::Klang, "begin"),
+                (dan.0, &dan.1),
+                (Dialect::Klang, "rollback"),
+            ],
+        )
+        .unwrap();
+
+        // Nothing is visible before the outer commit
+        assert!(names(&ex).is_empty());
+        assert!(session.transaction().is_some());
+
+        // A savepoint set outside of a nested scope can't be rolled back to from it
+        assert!(run(
+            &ex,
+            &mut session,
+            &[(Dialect::Klang, "begin; rollback to s1")]
+        )
+        .is_err());
+
+        // Rolling back to s1 also undoes the nested scope committed after it
+        run(
+            &ex,
+            &mut session,
+            &[(Dialect::Klang, "rollback; rollback to s1; commit")],
+        )
+        .unwrap();
+        assert!(session.transaction().is_none());
+        assert_eq!(names(&ex), ["ann"]);
+    }
+
+    #[test]
+    fn own_writes_are_visible() {
+        let ex = Executor::new(memory::Datastore::new());
+        block_on(ex.create_collection(String::from("users"))).unwrap();
+        let mut session = Session::new();
+        let context = ExecutionContext {
+            parameters: Vec::new(),
+        };
+        let mut query = |dialect: Dialect, text: &str| {
+            let statement = block_on(ex.prepare_in_session(text, dialect, &mut session)).unwrap();
+            block_on(ex.execute_in_session(&statement, &context, &mut session))
+                .unwrap()
+                .pop()
+                .unwrap()
+                .records
+        };
+        let count =
+            |records: Vec<bson::Bson>| records[0].as_document().unwrap().get_i64("n").unwrap();
+
+        query(Dialect::Klang, "begin");
+        query(
+            Dialect::Sql,
+            "INSERT INTO users (name, age) VALUES ('ann', 30), ('bob', 40)",
+        );
+        assert_eq!(
+            count(query(Dialect::Klang, "users | summarize n = count()")),
+            2
+        );
+
+        query(Dialect::Sql, "UPDATE users SET age = 50 WHERE name = 'ann'");
+        assert_eq!(
+            count(query(
+                Dialect::Klang,
+                "users | where age = 50 | summarize n = count()"
+            )),
+            1
+        );
+
+        query(Dialect::Sql, "DELETE FROM users WHERE name = 'bob'");
+        assert_eq!(
+            count(query(Dialect::Klang, "users | summarize n = count()")),
+            1
+        );
+
+        // Nothing is visible outside of the session before it commits
+        assert!(names(&ex).is_empty());
+        query(Dialect::Klang, "commit");
+        assert_eq!(names(&ex), ["ann"]);
+
+        // Within one script the statements after an insert see it too
+        let statement = block_on(ex.prepare_dialect(
+            "begin; users | summarize n = count(); commit",
+            Dialect::Klang,
+        ))
+        .unwrap();
+        let mut results = block_on(ex.execute_prepared(&statement, &context)).unwrap();
+        assert_eq!(count(results.remove(1).records), 1);
+    }
+
+    #[test]
+    fn scripts_end_their_transactions() {
+        let ex = Executor::new(memory::Datastore::new());
+        block_on(ex.create_collection(String::from("users"))).unwrap();
+        let context = ExecutionContext {
+            parameters: Vec::new(),
+        };
+        let execute = |text: &str| {
+            let statement = block_on(ex.prepare_dialect(text, Dialect::Sql)).unwrap();
+            block_on(ex.execute_prepared(&statement, &context))
+        };
+
+        assert!(execute("BEGIN; INSERT INTO users (name) VALUES ('ann')").is_err());
+        assert!(names(&ex).is_empty());
+        assert!(execute("COMMIT").is_err());
+
+        let results = execute("BEGIN; INSERT INTO users (name) VALUES ('bob'); COMMIT").unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(names(&ex), ["bob"]);
+    }
+}
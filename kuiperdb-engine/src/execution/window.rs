@@ -0,0 +1,265 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+use bson::Bson;
+use kuiperdb_core::error::{Error, Result};
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+
+use crate::plan::Direction;
+use crate::types::expression::Expression;
+use crate::types::hash::canonical_key;
+use crate::types::ordering::compare_values;
+use crate::types::KuiperObject;
+
+/// Sorts documents by their partition values and then by the order, keeping the input order
+/// of documents that compare equal.
+pub fn serialize(
+    documents: Vec<KuiperObject>,
+    partition_by: &[Expression],
+    order_by: &[(Expression, Direction)],
+) -> Result<Vec<KuiperObject>> {
+    let mut keyed = Vec::with_capacity(documents.len());
+
+    for document in documents {
+        let partition = evaluate_all(partition_by, &document)?;
+        let order = order_by
+            .iter()
+            .map(|(expr, _)| expr.evaluate(Some(&document)))
+            .collect::<Result<Vec<_>>>()?;
+        keyed.push((partition, order, document));
+    }
+
+    keyed.sort_by(|(lp, lo, _), (rp, ro, _)| {
+        let partition = lp
+            .iter()
+            .zip(rp)
+            .map(|(l, r)| compare_values(l, r))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal);
+
+        partition.then_with(|| {
+            lo.iter()
+                .zip(ro)
+                .zip(order_by)
+                .map(|((l, r), (_, direction))| match direction {
+                    Direction::Ascending => compare_values(l, r),
+                    Direction::Descending => compare_values(r, l),
+                })
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(Ordering::Equal)
+        })
+    });
+
+    Ok(keyed.into_iter().map(|(_, _, document)| document).collect())
+}
+
+/// Computes window fields over serialized documents. Consecutive documents with the same
+/// partition values form a window, fields are added in order so a field can use the ones
+/// before it.
+pub fn extend_windows(
+    mut documents: Vec<KuiperObject>,
+    partition_by: &[Expression],
+    fields: &[(String, Expression)],
+) -> Result<Vec<KuiperObject>> {
+    let mut start = 0;
+
+    while start < documents.len() {
+        let key = canonical_key(&evaluate_all(partition_by, &documents[start])?);
+        let mut end = start + 1;
+        while end < documents.len()
+            && canonical_key(&evaluate_all(partition_by, &documents[end])?) == key
+        {
+            end += 1;
+        }
+
+        for (name, expr) in fields {
+            extend_partition(&mut documents[start..end], name, expr)?;
+        }
+
+        start = end;
+    }
+
+    Ok(documents)
+}
+
+/// Adds a single field to every document of a partition.
+fn extend_partition(rows: &mut [KuiperObject], name: &str, expr: &Expression) -> Result<()> {
+    // Collect the window calls, they are replaced in the same order for every row below
+    let calls = RefCell::new(Vec::new());
+    expr.clone().transform(
+        &|e| match e {
+            Expression::Call(name, args) if Expression::is_window_function(&name) => {
+                calls.borrow_mut().push((name, args));
+                Ok(Expression::Constant(Bson::Null))
+            }
+            e => Ok(e),
+        },
+        &|e| Ok(e),
+    )?;
+
+    let mut values = Vec::new();
+    for (function, args) in calls.into_inner() {
+        if args.iter().any(Expression::contains_window_function) {
+            return Err(Error::Value(format!(
+                "Window functions can't be nested in {}()",
+                function
+            )));
+        }
+        values.push(evaluate_window_function(rows, &function, &args)?);
+    }
+
+    let mut results = Vec::with_capacity(rows.len());
+    for (i, row) in rows.iter().enumerate() {
+        let call = Cell::new(0);
+        let expr = expr.clone().transform(
+            &|e| match e {
+                Expression::Call(name, _) if Expression::is_window_function(&name) => {
+                    let value = values[call.get()][i].clone();
+                    call.set(call.get() + 1);
+                    Ok(Expression::Constant(value))
+                }
+                e => Ok(e),
+            },
+            &|e| Ok(e),
+        )?;
+
+        results.push(expr.evaluate(Some(row))?);
+    }
+
+    for (row, value) in rows.iter_mut().zip(results) {
+        row.insert(name, value);
+    }
+
+    Ok(())
+}
+
+/// Computes the value of a window function for every row of a partition.
+fn evaluate_window_function(
+    rows: &[KuiperObject],
+    function: &str,
+    args: &[Expression],
+) -> Result<Vec<Bson>> {
+    match function {
+        "row_number" => Ok((1..=rows.len() as i64).map(Bson::Int64).collect()),
+        "prev" | "next" => {
+            let offset = match args.get(1).map(|arg| arg.evaluate(None)).transpose()? {
+                None => 1,
+                Some(Bson::Int32(n)) if n >= 0 => n as usize,
+                Some(Bson::Int64(n)) if n >= 0 => n as usize,
+                Some(n) => {
+                    return Err(Error::Value(format!(
+                        "{}() expects a positive offset, got {}",
+                        function, n
+                    )))
+                }
+            };
+            let default = match args.get(2) {
+                Some(arg) => arg.evaluate(None)?,
+                None => Bson::Null,
+            };
+
+            (0..rows.len())
+                .map(|i| {
+                    let target = match function {
+                        "prev" => i.checked_sub(offset),
+                        _ => Some(i + offset).filter(|target| *target < rows.len()),
+                    };
+                    match target {
+                        Some(target) => args[0].evaluate(Some(&rows[target])),
+                        None => Ok(default.clone()),
+                    }
+                })
+                .collect()
+        }
+        "row_cumsum" => {
+            let mut sum = Bson::Int64(0);
+            let mut values = Vec::with_capacity(rows.len());
+
+            for row in rows {
+                let value = match args[0].evaluate(Some(row))? {
+                    Bson::Int32(i) => Bson::Int64(i as i64),
+                    value => value,
+                };
+                // Null values don't contribute to the sum
+                if value != Bson::Null {
+                    sum = Expression::Add(
+                        Expression::Constant(sum).into(),
+                        Expression::Constant(value).into(),
+                    )
+                    .evaluate(None)?;
+                }
+                values.push(sum.clone());
+            }
+
+            Ok(values)
+        }
+        function => Err(Error::Value(format!(
+            "Unknown window function {}()",
+            function
+        ))),
+    }
+}
+
+fn evaluate_all(exprs: &[Expression], document: &KuiperObject) -> Result<Vec<Bson>> {
+    exprs
+        .iter()
+        .map(|expr| expr.evaluate(Some(document)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    fn field(name: &str) -> Expression {
+        Expression::Field(0, Some((None, String::from(name))))
+    }
+
+    fn call(name: &str, args: Vec<Expression>) -> Expression {
+        Expression::Call(String::from(name), args)
+    }
+
+    #[test]
+    fn window_functions_restart_per_partition() {
+        let documents = vec![
+            doc! { "user": "b", "ts": 2, "amount": 5 },
+            doc! { "user": "a", "ts": 2, "amount": 2 },
+            doc! { "user": "a", "ts": 1, "amount": 1 },
+            doc! { "user": "b", "ts": 1, "amount": 4 },
+        ];
+
+        let documents = serialize(
+            documents,
+            &[field("user")],
+            &[(field("ts"), Direction::Ascending)],
+        )
+        .unwrap();
+
+        let documents = extend_windows(
+            documents,
+            &[field("user")],
+            &[
+                (String::from("rn"), call("row_number", vec![])),
+                (String::from("before"), call("prev", vec![field("amount")])),
+                (
+                    String::from("total"),
+                    call("row_cumsum", vec![field("amount")]),
+                ),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            documents,
+            vec![
+                doc! { "user": "a", "ts": 1, "amount": 1, "rn": 1_i64, "before": Bson::Null, "total": 1_i64 },
+                doc! { "user": "a", "ts": 2, "amount": 2, "rn": 2_i64, "before": 1, "total": 3_i64 },
+                doc! { "user": "b", "ts": 1, "amount": 4, "rn": 1_i64, "before": Bson::Null, "total": 4_i64 },
+                doc! { "user": "b", "ts": 2, "amount": 5, "rn": 2_i64, "before": 4, "total": 9_i64 },
+            ]
+        );
+    }
+}
@@ -3,5 +3,6 @@
 //--------------------------------------------------------------------------
 
 pub mod execution;
+pub mod mql;
 pub mod plan;
 pub mod types;
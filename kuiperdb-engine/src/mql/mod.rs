@@ -0,0 +1,553 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+//! MongoDB style filter, projection and update documents, translated into expressions and plan
+//! nodes.
+
+use bson::{Bson, Document};
+use kuiperdb_core::error::{Error, Result};
+use std::collections::HashSet;
+
+use crate::plan::{CollectionScan, FieldUpdate, Node, QueryPlan};
+use crate::types::expression::Expression;
+
+/// Plans a query that returns the documents of a collection matching the filter, optionally
+/// shaped by a projection document.
+pub fn find(
+    collection: &str,
+    filter_document: &Document,
+    projection_document: Option<&Document>,
+) -> Result<QueryPlan> {
+    let node = scan(collection, filter_document)?;
+
+    Ok(QueryPlan(match projection_document {
+        Some(projection_document) => projection(node, projection_document)?,
+        None => node,
+    }))
+}
+
+/// Plans an update of every document of a collection that matches the filter.
+pub fn update_many(
+    collection: &str,
+    filter_document: &Document,
+    update_document: &Document,
+) -> Result<QueryPlan> {
+    Ok(QueryPlan(Node::Update {
+        source: Box::new(scan(collection, filter_document)?),
+        schema: String::from("default"),
+        collection: collection.to_owned(),
+        updates: update(update_document)?,
+    }))
+}
+
+/// Plans the removal of every document of a collection that matches the filter.
+pub fn delete_many(collection: &str, filter_document: &Document) -> Result<QueryPlan> {
+    Ok(QueryPlan(Node::Delete {
+        source: Box::new(scan(collection, filter_document)?),
+        schema: String::from("default"),
+        collection: collection.to_owned(),
+    }))
+}
+
+fn scan(collection: &str, filter_document: &Document) -> Result<Node> {
+    Ok(Node::CollectionScan(CollectionScan {
+        schema: String::from("default"),
+        collection: collection.to_owned(),
+        alias: None,
+        expr: filter(filter_document)?,
+    }))
+}
+
+/// Translates a filter document such as `{ age: { $gt: 30 }, tags: { $in: ["a"] } }`. An empty
+/// filter matches every document and has no expression.
+pub fn filter(document: &Document) -> Result<Option<Expression>> {
+    let mut conditions = Vec::new();
+
+    for (key, value) in document {
+        conditions.push(match key.as_str() {
+            "$and" => Expression::from_cnf_vec(filters(key, value)?).unwrap_or(truth()),
+            "$or" => Expression::from_dnf_vec(filters(key, value)?).unwrap_or(falsehood()),
+            "$nor" => {
+                let any = Expression::from_dnf_vec(filters(key, value)?).unwrap_or(falsehood());
+                not_holds(any)
+            }
+            key if key.starts_with('$') => {
+                return Err(Error::Parse(format!("Unknown query operator {}", key)))
+            }
+            field => match value {
+                Bson::Document(operators) if is_operator_document(operators)? => {
+                    field_conditions(field, operators)?
+                }
+                value => equal(field, value),
+            },
+        });
+    }
+
+    Ok(Expression::from_cnf_vec(conditions))
+}
+
+/// The filters of an `$and`, `$or` or `$nor`, which must be a non empty array of documents.
+fn filters(operator: &str, value: &Bson) -> Result<Vec<Expression>> {
+    match value {
+        Bson::Array(values) if !values.is_empty() => values
+            .iter()
+            .map(|value| match value {
+                Bson::Document(document) => Ok(filter(document)?.unwrap_or(truth())),
+                value => Err(Error::Parse(format!(
+                    "{} expects documents, got {}",
+                    operator, value
+                ))),
+            })
+            .collect(),
+        value => Err(Error::Parse(format!(
+            "{} expects a non empty array, got {}",
+            operator, value
+        ))),
+    }
+}
+
+/// Whether a document holds query operators, rather than being a value to compare with.
+fn is_operator_document(document: &Document) -> Result<bool> {
+    let operators = document.keys().filter(|key| key.starts_with('$')).count();
+
+    match operators {
+        0 => Ok(false),
+        n if n == document.len() => Ok(true),
+        _ => Err(Error::Parse(String::from(
+            "Query operators can't be mixed with fields",
+        ))),
+    }
+}
+
+/// The conditions of an operator document applied to a single field, all of them must hold.
+fn field_conditions(name: &str, operators: &Document) -> Result<Expression> {
+    let mut conditions = Vec::new();
+
+    for (operator, value) in operators {
+        conditions.push(match operator.as_str() {
+            "$eq" => equal(name, value),
+            "$ne" => not_holds(equal(name, value)),
+            "$gt" => Expression::GreaterThan(field(name).into(), constant(value).into()),
+            "$gte" => Expression::Or(
+                Expression::GreaterThan(field(name).into(), constant(value).into()).into(),
+                Expression::Equal(field(name).into(), constant(value).into()).into(),
+            ),
+            "$lt" => Expression::LessThan(field(name).into(), constant(value).into()),
+            "$lte" => Expression::Or(
+                Expression::LessThan(field(name).into(), constant(value).into()).into(),
+                Expression::Equal(field(name).into(), constant(value).into()).into(),
+            ),
+            "$in" => Expression::In(field(name).into(), values(operator, value)?),
+            "$nin" => not_holds(Expression::In(field(name).into(), values(operator, value)?)),
+            // Missing fields read as null, so existence can't tell them apart
+            "$exists" => match value {
+                Bson::Boolean(true) => not(is_null(name)),
+                Bson::Boolean(false) => is_null(name),
+                value => {
+                    return Err(Error::Parse(format!(
+                        "$exists expects a boolean, got {}",
+                        value
+                    )))
+                }
+            },
+            "$not" => match value {
+                Bson::Document(inner) if is_operator_document(inner)? => {
+                    not_holds(field_conditions(name, inner)?)
+                }
+                value => {
+                    return Err(Error::Parse(format!(
+                        "$not expects an operator document, got {}",
+                        value
+                    )))
+                }
+            },
+            "$regex" => regex(name, value, operators.get("$options"))?,
+            "$options" if operators.contains_key("$regex") => continue,
+            operator => {
+                return Err(Error::Parse(format!(
+                    "Unsupported query operator {}",
+                    operator
+                )))
+            }
+        });
+    }
+
+    Expression::from_cnf_vec(conditions)
+        .ok_or_else(|| Error::Parse(format!("No conditions were given for {}", name)))
+}
+
+/// A `$regex` condition, its `$options` flags are written inline into the pattern.
+fn regex(name: &str, pattern: &Bson, options: Option<&Bson>) -> Result<Expression> {
+    let pattern = match pattern {
+        Bson::String(pattern) => pattern.clone(),
+        Bson::RegularExpression(regex) => format!("(?{}){}", regex.options, regex.pattern),
+        pattern => {
+            return Err(Error::Parse(format!(
+                "$regex expects a string, got {}",
+                pattern
+            )))
+        }
+    };
+
+    let pattern = match options {
+        None => pattern,
+        Some(Bson::String(options)) if options.chars().all(|c| "imsx".contains(c)) => {
+            format!("(?{}){}", options, pattern)
+        }
+        Some(options) => {
+            return Err(Error::Parse(format!(
+                "Unsupported $regex options {}",
+                options
+            )))
+        }
+    };
+
+    Ok(Expression::Call(
+        String::from("matches_regex"),
+        vec![field(name), Expression::Constant(Bson::String(pattern))],
+    ))
+}
+
+fn values(operator: &str, value: &Bson) -> Result<Vec<Expression>> {
+    match value {
+        Bson::Array(values) => Ok(values.iter().map(constant).collect()),
+        value => Err(Error::Parse(format!(
+            "{} expects an array, got {}",
+            operator, value
+        ))),
+    }
+}
+
+/// Equality with a value, where null also matches a missing field.
+fn equal(name: &str, value: &Bson) -> Expression {
+    match value {
+        Bson::Null => is_null(name),
+        value => Expression::Equal(field(name).into(), constant(value).into()),
+    }
+}
+
+/// Negates a condition so it also holds where the condition itself is null, which is how
+/// `$ne`, `$nin`, `$not` and `$nor` treat missing fields.
+fn not_holds(condition: Expression) -> Expression {
+    Expression::Or(
+        Expression::IsNull(condition.clone().into()).into(),
+        not(condition).into(),
+    )
+}
+
+/// Translates a projection document. Fields are either all included, `{ name: 1 }`, where
+/// `_id` is kept unless it is excluded, or all excluded, `{ secret: 0 }`.
+pub fn projection(source: Node, document: &Document) -> Result<Node> {
+    let mut included = Vec::new();
+    let mut excluded = Vec::new();
+
+    for (name, value) in document {
+        let include = match value {
+            Bson::Boolean(include) => *include,
+            Bson::Int32(i) => *i != 0,
+            Bson::Int64(i) => *i != 0,
+            Bson::Double(f) => *f != 0.0,
+            value => {
+                return Err(Error::Parse(format!(
+                    "Projection of {} must be 1 or 0, got {}",
+                    name, value
+                )))
+            }
+        };
+
+        match include {
+            true => included.push(name.clone()),
+            false => excluded.push(name.clone()),
+        }
+    }
+
+    let id_excluded = excluded.iter().any(|name| name == "_id");
+    if !included.is_empty() {
+        if excluded.iter().any(|name| name != "_id") {
+            return Err(Error::Parse(String::from(
+                "Projection can't mix inclusion and exclusion",
+            )));
+        }
+        if !id_excluded && !included.iter().any(|name| name == "_id") {
+            included.insert(0, String::from("_id"));
+        }
+
+        return Ok(Node::Project {
+            source: Box::new(source),
+            fields: included
+                .into_iter()
+                .map(|name| {
+                    let expr = field(&name);
+                    (name, expr)
+                })
+                .collect(),
+        });
+    }
+
+    Ok(match excluded.is_empty() {
+        true => source,
+        false => Node::ProjectAway {
+            source: Box::new(source),
+            fields: excluded,
+        },
+    })
+}
+
+/// Translates an update document using `$set`, `$unset`, `$inc` and `$push`. A field can only
+/// be changed by one operator.
+pub fn update(document: &Document) -> Result<Vec<FieldUpdate>> {
+    let mut updates = Vec::new();
+    let mut changed = HashSet::new();
+
+    if document.is_empty() {
+        return Err(Error::Parse(String::from("The update document is empty")));
+    }
+
+    for (operator, fields) in document {
+        let fields = match fields {
+            Bson::Document(fields) if operator.starts_with('$') => fields,
+            _ => {
+                return Err(Error::Parse(String::from(
+                    "Update documents must use update operators such as $set",
+                )))
+            }
+        };
+
+        for (name, value) in fields {
+            if name == "_id" {
+                return Err(Error::Parse(String::from("_id can't be updated")));
+            }
+            if !changed.insert(name.clone()) {
+                return Err(Error::Parse(format!(
+                    "{} is changed by more than one update operator",
+                    name
+                )));
+            }
+
+            updates.push(match operator.as_str() {
+                "$set" => FieldUpdate::Set(name.clone(), constant(value)),
+                "$unset" => FieldUpdate::Unset(name.clone()),
+                // A missing field is incremented from zero
+                "$inc" => match value {
+                    Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) => FieldUpdate::Set(
+                        name.clone(),
+                        Expression::Add(
+                            coalesce(field(name), Bson::Int64(0)).into(),
+                            constant(value).into(),
+                        ),
+                    ),
+                    value => {
+                        return Err(Error::Parse(format!(
+                            "$inc expects a number, got {}",
+                            value
+                        )))
+                    }
+                },
+                // A missing field is pushed to as an empty array
+                "$push" => {
+                    let values = match value {
+                        Bson::Document(each) if each.contains_key("$each") => {
+                            match each.get("$each") {
+                                Some(Bson::Array(values)) if each.len() == 1 => values.clone(),
+                                _ => {
+                                    return Err(Error::Parse(String::from(
+                                        "$push only supports $each with an array",
+                                    )))
+                                }
+                            }
+                        }
+                        value => vec![value.clone()],
+                    };
+
+                    FieldUpdate::Set(
+                        name.clone(),
+                        Expression::Call(
+                            String::from("array_concat"),
+                            vec![
+                                coalesce(field(name), Bson::Array(Vec::new())),
+                                Expression::Constant(Bson::Array(values)),
+                            ],
+                        ),
+                    )
+                }
+                operator => {
+                    return Err(Error::Parse(format!(
+                        "Unsupported update operator {}",
+                        operator
+                    )))
+                }
+            });
+        }
+    }
+
+    Ok(updates)
+}
+
+fn field(name: &str) -> Expression {
+    Expression::Field(0, Some((None, name.to_owned())))
+}
+
+/// A literal value, integers are widened to 64 bits as they are for the query language.
+fn constant(value: &Bson) -> Expression {
+    Expression::Constant(match value {
+        Bson::Int32(i) => Bson::Int64(*i as i64),
+        value => value.clone(),
+    })
+}
+
+fn coalesce(expr: Expression, default: Bson) -> Expression {
+    Expression::Call(
+        String::from("coalesce"),
+        vec![expr, Expression::Constant(default)],
+    )
+}
+
+fn is_null(name: &str) -> Expression {
+    Expression::IsNull(field(name).into())
+}
+
+fn not(expr: Expression) -> Expression {
+    Expression::Not(expr.into())
+}
+
+fn truth() -> Expression {
+    Expression::Constant(Bson::Boolean(true))
+}
+
+fn falsehood() -> Expression {
+    Expression::Constant(Bson::Boolean(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::Executor;
+    use bson::doc;
+
+    fn matches(filter_document: Document, document: Document) -> bool {
+        let expr = filter(&filter_document).unwrap().unwrap();
+        expr.evaluate(Some(&document)).unwrap() == Bson::Boolean(true)
+    }
+
+    #[test]
+    fn comparison_operators() {
+        let filter_document =
+            doc! { "age": { "$gt": 30, "$lte": 40 }, "tags": { "$in": ["a", "b"] } };
+
+        assert!(matches(
+            filter_document.clone(),
+            doc! { "age": 35_i64, "tags": "a" }
+        ));
+        assert!(!matches(
+            filter_document.clone(),
+            doc! { "age": 41_i64, "tags": "a" }
+        ));
+        assert!(!matches(
+            filter_document,
+            doc! { "age": 35_i64, "tags": "c" }
+        ));
+    }
+
+    #[test]
+    fn negations_match_missing_fields() {
+        assert!(matches(doc! { "a": { "$ne": 1 } }, doc! { "b": 1_i64 }));
+        assert!(matches(doc! { "a": { "$nin": [1, 2] } }, doc! {}));
+        assert!(matches(
+            doc! { "a": { "$not": { "$gt": 5 } } },
+            doc! { "a": 3_i64 }
+        ));
+        assert!(!matches(doc! { "a": { "$ne": 1 } }, doc! { "a": 1_i64 }));
+        assert!(matches(doc! { "a": null }, doc! {}));
+        assert!(matches(doc! { "a": { "$exists": false } }, doc! {}));
+    }
+
+    #[test]
+    fn logical_operators_and_regex() {
+        let filter_document = doc! {
+            "$or": [{ "name": { "$regex": "^jo", "$options": "i" } }, { "age": { "$lt": 18 } }],
+            "$nor": [{ "banned": true }],
+        };
+
+        assert!(matches(
+            filter_document.clone(),
+            doc! { "name": "John", "age": 30_i64 }
+        ));
+        assert!(matches(
+            filter_document.clone(),
+            doc! { "name": "Ann", "age": 12_i64 }
+        ));
+        assert!(!matches(
+            filter_document.clone(),
+            doc! { "name": "Ann", "age": 30_i64 }
+        ));
+        assert!(!matches(
+            filter_document,
+            doc! { "name": "John", "age": 30_i64, "banned": true }
+        ));
+    }
+
+    #[test]
+    fn invalid_filters() {
+        assert!(filter(&doc! { "$where": "x" }).is_err());
+        assert!(filter(&doc! { "a": { "$gt": 1, "b": 2 } }).is_err());
+        assert!(filter(&doc! { "$or": [] }).is_err());
+        assert!(filter(&doc! {}).unwrap().is_none());
+    }
+
+    #[test]
+    fn projections() {
+        let scan = || {
+            Node::CollectionScan(CollectionScan {
+                schema: String::from("default"),
+                collection: String::from("users"),
+                alias: None,
+                expr: None,
+            })
+        };
+
+        match projection(scan(), &doc! { "name": 1 }).unwrap() {
+            Node::Project { fields, .. } => assert_eq!(
+                fields
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>(),
+                vec!["_id", "name"]
+            ),
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+        assert!(matches!(
+            projection(scan(), &doc! { "secret": 0, "_id": false }).unwrap(),
+            Node::ProjectAway { fields, .. } if fields == vec!["secret", "_id"]
+        ));
+        assert!(projection(scan(), &doc! { "name": 1, "secret": 0 }).is_err());
+    }
+
+    #[test]
+    fn update_operators() {
+        let updates = update(&doc! {
+            "$set": { "name": "ann" },
+            "$unset": { "temp": "" },
+            "$inc": { "visits": 1, "score": 1.5 },
+            "$push": { "tags": "new", "log": { "$each": [1, 2] } },
+        })
+        .unwrap();
+
+        let document = doc! { "_id": 1, "temp": true, "score": 1_i64, "tags": ["old"] };
+        assert_eq!(
+            <Executor>::apply_updates(&document, &updates).unwrap(),
+            doc! {
+                "_id": 1,
+                "score": 2.5,
+                "tags": ["old", "new"],
+                "name": "ann",
+                "visits": 1_i64,
+                "log": [1, 2],
+            }
+        );
+
+        assert!(update(&doc! { "name": "ann" }).is_err());
+        assert!(update(&doc! { "$set": { "_id": 1 } }).is_err());
+        assert!(update(&doc! { "$set": { "a": 1 }, "$inc": { "a": 1 } }).is_err());
+    }
+}
@@ -2,8 +2,12 @@
 // (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
 //--------------------------------------------------------------------------
 
+use kuiperdb_core::error::{Error, Result};
+use kuiperdb_lang::ast::{self, ScalarValue};
+use kuiperdb_lang::catalog;
 use serde_derive::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     fmt::{self, Display},
     str::FromStr,
 };
@@ -13,7 +17,7 @@ use crate::types::expression::Expression;
 /// https://github.com/erikgrinaker/toydb/blob/master/src/sql/plan/mod.rs
 
 /// A query plan
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct QueryPlan(pub Node);
 
 impl Display for QueryPlan {
@@ -22,27 +26,786 @@ impl Display for QueryPlan {
     }
 }
 
+/// Names bound by `let` statements, visible to the statements that follow them
+#[derive(Default)]
+struct Bindings {
+    scalars: HashMap<String, Expression>,
+    tables: HashMap<String, Node>,
+}
+
+impl Bindings {
+    /// Builds an expression from the AST, planning its subqueries and resolving bindings
+    fn lower(&self, node: &ast::Node) -> Result<Expression> {
+        let expr = Expression::from_ast_with(node, &|query| QueryPlan::build_query(query, self))?;
+        self.resolve(expr)
+    }
+
+    /// Replaces references to scalar bindings with their bound expression. A tabular binding
+    /// on its own in an `in` list is read as a subquery.
+    fn resolve(&self, expr: Expression) -> Result<Expression> {
+        expr.transform(
+            &|e| match e {
+                Expression::Field(_, Some((None, name))) if self.scalars.contains_key(&name) => {
+                    Ok(self.scalars[&name].clone())
+                }
+                Expression::In(expr, values) => match values.as_slice() {
+                    [Expression::Field(_, Some((None, name)))]
+                        if self.tables.contains_key(name) =>
+                    {
+                        Ok(Expression::InSubquery(
+                            expr,
+                            self.tables[name].clone().into(),
+                        ))
+                    }
+                    _ => Ok(Expression::In(expr, values)),
+                },
+                e => Ok(e),
+            },
+            &|e| Ok(e),
+        )
+    }
+}
+
 impl QueryPlan {
-    pub fn from_ast(query_expr: &kuiperdb_lang::ast::QueryExpr) -> QueryPlan {
-        let collection_scan = Node::CollectionScan(CollectionScan {
-            alias: query_expr.table.alias.clone(),
-            collection: query_expr.table.value.clone(),
-            expr: Option::None,
+    pub fn from_ast(query_expr: &ast::QueryExpr) -> Result<QueryPlan> {
+        Self::from_ast_with(query_expr, &Bindings::default())
+    }
+
+    /// Plans every statement of a script. `let` statements produce no plan of their own, they
+    /// bind a name for the statements that follow them.
+    pub fn from_script(statements: &[ast::Node]) -> Result<Vec<QueryPlan>> {
+        let mut bindings = Bindings::default();
+        let mut plans = Vec::new();
+
+        for statement in statements {
+            match statement {
+                ast::Node::Query(query) => plans.push(Self::from_ast_with(query, &bindings)?),
+                ast::Node::Insert(insert) => plans.push(QueryPlan(Node::Insert {
+                    schema: String::from("default"),
+                    collection: insert.table.value.clone(),
+                    rows: insert
+                        .rows
+                        .iter()
+                        .map(|row| Self::build_assignments(row, &bindings))
+                        .collect::<Result<_>>()?,
+                })),
+                ast::Node::Update(update) => {
+                    let fields = Self::build_assignments(&update.assignments, &bindings)?;
+                    if fields.iter().any(|(name, _)| name == "_id") {
+                        return Err(Error::Parse(String::from("_id can't be updated")));
+                    }
+
+                    plans.push(QueryPlan(Node::Update {
+                        source: Box::new(Self::build_target(
+                            &update.table,
+                            update.filter.as_deref(),
+                            &bindings,
+                        )?),
+                        schema: String::from("default"),
+                        collection: update.table.value.clone(),
+                        updates: fields
+                            .into_iter()
+                            .map(|(name, expr)| FieldUpdate::Set(name, expr))
+                            .collect(),
+                    }))
+                }
+                ast::Node::Delete(delete) => plans.push(QueryPlan(Node::Delete {
+                    source: Box::new(Self::build_target(
+                        &delete.table,
+                        delete.filter.as_deref(),
+                        &bindings,
+                    )?),
+                    schema: String::from("default"),
+                    collection: delete.table.value.clone(),
+                })),
+                ast::Node::Transaction(statement) => {
+                    plans.push(QueryPlan(Node::Transaction(statement.clone())))
+                }
+                ast::Node::Let(binding) => match &*binding.value {
+                    // A name bound to a scalar is parsed as a query, it binds the scalar
+                    ast::Node::Query(query)
+                        if query
+                            .bare_name()
+                            .is_some_and(|name| bindings.scalars.contains_key(name)) =>
+                    {
+                        let expr = bindings.scalars[query.bare_name().unwrap()].clone();
+                        bindings.tables.remove(&binding.name);
+                        bindings.scalars.insert(binding.name.clone(), expr);
+                    }
+                    ast::Node::Query(query) => {
+                        let plan = Self::from_ast_with(query, &bindings)?;
+                        bindings.scalars.remove(&binding.name);
+                        bindings.tables.insert(binding.name.clone(), plan.0);
+                    }
+                    value => {
+                        let expr = bindings.lower(value)?;
+                        bindings.tables.remove(&binding.name);
+                        bindings.scalars.insert(binding.name.clone(), expr);
+                    }
+                },
+                _ => return Err(Error::Parse(String::from("Only statements can be planned"))),
+            }
+        }
+
+        Ok(plans)
+    }
+
+    fn build_scan(table: &ast::IdentityValue) -> Node {
+        Node::CollectionScan(CollectionScan {
+            alias: table.alias.clone(),
+            collection: table.value.clone(),
+            expr: None,
             schema: String::from_str("default").unwrap(),
-        });
+        })
+    }
+
+    /// The documents of a collection an update or delete applies to.
+    fn build_target(
+        table: &ast::IdentityValue,
+        filter: Option<&ast::Node>,
+        bindings: &Bindings,
+    ) -> Result<Node> {
+        let scan = Self::build_scan(table);
+
+        Ok(match filter {
+            Some(filter) => Self::build_filter(scan, bindings.lower(filter)?),
+            None => scan,
+        })
+    }
 
-        return QueryPlan(collection_scan);
+    fn from_ast_with(query_expr: &ast::QueryExpr, bindings: &Bindings) -> Result<QueryPlan> {
+        Ok(QueryPlan(Self::build_query(query_expr, bindings)?))
+    }
+
+    /// Lowers the source of a query and then each of its piped operators, in order.
+    fn build_query(query_expr: &ast::QueryExpr, bindings: &Bindings) -> Result<Node> {
+        let mut node = match &query_expr.source {
+            ast::QuerySource::Table(table) => match bindings.tables.get(&table.value) {
+                // A tabular binding is expanded in place
+                Some(node) => {
+                    let mut node = node.clone();
+                    if let (Node::CollectionScan(scan), Some(alias)) = (&mut node, &table.alias) {
+                        scan.alias = Some(alias.clone());
+                    }
+                    node
+                }
+                None => Self::build_scan(table),
+            },
+            ast::QuerySource::Union(union) => Self::build_union(None, union, bindings)?,
+        };
+
+        for operator in &query_expr.operators {
+            node = match operator {
+                ast::QueryOperator::Where(filter) => {
+                    Self::build_filter(node, bindings.lower(filter)?)
+                }
+                ast::QueryOperator::Project(assignments) => Node::Project {
+                    source: Box::new(node),
+                    fields: Self::build_assignments(assignments, bindings)?,
+                },
+                ast::QueryOperator::Distinct(fields) => Node::Distinct {
+                    source: Box::new(node),
+                    fields: fields.iter().map(|field| field.value.clone()).collect(),
+                },
+                ast::QueryOperator::Serialize(window) => Node::Serialize {
+                    source: Box::new(node),
+                    partition_by: window
+                        .partition_by
+                        .iter()
+                        .map(|field| Expression::Field(0, Some((None, field.value.clone()))))
+                        .collect(),
+                    order_by: window
+                        .order_by
+                        .iter()
+                        .map(|order| {
+                            let field =
+                                Expression::Field(0, Some((None, order.identity.value.clone())));
+                            let direction = match order.direction {
+                                ast::OrderByDirection::Asc => Direction::Ascending,
+                                ast::OrderByDirection::Desc => Direction::Descending,
+                            };
+                            (field, direction)
+                        })
+                        .collect(),
+                },
+                ast::QueryOperator::Extend(assignments) => {
+                    let fields = Self::build_assignments(assignments, bindings)?;
+
+                    if fields
+                        .iter()
+                        .any(|(_, expr)| expr.contains_window_function())
+                    {
+                        let partition_by = match node.window_partition() {
+                            Some(partition_by) => partition_by.to_vec(),
+                            None => {
+                                return Err(Error::Parse(String::from(
+                                    "Window functions require serialized input, add a `serialize` first",
+                                )))
+                            }
+                        };
+
+                        Node::Window {
+                            source: Box::new(node),
+                            partition_by,
+                            fields,
+                        }
+                    } else {
+                        Node::Extend {
+                            source: Box::new(node),
+                            fields,
+                        }
+                    }
+                }
+                ast::QueryOperator::Union(union) => Self::build_union(Some(node), union, bindings)?,
+                ast::QueryOperator::Intersect(operands) => Node::Intersect {
+                    left: Box::new(node),
+                    right: Box::new(Self::build_operands(operands, bindings)?),
+                },
+                ast::QueryOperator::Except(operands) => Node::Except {
+                    left: Box::new(node),
+                    right: Box::new(Self::build_operands(operands, bindings)?),
+                },
+                ast::QueryOperator::Take(limit) => Node::Take {
+                    source: Box::new(node),
+                    limit: *limit,
+                },
+                ast::QueryOperator::ForUpdate => {
+                    let (schema, collection) = match node.source_scan() {
+                        Some(scan) if !scan.is_pattern() => {
+                            (scan.schema.clone(), scan.collection.clone())
+                        }
+                        _ => {
+                            return Err(Error::Parse(String::from(
+                                "for update needs the documents of a single collection",
+                            )))
+                        }
+                    };
+
+                    Node::Lock {
+                        source: Box::new(node),
+                        schema,
+                        collection,
+                    }
+                }
+                ast::QueryOperator::Summarize(summarize) => {
+                    let aggregates = Self::build_assignments(&summarize.aggregates, bindings)?;
+                    for (name, expr) in &aggregates {
+                        Self::check_aggregate(name, expr)?;
+                    }
+
+                    Node::Aggregate {
+                        source: Box::new(node),
+                        group_by: Self::build_assignments(&summarize.by, bindings)?,
+                        aggregates,
+                    }
+                }
+            };
+        }
+
+        Ok(node)
+    }
+
+    /// Applies a `where` predicate. Conditions that test membership in a subquery are planned
+    /// as semi-joins, the remaining ones as a filter that runs before them.
+    fn build_filter(node: Node, predicate: Expression) -> Node {
+        let mut joins = Vec::new();
+        let mut filters = Vec::new();
+
+        for conjunct in predicate.into_cnf_vec() {
+            match conjunct {
+                Expression::InSubquery(expr, subquery) => joins.push((*expr, subquery, false)),
+                Expression::Not(inner) => match *inner {
+                    Expression::InSubquery(expr, subquery) => joins.push((*expr, subquery, true)),
+                    inner => filters.push(Expression::Not(inner.into())),
+                },
+                conjunct => filters.push(conjunct),
+            }
+        }
+
+        let mut node = match Expression::from_cnf_vec(filters) {
+            Some(predicate) => node.filter(predicate),
+            None => node,
+        };
+
+        for (expr, subquery, negated) in joins {
+            node = Node::SemiJoin {
+                source: Box::new(node),
+                expr,
+                subquery,
+                negated,
+            };
+        }
+
+        node
+    }
+
+    /// Every field of a `summarize` must aggregate, and may only read the documents from inside
+    /// its aggregate functions.
+    fn check_aggregate(name: &str, expr: &Expression) -> Result<()> {
+        let outside = expr.clone().transform(
+            &|e| match e {
+                Expression::Call(function, args) if Expression::is_aggregate_function(&function) => {
+                    if args.iter().any(|arg| {
+                        arg.contains(&|e| {
+                            matches!(e, Expression::Call(f, _) if Expression::is_aggregate_function(f))
+                        })
+                    }) {
+                        return Err(Error::Parse(format!(
+                            "Aggregate functions can't be nested in {}()",
+                            function
+                        )));
+                    }
+                    Ok(Expression::Constant(bson::Bson::Null))
+                }
+                e => Ok(e),
+            },
+            &|e| Ok(e),
+        )?;
+        // Aggregate calls were replaced, so an unchanged expression doesn't aggregate
+        if &outside == expr || outside.contains(&|e| matches!(e, Expression::Field(_, _))) {
+            return Err(Error::Parse(format!(
+                "{} must be computed by aggregate functions such as count() or sum(x)",
+                name
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn build_assignments(
+        assignments: &[ast::Assignment],
+        bindings: &Bindings,
+    ) -> Result<Vec<(String, Expression)>> {
+        assignments
+            .iter()
+            .map(|assignment| Ok((assignment.name.clone(), bindings.lower(&assignment.value)?)))
+            .collect()
+    }
+
+    fn build_union(
+        input: Option<Node>,
+        union: &ast::UnionExpr,
+        bindings: &Bindings,
+    ) -> Result<Node> {
+        let mut inputs: Vec<Node> = input.into_iter().collect();
+        for operand in &union.operands {
+            inputs.push(Self::build_query(operand, bindings)?);
+        }
+
+        Ok(Node::Union {
+            inputs,
+            with_source: union.with_source.clone(),
+        })
+    }
+
+    /// Several operands on the right of a set operator are read as their union
+    fn build_operands(operands: &[ast::QueryExpr], bindings: &Bindings) -> Result<Node> {
+        let mut inputs = operands
+            .iter()
+            .map(|operand| Self::build_query(operand, bindings))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(match inputs.len() {
+            1 => inputs.remove(0),
+            _ => Node::Union {
+                inputs,
+                with_source: None,
+            },
+        })
     }
 }
 
 /// A plan node
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Node {
     CollectionScan(CollectionScan),
+    Filter {
+        source: Box<Node>,
+        predicate: Expression,
+    },
+    /// Keeps the documents whose expression is (or with `negated`, isn't) equal to the first
+    /// field of a document of the subquery. Subqueries can't refer to the outer documents, so
+    /// the subquery runs once.
+    SemiJoin {
+        source: Box<Node>,
+        expr: Expression,
+        subquery: Box<Node>,
+        negated: bool,
+    },
+    /// Replaces every document with one holding only the given fields
+    Project {
+        source: Box<Node>,
+        fields: Vec<(String, Expression)>,
+    },
+    /// Removes the given fields from every document
+    ProjectAway {
+        source: Box<Node>,
+        fields: Vec<String>,
+    },
+    /// Unique combinations of the fields, each output document only holds those fields
+    Distinct {
+        source: Box<Node>,
+        fields: Vec<String>,
+    },
+    /// Sorts the documents by their partition and then by the order, giving window functions
+    /// a stable row order
+    Serialize {
+        source: Box<Node>,
+        partition_by: Vec<Expression>,
+        order_by: Vec<(Expression, Direction)>,
+    },
+    /// Adds computed fields to every document
+    Extend {
+        source: Box<Node>,
+        fields: Vec<(String, Expression)>,
+    },
+    /// Adds fields computed by window functions over the partitions of serialized input
+    Window {
+        source: Box<Node>,
+        partition_by: Vec<Expression>,
+        fields: Vec<(String, Expression)>,
+    },
+    /// Concatenates the output of every input. Collection names may contain `*` wildcards,
+    /// these are expanded against the catalog at execution time.
+    Union {
+        inputs: Vec<Node>,
+        with_source: Option<String>,
+    },
+    /// Documents of `left` that are equal to a document of `right`
+    Intersect {
+        left: Box<Node>,
+        right: Box<Node>,
+    },
+    /// Documents of `left` that are not equal to any document of `right`
+    Except {
+        left: Box<Node>,
+        right: Box<Node>,
+    },
+    /// The first documents of the input
+    Take {
+        source: Box<Node>,
+        limit: u64,
+    },
+    /// Groups the documents by the values of `group_by` and computes the aggregate fields over
+    /// every group, without grouping fields the whole input is one group
+    Aggregate {
+        source: Box<Node>,
+        group_by: Vec<(String, Expression)>,
+        aggregates: Vec<(String, Expression)>,
+    },
+    /// Locks the documents of the source, found by their `_id`, until the transaction of the
+    /// statement completes
+    Lock {
+        source: Box<Node>,
+        schema: String,
+        collection: String,
+    },
+    /// Adds a document for every row of fields
+    Insert {
+        schema: String,
+        collection: String,
+        rows: Vec<Vec<(String, Expression)>>,
+    },
+    /// Changes the fields of every document of the source and writes them back to the
+    /// collection
+    Update {
+        source: Box<Node>,
+        schema: String,
+        collection: String,
+        updates: Vec<FieldUpdate>,
+    },
+    /// Removes every document of the source from the collection
+    Delete {
+        source: Box<Node>,
+        schema: String,
+        collection: String,
+    },
+    /// Begins, ends or marks a point in the transaction of a session, the statements that
+    /// follow run in it
+    Transaction(ast::TransactionStmt),
+}
+
+impl Node {
+    /// Applies a predicate to the node. Predicates are pushed into a collection scan when
+    /// possible, where every `where` clause must hold so they are joined together with AND.
+    pub fn filter(self, predicate: Expression) -> Node {
+        match self {
+            Node::CollectionScan(mut scan) if !scan.is_pattern() => {
+                scan.expr =
+                    Expression::from_cnf_vec(scan.expr.into_iter().chain([predicate]).collect());
+                Node::CollectionScan(scan)
+            }
+            Node::Filter {
+                source,
+                predicate: existing,
+            } => Node::Filter {
+                source,
+                predicate: Expression::And(existing.into(), predicate.into()),
+            },
+            node => Node::Filter {
+                source: Box::new(node),
+                predicate,
+            },
+        }
+    }
+
+    /// Applies a transformation to every expression in the plan tree.
+    pub fn transform_expressions<F>(self, f: &F) -> Result<Node>
+    where
+        F: Fn(Expression) -> Result<Expression>,
+    {
+        Ok(match self {
+            Node::CollectionScan(mut scan) => {
+                scan.expr = scan.expr.map(f).transpose()?;
+                Node::CollectionScan(scan)
+            }
+            Node::Filter { source, predicate } => Node::Filter {
+                source: Box::new(source.transform_expressions(f)?),
+                predicate: f(predicate)?,
+            },
+            Node::SemiJoin {
+                source,
+                expr,
+                subquery,
+                negated,
+            } => Node::SemiJoin {
+                source: Box::new(source.transform_expressions(f)?),
+                expr: f(expr)?,
+                subquery: Box::new(subquery.transform_expressions(f)?),
+                negated,
+            },
+            Node::Project { source, fields } => Node::Project {
+                source: Box::new(source.transform_expressions(f)?),
+                fields: Self::transform_fields(fields, f)?,
+            },
+            Node::ProjectAway { source, fields } => Node::ProjectAway {
+                source: Box::new(source.transform_expressions(f)?),
+                fields,
+            },
+            Node::Distinct { source, fields } => Node::Distinct {
+                source: Box::new(source.transform_expressions(f)?),
+                fields,
+            },
+            Node::Serialize {
+                source,
+                partition_by,
+                order_by,
+            } => Node::Serialize {
+                source: Box::new(source.transform_expressions(f)?),
+                partition_by: partition_by.into_iter().map(f).collect::<Result<_>>()?,
+                order_by: order_by
+                    .into_iter()
+                    .map(|(expr, direction)| Ok((f(expr)?, direction)))
+                    .collect::<Result<_>>()?,
+            },
+            Node::Extend { source, fields } => Node::Extend {
+                source: Box::new(source.transform_expressions(f)?),
+                fields: Self::transform_fields(fields, f)?,
+            },
+            Node::Window {
+                source,
+                partition_by,
+                fields,
+            } => Node::Window {
+                source: Box::new(source.transform_expressions(f)?),
+                partition_by: partition_by.into_iter().map(f).collect::<Result<_>>()?,
+                fields: Self::transform_fields(fields, f)?,
+            },
+            Node::Union {
+                inputs,
+                with_source,
+            } => Node::Union {
+                inputs: inputs
+                    .into_iter()
+                    .map(|input| input.transform_expressions(f))
+                    .collect::<Result<_>>()?,
+                with_source,
+            },
+            Node::Intersect { left, right } => Node::Intersect {
+                left: Box::new(left.transform_expressions(f)?),
+                right: Box::new(right.transform_expressions(f)?),
+            },
+            Node::Except { left, right } => Node::Except {
+                left: Box::new(left.transform_expressions(f)?),
+                right: Box::new(right.transform_expressions(f)?),
+            },
+            Node::Take { source, limit } => Node::Take {
+                source: Box::new(source.transform_expressions(f)?),
+                limit,
+            },
+            Node::Aggregate {
+                source,
+                group_by,
+                aggregates,
+            } => Node::Aggregate {
+                source: Box::new(source.transform_expressions(f)?),
+                group_by: Self::transform_fields(group_by, f)?,
+                aggregates: Self::transform_fields(aggregates, f)?,
+            },
+            Node::Lock {
+                source,
+                schema,
+                collection,
+            } => Node::Lock {
+                source: Box::new(source.transform_expressions(f)?),
+                schema,
+                collection,
+            },
+            Node::Insert {
+                schema,
+                collection,
+                rows,
+            } => Node::Insert {
+                schema,
+                collection,
+                rows: rows
+                    .into_iter()
+                    .map(|row| Self::transform_fields(row, f))
+                    .collect::<Result<_>>()?,
+            },
+            Node::Update {
+                source,
+                schema,
+                collection,
+                updates,
+            } => Node::Update {
+                source: Box::new(source.transform_expressions(f)?),
+                schema,
+                collection,
+                updates: updates
+                    .into_iter()
+                    .map(|update| {
+                        Ok(match update {
+                            FieldUpdate::Set(name, expr) => FieldUpdate::Set(name, f(expr)?),
+                            FieldUpdate::Unset(name) => FieldUpdate::Unset(name),
+                        })
+                    })
+                    .collect::<Result<_>>()?,
+            },
+            Node::Delete {
+                source,
+                schema,
+                collection,
+            } => Node::Delete {
+                source: Box::new(source.transform_expressions(f)?),
+                schema,
+                collection,
+            },
+            Node::Transaction(statement) => Node::Transaction(statement),
+        })
+    }
+
+    fn transform_fields<F>(
+        fields: Vec<(String, Expression)>,
+        f: &F,
+    ) -> Result<Vec<(String, Expression)>>
+    where
+        F: Fn(Expression) -> Result<Expression>,
+    {
+        fields
+            .into_iter()
+            .map(|(name, expr)| Ok((name, f(expr)?)))
+            .collect()
+    }
+
+    /// The partitioning of the node's output if it is serialized, operators that keep the row
+    /// order keep the input serialized.
+    pub fn window_partition(&self) -> Option<&[Expression]> {
+        match self {
+            Node::Serialize { partition_by, .. } => Some(partition_by),
+            Node::Filter { source, .. }
+            | Node::SemiJoin { source, .. }
+            | Node::Project { source, .. }
+            | Node::ProjectAway { source, .. }
+            | Node::Extend { source, .. }
+            | Node::Window { source, .. }
+            | Node::Take { source, .. }
+            | Node::Lock { source, .. } => source.window_partition(),
+            _ => None,
+        }
+    }
+
+    /// Whether the node writes to the collections or locks their documents, rather than only
+    /// reading them, and so must run in a writable transaction.
+    pub fn writes(&self) -> bool {
+        match self {
+            Node::Insert { .. } | Node::Update { .. } | Node::Delete { .. } | Node::Lock { .. } => {
+                true
+            }
+            Node::CollectionScan(_) | Node::Transaction(_) => false,
+            Node::Filter { source, .. }
+            | Node::Project { source, .. }
+            | Node::ProjectAway { source, .. }
+            | Node::Distinct { source, .. }
+            | Node::Serialize { source, .. }
+            | Node::Extend { source, .. }
+            | Node::Window { source, .. }
+            | Node::Take { source, .. }
+            | Node::Aggregate { source, .. } => source.writes(),
+            Node::SemiJoin {
+                source, subquery, ..
+            } => source.writes() || subquery.writes(),
+            Node::Intersect { left, right } | Node::Except { left, right } => {
+                left.writes() || right.writes()
+            }
+            Node::Union { inputs, .. } => inputs.iter().any(Node::writes),
+        }
+    }
+
+    /// Binds parameter values to every expression in the plan tree.
+    pub fn bind(self, parameters: &[(String, ScalarValue)]) -> Result<Node> {
+        self.transform_expressions(&|expr| expr.bind(parameters))
+    }
+
+    /// The name of the collection the node's documents originate from, used by `withsource`.
+    pub fn source_name(&self) -> Option<&str> {
+        self.source_scan().map(|scan| scan.collection.as_str())
+    }
+
+    /// The scan of the collection the node's documents originate from.
+    pub fn source_scan(&self) -> Option<&CollectionScan> {
+        match self {
+            Node::CollectionScan(scan) => Some(scan),
+            Node::Filter { source, .. }
+            | Node::SemiJoin { source, .. }
+            | Node::Project { source, .. }
+            | Node::ProjectAway { source, .. }
+            | Node::Serialize { source, .. }
+            | Node::Extend { source, .. }
+            | Node::Window { source, .. }
+            | Node::Take { source, .. }
+            | Node::Lock { source, .. } => source.source_scan(),
+            Node::Intersect { left, .. } | Node::Except { left, .. } => left.source_scan(),
+            Node::Distinct { .. }
+            | Node::Union { .. }
+            | Node::Aggregate { .. }
+            | Node::Insert { .. }
+            | Node::Update { .. }
+            | Node::Delete { .. }
+            | Node::Transaction(_) => None,
+        }
+    }
+}
+
+/// A change an update makes to a field of a document
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FieldUpdate {
+    /// Sets the field to the value of the expression, computed from the document as it was
+    /// before the update
+    Set(String, Expression),
+    /// Removes the field
+    Unset(String),
+}
+
+/// A sort direction
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Direction {
+    Ascending,
+    Descending,
 }
 
 /// A query plan
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct CollectionScan {
     // pub source: Box<Node>,
     pub schema: String,
@@ -51,9 +814,122 @@ pub struct CollectionScan {
     pub expr: Option<Expression>,
 }
 
+impl CollectionScan {
+    /// Whether the collection name is a `*` wildcard pattern rather than a single collection
+    pub fn is_pattern(&self) -> bool {
+        self.collection.contains('*')
+    }
+
+    /// Matches a collection name against the `*` wildcard pattern of this scan
+    pub fn matches(&self, collection: &str) -> bool {
+        catalog::matches_pattern(&self.collection, collection)
+    }
+}
+
 impl Display for Node {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // write!(f, "{}", self.format("".into(), true, true))
         write!(f, ">> todo >>")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuiperdb_lang::parser::parse_query;
+
+    fn plan(source: &str) -> Node {
+        let ast = parse_query(source).unwrap();
+        QueryPlan::from_script(&ast).unwrap().pop().unwrap().0
+    }
+
+    #[test]
+    fn in_subquery_is_semi_join() {
+        let node = plan(
+            r#"orders | where customerId !in (customers | where tier == "gold" | project _id) and total > 10"#,
+        );
+
+        match node {
+            Node::SemiJoin {
+                source,
+                subquery,
+                negated,
+                ..
+            } => {
+                assert!(negated);
+                assert!(matches!(
+                    *source,
+                    Node::CollectionScan(CollectionScan { expr: Some(_), .. })
+                ));
+                assert!(matches!(*subquery, Node::Project { .. }));
+            }
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+    }
+
+    #[test]
+    fn tabular_binding_in_list_is_subquery() {
+        let node =
+            plan("let gold = customers | where tier == 1; orders | where id in (gold) or id == 0");
+
+        match node {
+            Node::CollectionScan(CollectionScan {
+                expr: Some(Expression::Or(lhs, _)),
+                ..
+            }) => assert!(matches!(*lhs, Expression::InSubquery(_, _))),
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+    }
+
+    #[test]
+    fn summarize_requires_aggregates() {
+        let plan = |source: &str| QueryPlan::from_script(&parse_query(source).unwrap());
+
+        assert!(plan("events | summarize n = count(), top = max(ts) + 1 by user").is_ok());
+        assert!(plan("events | summarize n = count() + amount").is_err());
+        assert!(plan("events | summarize n = sum(count())").is_err());
+        assert!(plan("events | summarize user").is_err());
+    }
+
+    #[test]
+    fn for_update_locks_collection() {
+        let node = plan("accounts | where owner == 1 | for update | project balance");
+        assert!(node.writes());
+
+        match node {
+            Node::Project { source, .. } => assert!(matches!(
+                *source,
+                Node::Lock { ref collection, ref source, .. }
+                    if collection == "accounts"
+                        && matches!(**source, Node::CollectionScan(CollectionScan { expr: Some(_), .. }))
+            )),
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+
+        assert!(!plan("accounts | where owner == 1").writes());
+        assert!(QueryPlan::from_script(&parse_query("union a*, b | for update").unwrap()).is_err());
+    }
+
+    #[test]
+    fn sql_plans_like_klang() {
+        let sql = kuiperdb_lang::sql::parse_sql(
+            "SELECT user, COUNT(*) AS n FROM events WHERE amount > 1 GROUP BY user LIMIT 5",
+        )
+        .unwrap();
+
+        match QueryPlan::from_script(&sql).unwrap().pop().unwrap().0 {
+            Node::Take { source, limit } => {
+                assert_eq!(limit, 5);
+                match *source {
+                    Node::Project { source, .. } => assert!(matches!(
+                        *source,
+                        Node::Aggregate { ref source, .. }
+                            if matches!(**source, Node::CollectionScan(CollectionScan { expr: Some(_), .. }))
+                    )),
+                    invalid => panic!("Invalid node: {:?}", invalid),
+                }
+            }
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+    }
+}
@@ -2,14 +2,19 @@
 // (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
 //--------------------------------------------------------------------------
 
-use bson::Bson;
+use bson::oid::ObjectId;
+use bson::{Bson, DateTime, Document, Uuid};
 use kuiperdb_core::error::{Error, Result};
+use kuiperdb_lang::ast::{self, BinaryOp, ScalarValue, UnaryOp};
+use kuiperdb_lang::functions::{self, FunctionKind};
 
 use regex::Regex;
 use serde_derive::{Deserialize, Serialize};
 use std::fmt::{self, Display};
 use std::mem::replace;
 
+use crate::plan::Node;
+use crate::types::hash::canonical_key;
 use crate::types::KuiperObject;
 
 /// An expression, made up of constants and operations
@@ -18,6 +23,16 @@ pub enum Expression {
     // Values
     Constant(Bson),
     Field(usize, Option<(Option<String>, String)>),
+    Parameter(String),
+
+    // Built-in function calls, e.g. now() or ago(1d)
+    Call(String, Vec<Expression>),
+
+    // Membership in a list of values, or in the first field of a subquery's documents
+    In(Box<Expression>, Vec<Expression>),
+    InSubquery(Box<Expression>, Box<Node>),
+    // The first field of the first document of a subquery
+    ScalarSubquery(Box<Node>),
 
     // Logical operations
     And(Box<Expression>, Box<Expression>),
@@ -46,6 +61,157 @@ pub enum Expression {
 }
 
 impl Expression {
+    /// Builds an expression from a language AST node, which must not contain subqueries.
+    pub fn from_ast(node: &ast::Node) -> Result<Self> {
+        Self::from_ast_with(node, &|_| {
+            Err(Error::Parse(String::from(
+                "A subquery can't be used in this expression",
+            )))
+        })
+    }
+
+    /// Builds an expression from a language AST node, planning subqueries with the closure.
+    pub fn from_ast_with<F>(node: &ast::Node, subquery: &F) -> Result<Self>
+    where
+        F: Fn(&ast::QueryExpr) -> Result<Node>,
+    {
+        use Expression::*;
+        Ok(match node {
+            ast::Node::Scalar(scalar) => Constant(Self::constant_from_ast(scalar)?),
+            ast::Node::Identity(identity) => Field(0, Some((None, identity.value.clone()))),
+            ast::Node::Parameter(name) => Parameter(name.clone()),
+            ast::Node::UnaryExpr(unary) => {
+                let expr = Self::from_ast_with(&unary.expr, subquery)?.into();
+                match unary.op {
+                    UnaryOp::Negate => Negate(expr),
+                    UnaryOp::Not => Not(expr),
+                }
+            }
+            ast::Node::BinaryExpr(binary) => {
+                let lhs: Box<Expression> = Self::from_ast_with(&binary.left, subquery)?.into();
+                let rhs: Box<Expression> = Self::from_ast_with(&binary.right, subquery)?.into();
+                match binary.op {
+                    BinaryOp::And => And(lhs, rhs),
+                    BinaryOp::Or => Or(lhs, rhs),
+                    BinaryOp::Eq => Equal(lhs, rhs),
+                    BinaryOp::Ne => Not(Equal(lhs, rhs).into()),
+                    BinaryOp::Gt => GreaterThan(lhs, rhs),
+                    BinaryOp::GtEq => Or(
+                        GreaterThan(lhs.clone(), rhs.clone()).into(),
+                        Equal(lhs, rhs).into(),
+                    ),
+                    BinaryOp::Lt => LessThan(lhs, rhs),
+                    BinaryOp::LtEq => Or(
+                        LessThan(lhs.clone(), rhs.clone()).into(),
+                        Equal(lhs, rhs).into(),
+                    ),
+                    BinaryOp::Add => Add(lhs, rhs),
+                    BinaryOp::Subtract => Subtract(lhs, rhs),
+                    BinaryOp::Multiply => Multiply(lhs, rhs),
+                    BinaryOp::Divide => Divide(lhs, rhs),
+                    BinaryOp::Modulo => Modulo(lhs, rhs),
+                    BinaryOp::Exponentiate => Exponentiate(lhs, rhs),
+                    BinaryOp::Like => Like(lhs, rhs),
+                }
+            }
+            ast::Node::FunctionCall(call) => {
+                let args = call
+                    .args
+                    .iter()
+                    .map(|arg| Self::from_ast_with(arg, subquery))
+                    .collect::<Result<Vec<_>>>()?;
+
+                // Unknown functions and wrong arities are rejected while planning rather
+                // than for every evaluated row
+                let function = match functions::lookup(&call.name) {
+                    Some(function) => function,
+                    None => return Err(Error::Parse(format!("Unknown function {}()", call.name))),
+                };
+                if args.len() < function.min_args || args.len() > function.max_args {
+                    return Err(Error::Parse(format!(
+                        "{}() expects {} argument(s), got {}",
+                        call.name,
+                        function.arity(),
+                        args.len()
+                    )));
+                }
+
+                match call.name.as_str() {
+                    "isnull" => IsNull(args.into_iter().next().unwrap().into()),
+                    "isnotnull" => Not(IsNull(args.into_iter().next().unwrap().into()).into()),
+                    name => Call(name.to_owned(), args),
+                }
+            }
+            ast::Node::In(in_expr) => {
+                let expr: Box<Expression> = Self::from_ast_with(&in_expr.expr, subquery)?.into();
+                let in_expr_lowered = match &in_expr.set {
+                    ast::InSet::List(values) => In(
+                        expr,
+                        values
+                            .iter()
+                            .map(|value| Self::from_ast_with(value, subquery))
+                            .collect::<Result<_>>()?,
+                    ),
+                    ast::InSet::Query(query) => InSubquery(expr, subquery(query)?.into()),
+                };
+                match in_expr.negated {
+                    true => Not(in_expr_lowered.into()),
+                    false => in_expr_lowered,
+                }
+            }
+            ast::Node::ToScalar(query) => ScalarSubquery(subquery(query)?.into()),
+            ast::Node::Query(_)
+            | ast::Node::Let(_)
+            | ast::Node::Insert(_)
+            | ast::Node::Update(_)
+            | ast::Node::Delete(_)
+            | ast::Node::Transaction(_) => {
+                return Err(Error::Parse(String::from(
+                    "A statement can't be used as an expression",
+                )))
+            }
+        })
+    }
+
+    /// Converts a language literal into its native BSON value.
+    pub fn constant_from_ast(scalar: &ScalarValue) -> Result<Bson> {
+        Ok(match scalar {
+            ScalarValue::Int(i) => Bson::Int64(*i),
+            ScalarValue::Decimal(f) => Bson::Double(*f),
+            ScalarValue::String(s) => Bson::String(s.clone()),
+            ScalarValue::Boolean(b) => Bson::Boolean(*b),
+            ScalarValue::Date(d) => Bson::DateTime(
+                DateTime::parse_rfc3339_str(d)
+                    .map_err(|e| Error::Value(format!("Invalid datetime {}: {}", d, e)))?,
+            ),
+            // Timespans are stored as a number of milliseconds so they can be added to dates
+            ScalarValue::Timespan(ms) => Bson::Int64(*ms),
+            ScalarValue::ObjectId(id) => Bson::ObjectId(
+                ObjectId::parse_str(id)
+                    .map_err(|e| Error::Value(format!("Invalid objectid {}: {}", id, e)))?,
+            ),
+            ScalarValue::Uuid(id) => Bson::from(
+                Uuid::parse_str(id)
+                    .map_err(|e| Error::Value(format!("Invalid uuid {}: {}", id, e)))?,
+            ),
+            ScalarValue::Array(values) => Bson::Array(
+                values
+                    .iter()
+                    .map(Self::constant_from_ast)
+                    .collect::<Result<_>>()?,
+            ),
+            ScalarValue::Object(fields) => {
+                let mut document = Document::new();
+                for (key, value) in fields {
+                    document.insert(key.clone(), Self::constant_from_ast(value)?);
+                }
+                Bson::Document(document)
+            }
+            ScalarValue::Null => Bson::Null,
+            ScalarValue::Undefined => Bson::Undefined,
+        })
+    }
+
     /// Evaluates an expression to a value, given an environment
     pub fn evaluate(&self, row: Option<&KuiperObject>) -> Result<Bson> {
         use bson::Bson::*;
@@ -56,6 +222,90 @@ impl Expression {
                 let field = &f.as_ref().unwrap().1;
                 row.and_then(|row| row.get(field)).unwrap_or(&Null).into()
             }
+            Self::Parameter(name) => {
+                return Err(Error::Value(format!(
+                    "No value was bound to parameter @{}",
+                    name
+                )))
+            }
+            Self::Call(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.evaluate(row))
+                    .collect::<Result<Vec<_>>>()?;
+
+                match (name.as_str(), args.as_slice()) {
+                    ("now", []) => DateTime(bson::DateTime::now()),
+                    ("ago", [Int64(ms)]) => DateTime(bson::DateTime::from_millis(
+                        bson::DateTime::now().timestamp_millis() - ms,
+                    )),
+                    ("ago", [Null]) => Null,
+                    ("coalesce", args) => args
+                        .iter()
+                        .find(|arg| **arg != Null)
+                        .cloned()
+                        .unwrap_or(Null),
+                    ("array_concat", args) if args.contains(&Null) => Null,
+                    ("array_concat", args) => {
+                        let mut values = Vec::new();
+                        for arg in args {
+                            match arg {
+                                Array(array) => values.extend(array.iter().cloned()),
+                                arg => {
+                                    return Err(Error::Value(format!(
+                                        "array_concat() expects arrays, got {}",
+                                        arg
+                                    )))
+                                }
+                            }
+                        }
+                        Array(values)
+                    }
+                    ("matches_regex", [String(value), String(pattern)]) => {
+                        Boolean(Regex::new(pattern)?.is_match(value))
+                    }
+                    ("matches_regex", [Null, _]) | ("matches_regex", [_, Null]) => Null,
+                    (name, _) if Self::is_window_function(name) => {
+                        return Err(Error::Value(format!(
+                            "{}() can only be used in extend after serialize",
+                            name
+                        )))
+                    }
+                    (name, _) if Self::is_aggregate_function(name) => {
+                        return Err(Error::Value(format!(
+                            "{}() can only be used in summarize",
+                            name
+                        )))
+                    }
+                    (name, args) => {
+                        return Err(Error::Value(format!(
+                            "Can't call {}() with {:?}",
+                            name, args
+                        )))
+                    }
+                }
+            }
+
+            Self::In(expr, values) => match expr.evaluate(row)? {
+                Null => Null,
+                value => {
+                    // Numbers match regardless of their BSON type, as in `distinct`
+                    let key = canonical_key([&value]);
+                    let mut found = false;
+                    for candidate in values {
+                        if canonical_key([&candidate.evaluate(row)?]) == key {
+                            found = true;
+                            break;
+                        }
+                    }
+                    Boolean(found)
+                }
+            },
+            Self::InSubquery(_, _) | Self::ScalarSubquery(_) => {
+                return Err(Error::Value(
+                    "Subqueries must be evaluated before the expression".into(),
+                ))
+            }
 
             // Logical operations
             Self::And(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
@@ -86,6 +336,11 @@ impl Expression {
             #[allow(clippy::float_cmp)] // Up to the user if they want to compare or not
             Self::Equal(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
                 (Boolean(lhs), Boolean(rhs)) => Boolean(lhs == rhs),
+                (DateTime(lhs), DateTime(rhs)) => Boolean(lhs == rhs),
+                (ObjectId(lhs), ObjectId(rhs)) => Boolean(lhs == rhs),
+                (Binary(lhs), Binary(rhs)) => Boolean(lhs == rhs),
+                (Array(lhs), Array(rhs)) => Boolean(lhs == rhs),
+                (Document(lhs), Document(rhs)) => Boolean(lhs == rhs),
                 (Int64(lhs), Int64(rhs)) => Boolean(lhs == rhs),
                 (Int64(lhs), Double(rhs)) => Boolean(lhs as f64 == rhs),
                 (Double(lhs), Int64(rhs)) => Boolean(lhs == rhs as f64),
@@ -99,6 +354,8 @@ impl Expression {
             Self::GreaterThan(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
                 #[allow(clippy::bool_comparison)]
                 (Boolean(lhs), Boolean(rhs)) => Boolean(lhs > rhs),
+                (DateTime(lhs), DateTime(rhs)) => Boolean(lhs > rhs),
+                (ObjectId(lhs), ObjectId(rhs)) => Boolean(lhs > rhs),
                 (Int64(lhs), Int64(rhs)) => Boolean(lhs > rhs),
                 (Int64(lhs), Double(rhs)) => Boolean(lhs as f64 > rhs),
                 (Double(lhs), Int64(rhs)) => Boolean(lhs > rhs as f64),
@@ -112,6 +369,8 @@ impl Expression {
             Self::LessThan(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
                 #[allow(clippy::bool_comparison)]
                 (Boolean(lhs), Boolean(rhs)) => Boolean(lhs < rhs),
+                (DateTime(lhs), DateTime(rhs)) => Boolean(lhs < rhs),
+                (ObjectId(lhs), ObjectId(rhs)) => Boolean(lhs < rhs),
                 (Int64(lhs), Int64(rhs)) => Boolean(lhs < rhs),
                 (Int64(lhs), Double(rhs)) => Boolean((lhs as f64) < rhs),
                 (Double(lhs), Int64(rhs)) => Boolean(lhs < rhs as f64),
@@ -135,6 +394,14 @@ impl Expression {
                 ),
                 (Int64(lhs), Double(rhs)) => Double(lhs as f64 + rhs),
                 (Int64(_), Null) => Null,
+                (DateTime(lhs), Int64(rhs)) | (Int64(rhs), DateTime(lhs)) => {
+                    DateTime(bson::DateTime::from_millis(
+                        lhs.timestamp_millis()
+                            .checked_add(rhs)
+                            .ok_or_else(|| Error::Value("Datetime overflow".into()))?,
+                    ))
+                }
+                (DateTime(_), Null) | (Null, DateTime(_)) => Null,
                 (Double(lhs), Double(rhs)) => Double(lhs + rhs),
                 (Double(lhs), Int64(rhs)) => Double(lhs + rhs as f64),
                 (Double(_), Null) => Null,
@@ -248,6 +515,18 @@ impl Expression {
                 ),
                 (Int64(lhs), Double(rhs)) => Double(lhs as f64 - rhs),
                 (Int64(_), Null) => Null,
+                (DateTime(lhs), Int64(rhs)) => DateTime(bson::DateTime::from_millis(
+                    lhs.timestamp_millis()
+                        .checked_sub(rhs)
+                        .ok_or_else(|| Error::Value("Datetime overflow".into()))?,
+                )),
+                // The difference between two datetimes is a timespan in milliseconds
+                (DateTime(lhs), DateTime(rhs)) => Int64(
+                    lhs.timestamp_millis()
+                        .checked_sub(rhs.timestamp_millis())
+                        .ok_or_else(|| Error::Value("Integer overflow".into()))?,
+                ),
+                (DateTime(_), Null) | (Null, DateTime(_)) => Null,
                 (Double(lhs), Int64(rhs)) => Double(lhs - rhs as f64),
                 (Double(lhs), Double(rhs)) => Double(lhs - rhs),
                 (Double(_), Null) => Null,
@@ -279,6 +558,22 @@ impl Expression {
         })
     }
 
+    /// Whether the function is computed over the rows of a serialized window, rather than from
+    /// a single row.
+    pub fn is_window_function(name: &str) -> bool {
+        matches!(functions::lookup(name), Some(function) if function.kind == FunctionKind::Window)
+    }
+
+    /// Whether the function is computed over the documents of a `summarize` group.
+    pub fn is_aggregate_function(name: &str) -> bool {
+        matches!(functions::lookup(name), Some(function) if function.kind == FunctionKind::Aggregate)
+    }
+
+    /// Whether the expression calls a window function.
+    pub fn contains_window_function(&self) -> bool {
+        self.contains(&|e| matches!(e, Self::Call(name, _) if Self::is_window_function(name)))
+    }
+
     /// Walks the expression tree while calling a closure. Returns true as soon as the closure
     /// returns true. This is the inverse of walk().
     pub fn contains<F: Fn(&Expression) -> bool>(&self, visitor: &F) -> bool {
@@ -324,7 +619,26 @@ impl Expression {
             | Self::Negate(expr)
             | Self::Not(expr) => Self::replace_with(expr, |e| e.transform(before, after))?,
 
-            Self::Constant(_) | Self::Field(_, _) => {}
+            Self::Call(_, args) => {
+                for arg in args.iter_mut() {
+                    Self::replace_with(arg, |e| e.transform(before, after))?;
+                }
+            }
+
+            Self::In(expr, values) => {
+                Self::replace_with(expr, |e| e.transform(before, after))?;
+                for value in values.iter_mut() {
+                    Self::replace_with(value, |e| e.transform(before, after))?;
+                }
+            }
+
+            // Subqueries are planned on their own, only the tested expression is transformed
+            Self::InSubquery(expr, _) => Self::replace_with(expr, |e| e.transform(before, after))?,
+
+            Self::Constant(_)
+            | Self::Field(_, _)
+            | Self::Parameter(_)
+            | Self::ScalarSubquery(_) => {}
         };
         after(self)
     }
@@ -352,10 +666,44 @@ impl Expression {
                 | Self::Negate(expr)
                 | Self::Not(expr) => expr.walk(visitor),
 
-                Self::Constant(_) | Self::Field(_, _) => true,
+                Self::Call(_, args) => args.iter().all(|arg| arg.walk(visitor)),
+
+                Self::In(expr, values) => {
+                    expr.walk(visitor) && values.iter().all(|value| value.walk(visitor))
+                }
+                Self::InSubquery(expr, _) => expr.walk(visitor),
+
+                Self::Constant(_)
+                | Self::Field(_, _)
+                | Self::Parameter(_)
+                | Self::ScalarSubquery(_) => true,
             }
     }
 
+    /// Replaces every parameter placeholder with its bound value. Fails if a parameter in the
+    /// expression has no value.
+    pub fn bind(self, parameters: &[(String, ScalarValue)]) -> Result<Self> {
+        self.transform(
+            &|e| match e {
+                Self::InSubquery(expr, node) => {
+                    Ok(Self::InSubquery(expr, node.bind(parameters)?.into()))
+                }
+                Self::ScalarSubquery(node) => {
+                    Ok(Self::ScalarSubquery(node.bind(parameters)?.into()))
+                }
+                Self::Parameter(name) => match parameters.iter().find(|(n, _)| *n == name) {
+                    Some((_, value)) => Ok(Self::Constant(Self::constant_from_ast(value)?)),
+                    None => Err(Error::Value(format!(
+                        "No value was bound to parameter @{}",
+                        name
+                    ))),
+                },
+                e => Ok(e),
+            },
+            &|e| Ok(e),
+        )
+    }
+
     /// Converts the expression into its negation normal form. This pushes NOT operators into the
     /// tree using De Morgan's laws, such that they never occur before other logical operators.
     pub fn into_nnf(self) -> Self {
@@ -548,6 +896,26 @@ impl Display for Expression {
             Self::Field(i, None) => format!("#{}", i),
             Self::Field(_, Some((None, name))) => name.to_string(),
             Self::Field(_, Some((Some(table), name))) => format!("{}.{}", table, name),
+            Self::Parameter(name) => format!("@{}", name),
+            Self::In(expr, values) => format!(
+                "{} IN ({})",
+                expr,
+                values
+                    .iter()
+                    .map(|value| value.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Self::InSubquery(expr, _) => format!("{} IN (subquery)", expr),
+            Self::ScalarSubquery(_) => String::from("toscalar(subquery)"),
+            Self::Call(name, args) => format!(
+                "{}({})",
+                name,
+                args.iter()
+                    .map(|arg| arg.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
 
             Self::And(lhs, rhs) => format!("{} AND {}", lhs, rhs),
             Self::Or(lhs, rhs) => format!("{} OR {}", lhs, rhs),
@@ -0,0 +1,103 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+use bson::{Bson, Document};
+
+// Type tags of the canonical encoding, numbers share a tag regardless of their BSON type
+const TAG_NULL: u8 = 0x00;
+const TAG_UNDEFINED: u8 = 0x01;
+const TAG_BOOLEAN: u8 = 0x02;
+const TAG_INTEGER: u8 = 0x03;
+const TAG_FLOAT: u8 = 0x04;
+const TAG_STRING: u8 = 0x05;
+const TAG_ARRAY: u8 = 0x06;
+const TAG_DOCUMENT: u8 = 0x07;
+const TAG_DATETIME: u8 = 0x08;
+const TAG_OBJECTID: u8 = 0x09;
+const TAG_OTHER: u8 = 0xff;
+
+/// Appends a canonical byte encoding of a BSON value to `buf`. Values that compare equal
+/// encode to the same bytes, so the encoding can be hashed or compared directly: `Int32(1)`,
+/// `Int64(1)` and `Double(1.0)` all encode the same.
+pub fn write_canonical(value: &Bson, buf: &mut Vec<u8>) {
+    match value {
+        Bson::Null => buf.push(TAG_NULL),
+        Bson::Undefined => buf.push(TAG_UNDEFINED),
+        Bson::Boolean(b) => buf.extend([TAG_BOOLEAN, *b as u8]),
+        Bson::Int32(i) => write_integer(*i as i64, buf),
+        Bson::Int64(i) => write_integer(*i, buf),
+        Bson::Double(f) => {
+            // Integral doubles are encoded as integers, -0.0 included
+            if f.fract() == 0.0 && *f >= i64::MIN as f64 && *f < i64::MAX as f64 {
+                write_integer(*f as i64, buf);
+            } else {
+                let f = if f.is_nan() { f64::NAN } else { *f };
+                buf.push(TAG_FLOAT);
+                buf.extend(f.to_bits().to_be_bytes());
+            }
+        }
+        Bson::String(s) => {
+            buf.push(TAG_STRING);
+            write_bytes(s.as_bytes(), buf);
+        }
+        Bson::Array(values) => {
+            buf.push(TAG_ARRAY);
+            buf.extend((values.len() as u32).to_be_bytes());
+            for value in values {
+                write_canonical(value, buf);
+            }
+        }
+        Bson::Document(document) => write_document(document, buf),
+        Bson::DateTime(d) => {
+            buf.push(TAG_DATETIME);
+            buf.extend(d.timestamp_millis().to_be_bytes());
+        }
+        Bson::ObjectId(id) => {
+            buf.push(TAG_OBJECTID);
+            buf.extend(id.bytes());
+        }
+        other => {
+            // The remaining types have no cross-type equality, their raw BSON is canonical
+            buf.push(TAG_OTHER);
+            buf.push(other.element_type() as u8);
+            let raw = bson::to_vec(&bson::doc! { "v": other.clone() }).unwrap_or_default();
+            write_bytes(&raw, buf);
+        }
+    }
+}
+
+/// Returns the canonical encoding of a sequence of values, see [`write_canonical`].
+pub fn canonical_key<'a, I: IntoIterator<Item = &'a Bson>>(values: I) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for value in values {
+        write_canonical(value, &mut buf);
+    }
+    buf
+}
+
+/// Returns the canonical encoding of a whole document, see [`write_canonical`].
+pub fn document_key(document: &Document) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_document(document, &mut buf);
+    buf
+}
+
+fn write_document(document: &Document, buf: &mut Vec<u8>) {
+    buf.push(TAG_DOCUMENT);
+    buf.extend((document.len() as u32).to_be_bytes());
+    for (key, value) in document {
+        write_bytes(key.as_bytes(), buf);
+        write_canonical(value, buf);
+    }
+}
+
+fn write_integer(i: i64, buf: &mut Vec<u8>) {
+    buf.push(TAG_INTEGER);
+    buf.extend(i.to_be_bytes());
+}
+
+fn write_bytes(bytes: &[u8], buf: &mut Vec<u8>) {
+    buf.extend((bytes.len() as u32).to_be_bytes());
+    buf.extend(bytes);
+}
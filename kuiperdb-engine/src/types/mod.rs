@@ -6,6 +6,8 @@ use bson::Document;
 use kuiperdb_core::error::Result;
 
 pub mod expression;
+pub mod hash;
+pub mod ordering;
 
 /// A kuiper object (which is just a bson document)
 pub type KuiperObject = Document;
@@ -0,0 +1,68 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+use bson::Bson;
+use std::cmp::Ordering;
+
+/// The rank of each type when values of different types are compared, numbers of any BSON
+/// type share a rank and are compared by value.
+fn type_rank(value: &Bson) -> u8 {
+    match value {
+        Bson::MinKey => 0,
+        Bson::Undefined | Bson::Null => 1,
+        Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) | Bson::Decimal128(_) => 2,
+        Bson::String(_) | Bson::Symbol(_) => 3,
+        Bson::Document(_) => 4,
+        Bson::Array(_) => 5,
+        Bson::Binary(_) => 6,
+        Bson::ObjectId(_) => 7,
+        Bson::Boolean(_) => 8,
+        Bson::DateTime(_) => 9,
+        Bson::Timestamp(_) => 10,
+        Bson::RegularExpression(_) => 11,
+        Bson::MaxKey => 255,
+        _ => 12,
+    }
+}
+
+/// Compares two values for sorting, using a total order across all BSON types.
+pub fn compare_values(lhs: &Bson, rhs: &Bson) -> Ordering {
+    use Bson::*;
+    match (lhs, rhs) {
+        (Int32(l), Int32(r)) => l.cmp(r),
+        (Int64(l), Int64(r)) => l.cmp(r),
+        (Int32(l), Int64(r)) => (*l as i64).cmp(r),
+        (Int64(l), Int32(r)) => l.cmp(&(*r as i64)),
+        (Int32(_) | Int64(_) | Double(_), Int32(_) | Int64(_) | Double(_)) => {
+            as_f64(lhs).total_cmp(&as_f64(rhs))
+        }
+        (String(l), String(r)) => l.cmp(r),
+        (Boolean(l), Boolean(r)) => l.cmp(r),
+        (DateTime(l), DateTime(r)) => l.cmp(r),
+        (ObjectId(l), ObjectId(r)) => l.bytes().cmp(&r.bytes()),
+        (Timestamp(l), Timestamp(r)) => (l.time, l.increment).cmp(&(r.time, r.increment)),
+        (Array(l), Array(r)) => l
+            .iter()
+            .zip(r.iter())
+            .map(|(l, r)| compare_values(l, r))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| l.len().cmp(&r.len())),
+        (Document(l), Document(r)) => l
+            .iter()
+            .zip(r.iter())
+            .map(|((lk, lv), (rk, rv))| lk.cmp(rk).then_with(|| compare_values(lv, rv)))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| l.len().cmp(&r.len())),
+        (lhs, rhs) => type_rank(lhs).cmp(&type_rank(rhs)),
+    }
+}
+
+fn as_f64(value: &Bson) -> f64 {
+    match value {
+        Bson::Int32(i) => *i as f64,
+        Bson::Int64(i) => *i as f64,
+        Bson::Double(f) => *f,
+        _ => f64::NAN,
+    }
+}
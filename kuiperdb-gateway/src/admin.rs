@@ -0,0 +1,168 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+//! The admin commands, sent in place of a query and starting with a dot. They work on the
+//! datastore directly:
+//!
+//! - `.backup` takes a backup, `.backup list` lists the kept backups, and `.backup verify <id>`
+//!   checks the files of one
+//! - `.checkpoint <path>` writes a checkpoint of the database to a new directory
+//! - `.restore [<id>] <path>` restores a backup, the latest one when no id is given, into a new
+//!   directory the database can then be started from
+//!
+//! The commands only run when the config enables them, and the directories they write are
+//! resolved under its checkpoint directory. The copies are taken on the blocking thread pool,
+//! queries keep being served meanwhile.
+
+use std::path::{Component, Path, PathBuf};
+
+use kuiperdb_core::error::Error;
+use kuiperdb_core::storage::rocksdb::Datastore;
+use serde_json::{json, Value};
+
+/// Runs an admin command, given without its leading dot, and returns its result records
+pub async fn run(ds: &Datastore, command: &str) -> Result<Vec<Value>, Error> {
+    if !ds.config().admin_commands {
+        return Err(Error::Config(String::from(
+            "Admin commands are disabled, set admin_commands in the config to enable them",
+        )));
+    }
+
+    let ds = ds.clone();
+    let command = command.to_owned();
+
+    tokio::task::spawn_blocking(move || execute(&ds, &command))
+        .await
+        .map_err(|e| Error::Io(e.to_string()))?
+}
+
+fn execute(ds: &Datastore, command: &str) -> Result<Vec<Value>, Error> {
+    let words: Vec<&str> = command.split_whitespace().collect();
+
+    match words.as_slice() {
+        ["backup"] => Ok(vec![record(ds.backup()?)]),
+        ["backup", "list"] => Ok(ds.backups()?.into_iter().map(record).collect()),
+        ["backup", "verify", id] => {
+            let id = parse_id(id)?;
+            ds.verify_backup(id)?;
+            Ok(vec![json!({ "id": id, "verified": true })])
+        }
+        ["checkpoint", path] => {
+            let path = resolve(ds, path)?;
+            ds.checkpoint(&path)?;
+            Ok(vec![json!({ "path": path })])
+        }
+        ["restore", path] => restore(ds, None, path),
+        ["restore", id, path] => restore(ds, Some(parse_id(id)?), path),
+        _ => Err(Error::Parse(format!("Unknown admin command .{}", command))),
+    }
+}
+
+/// Restores into a new directory only, so the directory of the running database, or of any
+/// other, can't be overwritten
+fn restore(ds: &Datastore, id: Option<u32>, path: &str) -> Result<Vec<Value>, Error> {
+    let path = resolve(ds, path)?;
+    if path.exists() {
+        return Err(Error::Io(format!(
+            "Can't restore into {}, the directory already exists",
+            path.display()
+        )));
+    }
+
+    Datastore::restore(ds.config(), id, &path)?;
+    Ok(vec![json!({ "id": id, "path": path })])
+}
+
+/// Resolves the directory a command writes to under the checkpoint directory of the config,
+/// creating the directories above it. The path is relative to the checkpoint directory, or
+/// starts with it, and can't leave it with `..`.
+fn resolve(ds: &Datastore, path: &str) -> Result<PathBuf, Error> {
+    let root = Path::new(&ds.config().checkpoint_path);
+    let relative = Path::new(path)
+        .strip_prefix(root)
+        .unwrap_or(Path::new(path));
+
+    let mut components = relative.components().peekable();
+    if components.peek().is_none() || !components.all(|c| matches!(c, Component::Normal(_))) {
+        return Err(Error::Io(format!(
+            "{} isn't a directory under {}",
+            path,
+            root.display()
+        )));
+    }
+
+    let path = root.join(relative);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| Error::Io(e.to_string()))?;
+    }
+
+    Ok(path)
+}
+
+fn parse_id(id: &str) -> Result<u32, Error> {
+    id.parse()
+        .map_err(|_| Error::Parse(format!("Invalid backup id {}", id)))
+}
+
+fn record(info: impl serde::Serialize) -> Value {
+    serde_json::to_value(info).unwrap_or(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::oid::ObjectId;
+    use kuiperdb_core::storage::rocksdb::DatastoreConfig;
+
+    #[tokio::test]
+    async fn commands() {
+        let dir = std::env::temp_dir().join(format!("kuiperdb-admin-{}", ObjectId::new()));
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_owned();
+        let ds = Datastore::open(DatastoreConfig {
+            backup_path: path("backups"),
+            admin_commands: true,
+            checkpoint_path: path("copies"),
+            ..DatastoreConfig::with_path(&path("db"))
+        })
+        .await
+        .unwrap();
+
+        let backup = run(&ds, "backup").await.unwrap();
+        let id = backup[0]["id"].as_u64().unwrap();
+        assert_eq!(run(&ds, "backup list").await.unwrap(), backup);
+        assert!(run(&ds, &format!("backup verify {}", id)).await.is_ok());
+        assert!(run(&ds, "backup verify x").await.is_err());
+
+        run(&ds, "checkpoint daily/monday").await.unwrap();
+        assert!(Path::new(&path("copies/daily/monday")).exists());
+        run(&ds, &format!("restore {} {}", id, path("copies/restored")))
+            .await
+            .unwrap();
+        assert!(Path::new(&path("copies/restored")).exists());
+
+        // Existing directories aren't restored into, and nothing is written outside of the
+        // checkpoint directory
+        assert!(run(&ds, "restore restored").await.is_err());
+        for outside in [path("db"), path("elsewhere"), String::from("../db")] {
+            assert!(run(&ds, &format!("checkpoint {}", outside)).await.is_err());
+            assert!(run(&ds, &format!("restore {}", outside)).await.is_err());
+        }
+        assert!(!Path::new(&path("elsewhere")).exists());
+        assert!(run(&ds, "vacuum").await.is_err());
+
+        drop(ds);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn disabled_by_default() {
+        let dir = std::env::temp_dir().join(format!("kuiperdb-admin-{}", ObjectId::new()));
+        let ds = Datastore::new(dir.to_str().unwrap()).await.unwrap();
+
+        assert!(matches!(run(&ds, "backup").await, Err(Error::Config(_))));
+
+        drop(ds);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
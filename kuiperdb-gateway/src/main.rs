@@ -2,23 +2,42 @@
 // (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
 //--------------------------------------------------------------------------
 
-use std::time::Instant;
-
-use actix_web::{post, web, App, HttpResponse, HttpServer, Responder};
-use kuiperdb_core::storage::rocksdb::Datastore;
-use kuiperdb_engine::{
-    execution::{Executor, QueryResult},
-    plan::QueryPlan,
-};
-use kuiperdb_lang::ast::Node;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder};
+use kuiperdb_core::storage::rocksdb::{Datastore, DatastoreConfig};
+use kuiperdb_engine::execution::session::Session;
+use kuiperdb_engine::execution::{Dialect, ExecutionContext, Executor, PreparedStatement};
+use kuiperdb_engine::plan;
+use kuiperdb_lang::ast::{Node, ScalarValue, TransactionStmt};
 use serde::Deserialize;
 use serde_derive::Serialize;
 use serde_json::Value;
 
+use crate::sessions::Sessions;
+
+mod admin;
+mod sessions;
+
+/// The number of transactions clients can keep open at once
+const MAX_OPEN_TRANSACTIONS: usize = 64;
+
+/// How long an open transaction can go without a request before it is rolled back
+const TRANSACTION_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
 #[derive(Deserialize)]
 struct Command {
     operation: String,
     command: String,
+
+    #[serde(default)]
+    parameters: HashMap<String, Value>,
+
+    /// The id of the open transaction to run the command in
+    #[serde(default)]
+    transaction: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -32,6 +51,10 @@ struct CommandResult {
     #[serde(skip_serializing_if = "Option::is_none")]
     execution_plan: Option<Vec<Node>>,
 
+    /// The id of the transaction the command left open, later commands run in it by naming it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transaction: Option<String>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     time_elapsed_µs: Option<u128>,
 
@@ -39,73 +62,229 @@ struct CommandResult {
     time_elapsed_ms: Option<u128>,
 }
 
-#[post("/")]
-async fn execute(ds: web::Data<Datastore>, command: web::Json<Command>) -> impl Responder {
-    let dsx = ds.into_inner();
-    let dsr = dsx.as_ref();
-    let ex = Executor::new(dsr.clone());
+/// Converts a JSON request parameter into a language literal.
+fn scalar_from_json(value: &Value) -> ScalarValue {
+    match value {
+        Value::Null => ScalarValue::Null,
+        Value::Bool(b) => ScalarValue::Boolean(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => ScalarValue::Int(i),
+            None => ScalarValue::Decimal(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => ScalarValue::String(s.clone()),
+        Value::Array(values) => ScalarValue::Array(values.iter().map(scalar_from_json).collect()),
+        Value::Object(fields) => ScalarValue::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), scalar_from_json(value)))
+                .collect(),
+        ),
+    }
+}
 
+/// Whether a script begins a transaction, which it can leave open for the requests that follow
+fn begins_transaction(statement: &PreparedStatement) -> bool {
+    statement
+        .plans
+        .iter()
+        .any(|plan| matches!(plan.0, plan::Node::Transaction(TransactionStmt::Begin)))
+}
+
+fn too_many_transactions() -> String {
+    format!(
+        "Too many open transactions, at most {} can be open at once",
+        MAX_OPEN_TRANSACTIONS
+    )
+}
+
+#[post("/")]
+async fn execute(
+    ex: web::Data<Executor>,
+    ds: web::Data<Datastore>,
+    sessions: web::Data<Sessions>,
+    command: web::Json<Command>,
+) -> impl Responder {
     let now = Instant::now();
-    let result = kuiperdb_lang::parser::parse_query(&command.command);
 
+    // Admin commands, such as `.backup`, work on the datastore instead of running a query
+    if let Some(admin) = command.command.trim().strip_prefix('.') {
+        let executed = admin::run(&ds, admin).await;
+        let (result, error) = match executed {
+            Ok(records) => (Some(records), None),
+            Err(err) => (None, Some(format!("Admin Error: {}", err))),
+        };
+
+        return HttpResponse::Ok().json(CommandResult {
+            result,
+            error,
+            execution_plan: Option::None,
+            transaction: Option::None,
+            time_elapsed_ms: Some(now.elapsed().as_millis()),
+            time_elapsed_µs: Some(now.elapsed().as_micros()),
+        });
+    }
+
+    let dialect = match command.operation.to_lowercase().as_str() {
+        "sql" => Dialect::Sql,
+        _ => Dialect::Klang,
+    };
     let mut command_result = CommandResult {
         result: Option::None,
         error: Option::None,
         execution_plan: Option::None,
-        time_elapsed_ms: Some(now.elapsed().as_millis()),
-        time_elapsed_µs: Some(now.elapsed().as_micros()),
+        transaction: Option::None,
+        time_elapsed_ms: Option::None,
+        time_elapsed_µs: Option::None,
+    };
+
+    // Commands run in the session of the transaction they name, or in a new one
+    let session = match &command.transaction {
+        Some(id) => match sessions.get(id) {
+            Some(session) => session,
+            None => {
+                command_result.error =
+                    Some(format!("Transaction {} doesn't exist or has expired", id));
+                return HttpResponse::Ok().json(command_result);
+            }
+        },
+        None => Arc::new(tokio::sync::Mutex::new(Session::new())),
     };
+    let mut session_guard = session.lock().await;
+
+    // It expired while the command waited for the session
+    if command.transaction.is_some() && session_guard.transaction().is_none() {
+        command_result.error = Some(String::from("The transaction has expired"));
+        return HttpResponse::Ok().json(command_result);
+    }
+
+    // The command can query the collections its transaction created
+    let result = ex
+        .prepare_in_session(&command.command, dialect, &mut session_guard)
+        .await;
+    command_result.time_elapsed_ms = Some(now.elapsed().as_millis());
+    command_result.time_elapsed_µs = Some(now.elapsed().as_micros());
+
+    match result {
+        Ok(statement) => {
+            command_result.execution_plan = Some(statement.ast.clone());
+
+            // Parameters are bound to the plan, they are never spliced into the query text
+            let context = ExecutionContext {
+                parameters: command
+                    .parameters
+                    .iter()
+                    .map(|(name, value)| (name.clone(), scalar_from_json(value)))
+                    .collect(),
+            };
+
+            // A script that could leave a transaction open isn't run once no more can be kept
+            if command.transaction.is_none() && begins_transaction(&statement) && sessions.is_full()
+            {
+                command_result.error = Some(too_many_transactions());
+                return HttpResponse::Ok().json(command_result);
+            }
+
+            let executed = ex
+                .execute_in_session(&statement, &context, &mut session_guard)
+                .await;
 
-    if (result.is_ok()) {
-        command_result.execution_plan = result.clone().ok();
-
-        match result.clone().ok().unwrap().first().unwrap() {
-            Node::Query(q) => {
-                // Execute Query
-                let query_plan = QueryPlan::from_ast(q);
-                match query_plan.0 {
-                    kuiperdb_engine::plan::Node::CollectionScan(x) => {
-                        let execution_plan = kuiperdb_engine::execution::QueryPlan {
-                            collection: x.collection.clone(),
-                            schema: x.schema.clone(),
-                        };
-
-                        let query_result: Result<
-                            kuiperdb_engine::execution::QueryResult,
-                            kuiperdb_core::error::Error,
-                        > = ex.execute_select(execution_plan).await;
-
-                        if (query_result.is_err()) {
-                            command_result.error =
-                                Some(format!("Execution Error: {:?}", query_result.err()))
-                        } else {
-                            let mut records: Vec<Value> = Vec::new();
-
-                            for bson in query_result.ok().unwrap().records {
-                                records.push(bson.into_canonical_extjson());
-                            }
-
-                            command_result.result = Some(records);
+            let open = session_guard.transaction().is_some();
+            match (&command.transaction, open) {
+                (Some(id), true) => {
+                    sessions.touch(id);
+                    command_result.transaction = Some(id.clone());
+                }
+                (Some(id), false) => sessions.remove(id),
+                (None, true) => match sessions.insert(session.clone()) {
+                    Some(id) => command_result.transaction = Some(id),
+                    None => {
+                        let _ = session_guard.abort().await;
+                        command_result.error = Some(too_many_transactions());
+                        return HttpResponse::Ok().json(command_result);
+                    }
+                },
+                (None, false) => {}
+            }
+
+            match executed {
+                Ok(query_results) => {
+                    let mut records: Vec<Value> = Vec::new();
+
+                    // A script returns the result of its last query
+                    if let Some(query_result) = query_results.into_iter().last() {
+                        for bson in query_result.records {
+                            records.push(bson.into_canonical_extjson());
                         }
                     }
-                    invalid => command_result.error = Some(format!("Invalid Plan! {:?}", invalid)),
+
+                    command_result.result = Some(records);
                 }
+                Err(err) => command_result.error = Some(format!("Execution Error: {}", err)),
             }
-
-            invalid => command_result.error = Some(format!("Invalid Rule! {:?}", invalid)),
         }
-    } else {
-        command_result.error = Some(format!("{:?}", result.err()));
+        Err(err) => command_result.error = Some(format!("{}", err)),
     }
 
     HttpResponse::Ok().json(command_result)
 }
 
+#[derive(Deserialize)]
+struct CatalogQuery {
+    #[serde(default = "default_sample")]
+    sample: usize,
+}
+
+fn default_sample() -> usize {
+    100
+}
+
+/// Describes the collections from a sample of their documents, for editors to check and
+/// complete queries with.
+#[get("/catalog")]
+async fn catalog(ex: web::Data<Executor>, query: web::Query<CatalogQuery>) -> impl Responder {
+    match ex.catalog_snapshot(query.sample).await {
+        Ok(catalog) => HttpResponse::Ok().json(catalog),
+        Err(err) => HttpResponse::InternalServerError().json(CommandResult {
+            result: Option::None,
+            error: Some(format!("{}", err)),
+            execution_plan: Option::None,
+            transaction: Option::None,
+            time_elapsed_ms: Option::None,
+            time_elapsed_µs: Option::None,
+        }),
+    }
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let ds = web::Data::new(Datastore::new(".\\dbs\\vuln_db").await.unwrap());
-    HttpServer::new(move || App::new().app_data(ds.clone()).service(execute))
-        .bind(("127.0.0.1", 8080))?
-        .run()
-        .await
+    let config = DatastoreConfig::load().map_err(|e| std::io::Error::other(e.to_string()))?;
+    let ds = Datastore::open(config).await.unwrap();
+    let admin = web::Data::new(ds.clone());
+    let ex = web::Data::new(Executor::new(ds));
+    let sessions = web::Data::new(<Sessions>::new(
+        MAX_OPEN_TRANSACTIONS,
+        TRANSACTION_IDLE_TIMEOUT,
+    ));
+
+    // Roll back the transactions clients left idle
+    let expiring = sessions.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            expiring.expire().await;
+        }
+    });
+
+    HttpServer::new(move || {
+        App::new()
+            .app_data(ex.clone())
+            .app_data(admin.clone())
+            .app_data(sessions.clone())
+            .service(execute)
+            .service(catalog)
+    })
+    .bind(("127.0.0.1", 8080))?
+    .run()
+    .await
 }
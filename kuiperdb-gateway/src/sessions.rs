@@ -0,0 +1,203 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+//! The transactions clients keep open across requests. A request whose script begins a
+//! transaction without ending it gets the id of its session back, and the requests that name
+//! the id run in that transaction until one of them commits or rolls it back.
+//!
+//! Transactions hold on to a snapshot and, in pessimistic mode, their locks, so sessions
+//! that sit idle are rolled back, and only a limited number can be open at once. Ids are
+//! random, a client can't guess the id of another client's transaction.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use kuiperdb_core::storage::kv::Store;
+use kuiperdb_core::storage::rocksdb::Datastore;
+use kuiperdb_engine::execution::session::Session;
+use uuid::Uuid;
+
+/// A session shared by the requests that name it, they take turns running in it
+pub type SharedSession<S> = Arc<tokio::sync::Mutex<Session<S>>>;
+
+/// The sessions with an open transaction, by id
+pub struct Sessions<S: Store = Datastore> {
+    open: Mutex<HashMap<String, Entry<S>>>,
+
+    // The number of sessions that can be open at once
+    capacity: usize,
+
+    // How long a session can go without a request before it is rolled back
+    idle_timeout: Duration,
+}
+
+struct Entry<S: Store> {
+    session: SharedSession<S>,
+    last_used: Instant,
+}
+
+impl<S: Store> Sessions<S> {
+    pub fn new(capacity: usize, idle_timeout: Duration) -> Self {
+        Sessions {
+            open: Mutex::new(HashMap::new()),
+            capacity,
+            idle_timeout,
+        }
+    }
+
+    /// Keeps a session with an open transaction and returns its id, or `None` when as many
+    /// sessions as allowed are already open
+    pub fn insert(&self, session: SharedSession<S>) -> Option<String> {
+        let mut open = self.open.lock().unwrap();
+        if open.len() >= self.capacity {
+            return None;
+        }
+
+        let id = Uuid::new_v4().simple().to_string();
+        open.insert(
+            id.clone(),
+            Entry {
+                session,
+                last_used: Instant::now(),
+            },
+        );
+
+        Some(id)
+    }
+
+    /// Whether as many sessions as allowed are open, so no other can be kept
+    pub fn is_full(&self) -> bool {
+        self.open.lock().unwrap().len() >= self.capacity
+    }
+
+    /// The session with an id, it counts as used
+    pub fn get(&self, id: &str) -> Option<SharedSession<S>> {
+        let mut open = self.open.lock().unwrap();
+        let entry = open.get_mut(id)?;
+        entry.last_used = Instant::now();
+
+        Some(entry.session.clone())
+    }
+
+    /// Counts a session as used, once a request that ran in it finishes
+    pub fn touch(&self, id: &str) {
+        if let Some(entry) = self.open.lock().unwrap().get_mut(id) {
+            entry.last_used = Instant::now();
+        }
+    }
+
+    /// Forgets a session whose transaction ended
+    pub fn remove(&self, id: &str) {
+        self.open.lock().unwrap().remove(id);
+    }
+
+    /// Rolls back the sessions that were idle for longer than the timeout. Sessions a request
+    /// is running in are left alone.
+    pub async fn expire(&self) {
+        let expired: Vec<SharedSession<S>> = {
+            let mut open = self.open.lock().unwrap();
+            let idle: Vec<String> = open
+                .iter()
+                .filter(|(_, entry)| {
+                    entry.last_used.elapsed() >= self.idle_timeout
+                        && entry.session.try_lock().is_ok()
+                })
+                .map(|(id, _)| id.clone())
+                .collect();
+
+            idle.iter()
+                .filter_map(|id| open.remove(id))
+                .map(|entry| entry.session)
+                .collect()
+        };
+
+        for session in expired {
+            if let Err(err) = session.lock().await.abort().await {
+                log::warn!("Couldn't roll back an idle transaction: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuiperdb_core::storage::kv::Transaction as _;
+    use kuiperdb_core::storage::memory;
+    use kuiperdb_lang::ast::TransactionStmt;
+
+    async fn begin(ds: &memory::Datastore) -> SharedSession<memory::Datastore> {
+        let mut session = Session::new();
+        session.execute(ds, &TransactionStmt::Begin).await.unwrap();
+        Arc::new(tokio::sync::Mutex::new(session))
+    }
+
+    #[tokio::test]
+    async fn capacity() {
+        let ds = memory::Datastore::new();
+        let sessions = Sessions::new(2, Duration::from_secs(60));
+
+        let first = sessions.insert(begin(&ds).await).unwrap();
+        assert!(!sessions.is_full());
+        let second = sessions.insert(begin(&ds).await).unwrap();
+        assert_ne!(first, second);
+        assert!(Uuid::parse_str(&first).is_ok());
+        assert!(sessions.is_full());
+        assert!(sessions.insert(begin(&ds).await).is_none());
+
+        sessions.remove(&first);
+        assert!(sessions.get(&first).is_none());
+        assert!(sessions.get(&second).is_some());
+        assert!(sessions.insert(begin(&ds).await).is_some());
+    }
+
+    #[tokio::test]
+    async fn idle_sessions_roll_back() {
+        let ds = memory::Datastore::new();
+        let sessions = Sessions::new(8, Duration::ZERO);
+
+        let idle = begin(&ds).await;
+        idle.lock()
+            .await
+            .transaction()
+            .unwrap()
+            .upsert("a", "1")
+            .await
+            .unwrap();
+        let idle_id = sessions.insert(idle.clone()).unwrap();
+
+        // A session in use isn't expired
+        let busy_id = sessions.insert(begin(&ds).await).unwrap();
+        let busy = sessions.get(&busy_id).unwrap();
+        let guard = busy.lock().await;
+
+        sessions.expire().await;
+        assert!(sessions.get(&idle_id).is_none());
+        assert!(idle.lock().await.transaction().is_none());
+        assert!(sessions.get(&busy_id).is_some());
+        drop(guard);
+
+        let mut txn = ds.transaction(false).await.unwrap();
+        assert_eq!(txn.get("a").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn finished_requests_count_as_use() {
+        let ds = memory::Datastore::new();
+        let sessions = Sessions::new(8, Duration::from_millis(50));
+        let id = sessions.insert(begin(&ds).await).unwrap();
+
+        // A request that ran for longer than the timeout leaves its session open
+        let session = sessions.get(&id).unwrap();
+        std::thread::sleep(Duration::from_millis(60));
+        sessions.touch(&id);
+        sessions.expire().await;
+        assert!(session.lock().await.transaction().is_some());
+
+        std::thread::sleep(Duration::from_millis(60));
+        sessions.expire().await;
+        assert!(sessions.get(&id).is_none());
+    }
+}
@@ -0,0 +1,1024 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+//! Checks parsed statements before they are planned. Collections and fields are resolved through
+//! the operators of every query, so a field removed by `project` is unknown to the operators
+//! that follow, and the types of expressions are inferred to find operations that would fail
+//! for every document. Fields the catalog doesn't know the type of are never reported.
+//!
+//! The catalog only describes samples of the collections, so a field missing from them is a
+//! warning. Inserting into a collection the catalog doesn't list creates it. Without a catalog, as when the engine prepares a statement, the collections may
+//! hold anything and only the mistakes the query makes on its own are errors.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{
+    Assignment, BinaryOp, IdentityValue, InSet, Node, QueryExpr, QueryOperator, QuerySource,
+    ScalarValue, Span, UnaryOp, UnionExpr,
+};
+use crate::catalog::{Catalog, CollectionInfo, FieldInfo, ValueType};
+use crate::functions::{self, FunctionKind, Returns};
+
+/// An inferred type, `None` when it can't be told before running the query.
+pub type Type = Option<ValueType>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// A name the analyzer resolved, with a description for editors.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Symbol {
+    pub span: Span,
+    pub description: String,
+}
+
+/// The fields of the documents a query produces.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Schema {
+    pub fields: Vec<(String, Type)>,
+
+    /// Whether documents may have fields that aren't listed, then no field is unknown.
+    pub open: bool,
+
+    /// Whether the fields were sampled from stored documents, then unlisted fields are only
+    /// unlikely.
+    pub sampled: bool,
+}
+
+impl Schema {
+    /// A schema that nothing is known about.
+    pub fn open() -> Self {
+        Schema {
+            fields: Vec::new(),
+            open: true,
+            sampled: false,
+        }
+    }
+
+    fn from_collection(collection: &CollectionInfo) -> Self {
+        Schema {
+            fields: collection
+                .fields
+                .iter()
+                .map(|field| (field.name.clone(), field_type(field)))
+                .collect(),
+            open: collection.sampled == 0,
+            sampled: true,
+        }
+    }
+
+    pub fn field(&self, name: &str) -> Option<Type> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, ty)| *ty)
+    }
+
+    fn set(&mut self, name: &str, ty: Type) {
+        match self.fields.iter_mut().find(|(field, _)| field == name) {
+            Some(field) => field.1 = ty,
+            None => self.fields.push((name.to_owned(), ty)),
+        }
+    }
+
+    /// Combines the schemas of the inputs of a union, fields keep a type all inputs agree on.
+    fn merge(&mut self, other: Schema) {
+        for (name, ty) in other.fields {
+            match self.fields.iter_mut().find(|(field, _)| *field == name) {
+                Some(field) if field.1 != ty => field.1 = None,
+                Some(_) => {}
+                None => self.fields.push((name, ty)),
+            }
+        }
+        self.open |= other.open;
+        self.sampled |= other.sampled;
+    }
+}
+
+/// The single type a field was seen with, null values aside.
+fn field_type(field: &FieldInfo) -> Type {
+    let mut types = field.types.iter().filter(|ty| **ty != ValueType::Null);
+
+    match (types.next(), types.next()) {
+        (Some(ty), None) => Some(*ty),
+        (None, _) if !field.types.is_empty() => Some(ValueType::Null),
+        _ => None,
+    }
+}
+
+pub fn describe_type(ty: Type) -> String {
+    match ty {
+        Some(ty) => ty.to_string(),
+        None => String::from("any"),
+    }
+}
+
+/// Checks a script, returning the problems found in source order of the statements.
+pub fn analyze(statements: &[Node], catalog: &Catalog) -> Vec<Diagnostic> {
+    let mut analyzer = Analyzer::new(Some(catalog));
+    for statement in statements {
+        analyzer.statement(statement);
+    }
+
+    analyzer.diagnostics
+}
+
+pub struct Analyzer<'a> {
+    catalog: Option<&'a Catalog>,
+
+    /// Names bound by `let` statements, visible to the statements that follow them.
+    pub tables: HashMap<String, Schema>,
+    pub scalars: HashMap<String, Type>,
+
+    // The collections created by inserts, in lower case
+    created: HashSet<String>,
+
+    // Where aggregate and window functions can be called
+    in_summarize: bool,
+    in_aggregate: Option<&'static str>,
+    in_extend: bool,
+    serialized: bool,
+
+    pub diagnostics: Vec<Diagnostic>,
+    pub symbols: Vec<Symbol>,
+}
+
+impl<'a> Analyzer<'a> {
+    pub fn new(catalog: Option<&'a Catalog>) -> Self {
+        Analyzer {
+            catalog,
+            tables: HashMap::new(),
+            scalars: HashMap::new(),
+            created: HashSet::new(),
+            in_summarize: false,
+            in_aggregate: None,
+            in_extend: false,
+            serialized: false,
+            diagnostics: Vec::new(),
+            symbols: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, span: Span, message: String) {
+        self.diagnostics.push(Diagnostic {
+            span,
+            severity: Severity::Error,
+            message,
+        });
+    }
+
+    fn symbol(&mut self, span: Span, description: String) {
+        self.symbols.push(Symbol { span, description });
+    }
+
+    /// Checks a statement and returns the schema of the documents it produces. `let` statements
+    /// produce none, they bind their name for the statements that follow.
+    pub fn statement(&mut self, node: &Node) -> Option<Schema> {
+        match node {
+            Node::Query(query) => return Some(self.query(query)),
+            Node::Let(binding) => match binding.value.as_ref() {
+                // A name bound to a scalar is parsed as a query, it binds the scalar
+                Node::Query(query)
+                    if query
+                        .bare_name()
+                        .is_some_and(|name| self.scalars.contains_key(name)) =>
+                {
+                    let ty = self.scalars[query.bare_name().unwrap()];
+                    self.symbol(
+                        binding.span,
+                        format!("let {}: {}", binding.name, describe_type(ty)),
+                    );
+                    self.tables.remove(&binding.name);
+                    self.scalars.insert(binding.name.clone(), ty);
+                }
+                Node::Query(query) => {
+                    let schema = self.query(query);
+                    self.symbol(
+                        binding.span,
+                        format!("let {}: query with {}", binding.name, describe(&schema)),
+                    );
+                    self.scalars.remove(&binding.name);
+                    self.tables.insert(binding.name.clone(), schema);
+                }
+                value => {
+                    let ty = self.expression(value, &Schema::open());
+                    self.symbol(
+                        binding.span,
+                        format!("let {}: {}", binding.name, describe_type(ty)),
+                    );
+                    self.tables.remove(&binding.name);
+                    self.scalars.insert(binding.name.clone(), ty);
+                }
+            },
+            // Changes report the documents they changed
+            Node::Insert(_) | Node::Update(_) | Node::Delete(_) => {
+                self.change(node);
+                return Some(Schema::open());
+            }
+            // Transaction statements don't return documents
+            Node::Transaction(_) => return Some(Schema::default()),
+            expression => {
+                self.expression(expression, &Schema::open());
+            }
+        }
+
+        None
+    }
+
+    fn change(&mut self, node: &Node) {
+        match node {
+            Node::Insert(insert) => {
+                // The collection is created by the insert when it doesn't exist yet
+                if let Some(catalog) = self.catalog {
+                    if catalog.collection(&insert.table.value).is_none() {
+                        self.created.insert(insert.table.value.to_lowercase());
+                    }
+                }
+
+                let schema = self.table(&insert.table);
+                for row in &insert.rows {
+                    for assignment in row {
+                        self.expression(&assignment.value, &schema);
+                    }
+                }
+            }
+            Node::Update(update) => {
+                let schema = self.table(&update.table);
+                for assignment in &update.assignments {
+                    self.expression(&assignment.value, &schema);
+                }
+                if let Some(filter) = &update.filter {
+                    self.filter(filter, &schema);
+                }
+            }
+            Node::Delete(delete) => {
+                let schema = self.table(&delete.table);
+                if let Some(filter) = &delete.filter {
+                    self.filter(filter, &schema);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Checks a query and returns the schema of the documents it produces.
+    pub fn query(&mut self, query: &QueryExpr) -> Schema {
+        let mut schema = match &query.source {
+            QuerySource::Table(table) => self.table(table),
+            QuerySource::Union(union) => self.union(union),
+        };
+
+        let serialized = std::mem::replace(&mut self.serialized, false);
+        for operator in &query.operators {
+            schema = self.operator(operator, schema);
+        }
+        self.serialized = serialized;
+
+        schema
+    }
+
+    /// The schema of the documents after an operator.
+    fn operator(&mut self, operator: &QueryOperator, input: Schema) -> Schema {
+        // Only the operators that keep the order of the documents keep them serialized
+        if !matches!(
+            operator,
+            QueryOperator::Where(_)
+                | QueryOperator::Project(_)
+                | QueryOperator::Extend(_)
+                | QueryOperator::Serialize(_)
+                | QueryOperator::Take(_)
+                | QueryOperator::ForUpdate
+        ) {
+            self.serialized = false;
+        }
+
+        match operator {
+            QueryOperator::Where(filter) => {
+                self.filter(filter, &input);
+                input
+            }
+            QueryOperator::Project(assignments) => Schema {
+                fields: self.assignments(assignments, &input),
+                ..Schema::default()
+            },
+            QueryOperator::Extend(assignments) => {
+                self.in_extend = true;
+                let fields = self.assignments(assignments, &input);
+                self.in_extend = false;
+
+                let mut schema = input;
+                for (name, ty) in fields {
+                    schema.set(&name, ty);
+                }
+                schema
+            }
+            QueryOperator::Distinct(fields) if fields.is_empty() => input,
+            QueryOperator::Distinct(fields) => Schema {
+                fields: fields
+                    .iter()
+                    .map(|field| (field.value.clone(), self.field(field, &input)))
+                    .collect(),
+                ..Schema::default()
+            },
+            QueryOperator::Serialize(window) => {
+                for field in &window.partition_by {
+                    self.field(field, &input);
+                }
+                for clause in &window.order_by {
+                    self.field(&clause.identity, &input);
+                }
+                self.serialized = true;
+                input
+            }
+            QueryOperator::Union(union) => {
+                let mut schema = input;
+                schema.merge(self.union(union));
+                schema
+            }
+            QueryOperator::Intersect(operands) | QueryOperator::Except(operands) => {
+                for operand in operands {
+                    self.query(operand);
+                }
+                input
+            }
+            QueryOperator::Take(_) | QueryOperator::ForUpdate => input,
+            QueryOperator::Summarize(summarize) => {
+                let mut fields = self.assignments(&summarize.by, &input);
+
+                self.in_summarize = true;
+                for assignment in &summarize.aggregates {
+                    if let (false, _) | (_, true) = self.aggregates(&assignment.value) {
+                        self.error(
+                            assignment.span,
+                            format!(
+                                "{} must be computed by aggregate functions such as count() or sum(x)",
+                                assignment.name
+                            ),
+                        );
+                    }
+                    let ty = self.expression(&assignment.value, &input);
+                    fields.push((assignment.name.clone(), ty));
+                }
+                self.in_summarize = false;
+
+                Schema {
+                    fields,
+                    ..Schema::default()
+                }
+            }
+        }
+    }
+
+    fn assignments(&mut self, assignments: &[Assignment], input: &Schema) -> Vec<(String, Type)> {
+        assignments
+            .iter()
+            .map(|assignment| {
+                let ty = self.expression(&assignment.value, input);
+                (assignment.name.clone(), ty)
+            })
+            .collect()
+    }
+
+    /// Whether an expression calls aggregate functions, and whether it reads fields outside of
+    /// them, which `summarize` doesn't allow.
+    fn aggregates(&self, node: &Node) -> (bool, bool) {
+        match node {
+            Node::FunctionCall(call) => match functions::lookup(&call.name) {
+                Some(function) if function.kind == FunctionKind::Aggregate => (true, false),
+                _ => self.aggregates_all(&call.args),
+            },
+            Node::Identity(identity) => (false, !self.scalars.contains_key(&identity.value)),
+            Node::BinaryExpr(binary) => {
+                let (left_calls, left_reads) = self.aggregates(&binary.left);
+                let (right_calls, right_reads) = self.aggregates(&binary.right);
+                (left_calls || right_calls, left_reads || right_reads)
+            }
+            Node::UnaryExpr(unary) => self.aggregates(&unary.expr),
+            _ => (false, false),
+        }
+    }
+
+    fn aggregates_all(&self, nodes: &[Node]) -> (bool, bool) {
+        nodes.iter().fold((false, false), |(calls, reads), node| {
+            let (node_calls, node_reads) = self.aggregates(node);
+            (calls || node_calls, reads || node_reads)
+        })
+    }
+
+    /// The documents of a collection, or of a query bound with `let`.
+    fn table(&mut self, table: &IdentityValue) -> Schema {
+        if let Some(schema) = self.tables.get(&table.value) {
+            let schema = schema.clone();
+            self.symbol(
+                table.span,
+                format!("let {}: query with {}", table.value, describe(&schema)),
+            );
+            return schema;
+        }
+
+        let catalog = match self.catalog {
+            Some(catalog) => catalog,
+            None => return Schema::open(),
+        };
+
+        if table.value.contains('*') {
+            let mut matches = catalog.matching(&table.value).peekable();
+            if matches.peek().is_none() {
+                self.diagnostics.push(Diagnostic {
+                    span: table.span,
+                    severity: Severity::Warning,
+                    message: format!("No collection matches {}", table.value),
+                });
+                return Schema::open();
+            }
+
+            let mut schema = Schema::default();
+            for collection in matches {
+                schema.merge(Schema::from_collection(collection));
+            }
+            return schema;
+        }
+
+        match catalog.collection(&table.value) {
+            Some(collection) => {
+                let schema = Schema::from_collection(collection);
+                self.symbol(
+                    table.span,
+                    format!(
+                        "collection {}: {}, sampled from {} document(s)",
+                        collection.name,
+                        describe(&schema),
+                        collection.sampled
+                    ),
+                );
+                schema
+            }
+            None if self.created.contains(&table.value.to_lowercase()) => Schema::open(),
+            None => {
+                self.error(table.span, format!("Unknown collection {}", table.value));
+                Schema::open()
+            }
+        }
+    }
+
+    fn union(&mut self, union: &UnionExpr) -> Schema {
+        let mut schema = Schema::default();
+        for operand in &union.operands {
+            schema.merge(self.query(operand));
+        }
+
+        if let Some(name) = &union.with_source {
+            schema.set(name, Some(ValueType::String));
+        }
+
+        schema
+    }
+
+    /// Resolves a field, let bindings hide the fields of the documents.
+    fn field(&mut self, identity: &IdentityValue, schema: &Schema) -> Type {
+        if let Some(ty) = self.scalars.get(&identity.value) {
+            let ty = *ty;
+            self.symbol(
+                identity.span,
+                format!("let {}: {}", identity.value, describe_type(ty)),
+            );
+            return ty;
+        }
+
+        match schema.field(&identity.value) {
+            Some(ty) => {
+                self.symbol(
+                    identity.span,
+                    format!("field {}: {}", identity.value, describe_type(ty)),
+                );
+                ty
+            }
+            None if schema.open => None,
+            None => {
+                self.diagnostics.push(Diagnostic {
+                    span: identity.span,
+                    severity: if schema.sampled {
+                        Severity::Warning
+                    } else {
+                        Severity::Error
+                    },
+                    message: format!("Unknown field {}", identity.value),
+                });
+                None
+            }
+        }
+    }
+
+    fn filter(&mut self, filter: &Node, schema: &Schema) {
+        match self.expression(filter, schema) {
+            Some(ValueType::Boolean) | Some(ValueType::Null) | None => {}
+            Some(ty) => self.error(
+                filter.span().unwrap_or_default(),
+                format!("A filter must be a boolean, not {}", ty),
+            ),
+        }
+    }
+
+    /// Infers the type of an expression, reporting the operations that can't succeed.
+    pub fn expression(&mut self, node: &Node, schema: &Schema) -> Type {
+        match node {
+            Node::Identity(identity) => self.field(identity, schema),
+            Node::Scalar(scalar) => scalar_type(scalar),
+            Node::Parameter(_) => None,
+            Node::BinaryExpr(binary) => {
+                let left = self.expression(&binary.left, schema);
+                let right = self.expression(&binary.right, schema);
+
+                match binary_type(&binary.op, left, right) {
+                    Ok(ty) => ty,
+                    Err(message) => {
+                        self.error(binary.span, message);
+                        None
+                    }
+                }
+            }
+            Node::UnaryExpr(unary) => {
+                let ty = self.expression(&unary.expr, schema);
+
+                match (&unary.op, ty) {
+                    (_, None) | (_, Some(ValueType::Null)) => ty,
+                    (UnaryOp::Not, Some(ValueType::Boolean)) => ty,
+                    (UnaryOp::Negate, Some(ty)) if ty.is_numeric() => Some(ty),
+                    (_, Some(ty)) => {
+                        self.error(unary.span, format!("Can't negate {}", ty));
+                        None
+                    }
+                }
+            }
+            Node::FunctionCall(call) => {
+                let function = match functions::lookup(&call.name) {
+                    Some(function) => function,
+                    None => {
+                        self.error(call.span, format!("Unknown function {}()", call.name));
+                        for arg in &call.args {
+                            self.expression(arg, schema);
+                        }
+                        return None;
+                    }
+                };
+                self.symbol(
+                    call.span,
+                    format!("{}\n\n{}", function.signature, function.description),
+                );
+
+                if call.args.len() < function.min_args || call.args.len() > function.max_args {
+                    self.error(
+                        call.span,
+                        format!(
+                            "{}() expects {} argument(s), got {}",
+                            call.name,
+                            function.arity(),
+                            call.args.len()
+                        ),
+                    );
+                }
+
+                match function.kind {
+                    FunctionKind::Aggregate if self.in_aggregate.is_some() => self.error(
+                        call.span,
+                        format!(
+                            "Aggregate functions can't be nested in {}()",
+                            self.in_aggregate.unwrap_or_default()
+                        ),
+                    ),
+                    FunctionKind::Aggregate if !self.in_summarize => self.error(
+                        call.span,
+                        format!("{}() can only be used in summarize", call.name),
+                    ),
+                    FunctionKind::Window if !self.in_extend => self.error(
+                        call.span,
+                        format!("{}() can only be used in extend after serialize", call.name),
+                    ),
+                    FunctionKind::Window if !self.serialized => self.error(
+                        call.span,
+                        String::from(
+                            "Window functions require serialized input, add a `serialize` first",
+                        ),
+                    ),
+                    _ => {}
+                }
+
+                let in_aggregate = self.in_aggregate;
+                if function.kind == FunctionKind::Aggregate {
+                    self.in_aggregate = Some(function.name);
+                }
+                let mut types = Vec::with_capacity(call.args.len());
+                for (i, arg) in call.args.iter().enumerate() {
+                    let ty = self.expression(arg, schema);
+                    match (parameter_type(function.name, i), ty) {
+                        (Some(expected), Some(ty)) if ty != expected && ty != ValueType::Null => {
+                            self.error(
+                                arg.span().unwrap_or(call.span),
+                                format!("{}() expects {}, got {}", call.name, expected, ty),
+                            )
+                        }
+                        _ => {}
+                    }
+                    types.push(ty);
+                }
+                self.in_aggregate = in_aggregate;
+
+                match function.returns {
+                    Returns::Type(ty) => Some(ty),
+                    Returns::FirstArgument => types.first().copied().flatten(),
+                }
+            }
+            Node::In(in_expr) => {
+                self.expression(&in_expr.expr, schema);
+                match &in_expr.set {
+                    // A query bound with `let` on its own is a subquery
+                    InSet::List(values)
+                        if matches!(values.as_slice(), [Node::Identity(table)]
+                            if self.tables.contains_key(&table.value)) => {}
+                    InSet::List(values) => {
+                        for value in values {
+                            self.expression(value, schema);
+                        }
+                    }
+                    InSet::Query(query) => {
+                        self.query(query);
+                    }
+                }
+                Some(ValueType::Boolean)
+            }
+            Node::ToScalar(query) => {
+                let schema = self.query(query);
+                schema.fields.first().and_then(|(_, ty)| *ty)
+            }
+            Node::Query(_)
+            | Node::Let(_)
+            | Node::Insert(_)
+            | Node::Update(_)
+            | Node::Delete(_)
+            | Node::Transaction(_) => None,
+        }
+    }
+}
+
+/// The type a function requires of an argument, for the functions that require one.
+fn parameter_type(function: &str, index: usize) -> Type {
+    match (function, index) {
+        ("ago", 0) => Some(ValueType::Int),
+        ("matches_regex", 0 | 1) => Some(ValueType::String),
+        ("array_concat", _) => Some(ValueType::Array),
+        _ => None,
+    }
+}
+
+/// Lists the fields of a schema for a description.
+fn describe(schema: &Schema) -> String {
+    let fields: Vec<String> = schema
+        .fields
+        .iter()
+        .map(|(name, ty)| format!("{}: {}", name, describe_type(*ty)))
+        .collect();
+
+    match (fields.is_empty(), schema.open) {
+        (true, true) => String::from("unknown fields"),
+        (_, true) => format!("fields {}, and possibly others", fields.join(", ")),
+        (_, false) => format!("fields {}", fields.join(", ")),
+    }
+}
+
+pub fn scalar_type(scalar: &ScalarValue) -> Type {
+    Some(match scalar {
+        ScalarValue::Int(_) | ScalarValue::Timespan(_) => ValueType::Int,
+        ScalarValue::Decimal(_) => ValueType::Decimal,
+        ScalarValue::String(_) => ValueType::String,
+        ScalarValue::Boolean(_) => ValueType::Boolean,
+        ScalarValue::Date(_) => ValueType::Date,
+        ScalarValue::ObjectId(_) => ValueType::ObjectId,
+        ScalarValue::Uuid(_) => ValueType::Binary,
+        ScalarValue::Array(_) => ValueType::Array,
+        ScalarValue::Object(_) => ValueType::Object,
+        ScalarValue::Null | ScalarValue::Undefined => ValueType::Null,
+    })
+}
+
+/// The type of a binary operation, following the rules of expression evaluation. Operations
+/// with a null operand are null rather than an error.
+pub fn binary_type(op: &BinaryOp, left: Type, right: Type) -> Result<Type, String> {
+    use ValueType::*;
+
+    let boolean = matches!(
+        op,
+        BinaryOp::And
+            | BinaryOp::Or
+            | BinaryOp::Eq
+            | BinaryOp::Ne
+            | BinaryOp::Lt
+            | BinaryOp::LtEq
+            | BinaryOp::Gt
+            | BinaryOp::GtEq
+            | BinaryOp::Like
+    );
+
+    let (left, right) = match (left, right) {
+        (Some(left), Some(right)) if left != Null && right != Null => (left, right),
+        (Some(Null), _) | (_, Some(Null)) if !boolean => return Ok(Some(Null)),
+        _ if boolean => return Ok(Some(Boolean)),
+        _ => return Ok(None),
+    };
+
+    let numeric = |int: Type| match (left, right) {
+        (Int, Int) => Ok(int),
+        (left, right) if left.is_numeric() && right.is_numeric() => Ok(Some(Decimal)),
+        _ => Err(()),
+    };
+
+    let result = match op {
+        BinaryOp::And | BinaryOp::Or => match (left, right) {
+            (Boolean, Boolean) => Ok(Some(Boolean)),
+            _ => Err(()),
+        },
+        BinaryOp::Eq | BinaryOp::Ne => match (left, right) {
+            (left, right) if left == right => Ok(Some(Boolean)),
+            (left, right) if left.is_numeric() && right.is_numeric() => Ok(Some(Boolean)),
+            _ => Err(()),
+        },
+        BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq => match (left, right) {
+            (Binary | Array | Object, _) => Err(()),
+            (left, right) if left == right => Ok(Some(Boolean)),
+            (left, right) if left.is_numeric() && right.is_numeric() => Ok(Some(Boolean)),
+            _ => Err(()),
+        },
+        BinaryOp::Like => match (left, right) {
+            (String, String) => Ok(Some(Boolean)),
+            _ => Err(()),
+        },
+        BinaryOp::Add => match (left, right) {
+            (Date, Int) | (Int, Date) => Ok(Some(Date)),
+            _ => numeric(Some(Int)),
+        },
+        BinaryOp::Subtract => match (left, right) {
+            (Date, Int) => Ok(Some(Date)),
+            // The difference between two datetimes is a timespan
+            (Date, Date) => Ok(Some(Int)),
+            _ => numeric(Some(Int)),
+        },
+        BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Modulo => numeric(Some(Int)),
+        // Negative exponents make a decimal of integers
+        BinaryOp::Exponentiate => numeric(None),
+    };
+
+    result.map_err(|_| {
+        let verb = match op {
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+            BinaryOp::Eq
+            | BinaryOp::Ne
+            | BinaryOp::Lt
+            | BinaryOp::LtEq
+            | BinaryOp::Gt
+            | BinaryOp::GtEq => "compare",
+            BinaryOp::Like => "LIKE",
+            BinaryOp::Add => "add",
+            BinaryOp::Subtract => "subtract",
+            BinaryOp::Multiply => "multiply",
+            BinaryOp::Divide => "divide",
+            BinaryOp::Modulo => "take modulo of",
+            BinaryOp::Exponentiate => "exponentiate",
+        };
+        format!("Can't {} {} and {}", verb, left, right)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_query;
+
+    fn catalog() -> Catalog {
+        let field = |name: &str, types: &[ValueType]| FieldInfo {
+            name: name.to_owned(),
+            types: types.to_vec(),
+        };
+
+        Catalog {
+            collections: vec![
+                CollectionInfo {
+                    name: String::from("events"),
+                    fields: vec![
+                        field("_id", &[ValueType::ObjectId]),
+                        field("user", &[ValueType::String]),
+                        field("amount", &[ValueType::Int, ValueType::Null]),
+                        field("ts", &[ValueType::Date]),
+                        field("tags", &[ValueType::Array, ValueType::String]),
+                    ],
+                    sampled: 10,
+                },
+                CollectionInfo {
+                    name: String::from("empty"),
+                    fields: Vec::new(),
+                    sampled: 0,
+                },
+            ],
+        }
+    }
+
+    fn messages(source: &str) -> Vec<(String, &str)> {
+        analyze(&parse_query(source).unwrap(), &catalog())
+            .into_iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+            .map(|diagnostic| {
+                let span = diagnostic.span;
+                (diagnostic.message, &source[span.start..span.end])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resolves_through_operators() {
+        assert_eq!(
+            messages("events | where amount > 1 and ts > ago(1d) | project user, n = amount * 2"),
+            []
+        );
+        assert_eq!(
+            messages("event | where x == 1"),
+            [(String::from("Unknown collection event"), "event")]
+        );
+        assert_eq!(
+            messages("events | project user | where amount > 1"),
+            [(String::from("Unknown field amount"), "amount")]
+        );
+        assert_eq!(
+            messages("events | extend n = 1 | summarize total = sum(n) by user | where n > 1"),
+            [(String::from("Unknown field n"), "n")]
+        );
+
+        // Without samples nothing is known about the fields, tags may hold anything
+        assert_eq!(messages("empty | where x > 1 and events_x == 1"), []);
+        assert_eq!(messages("events | where tags == 1"), []);
+    }
+
+    #[test]
+    fn inserts_create_collections() {
+        let mut statements = crate::sql::parse_sql("INSERT INTO logs (a) VALUES (1)").unwrap();
+        statements.extend(parse_query("logs | where a > 1; log").unwrap());
+        let messages: Vec<String> = analyze(&statements, &catalog())
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect();
+
+        assert_eq!(messages, ["Unknown collection log"]);
+    }
+
+    #[test]
+    fn sampled_fields_warn() {
+        let source = "events | where nope == 1 | project user | where amount == 1";
+        let diagnostics = analyze(&parse_query(source).unwrap(), &catalog());
+        let severities: Vec<Severity> = diagnostics.iter().map(|d| d.severity).collect();
+
+        assert_eq!(severities, [Severity::Warning, Severity::Error]);
+    }
+
+    #[test]
+    fn without_catalog() {
+        let check = |source: &str| {
+            let mut analyzer = Analyzer::new(None);
+            let schemas: Vec<Option<Schema>> = parse_query(source)
+                .unwrap()
+                .iter()
+                .map(|statement| analyzer.statement(statement))
+                .collect();
+            let messages: Vec<String> = analyzer
+                .diagnostics
+                .into_iter()
+                .map(|diagnostic| diagnostic.message)
+                .collect();
+            (schemas, messages)
+        };
+
+        // Stored fields may hold anything, the fields a query computes are known
+        let (schemas, messages) =
+            check("let t = anything | where a > 1; t | project n = a, s = \"x\" | where s > n");
+        assert_eq!(messages, Vec::<String>::new());
+        assert_eq!(
+            schemas,
+            [
+                None,
+                Some(Schema {
+                    fields: vec![
+                        (String::from("n"), None),
+                        (String::from("s"), Some(ValueType::String))
+                    ],
+                    ..Schema::default()
+                })
+            ]
+        );
+
+        let (_, messages) = check(
+            "t | extend s = \"x\" | where s > 1 and ago(s) > now() | summarize n = count() by s | where t > 1",
+        );
+        assert_eq!(
+            messages,
+            [
+                String::from("Can't compare string and int"),
+                String::from("ago() expects int, got string"),
+                String::from("Unknown field t"),
+            ]
+        );
+
+        // A bound query on its own in an in list is a subquery
+        let (_, messages) = check("let ids = t | project id; t | where id in (ids)");
+        assert_eq!(messages, Vec::<String>::new());
+    }
+
+    #[test]
+    fn let_bindings() {
+        assert_eq!(
+            messages("let top = events | project user; let n = 5; top | where user == n"),
+            [(String::from("Can't compare string and int"), "user == n")]
+        );
+        assert_eq!(
+            messages(
+                "let n = 5; events | where amount > n | project a = amount | extend x = unknown"
+            ),
+            [(String::from("Unknown field unknown"), "unknown")]
+        );
+
+        // A name bound to a scalar isn't a collection
+        assert_eq!(
+            messages("let n = \"a\"; let m = n; events | where amount > m"),
+            [(String::from("Can't compare int and string"), "amount > m")]
+        );
+    }
+
+    #[test]
+    fn type_mismatches() {
+        assert_eq!(
+            messages("events | where user > 1 or amount * ts == 2 | where -user | where user"),
+            [
+                (String::from("Can't compare string and int"), "user > 1"),
+                (
+                    String::from("Can't multiply int and datetime"),
+                    "amount * ts"
+                ),
+                (String::from("Can't negate string"), "-user"),
+                (
+                    String::from("A filter must be a boolean, not string"),
+                    "user"
+                ),
+            ]
+        );
+        assert_eq!(
+            messages("events | where ts - ts > 1d and amount == null and ts + 1d > now()"),
+            []
+        );
+    }
+
+    #[test]
+    fn function_calls() {
+        assert_eq!(
+            messages("events | extend a = sum(amount), b = nope(), c = row_number(1)"),
+            [
+                (
+                    String::from("sum() can only be used in summarize"),
+                    "sum(amount)"
+                ),
+                (String::from("Unknown function nope()"), "nope()"),
+                (
+                    String::from("row_number() expects 0 argument(s), got 1"),
+                    "row_number(1)"
+                ),
+                (
+                    String::from(
+                        "Window functions require serialized input, add a `serialize` first"
+                    ),
+                    "row_number(1)"
+                ),
+            ]
+        );
+        assert_eq!(
+            messages("events | summarize n = count(), m = max(sum(amount)), u = user | serialize order by n | extend r = row_number()"),
+            [
+                (
+                    String::from("Aggregate functions can't be nested in max()"),
+                    "sum(amount)"
+                ),
+                (
+                    String::from(
+                        "u must be computed by aggregate functions such as count() or sum(x)"
+                    ),
+                    "u = user"
+                ),
+            ]
+        );
+    }
+}
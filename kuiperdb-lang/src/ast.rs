@@ -8,7 +8,40 @@ use serde_derive::{Deserialize, Serialize};
    NOTES: Missing Copy and Eq
 */
 
-/// Represents a binary operation for comparison or composition.
+/// A range of byte offsets into the query text a node was parsed from. Spans aren't
+/// serialized, tests compare parsed ASTs with hand built ones once their spans are cleared.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// The smallest span that covers both spans.
+    pub fn to(self, other: Span) -> Self {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+
+    /// The line and column the span starts at in the source, both counted from 1.
+    pub fn line_column(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start.min(source.len())];
+        let line_start = before.rfind('\n').map_or(0, |end| end + 1);
+
+        (
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    }
+}
+
+/// Represents a binary operation for comparison, composition or arithmetic.
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum BinaryOp {
     Eq,
@@ -19,6 +52,21 @@ pub enum BinaryOp {
     Ne,
     And,
     Or,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Exponentiate,
+    /// SQL pattern match, `%` matches any run of characters and `_` a single one.
+    Like,
+}
+
+/// Represents a prefix operation applied to a single operand.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum UnaryOp {
+    Negate,
+    Not,
 }
 
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
@@ -32,6 +80,27 @@ pub struct BinaryExpr {
     pub op: BinaryOp,
     pub left: Box<Node>,
     pub right: Box<Node>,
+
+    #[serde(skip)]
+    pub span: Span,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct UnaryExpr {
+    pub op: UnaryOp,
+    pub expr: Box<Node>,
+
+    #[serde(skip)]
+    pub span: Span,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    pub args: Vec<Node>,
+
+    #[serde(skip)]
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
@@ -40,7 +109,16 @@ pub enum ScalarValue {
     Decimal(f64),
     String(String),
     Boolean(bool),
+    /// An RFC 3339 timestamp, normalized to always include seconds and a time zone.
     Date(String),
+    /// A duration in milliseconds.
+    Timespan(i64),
+    /// The 24 character hex representation of an ObjectId.
+    ObjectId(String),
+    /// The hyphenated representation of a UUID.
+    Uuid(String),
+    Array(Vec<ScalarValue>),
+    Object(Vec<(String, ScalarValue)>),
     Null,
     Undefined,
 }
@@ -51,6 +129,9 @@ pub struct IdentityValue {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub alias: Option<String>,
+
+    #[serde(skip)]
+    pub span: Span,
 }
 
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
@@ -59,11 +140,174 @@ pub struct OrderByClause {
     pub direction: OrderByDirection,
 }
 
+/// Combines the results of several queries. Table names may contain `*` wildcards, which are
+/// matched against the catalog when the query runs.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct UnionExpr {
+    /// Name of the field that records which collection each document came from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub with_source: Option<String>,
+    pub operands: Vec<QueryExpr>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum QuerySource {
+    Table(IdentityValue),
+    Union(UnionExpr),
+}
+
+/// The partitioning and ordering a `serialize` applies before window functions are computed.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct WindowExpr {
+    pub partition_by: Vec<IdentityValue>,
+    pub order_by: Vec<OrderByClause>,
+}
+
+/// The aggregates of a `summarize`, computed for every combination of the `by` values. Without
+/// `by` the whole input is a single group.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SummarizeExpr {
+    pub aggregates: Vec<Assignment>,
+    pub by: Vec<Assignment>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Node,
+
+    #[serde(skip)]
+    pub span: Span,
+}
+
+/// A piped operator, applied in order to the output of the previous one.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum QueryOperator {
+    Where(Node),
+    /// Keeps only the listed fields, computed fields are given a name.
+    Project(Vec<Assignment>),
+    /// Unique combinations of the listed fields, or of whole documents when empty.
+    Distinct(Vec<IdentityValue>),
+    /// Orders the documents so window functions can be used by the following operators.
+    Serialize(WindowExpr),
+    /// Adds computed fields to every document.
+    Extend(Vec<Assignment>),
+    Union(UnionExpr),
+    Intersect(Vec<QueryExpr>),
+    Except(Vec<QueryExpr>),
+    /// Keeps at most this many documents.
+    Take(u64),
+    /// Groups the documents and computes aggregates over every group.
+    Summarize(SummarizeExpr),
+    /// Locks the documents until the transaction completes, so others can't change them.
+    ForUpdate,
+}
+
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct QueryExpr {
-    pub table: IdentityValue,
-    pub filter: Vec<BinaryExpr>,
+    pub source: QuerySource,
+    pub operators: Vec<QueryOperator>,
     pub order: Vec<OrderByClause>,
+
+    #[serde(skip)]
+    pub span: Span,
+}
+
+impl QueryExpr {
+    /// Builds a query that reads a whole table.
+    pub fn table(name: &str) -> Self {
+        QueryExpr {
+            source: QuerySource::Table(IdentityValue {
+                value: name.to_owned(),
+                alias: None,
+                span: Span::default(),
+            }),
+            operators: Vec::new(),
+            order: Vec::new(),
+            span: Span::default(),
+        }
+    }
+
+    /// The name a query reads when it is nothing but a name, which a `let` binds to whatever
+    /// was bound to the name before, a scalar or a query.
+    pub fn bare_name(&self) -> Option<&str> {
+        match &self.source {
+            QuerySource::Table(table)
+                if table.alias.is_none() && self.operators.is_empty() && self.order.is_empty() =>
+            {
+                Some(&table.value)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The values an `in` expression is matched against.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum InSet {
+    List(Vec<Node>),
+    /// The first field of every document the query returns.
+    Query(QueryExpr),
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct InExpr {
+    pub expr: Box<Node>,
+    pub negated: bool,
+    pub set: InSet,
+
+    #[serde(skip)]
+    pub span: Span,
+}
+
+/// Binds the result of a scalar expression or a query to a name for the statements that follow.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct LetExpr {
+    pub name: String,
+    pub value: Box<Node>,
+
+    #[serde(skip)]
+    pub span: Span,
+}
+
+/// Adds documents to a collection, one for every row of values.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct InsertExpr {
+    pub table: IdentityValue,
+    pub rows: Vec<Vec<Assignment>>,
+}
+
+/// Sets fields on the documents of a collection that match the filter.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct UpdateExpr {
+    pub table: IdentityValue,
+    pub assignments: Vec<Assignment>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<Box<Node>>,
+}
+
+/// Removes the documents of a collection that match the filter.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct DeleteExpr {
+    pub table: IdentityValue,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<Box<Node>>,
+}
+
+/// Controls the transaction a script runs in. The statements between `begin` and `commit` run
+/// in one transaction, a `begin` inside of it opens a nested scope that can be rolled back on
+/// its own.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum TransactionStmt {
+    Begin,
+    Commit,
+    Rollback,
+    /// Marks a point the transaction can be rolled back to by name
+    Savepoint(String),
+    /// Undoes the writes since the savepoint, which is kept
+    RollbackTo(String),
 }
 
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
@@ -71,7 +315,41 @@ pub enum Node {
     Identity(IdentityValue),
     Scalar(ScalarValue),
     BinaryExpr(BinaryExpr),
+    UnaryExpr(UnaryExpr),
+    /// A named placeholder (without its `@`/`$` sigil) that is bound at execution time.
+    Parameter(String),
+    FunctionCall(FunctionCall),
+    In(InExpr),
+    /// The first field of the first document a query returns.
+    ToScalar(Box<QueryExpr>),
     Query(QueryExpr),
+    Let(LetExpr),
+    Insert(InsertExpr),
+    Update(UpdateExpr),
+    Delete(DeleteExpr),
+    Transaction(TransactionStmt),
+}
+
+impl Node {
+    /// Where the node was parsed from, literals and parameters don't record one.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Node::Identity(identity) => Some(identity.span),
+            Node::BinaryExpr(binary) => Some(binary.span),
+            Node::UnaryExpr(unary) => Some(unary.span),
+            Node::FunctionCall(call) => Some(call.span),
+            Node::In(in_expr) => Some(in_expr.span),
+            Node::ToScalar(query) => Some(query.span),
+            Node::Query(query) => Some(query.span),
+            Node::Let(let_expr) => Some(let_expr.span),
+            Node::Scalar(_)
+            | Node::Parameter(_)
+            | Node::Insert(_)
+            | Node::Update(_)
+            | Node::Delete(_)
+            | Node::Transaction(_) => None,
+        }
+    }
 }
 
 impl TryInto<BinaryExpr> for Node {
@@ -86,6 +364,18 @@ impl TryInto<BinaryExpr> for Node {
     }
 }
 
+impl TryInto<UnaryExpr> for Node {
+    type Error = ();
+
+    fn try_into(self) -> Result<UnaryExpr, Self::Error> {
+        if let Node::UnaryExpr(value) = &self {
+            return Ok(value.clone());
+        }
+
+        Err(())
+    }
+}
+
 impl TryInto<IdentityValue> for Node {
     type Error = ();
 
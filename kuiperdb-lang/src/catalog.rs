@@ -0,0 +1,123 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+//! A description of the collections of a database, so queries can be checked and completed
+//! without access to the data. The engine takes snapshots from a sample of every collection.
+
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+
+/// The type of a value, as far as it can be told without running a query. Timespans are
+/// numbers of milliseconds and have the `Int` type.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueType {
+    Null,
+    Boolean,
+    Int,
+    Decimal,
+    String,
+    Date,
+    ObjectId,
+    Binary,
+    Array,
+    Object,
+}
+
+impl ValueType {
+    pub fn is_numeric(self) -> bool {
+        matches!(self, ValueType::Int | ValueType::Decimal)
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ValueType::Null => "null",
+            ValueType::Boolean => "boolean",
+            ValueType::Int => "int",
+            ValueType::Decimal => "decimal",
+            ValueType::String => "string",
+            ValueType::Date => "datetime",
+            ValueType::ObjectId => "objectid",
+            ValueType::Binary => "binary",
+            ValueType::Array => "array",
+            ValueType::Object => "object",
+        })
+    }
+}
+
+/// A top level field and every type it was seen with.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct FieldInfo {
+    pub name: String,
+    pub types: Vec<ValueType>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct CollectionInfo {
+    pub name: String,
+
+    /// The fields of the sampled documents, in the order they were first seen.
+    pub fields: Vec<FieldInfo>,
+
+    /// How many documents the fields were collected from. Nothing is known about the fields
+    /// of a collection without samples.
+    pub sampled: usize,
+}
+
+impl CollectionInfo {
+    pub fn field(&self, name: &str) -> Option<&FieldInfo> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct Catalog {
+    pub collections: Vec<CollectionInfo>,
+}
+
+impl Catalog {
+    /// Collection names are case insensitive, like in storage.
+    pub fn collection(&self, name: &str) -> Option<&CollectionInfo> {
+        self.collections
+            .iter()
+            .find(|collection| collection.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The collections a table pattern of a `union` refers to.
+    pub fn matching<'a>(&'a self, pattern: &'a str) -> impl Iterator<Item = &'a CollectionInfo> {
+        self.collections
+            .iter()
+            .filter(move |collection| matches_pattern(pattern, &collection.name))
+    }
+}
+
+/// Matches a collection name against a table pattern, where `*` matches any run of characters.
+/// Both are compared case insensitively.
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let name = name.to_lowercase();
+    let mut parts = pattern.split('*');
+
+    // The first part is anchored at the start, the last part at the end
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match name.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        if i == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.is_empty()
+}
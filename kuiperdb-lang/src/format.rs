@@ -0,0 +1,1019 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+// Turns an AST back into query text. The output is normalized: keywords are lower case, every
+// piped operator of a statement starts a new line and parentheses are only written where the
+// precedence of the operators requires them, so `parse_query(format_query(ast)) == ast`.
+
+use std::fmt;
+
+use crate::ast::{
+    Assignment, BinaryOp, IdentityValue, InSet, Node, OrderByClause, OrderByDirection, QueryExpr,
+    QueryOperator, QuerySource, ScalarValue, SummarizeExpr, TransactionStmt, UnaryOp, UnionExpr,
+    WindowExpr,
+};
+
+/// Returned for an AST that has no representation in the query language, such as the
+/// statements only the SQL front-end produces.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FormatError(pub String);
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+type Result<T> = std::result::Result<T, FormatError>;
+
+/// Formats the statements returned by `parse_query`, separated by `;` and a blank line.
+pub fn format_query(nodes: &[Node]) -> Result<String> {
+    let statements = nodes
+        .iter()
+        .map(format_statement)
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(statements.join(";\n\n"))
+}
+
+fn format_statement(node: &Node) -> Result<String> {
+    match node {
+        Node::Query(query) => format_query_expr(query, "\n"),
+        Node::Let(let_expr) => {
+            if !is_identifier(&let_expr.name) {
+                return Err(invalid_name(&let_expr.name));
+            }
+
+            let value = match let_expr.value.as_ref() {
+                Node::Query(query) => format_query_expr(query, "\n")?,
+                // A bare path or keyword, like `null`, would be read back as a table
+                Node::Identity(_) => format!("({})", format_expr(&let_expr.value)?),
+                value => match format_expr(value)? {
+                    text if is_identifier(&text) => format!("({})", text),
+                    text => text,
+                },
+            };
+
+            Ok(format!("let {} = {}", let_expr.name, value))
+        }
+        Node::Transaction(statement) => format_transaction(statement),
+        _ => Err(FormatError(String::from(
+            "Only queries, let and transaction statements can be formatted",
+        ))),
+    }
+}
+
+fn format_transaction(statement: &TransactionStmt) -> Result<String> {
+    Ok(match statement {
+        TransactionStmt::Begin => String::from("begin"),
+        TransactionStmt::Commit => String::from("commit"),
+        TransactionStmt::Rollback => String::from("rollback"),
+        TransactionStmt::Savepoint(name) | TransactionStmt::RollbackTo(name)
+            if !is_identifier(name) =>
+        {
+            return Err(invalid_name(name))
+        }
+        TransactionStmt::Savepoint(name) => format!("savepoint {}", name),
+        TransactionStmt::RollbackTo(name) => format!("rollback to {}", name),
+    })
+}
+
+/// Formats a query, `separator` is written before every piped operator.
+fn format_query_expr(query: &QueryExpr, separator: &str) -> Result<String> {
+    if !query.order.is_empty() {
+        return Err(FormatError(String::from(
+            "Query ordering has no representation, use serialize",
+        )));
+    }
+
+    let mut text = match &query.source {
+        QuerySource::Table(table) => format_table(table)?,
+        QuerySource::Union(union) => format_union(union)?,
+    };
+
+    for operator in &query.operators {
+        text.push_str(separator);
+        text.push_str("| ");
+        text.push_str(&format_operator(operator)?);
+    }
+
+    Ok(text)
+}
+
+/// Nested queries are written on a single line.
+fn format_subquery(query: &QueryExpr) -> Result<String> {
+    format_query_expr(query, " ")
+}
+
+fn format_table(table: &IdentityValue) -> Result<String> {
+    match &table.alias {
+        Some(alias) if !is_identifier(alias) => Err(invalid_name(alias)),
+        Some(alias) => Ok(format!("{} as {}", table.value, alias)),
+        None => Ok(table.value.clone()),
+    }
+}
+
+fn format_operator(operator: &QueryOperator) -> Result<String> {
+    Ok(match operator {
+        QueryOperator::Where(expr) => format!("where {}", format_expr(expr)?),
+        QueryOperator::Project(items) => {
+            let items = items
+                .iter()
+                .map(|item| match &item.value {
+                    Node::Identity(identity)
+                        if identity.alias.is_none() && identity.value == item.name =>
+                    {
+                        Ok(item.name.clone())
+                    }
+                    _ => format_assignment(item),
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            format!("project {}", items.join(", "))
+        }
+        QueryOperator::Distinct(fields) if fields.is_empty() => String::from("distinct *"),
+        QueryOperator::Distinct(fields) => format!("distinct {}", format_paths(fields)?),
+        QueryOperator::Serialize(window) => format_window(window)?,
+        QueryOperator::Extend(items) => format!("extend {}", format_assignments(items)?),
+        QueryOperator::Union(union) => format_union(union)?,
+        QueryOperator::Intersect(operands) => {
+            format!("intersect {}", format_set_operands(operands)?)
+        }
+        QueryOperator::Except(operands) => format!("except {}", format_set_operands(operands)?),
+        QueryOperator::Take(limit) => format!("take {}", limit),
+        QueryOperator::Summarize(summarize) => format_summarize(summarize)?,
+        QueryOperator::ForUpdate => String::from("for update"),
+    })
+}
+
+fn format_union(union: &UnionExpr) -> Result<String> {
+    let operands = format_set_operands(&union.operands)?;
+
+    match &union.with_source {
+        Some(name) if !is_identifier(name) => Err(invalid_name(name)),
+        Some(name) => Ok(format!("union withsource={} {}", name, operands)),
+        None => Ok(format!("union {}", operands)),
+    }
+}
+
+/// Plain tables are written as their name, anything else as a parenthesized query.
+fn format_set_operands(operands: &[QueryExpr]) -> Result<String> {
+    let operands = operands
+        .iter()
+        .map(|operand| match &operand.source {
+            QuerySource::Table(table) if table.alias.is_none() && operand.operators.is_empty() => {
+                Ok(table.value.clone())
+            }
+            _ => Ok(format!("({})", format_subquery(operand)?)),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(operands.join(", "))
+}
+
+fn format_window(window: &WindowExpr) -> Result<String> {
+    let mut text = String::from("serialize");
+
+    if !window.partition_by.is_empty() {
+        text.push_str(" partition by ");
+        text.push_str(&format_paths(&window.partition_by)?);
+    }
+
+    if !window.order_by.is_empty() {
+        let order_by = window
+            .order_by
+            .iter()
+            .map(format_order_by)
+            .collect::<Result<Vec<_>>>()?;
+
+        text.push_str(" order by ");
+        text.push_str(&order_by.join(", "));
+    }
+
+    Ok(text)
+}
+
+fn format_order_by(clause: &OrderByClause) -> Result<String> {
+    let direction = match clause.direction {
+        OrderByDirection::Asc => "asc",
+        OrderByDirection::Desc => "desc",
+    };
+
+    Ok(format!("{} {}", format_path(&clause.identity)?, direction))
+}
+
+/// Items whose name is the one the parser would give them are written without it.
+fn format_summarize(summarize: &SummarizeExpr) -> Result<String> {
+    let mut aggregates = Vec::with_capacity(summarize.aggregates.len());
+    for (i, item) in summarize.aggregates.iter().enumerate() {
+        let default_name = match &item.value {
+            Node::FunctionCall(call) => match call.args.as_slice() {
+                [Node::Identity(identity)] => format!("{}_{}", call.name, identity.value),
+                _ => format!("{}_", call.name),
+            },
+            _ => format!("Column{}", i + 1),
+        };
+
+        aggregates.push(format_summarize_item(item, &default_name)?);
+    }
+
+    let mut text = format!("summarize {}", aggregates.join(", "));
+
+    if !summarize.by.is_empty() {
+        let mut by = Vec::with_capacity(summarize.by.len());
+        for (i, item) in summarize.by.iter().enumerate() {
+            let default_name = match &item.value {
+                Node::Identity(identity) => identity.value.clone(),
+                _ => format!("Column{}", i + 1),
+            };
+
+            by.push(format_summarize_item(item, &default_name)?);
+        }
+
+        text.push_str(" by ");
+        text.push_str(&by.join(", "));
+    }
+
+    Ok(text)
+}
+
+fn format_summarize_item(item: &Assignment, default_name: &str) -> Result<String> {
+    if item.name == default_name {
+        format_expr(&item.value)
+    } else {
+        format_assignment(item)
+    }
+}
+
+fn format_assignments(items: &[Assignment]) -> Result<String> {
+    let items = items
+        .iter()
+        .map(format_assignment)
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(items.join(", "))
+}
+
+fn format_assignment(item: &Assignment) -> Result<String> {
+    if !is_identifier(&item.name) {
+        return Err(invalid_name(&item.name));
+    }
+
+    Ok(format!("{} = {}", item.name, format_expr(&item.value)?))
+}
+
+fn format_paths(fields: &[IdentityValue]) -> Result<String> {
+    let fields = fields.iter().map(format_path).collect::<Result<Vec<_>>>()?;
+
+    Ok(fields.join(", "))
+}
+
+fn format_path(identity: &IdentityValue) -> Result<String> {
+    match identity.alias {
+        Some(_) => Err(FormatError(format!(
+            "Only tables can have an alias, found one on {}",
+            identity.value
+        ))),
+        None => Ok(identity.value.clone()),
+    }
+}
+
+/// Binding strength of an expression, matching the precedence of the parser, higher binds
+/// tighter. Terms that never need parentheses share the highest value.
+fn precedence(node: &Node) -> u8 {
+    match node {
+        Node::BinaryExpr(binary) => match binary.op {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Eq
+            | BinaryOp::Ne
+            | BinaryOp::Lt
+            | BinaryOp::LtEq
+            | BinaryOp::Gt
+            | BinaryOp::GtEq
+            | BinaryOp::Like => 4,
+            BinaryOp::Add | BinaryOp::Subtract => 6,
+            BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Modulo => 7,
+            BinaryOp::Exponentiate => 9,
+        },
+        Node::UnaryExpr(unary) => match unary.op {
+            UnaryOp::Not => 3,
+            UnaryOp::Negate => 8,
+        },
+        Node::In(_) => 5,
+        // The sign of a literal binds like a negation, `-2 ^ 2` negates the power
+        Node::Scalar(ScalarValue::Int(value)) if *value < 0 => 8,
+        Node::Scalar(ScalarValue::Decimal(value)) if value.is_sign_negative() => 8,
+        Node::Scalar(ScalarValue::Timespan(value)) if *value < 0 => 8,
+        _ => 10,
+    }
+}
+
+/// Formats an expression, wrapping it in parentheses when it binds looser than `min`.
+fn format_operand(node: &Node, min: u8) -> Result<String> {
+    let text = format_expr(node)?;
+
+    if precedence(node) < min {
+        Ok(format!("({})", text))
+    } else {
+        Ok(text)
+    }
+}
+
+/// Formats an expression with the minimal amount of parentheses.
+pub fn format_expr(node: &Node) -> Result<String> {
+    match node {
+        Node::Identity(identity) => format_path(identity),
+        Node::Scalar(scalar) => format_scalar(scalar),
+        Node::Parameter(name) => Ok(format!("@{}", name)),
+        Node::BinaryExpr(binary) => {
+            let op = match binary.op {
+                BinaryOp::Eq => "==",
+                BinaryOp::Ne => "!=",
+                BinaryOp::Lt => "<",
+                BinaryOp::LtEq => "<=",
+                BinaryOp::Gt => ">",
+                BinaryOp::GtEq => ">=",
+                BinaryOp::And => "and",
+                BinaryOp::Or => "or",
+                BinaryOp::Add => "+",
+                BinaryOp::Subtract => "-",
+                BinaryOp::Multiply => "*",
+                BinaryOp::Divide => "/",
+                BinaryOp::Modulo => "%",
+                BinaryOp::Exponentiate => "^",
+                BinaryOp::Like => {
+                    return Err(FormatError(String::from(
+                        "Like has no representation in the query language",
+                    )))
+                }
+            };
+
+            // Only exponentiation is right associative
+            let p = precedence(node);
+            let (left, right) = match binary.op {
+                BinaryOp::Exponentiate => (p + 1, p),
+                _ => (p, p + 1),
+            };
+
+            Ok(format!(
+                "{} {} {}",
+                format_operand(&binary.left, left)?,
+                op,
+                format_operand(&binary.right, right)?
+            ))
+        }
+        Node::UnaryExpr(unary) => {
+            let operand = format_operand(&unary.expr, precedence(node))?;
+
+            match unary.op {
+                UnaryOp::Not => Ok(format!("not {}", operand)),
+                // `--` would start a comment, and a sign before a number is part of its literal
+                UnaryOp::Negate
+                    if operand.starts_with(|c: char| c == '-' || c.is_ascii_digit()) =>
+                {
+                    Ok(format!("-({})", operand))
+                }
+                UnaryOp::Negate => Ok(format!("-{}", operand)),
+            }
+        }
+        Node::FunctionCall(call) => {
+            if !is_identifier(&call.name) {
+                return Err(invalid_name(&call.name));
+            }
+
+            let args = call
+                .args
+                .iter()
+                .map(format_expr)
+                .collect::<Result<Vec<_>>>()?;
+
+            Ok(format!("{}({})", call.name, args.join(", ")))
+        }
+        Node::In(in_expr) => {
+            let set = match &in_expr.set {
+                InSet::List(values) if values.is_empty() => {
+                    return Err(FormatError(String::from("An in list can't be empty")))
+                }
+                InSet::List(values) => values
+                    .iter()
+                    .map(format_expr)
+                    .collect::<Result<Vec<_>>>()?
+                    .join(", "),
+                // Without operators the query would be read back as a list
+                InSet::Query(query) if query.operators.is_empty() => {
+                    return Err(FormatError(String::from(
+                        "An in subquery needs at least one operator",
+                    )))
+                }
+                InSet::Query(query) => format_subquery(query)?,
+            };
+
+            let op = if in_expr.negated { "!in" } else { "in" };
+
+            Ok(format!(
+                "{} {} ({})",
+                format_operand(&in_expr.expr, precedence(node))?,
+                op,
+                set
+            ))
+        }
+        Node::ToScalar(query) => Ok(format!("toscalar({})", format_subquery(query)?)),
+        _ => Err(FormatError(String::from(
+            "A statement can't be formatted as an expression",
+        ))),
+    }
+}
+
+/// Formats a literal.
+fn format_scalar(scalar: &ScalarValue) -> Result<String> {
+    Ok(match scalar {
+        ScalarValue::Int(value) => value.to_string(),
+        ScalarValue::Decimal(value) if !value.is_finite() => {
+            return Err(FormatError(format!("{} can't be written", value)))
+        }
+        ScalarValue::Decimal(value) => {
+            // Display never uses an exponent, but drops the fraction of whole numbers
+            let text = value.to_string();
+            if text.contains('.') {
+                text
+            } else {
+                format!("{}.0", text)
+            }
+        }
+        ScalarValue::String(value) => format_string(value),
+        ScalarValue::Boolean(value) => value.to_string(),
+        ScalarValue::Date(value) => format!("datetime({})", value),
+        ScalarValue::Timespan(value) => format_timespan(*value),
+        ScalarValue::ObjectId(value) => format!("objectid(\"{}\")", value),
+        ScalarValue::Uuid(value) => format!("uuid(\"{}\")", value),
+        ScalarValue::Array(values) => {
+            let values = values
+                .iter()
+                .map(format_scalar)
+                .collect::<Result<Vec<_>>>()?;
+
+            format!("[{}]", values.join(", "))
+        }
+        ScalarValue::Object(fields) => {
+            let fields = fields
+                .iter()
+                .map(|(key, value)| {
+                    Ok(format!("{}: {}", format_string(key), format_scalar(value)?))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            format!("{{{}}}", fields.join(", "))
+        }
+        ScalarValue::Null => String::from("null"),
+        ScalarValue::Undefined => String::from("undefined"),
+    })
+}
+
+/// Writes a timespan in the largest unit that keeps it a whole number.
+fn format_timespan(value: i64) -> String {
+    let units = [
+        (86_400_000, "d"),
+        (3_600_000, "h"),
+        (60_000, "m"),
+        (1_000, "s"),
+    ];
+
+    for (scale, unit) in units {
+        if value != 0 && value % scale == 0 {
+            return format!("{}{}", value / scale, unit);
+        }
+    }
+
+    format!("{}ms", value)
+}
+
+/// Quotes a string, escaping the characters the parser resolves in `parse_string_literal`.
+fn format_string(value: &str) -> String {
+    let mut text = String::with_capacity(value.len() + 2);
+    text.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => text.push_str("\\\""),
+            '\\' => text.push_str("\\\\"),
+            '\u{0008}' => text.push_str("\\b"),
+            '\t' => text.push_str("\\t"),
+            '\n' => text.push_str("\\n"),
+            '\u{000C}' => text.push_str("\\f"),
+            '\r' => text.push_str("\\r"),
+            c if c.is_control() => {
+                let code = c as u32;
+                if code <= 0xFFFF {
+                    text.push_str(&format!("\\u{:04x}", code));
+                } else {
+                    text.push_str(&format!("\\U{:08x}", code));
+                }
+            }
+            c => text.push(c),
+        }
+    }
+
+    text.push('"');
+    text
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+fn invalid_name(name: &str) -> FormatError {
+    FormatError(format!("{:?} isn't a valid name", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{
+        BinaryExpr, FunctionCall, InExpr, LetExpr, OrderByClause, Span, UnaryExpr, WindowExpr,
+    };
+    use crate::parser::parse_query;
+    use crate::visit::clear_spans;
+    use proptest::collection::vec;
+    use proptest::option;
+    use proptest::prelude::*;
+    use proptest::sample::select;
+    use proptest::strategy::Union;
+
+    /// Parses a script with its spans cleared, the offsets differ between the two texts.
+    fn parse(
+        source: &str,
+    ) -> std::result::Result<Vec<Node>, pest::error::Error<crate::parser::Rule>> {
+        let mut ast = parse_query(source)?;
+        ast.iter_mut().for_each(clear_spans);
+        Ok(ast)
+    }
+
+    fn round_trip(source: &str) -> String {
+        let ast = parse(source).unwrap();
+        let text = format_query(&ast).unwrap();
+        assert_eq!(parse(&text).unwrap(), ast, "{}", text);
+        text
+    }
+
+    #[test]
+    fn normalizes_queries() {
+        let text = round_trip(
+            "events AS e | WHERE x = 1 AND NOT y IS NULL | project a.b, total = a + b * c\
+             | summarize count(), sum(amount), n = max(x) by user, day = bin(ts, 1d) | limit 10",
+        );
+
+        assert_eq!(
+            text,
+            "events as e\n\
+             | where x == 1 and not y == null\n\
+             | project a.b, total = a + b * c\n\
+             | summarize count(), sum(amount), n = max(x) by user, day = bin(ts, 1d)\n\
+             | take 10"
+        );
+    }
+
+    #[test]
+    fn minimal_parentheses() {
+        assert_eq!(
+            round_trip("t | where (a + b) * c == -(d - e) ^ 2 or (x or y) and z"),
+            "t\n| where (a + b) * c == -(d - e) ^ 2 or (x or y) and z"
+        );
+        assert_eq!(
+            round_trip("t | where (-2) ^ 2 == 2 ^ (-2) and (not a) in (1, 2)"),
+            "t\n| where (-2) ^ 2 == 2 ^ (-2) and (not a) in (1, 2)"
+        );
+        assert_eq!(round_trip("let x = - -a"), "let x = -(-a)");
+    }
+
+    #[test]
+    fn statements_and_subqueries() {
+        round_trip(
+            "let threshold = 100; let recent = events | where ts > ago(1d);\
+             union withsource=src events_*, (archive | where x = 1) | serialize partition by a \
+             order by ts desc, b | extend rn = row_number() | distinct * | intersect t, (u | take 1)\
+             | except v | where id !in (users | project _id) and n < toscalar(t | summarize count())\
+             | where p == @param",
+        );
+    }
+
+    #[test]
+    fn literals() {
+        let text = round_trip(
+            "t | where a == [1, -2.5, \"q\\\"\\\\\\n\\u0001\", {\"a b\": null, c: [true]}] \
+             and b == datetime(2024-01-01) and c == -90m and d == 0.00001 and e == 1500ms \
+             and f == objectid(\"64B7F0C2A1E4D3B2C1A09F8E\") and g == undefined \
+             and h == uuid(\"67e55044-10b1-426f-9247-bb680e5fe0c8\") and i == 100000000000000000000.0",
+        );
+
+        assert!(text.contains("datetime(2024-01-01T00:00:00Z)"));
+        assert!(text.contains("-90m"));
+        assert!(text.contains("1500ms"));
+        assert!(text.contains("100000000000000000000.0"));
+
+        let text = round_trip(
+            "t | where a == -9223372036854775808 and b == [-9223372036854775808, -1d] \
+             and c == -(7) and d == (-2) ^ 2",
+        );
+        assert_eq!(
+            text,
+            "t\n| where a == -9223372036854775808 and b == [-9223372036854775808, -1d] \
+             and c == -(7) and d == (-2) ^ 2"
+        );
+    }
+
+    #[test]
+    fn unsupported_nodes() {
+        let like = Node::BinaryExpr(BinaryExpr {
+            op: BinaryOp::Like,
+            left: Box::new(Node::Identity(IdentityValue {
+                value: String::from("name"),
+                alias: None,
+                span: Span::default(),
+            })),
+            right: Box::new(Node::Scalar(ScalarValue::String(String::from("a%")))),
+            span: Span::default(),
+        });
+        assert!(format_expr(&like).is_err());
+
+        let mut query = QueryExpr::table("t");
+        query
+            .operators
+            .push(QueryOperator::Project(vec![Assignment {
+                name: String::from("COUNT(*)"),
+                value: Node::FunctionCall(FunctionCall {
+                    name: String::from("count"),
+                    args: Vec::new(),
+                    span: Span::default(),
+                }),
+                span: Span::default(),
+            }]));
+        assert!(format_query(&[Node::Query(query)]).is_err());
+    }
+
+    const NAMES: &[&str] = &["a", "b", "user", "ts", "_id", "Total_2", "x1"];
+    const PATHS: &[&str] = &["a", "b.c", "user->name", "ts", "_x.y.z"];
+    const TABLES: &[&str] = &["events", "t", "archive_2023"];
+    const PATTERNS: &[&str] = &["events_*", "t", "*_2023"];
+    const CHARS: &[&str] = &[
+        "a", " ", "\"", "\\", "\n", "\t", "\u{1}", "é", "😀", "--", "#",
+    ];
+    const DATES: &[&str] = &[
+        "2024-01-01T00:00:00Z",
+        "2024-02-29T10:30:00.5+02:00",
+        "1999-12-31T23:59:59-05:00",
+    ];
+
+    fn identity(value: &str) -> IdentityValue {
+        IdentityValue {
+            value: value.to_owned(),
+            alias: None,
+            span: Span::default(),
+        }
+    }
+
+    fn pick(values: &'static [&'static str]) -> impl Strategy<Value = String> + Clone {
+        select(values).prop_map(String::from)
+    }
+
+    /// Integers of every magnitude.
+    fn int() -> impl Strategy<Value = i64> + Clone {
+        (any::<i64>(), 0..64_u32).prop_map(|(value, shift)| value >> shift)
+    }
+
+    fn scalar(depth: u32) -> BoxedStrategy<ScalarValue> {
+        let leaf = prop_oneof![
+            int().prop_map(ScalarValue::Int),
+            int().prop_map(|value| ScalarValue::Decimal(value as f64 / 1024.0)),
+            vec(select(CHARS), 0..6).prop_map(|chars| ScalarValue::String(chars.concat())),
+            any::<bool>().prop_map(ScalarValue::Boolean),
+            pick(DATES).prop_map(ScalarValue::Date),
+            (-1_i64 << 40..1_i64 << 40).prop_map(ScalarValue::Timespan),
+            Just(ScalarValue::ObjectId(String::from(
+                "64b7f0c2a1e4d3b2c1a09f8e"
+            ))),
+            Just(ScalarValue::Uuid(String::from(
+                "67e55044-10b1-426f-9247-bb680e5fe0c8"
+            ))),
+            Just(ScalarValue::Null),
+            Just(ScalarValue::Undefined),
+        ];
+        if depth == 0 {
+            return leaf.boxed();
+        }
+
+        let inner = scalar(depth - 1);
+        prop_oneof![
+            10 => leaf,
+            1 => vec(inner.clone(), 0..4).prop_map(ScalarValue::Array),
+            1 => vec((pick(&["a", "b c", "\"", "1"]), inner), 0..4).prop_map(ScalarValue::Object),
+        ]
+        .boxed()
+    }
+
+    /// The expressions and queries nested up to a depth, built once per level since every
+    /// level refers to the one below it many times.
+    #[derive(Clone)]
+    struct Grammar {
+        expr: BoxedStrategy<Node>,
+        query: BoxedStrategy<QueryExpr>,
+    }
+
+    fn grammar(depth: u32) -> Grammar {
+        let leaf = prop_oneof![
+            pick(PATHS).prop_map(|path| Node::Identity(identity(&path))),
+            scalar(2).prop_map(Node::Scalar),
+            pick(NAMES).prop_map(Node::Parameter),
+        ];
+        if depth == 0 {
+            let expr = leaf.boxed();
+            return Grammar {
+                query: query(&expr, None),
+                expr,
+            };
+        }
+
+        let inner = grammar(depth - 1);
+        let ops = [
+            BinaryOp::Eq,
+            BinaryOp::Lt,
+            BinaryOp::LtEq,
+            BinaryOp::Gt,
+            BinaryOp::GtEq,
+            BinaryOp::Ne,
+            BinaryOp::And,
+            BinaryOp::Or,
+            BinaryOp::Add,
+            BinaryOp::Subtract,
+            BinaryOp::Multiply,
+            BinaryOp::Divide,
+            BinaryOp::Modulo,
+            BinaryOp::Exponentiate,
+        ];
+        let binary = (select(ops.to_vec()), inner.expr.clone(), inner.expr.clone()).prop_map(
+            |(op, left, right)| {
+                Node::BinaryExpr(BinaryExpr {
+                    op,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                    span: Span::default(),
+                })
+            },
+        );
+        let unary = (inner.expr.clone(), any::<bool>()).prop_map(|(expr, not)| {
+            Node::UnaryExpr(UnaryExpr {
+                op: match not {
+                    true => UnaryOp::Not,
+                    false => UnaryOp::Negate,
+                },
+                expr: Box::new(expr),
+                span: Span::default(),
+            })
+        });
+        let call = (
+            select(&["count", "ago", "bin", "isnull"][..]),
+            vec(inner.expr.clone(), 0..3),
+        )
+            .prop_map(|(name, args)| {
+                Node::FunctionCall(FunctionCall {
+                    name: name.to_owned(),
+                    args,
+                    span: Span::default(),
+                })
+            });
+        let to_scalar = inner
+            .query
+            .clone()
+            .prop_map(|query| Node::ToScalar(Box::new(query)));
+        let in_query = (inner.expr.clone(), any::<bool>(), inner.query.clone()).prop_map(
+            |(expr, negated, mut query)| {
+                if query.operators.is_empty() {
+                    query.operators.push(QueryOperator::Take(1));
+                }
+                Node::In(InExpr {
+                    expr: Box::new(expr),
+                    negated,
+                    set: InSet::Query(query),
+                    span: Span::default(),
+                })
+            },
+        );
+        let in_list = (
+            inner.expr.clone(),
+            any::<bool>(),
+            vec(inner.expr.clone(), 1..4),
+        )
+            .prop_map(|(expr, negated, values)| {
+                Node::In(InExpr {
+                    expr: Box::new(expr),
+                    negated,
+                    set: InSet::List(values),
+                    span: Span::default(),
+                })
+            });
+
+        let expr = prop_oneof![
+            3 => leaf,
+            3 => binary,
+            1 => unary,
+            1 => call,
+            1 => prop_oneof![1 => to_scalar, 1 => in_query, 2 => in_list],
+        ]
+        .boxed();
+
+        Grammar {
+            query: query(&expr, Some(&inner)),
+            expr,
+        }
+    }
+
+    fn assignments(expr: &BoxedStrategy<Node>) -> impl Strategy<Value = Vec<Assignment>> + Clone {
+        vec((pick(NAMES), expr.clone()), 1..4).prop_map(|items| {
+            items
+                .into_iter()
+                .map(|(name, value)| Assignment {
+                    name,
+                    value,
+                    span: Span::default(),
+                })
+                .collect()
+        })
+    }
+
+    fn paths() -> impl Strategy<Value = Vec<IdentityValue>> + Clone {
+        vec(pick(PATHS).prop_map(|path| identity(&path)), 1..4)
+    }
+
+    fn set_operands(query: &BoxedStrategy<QueryExpr>) -> impl Strategy<Value = Vec<QueryExpr>> {
+        let operand = prop_oneof![select(PATTERNS).prop_map(QueryExpr::table), query.clone(),];
+        vec(operand, 1..4)
+    }
+
+    fn union(query: &BoxedStrategy<QueryExpr>) -> impl Strategy<Value = UnionExpr> {
+        (option::of(pick(NAMES)), set_operands(query)).prop_map(|(with_source, operands)| {
+            UnionExpr {
+                with_source,
+                operands,
+            }
+        })
+    }
+
+    /// Summarize items are named the way the parser names them when no name is written.
+    fn summarize(expr: &BoxedStrategy<Node>) -> impl Strategy<Value = SummarizeExpr> {
+        (
+            assignments(expr),
+            any::<bool>(),
+            option::of((assignments(expr), pick(PATHS))),
+        )
+            .prop_map(|(aggregates, sum, by)| {
+                let mut summarize = SummarizeExpr {
+                    aggregates,
+                    by: Vec::new(),
+                };
+
+                if sum {
+                    let value = Node::FunctionCall(FunctionCall {
+                        name: String::from("sum"),
+                        args: vec![Node::Identity(identity("amount"))],
+                        span: Span::default(),
+                    });
+                    summarize.aggregates.push(Assignment {
+                        name: String::from("sum_amount"),
+                        value,
+                        span: Span::default(),
+                    });
+                }
+
+                if let Some((by, path)) = by {
+                    summarize.by = by;
+                    summarize.by.push(Assignment {
+                        value: Node::Identity(identity(&path)),
+                        name: path,
+                        span: Span::default(),
+                    });
+                }
+
+                summarize
+            })
+    }
+
+    fn serialize() -> impl Strategy<Value = WindowExpr> {
+        let direction = prop_oneof![Just(OrderByDirection::Asc), Just(OrderByDirection::Desc)];
+        let order_by = vec(
+            (pick(PATHS), direction).prop_map(|(path, direction)| OrderByClause {
+                identity: identity(&path),
+                direction,
+            }),
+            1..4,
+        );
+
+        (option::of(paths()), option::of(order_by)).prop_map(|(partition_by, order_by)| {
+            WindowExpr {
+                partition_by: partition_by.unwrap_or_default(),
+                order_by: order_by.unwrap_or_default(),
+            }
+        })
+    }
+
+    /// Set operators nest the queries of the level below.
+    fn query(expr: &BoxedStrategy<Node>, inner: Option<&Grammar>) -> BoxedStrategy<QueryExpr> {
+        let project = (assignments(expr), pick(PATHS)).prop_map(|(mut items, path)| {
+            items.push(Assignment {
+                value: Node::Identity(identity(&path)),
+                name: path,
+                span: Span::default(),
+            });
+            QueryOperator::Project(items)
+        });
+        let take = (any::<u64>(), 0..64_u32).prop_map(|(count, shift)| count >> shift);
+
+        let mut operators = vec![
+            expr.clone().prop_map(QueryOperator::Where).boxed(),
+            project.boxed(),
+            option::of(paths())
+                .prop_map(|paths| QueryOperator::Distinct(paths.unwrap_or_default()))
+                .boxed(),
+            serialize().prop_map(QueryOperator::Serialize).boxed(),
+            assignments(expr).prop_map(QueryOperator::Extend).boxed(),
+            summarize(expr).prop_map(QueryOperator::Summarize).boxed(),
+            take.prop_map(QueryOperator::Take).boxed(),
+            Just(QueryOperator::ForUpdate).boxed(),
+        ];
+        let table = (pick(TABLES), option::of(pick(NAMES))).prop_map(|(name, alias)| {
+            QuerySource::Table(IdentityValue {
+                value: name,
+                alias,
+                span: Span::default(),
+            })
+        });
+        let source = match inner {
+            Some(inner) => {
+                operators.extend([
+                    union(&inner.query).prop_map(QueryOperator::Union).boxed(),
+                    set_operands(&inner.query)
+                        .prop_map(QueryOperator::Intersect)
+                        .boxed(),
+                    set_operands(&inner.query)
+                        .prop_map(QueryOperator::Except)
+                        .boxed(),
+                ]);
+                prop_oneof![3 => table, 1 => union(&inner.query).prop_map(QuerySource::Union)]
+                    .boxed()
+            }
+            None => table.boxed(),
+        };
+
+        (source, vec(Union::new(operators), 0..4))
+            .prop_map(|(source, operators)| QueryExpr {
+                source,
+                operators,
+                order: Vec::new(),
+                span: Span::default(),
+            })
+            .boxed()
+    }
+
+    fn statement(grammar: &Grammar) -> impl Strategy<Value = Node> {
+        let value = prop_oneof![
+            grammar.query.clone().prop_map(Node::Query),
+            grammar.expr.clone()
+        ];
+        prop_oneof![
+            1 => (pick(NAMES), value).prop_map(|(name, value)| Node::Let(LetExpr {
+                name,
+                value: Box::new(value),
+                span: Span::default(),
+            })),
+            2 => grammar.query.clone().prop_map(Node::Query),
+            1 => prop_oneof![
+                Just(TransactionStmt::Begin),
+                Just(TransactionStmt::Commit),
+                Just(TransactionStmt::Rollback),
+                pick(NAMES).prop_map(TransactionStmt::Savepoint),
+                pick(NAMES).prop_map(TransactionStmt::RollbackTo),
+            ]
+            .prop_map(Node::Transaction),
+        ]
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(1000))]
+
+        #[test]
+        fn random_asts_round_trip(ast in vec(statement(&grammar(3)), 1..4)) {
+            let text = format_query(&ast).unwrap();
+            let parsed = parse(&text).map_err(|e| TestCaseError::fail(format!("{}\n{}", e, text)))?;
+            prop_assert_eq!(parsed, ast, "{}", text);
+        }
+    }
+}
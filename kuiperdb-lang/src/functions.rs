@@ -0,0 +1,208 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+//! The built-in functions of the query language. The engine checks calls against this table
+//! when planning, editors use it for completions and signatures.
+
+use crate::catalog::ValueType;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FunctionKind {
+    /// Computed from a single document.
+    Scalar,
+    /// Computed over the documents of a `summarize` group.
+    Aggregate,
+    /// Computed over the rows of a serialized window, in an `extend` after `serialize`.
+    Window,
+}
+
+/// The type of the value a function returns.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Returns {
+    Type(ValueType),
+    /// The type of the first argument.
+    FirstArgument,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Function {
+    pub name: &'static str,
+    pub kind: FunctionKind,
+    pub min_args: usize,
+    pub max_args: usize,
+    pub returns: Returns,
+    pub signature: &'static str,
+    pub description: &'static str,
+}
+
+impl Function {
+    /// Describes the number of arguments the function takes, `1 to 3`.
+    pub fn arity(&self) -> String {
+        if self.min_args == self.max_args {
+            self.min_args.to_string()
+        } else if self.max_args == usize::MAX {
+            format!("at least {}", self.min_args)
+        } else {
+            format!("{} to {}", self.min_args, self.max_args)
+        }
+    }
+}
+
+const fn function(
+    name: &'static str,
+    kind: FunctionKind,
+    (min_args, max_args): (usize, usize),
+    returns: Returns,
+    signature: &'static str,
+    description: &'static str,
+) -> Function {
+    Function {
+        name,
+        kind,
+        min_args,
+        max_args,
+        returns,
+        signature,
+        description,
+    }
+}
+
+use FunctionKind::{Aggregate, Scalar, Window};
+use Returns::{FirstArgument, Type};
+
+pub const FUNCTIONS: &[Function] = &[
+    function(
+        "now",
+        Scalar,
+        (0, 0),
+        Type(ValueType::Date),
+        "now()",
+        "The current time.",
+    ),
+    function(
+        "ago",
+        Scalar,
+        (1, 1),
+        Type(ValueType::Date),
+        "ago(timespan)",
+        "The current time minus a timespan.",
+    ),
+    function(
+        "isnull",
+        Scalar,
+        (1, 1),
+        Type(ValueType::Boolean),
+        "isnull(value)",
+        "Whether the value is null or missing.",
+    ),
+    function(
+        "isnotnull",
+        Scalar,
+        (1, 1),
+        Type(ValueType::Boolean),
+        "isnotnull(value)",
+        "Whether the value is present and not null.",
+    ),
+    function(
+        "coalesce",
+        Scalar,
+        (1, usize::MAX),
+        FirstArgument,
+        "coalesce(value, ...)",
+        "The first argument that isn't null.",
+    ),
+    function(
+        "array_concat",
+        Scalar,
+        (1, usize::MAX),
+        Type(ValueType::Array),
+        "array_concat(array, ...)",
+        "The arrays joined into one, null if any of them is null.",
+    ),
+    function(
+        "matches_regex",
+        Scalar,
+        (2, 2),
+        Type(ValueType::Boolean),
+        "matches_regex(value, pattern)",
+        "Whether the string matches the regular expression.",
+    ),
+    function(
+        "count",
+        Aggregate,
+        (0, 1),
+        Type(ValueType::Int),
+        "count([value])",
+        "The number of documents in the group, or of the values that aren't null.",
+    ),
+    function(
+        "sum",
+        Aggregate,
+        (1, 1),
+        FirstArgument,
+        "sum(value)",
+        "The sum of the values of the group, null values are skipped.",
+    ),
+    function(
+        "min",
+        Aggregate,
+        (1, 1),
+        FirstArgument,
+        "min(value)",
+        "The smallest value of the group.",
+    ),
+    function(
+        "max",
+        Aggregate,
+        (1, 1),
+        FirstArgument,
+        "max(value)",
+        "The largest value of the group.",
+    ),
+    function(
+        "avg",
+        Aggregate,
+        (1, 1),
+        Type(ValueType::Decimal),
+        "avg(value)",
+        "The average of the values of the group, null values are skipped.",
+    ),
+    function(
+        "row_number",
+        Window,
+        (0, 0),
+        Type(ValueType::Int),
+        "row_number()",
+        "The position of the row in its partition, starting at 1.",
+    ),
+    function(
+        "prev",
+        Window,
+        (1, 3),
+        FirstArgument,
+        "prev(value[, offset[, default]])",
+        "The value of a previous row of the partition.",
+    ),
+    function(
+        "next",
+        Window,
+        (1, 3),
+        FirstArgument,
+        "next(value[, offset[, default]])",
+        "The value of a following row of the partition.",
+    ),
+    function(
+        "row_cumsum",
+        Window,
+        (1, 1),
+        FirstArgument,
+        "row_cumsum(value)",
+        "The running sum of the value up to the row, within its partition.",
+    ),
+];
+
+/// Finds a built-in function, names are lower case like the parser produces them.
+pub fn lookup(name: &str) -> Option<&'static Function> {
+    FUNCTIONS.iter().find(|function| function.name == name)
+}
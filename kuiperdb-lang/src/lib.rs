@@ -6,8 +6,15 @@ extern crate pest;
 #[macro_use]
 extern crate pest_derive;
 
+pub mod analyzer;
 pub mod ast;
+pub mod catalog;
+pub mod format;
+pub mod functions;
 pub mod parser;
+pub mod service;
+pub mod sql;
+pub mod visit;
 
 #[cfg(test)]
 mod tests;
@@ -3,10 +3,17 @@
 //--------------------------------------------------------------------------
 
 // NOTE: The `Pairs` import is used, it's just not detected and throws a random warning.
+use pest::error::ErrorVariant;
+use pest::pratt_parser::{Assoc, Op, PrattParser};
 #[allow(unused_imports)]
 use pest::{iterators::Pairs, Parser};
+use std::sync::OnceLock;
 
-use crate::ast::{BinaryExpr, BinaryOp, IdentityValue, Node, QueryExpr, ScalarValue};
+use crate::ast::{
+    Assignment, BinaryExpr, BinaryOp, FunctionCall, IdentityValue, InExpr, InSet, LetExpr, Node,
+    OrderByClause, OrderByDirection, QueryExpr, QueryOperator, QuerySource, ScalarValue, Span,
+    SummarizeExpr, TransactionStmt, UnaryExpr, UnaryOp, UnionExpr, WindowExpr,
+};
 
 #[derive(Parser)]
 #[grammar = "grammar.pest"]
@@ -15,12 +22,19 @@ pub struct KlangParser;
 pub fn parse_query(source: &str) -> std::result::Result<Vec<Node>, pest::error::Error<Rule>> {
     let mut ast = vec![];
     let pairs = KlangParser::parse(Rule::Statement, source)?;
+    check_literals(pairs.clone())?;
 
     for pair in pairs {
         match pair.as_rule() {
             Rule::Query => {
                 ast.push(build_ast_from_query_expr(pair));
             }
+            Rule::LetStmt => {
+                ast.push(build_ast_from_let_stmt(pair));
+            }
+            Rule::TransactionStmt => {
+                ast.push(build_ast_from_transaction_stmt(pair));
+            }
             Rule::EOI => {
                 break;
             }
@@ -33,79 +47,406 @@ pub fn parse_query(source: &str) -> std::result::Result<Vec<Node>, pest::error::
     Ok(ast)
 }
 
+/// Checks the literals whose range the grammar can't limit, so the AST can be built from the
+/// pairs without failing.
+fn check_literals(pairs: Pairs<Rule>) -> std::result::Result<(), pest::error::Error<Rule>> {
+    for pair in pairs {
+        let invalid = match pair.as_rule() {
+            Rule::Int if pair.as_str().parse::<i64>().is_err() => Some(pair),
+            Rule::Decimal if !pair.as_str().parse::<f64>().is_ok_and(f64::is_finite) => Some(pair),
+            Rule::Timespan if parse_timespan(pair.clone()).is_none() => Some(pair),
+            // Counts are unsigned, unlike the other integers
+            Rule::TakeClause => pair
+                .into_inner()
+                .nth(1)
+                .filter(|count| count.as_str().parse::<u64>().is_err()),
+            _ => {
+                check_literals(pair.into_inner())?;
+                None
+            }
+        };
+
+        if let Some(pair) = invalid {
+            return Err(pest::error::Error::new_from_span(
+                ErrorVariant::CustomError {
+                    message: format!("{} is out of range", pair.as_str()),
+                },
+                pair.as_span(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// The byte range of the source text a pair was parsed from, without the whitespace a rule
+/// that ends in an optional part can leave at its end.
+fn span_of(pair: &pest::iterators::Pair<Rule>) -> Span {
+    let start = pair.as_span().start();
+    Span::new(start, start + pair.as_str().trim_end().len())
+}
+
+fn build_ast_from_let_stmt(pair: pest::iterators::Pair<Rule>) -> Node {
+    let span = span_of(&pair);
+    let mut inner = pair.into_inner();
+    let _keyword = inner.next().unwrap();
+    let name = inner.next().unwrap().as_str().to_owned();
+    let value = inner.next().unwrap();
+
+    let value = match value.as_rule() {
+        Rule::Query => build_ast_from_query_expr(value),
+        _ => parse_binary_expr(value),
+    };
+
+    Node::Let(LetExpr {
+        name,
+        value: Box::new(value),
+        span,
+    })
+}
+
+fn build_ast_from_transaction_stmt(pair: pest::iterators::Pair<Rule>) -> Node {
+    let statement = pair.into_inner().next().unwrap();
+    let rule = statement.as_rule();
+    let name = statement
+        .into_inner()
+        .find(|part| part.as_rule() == Rule::Identifier)
+        .map(|name| name.as_str().to_owned());
+
+    Node::Transaction(match (rule, name) {
+        (Rule::BeginStmt, _) => TransactionStmt::Begin,
+        (Rule::CommitStmt, _) => TransactionStmt::Commit,
+        (Rule::SavepointStmt, Some(name)) => TransactionStmt::Savepoint(name),
+        (Rule::RollbackStmt, Some(name)) => TransactionStmt::RollbackTo(name),
+        (Rule::RollbackStmt, None) => TransactionStmt::Rollback,
+        (unknown, _) => panic!("Unknown transaction statement: {:?}", unknown),
+    })
+}
+
 fn build_ast_from_query_expr(pair: pest::iterators::Pair<Rule>) -> Node {
     match pair.as_rule() {
-        Rule::Query => {
-            let mut inner = pair.into_inner();
-            let table = parse_identity(inner.next().unwrap());
+        Rule::Query => Node::Query(parse_query_expr(pair)),
+        unknown => panic!("Unknown expression: {:?}", unknown),
+    }
+}
 
-            let mut query_expr = QueryExpr {
-                table: table.try_into().unwrap(),
-                filter: Vec::new(),
-                order: Vec::new(),
-            };
+fn parse_query_expr(pair: pest::iterators::Pair<Rule>) -> QueryExpr {
+    let span = span_of(&pair);
+    let mut inner = pair.into_inner();
+    let source = inner.next().unwrap();
 
-            while inner.len() > 0 {
-                let inner_pair = inner.next().unwrap();
-                let rule = inner_pair.as_rule();
-
-                match rule {
-                    Rule::WhereClause => {
-                        query_expr
-                            .filter
-                            .push(parse_binary_expr(inner_pair.into_inner().next().unwrap()));
-                    }
-                    invalid => panic!("Invalid Rule! {:?}", invalid),
-                }
+    let source = match source.as_rule() {
+        Rule::UnionStmt => QuerySource::Union(parse_union(source)),
+        _ => QuerySource::Table(parse_identity(source).try_into().unwrap()),
+    };
+
+    let mut query_expr = QueryExpr {
+        source,
+        operators: Vec::new(),
+        order: Vec::new(),
+        span,
+    };
+
+    for inner_pair in inner {
+        let operator = match inner_pair.as_rule() {
+            Rule::WhereClause => {
+                QueryOperator::Where(parse_binary_expr(inner_pair.into_inner().next().unwrap()))
             }
+            Rule::ProjectClause => QueryOperator::Project(
+                inner_pair
+                    .into_inner()
+                    .skip(1)
+                    .map(|item| {
+                        let item = item.into_inner().next().unwrap();
+                        match item.as_rule() {
+                            Rule::Assignment => parse_assignment(item),
+                            _ => Assignment {
+                                name: item.as_str().to_owned(),
+                                span: span_of(&item),
+                                value: parse_identity(item),
+                            },
+                        }
+                    })
+                    .collect(),
+            ),
+            Rule::DistinctClause => QueryOperator::Distinct(
+                inner_pair
+                    .into_inner()
+                    .skip(1)
+                    .map(|field| parse_identity(field).try_into().unwrap())
+                    .collect(),
+            ),
+            Rule::SerializeClause => QueryOperator::Serialize(parse_window(inner_pair)),
+            Rule::ExtendClause => QueryOperator::Extend(
+                inner_pair
+                    .into_inner()
+                    .skip(1)
+                    .map(parse_assignment)
+                    .collect(),
+            ),
+            Rule::UnionClause => QueryOperator::Union(parse_union(inner_pair)),
+            Rule::IntersectClause => QueryOperator::Intersect(parse_set_operands(inner_pair)),
+            Rule::ExceptClause => QueryOperator::Except(parse_set_operands(inner_pair)),
+            Rule::TakeClause => {
+                let count = inner_pair.into_inner().nth(1).unwrap();
+                QueryOperator::Take(count.as_str().parse().unwrap())
+            }
+            Rule::SummarizeClause => QueryOperator::Summarize(parse_summarize(inner_pair)),
+            Rule::ForUpdateClause => QueryOperator::ForUpdate,
+            invalid => panic!("Invalid Rule! {:?}", invalid),
+        };
+
+        query_expr.operators.push(operator);
+    }
+
+    query_expr
+}
 
-            return Node::Query(query_expr);
+fn parse_summarize(pair: pest::iterators::Pair<Rule>) -> SummarizeExpr {
+    let mut summarize = SummarizeExpr {
+        aggregates: Vec::new(),
+        by: Vec::new(),
+    };
+
+    for inner_pair in pair.into_inner().skip(1) {
+        match inner_pair.as_rule() {
+            Rule::SummarizeBy => {
+                for (i, item) in inner_pair.into_inner().skip(1).enumerate() {
+                    // Group keys are named after their field, other expressions by position
+                    let assignment = parse_summarize_item(item, |value| match value {
+                        Node::Identity(identity) => identity.value.clone(),
+                        _ => format!("Column{}", i + 1),
+                    });
+                    summarize.by.push(assignment);
+                }
+            }
+            _ => {
+                let position = summarize.aggregates.len() + 1;
+                // Aggregates are named after the function and the field it reads, `sum_amount`
+                let assignment = parse_summarize_item(inner_pair, |value| match value {
+                    Node::FunctionCall(call) => match call.args.as_slice() {
+                        [Node::Identity(identity)] => format!("{}_{}", call.name, identity.value),
+                        _ => format!("{}_", call.name),
+                    },
+                    _ => format!("Column{}", position),
+                });
+                summarize.aggregates.push(assignment);
+            }
         }
-        unknown => panic!("Unknown expression: {:?}", unknown),
     }
+
+    summarize
 }
 
-fn parse_binary_expr(pair: pest::iterators::Pair<Rule>) -> BinaryExpr {
-    match pair.as_rule() {
-        Rule::BinaryExpr => {
-            let mut inner_rules = pair.into_inner();
-            let lhs = parse_binary_term(inner_rules.next().unwrap());
-            let op = parse_binary_op(inner_rules.next().unwrap());
-            let rhs = parse_binary_term(inner_rules.next().unwrap());
+/// Parses an item of a `summarize`, expressions without a name are given the default one.
+fn parse_summarize_item<F>(pair: pest::iterators::Pair<Rule>, default_name: F) -> Assignment
+where
+    F: Fn(&Node) -> String,
+{
+    let span = span_of(&pair);
+    let item = pair.into_inner().next().unwrap();
 
-            let mut lhs_is_binaryexpr = false;
-            let mut rhs_is_binaryexpr = false;
+    match item.as_rule() {
+        Rule::Assignment => parse_assignment(item),
+        _ => {
+            let value = parse_binary_expr(item);
+            Assignment {
+                name: default_name(&value),
+                value,
+                span,
+            }
+        }
+    }
+}
 
-            match &lhs {
-                Node::BinaryExpr(_) => {
-                    lhs_is_binaryexpr = true;
-                }
-                _ => (),
+fn parse_window(pair: pest::iterators::Pair<Rule>) -> WindowExpr {
+    let mut window = WindowExpr {
+        partition_by: Vec::new(),
+        order_by: Vec::new(),
+    };
+
+    for inner_pair in pair.into_inner() {
+        match inner_pair.as_rule() {
+            Rule::PartitionBy => {
+                window.partition_by = inner_pair
+                    .into_inner()
+                    .skip(2)
+                    .map(|field| parse_identity(field).try_into().unwrap())
+                    .collect();
+            }
+            Rule::OrderBy => {
+                window.order_by = inner_pair
+                    .into_inner()
+                    .skip(2)
+                    .map(parse_order_by)
+                    .collect();
             }
+            _ => {}
+        }
+    }
 
-            match &rhs {
-                Node::BinaryExpr(_) => {
-                    rhs_is_binaryexpr = true;
-                }
-                _ => (),
+    window
+}
+
+fn parse_order_by(pair: pest::iterators::Pair<Rule>) -> OrderByClause {
+    let mut inner = pair.into_inner();
+    let identity = parse_identity(inner.next().unwrap()).try_into().unwrap();
+
+    let direction = match inner.next() {
+        Some(direction) if direction.as_str().eq_ignore_ascii_case("desc") => {
+            OrderByDirection::Desc
+        }
+        _ => OrderByDirection::Asc,
+    };
+
+    OrderByClause {
+        identity,
+        direction,
+    }
+}
+
+fn parse_assignment(pair: pest::iterators::Pair<Rule>) -> Assignment {
+    let span = span_of(&pair);
+    let mut inner = pair.into_inner();
+    let name = inner.next().unwrap().as_str().to_owned();
+
+    Assignment {
+        name,
+        value: parse_binary_expr(inner.next().unwrap()),
+        span,
+    }
+}
+
+fn parse_union(pair: pest::iterators::Pair<Rule>) -> UnionExpr {
+    let mut with_source = None;
+    let mut operands = Vec::new();
+
+    for inner_pair in pair.into_inner() {
+        match inner_pair.as_rule() {
+            Rule::Union => {}
+            Rule::WithSource => {
+                with_source = Some(inner_pair.into_inner().next().unwrap().as_str().to_owned());
             }
+            _ => operands.push(parse_set_operand(inner_pair)),
+        }
+    }
+
+    UnionExpr {
+        with_source,
+        operands,
+    }
+}
 
-            if (op == BinaryOp::And || op == BinaryOp::Or)
-                && (!rhs_is_binaryexpr || !lhs_is_binaryexpr)
-            {
-                panic!("Invalid use of OR|AND operator. The left and right expressions must be boolan evaluations.")
+fn parse_set_operands(pair: pest::iterators::Pair<Rule>) -> Vec<QueryExpr> {
+    pair.into_inner().skip(1).map(parse_set_operand).collect()
+}
+
+fn parse_set_operand(pair: pest::iterators::Pair<Rule>) -> QueryExpr {
+    let operand = pair.into_inner().next().unwrap();
+
+    match operand.as_rule() {
+        Rule::Query => parse_query_expr(operand),
+        _ => {
+            let span = span_of(&operand);
+            let mut query = QueryExpr::table(operand.as_str());
+            if let QuerySource::Table(table) = &mut query.source {
+                table.span = span;
             }
+            query.span = span;
+            query
+        }
+    }
+}
 
-            return BinaryExpr {
-                left: Box::new(lhs),
-                op,
-                right: Box::new(rhs),
-            };
+/// Operator precedence for expressions, from the loosest to the tightest binding.
+fn pratt_parser() -> &'static PrattParser<Rule> {
+    static PRATT_PARSER: OnceLock<PrattParser<Rule>> = OnceLock::new();
+
+    PRATT_PARSER.get_or_init(|| {
+        PrattParser::new()
+            .op(Op::infix(Rule::Or, Assoc::Left))
+            .op(Op::infix(Rule::And, Assoc::Left))
+            .op(Op::prefix(Rule::Not))
+            .op(Op::infix(Rule::Eq, Assoc::Left)
+                | Op::infix(Rule::Ne, Assoc::Left)
+                | Op::infix(Rule::Lt, Assoc::Left)
+                | Op::infix(Rule::LtEq, Assoc::Left)
+                | Op::infix(Rule::Gt, Assoc::Left)
+                | Op::infix(Rule::GtEq, Assoc::Left))
+            .op(Op::postfix(Rule::InClause))
+            .op(Op::infix(Rule::Add, Assoc::Left) | Op::infix(Rule::Subtract, Assoc::Left))
+            .op(Op::infix(Rule::Multiply, Assoc::Left)
+                | Op::infix(Rule::Divide, Assoc::Left)
+                | Op::infix(Rule::Modulo, Assoc::Left))
+            .op(Op::prefix(Rule::Neg))
+            .op(Op::infix(Rule::Exponentiate, Assoc::Right))
+    })
+}
+
+fn parse_binary_expr(pair: pest::iterators::Pair<Rule>) -> Node {
+    match pair.as_rule() {
+        // Every operand carries the span it covers, literals don't record their own
+        Rule::BinaryExpr => {
+            pratt_parser()
+                .map_primary(|primary| {
+                    let span = span_of(&primary);
+                    (parse_binary_term(primary), span)
+                })
+                .map_prefix(|op, (rhs, rhs_span)| {
+                    let span = span_of(&op).to(rhs_span);
+                    let node = Node::UnaryExpr(UnaryExpr {
+                        op: parse_unary_op(op.as_rule()),
+                        expr: Box::new(rhs),
+                        span,
+                    });
+                    (node, span)
+                })
+                .map_postfix(|(lhs, lhs_span), op| {
+                    let span = lhs_span.to(span_of(&op));
+                    (parse_in_clause(lhs, op, span), span)
+                })
+                .map_infix(|(lhs, lhs_span), op, (rhs, rhs_span)| {
+                    let span = lhs_span.to(rhs_span);
+                    let node = Node::BinaryExpr(BinaryExpr {
+                        left: Box::new(lhs),
+                        op: parse_binary_op(op),
+                        right: Box::new(rhs),
+                        span,
+                    });
+                    (node, span)
+                })
+                .parse(pair.into_inner())
+                .0
         }
         unknown => panic!("Unknown expression: {:?}", unknown),
     }
 }
 
+fn parse_in_clause(lhs: Node, pair: pest::iterators::Pair<Rule>, span: Span) -> Node {
+    let mut inner = pair.into_inner();
+    let negated = inner.next().unwrap().as_rule() == Rule::NotIn;
+
+    let set = match inner.peek().map(|pair| pair.as_rule()) {
+        Some(Rule::Subquery) => InSet::Query(parse_query_expr(inner.next().unwrap())),
+        _ => InSet::List(inner.map(parse_binary_expr).collect()),
+    };
+
+    Node::In(InExpr {
+        expr: Box::new(lhs),
+        negated,
+        set,
+        span,
+    })
+}
+
+fn parse_unary_op(rule: Rule) -> UnaryOp {
+    match rule {
+        Rule::Neg => UnaryOp::Negate,
+        Rule::Not => UnaryOp::Not,
+        unknown => panic!("Unknown operator: {:?}", unknown),
+    }
+}
+
 fn parse_binary_op(pair: pest::iterators::Pair<Rule>) -> BinaryOp {
     match pair.as_rule() {
         Rule::BinaryOp => parse_binary_op(pair.into_inner().next().unwrap()),
@@ -117,24 +458,37 @@ fn parse_binary_op(pair: pest::iterators::Pair<Rule>) -> BinaryOp {
         Rule::GtEq => BinaryOp::GtEq,
         Rule::Lt => BinaryOp::Lt,
         Rule::LtEq => BinaryOp::LtEq,
+        Rule::Add => BinaryOp::Add,
+        Rule::Subtract => BinaryOp::Subtract,
+        Rule::Multiply => BinaryOp::Multiply,
+        Rule::Divide => BinaryOp::Divide,
+        Rule::Modulo => BinaryOp::Modulo,
+        Rule::Exponentiate => BinaryOp::Exponentiate,
         unknown => panic!("Unknown operator: {:?}", unknown),
     }
 }
 
 fn parse_binary_term(pair: pest::iterators::Pair<Rule>) -> Node {
     match pair.as_rule() {
-        Rule::BinaryExpr => {
-            return Node::BinaryExpr(parse_binary_expr(pair));
-        }
-        Rule::ScalarValue => {
-            return parse_scalar_value(pair);
-        }
-        Rule::IdentifierPath => {
-            return parse_identity(pair);
-        }
-        Rule::BinaryTerm => {
-            return parse_binary_term(pair.into_inner().next().unwrap());
+        Rule::BinaryExpr => parse_binary_expr(pair),
+        Rule::ScalarValue => parse_scalar_value(pair),
+        Rule::IdentifierPath => parse_identity(pair),
+        Rule::Parameter => Node::Parameter(pair.into_inner().next().unwrap().as_str().to_owned()),
+        Rule::ToScalar => Node::ToScalar(Box::new(parse_query_expr(
+            pair.into_inner().next().unwrap(),
+        ))),
+        Rule::FunctionCall => {
+            let span = span_of(&pair);
+            let mut inner = pair.into_inner();
+            let name = inner.next().unwrap().as_str().to_lowercase();
+
+            Node::FunctionCall(FunctionCall {
+                name,
+                args: inner.map(parse_binary_expr).collect(),
+                span,
+            })
         }
+        Rule::BinaryTerm => parse_binary_term(pair.into_inner().next().unwrap()),
         unknown => panic!("Unknown expression: {:?}", unknown),
     }
 }
@@ -142,6 +496,7 @@ fn parse_binary_term(pair: pest::iterators::Pair<Rule>) -> Node {
 fn parse_identity(pair: pest::iterators::Pair<Rule>) -> Node {
     match pair.as_rule() {
         Rule::IdentifierStmt => {
+            let span = span_of(&pair);
             let mut pair = pair.into_inner();
             let identity = pair.next().unwrap();
             let alias = pair.next();
@@ -150,6 +505,7 @@ fn parse_identity(pair: pest::iterators::Pair<Rule>) -> Node {
                 return Node::Identity(IdentityValue {
                     value: identity.as_str().to_owned(),
                     alias: Option::None,
+                    span,
                 });
             }
 
@@ -164,30 +520,26 @@ fn parse_identity(pair: pest::iterators::Pair<Rule>) -> Node {
                         .as_str()
                         .to_owned(),
                 ),
+                span,
             })
         }
         Rule::IdentifierPath => Node::Identity(IdentityValue {
             value: pair.as_str().to_owned(),
             alias: Option::None,
+            span: span_of(&pair),
         }),
         unknown => panic!("Unknown identity: {:?}", unknown),
     }
 }
 
 fn parse_scalar_value(pair: pest::iterators::Pair<Rule>) -> Node {
+    Node::Scalar(parse_scalar(pair))
+}
+
+fn parse_scalar(pair: pest::iterators::Pair<Rule>) -> ScalarValue {
     match pair.as_rule() {
-        Rule::ScalarValue => {
-            return parse_scalar_value(pair.into_inner().next().unwrap());
-        }
-        Rule::Int => {
-            let istr = pair.as_str();
-            let (sign, istr) = match &istr[..1] {
-                "-" => (-1, &istr[1..]),
-                _ => (1, istr),
-            };
-            let int: i64 = istr.parse().unwrap();
-            Node::Scalar(ScalarValue::Int(sign * int))
-        }
+        Rule::ScalarValue => parse_scalar(pair.into_inner().next().unwrap()),
+        Rule::Int => ScalarValue::Int(pair.as_str().parse().unwrap()),
         Rule::Decimal => {
             let istr = pair.as_str();
             let (sign, istr) = match &istr[..1] {
@@ -195,30 +547,143 @@ fn parse_scalar_value(pair: pest::iterators::Pair<Rule>) -> Node {
                 _ => (1.0, istr),
             };
             let float: f64 = istr.parse().unwrap();
-            Node::Scalar(ScalarValue::Decimal(sign * float))
+            ScalarValue::Decimal(sign * float)
         }
         Rule::String => {
             let str = pair.as_str();
-            Node::Scalar(ScalarValue::String(String::from(str)))
+            ScalarValue::String(parse_string_literal(str))
         }
         Rule::Boolean => {
-            let str = pair.as_str();
+            let str = pair.as_str().to_lowercase();
             let value = str.parse().unwrap();
-            Node::Scalar(ScalarValue::Boolean(value))
+            ScalarValue::Boolean(value)
+        }
+        Rule::DateTime => {
+            let value = pair.into_inner().next().unwrap();
+            ScalarValue::Date(normalize_datetime(value.as_str()))
+        }
+        Rule::Timespan => ScalarValue::Timespan(parse_timespan(pair).unwrap()),
+        Rule::ObjectId => {
+            let value = pair.into_inner().next().unwrap();
+            ScalarValue::ObjectId(value.as_str().to_lowercase())
         }
-        Rule::Null => Node::Scalar(ScalarValue::Null),
-        Rule::Undefined => Node::Scalar(ScalarValue::Undefined),
+        Rule::Uuid => {
+            let value = pair.into_inner().next().unwrap();
+            ScalarValue::Uuid(value.as_str().to_lowercase())
+        }
+        Rule::ArrayLiteral => ScalarValue::Array(pair.into_inner().map(parse_scalar).collect()),
+        Rule::ObjectLiteral => ScalarValue::Object(
+            pair.into_inner()
+                .map(|field| {
+                    let mut inner = field.into_inner();
+                    let key = inner.next().unwrap();
+                    let key = match key.as_rule() {
+                        Rule::String => parse_string_literal(key.as_str()),
+                        _ => key.as_str().to_owned(),
+                    };
+
+                    (key, parse_scalar(inner.next().unwrap()))
+                })
+                .collect(),
+        ),
+        Rule::Null => ScalarValue::Null,
+        Rule::Undefined => ScalarValue::Undefined,
         unknown => panic!("Unknown scalar value: {:?}", unknown),
     }
 }
 
+/// Expands a datetime literal into a full RFC 3339 timestamp, missing parts default to
+/// midnight UTC.
+fn normalize_datetime(value: &str) -> String {
+    let (date, time) = match value.split_once('T') {
+        Some((date, time)) => (date, time),
+        None => (value, "00:00"),
+    };
+
+    let zone_at = time.find(['Z', '+', '-']);
+    let (time, zone) = match zone_at {
+        Some(index) => time.split_at(index),
+        None => (time, "Z"),
+    };
+
+    match time.len() {
+        5 => format!("{}T{}:00{}", date, time, zone),
+        _ => format!("{}T{}{}", date, time, zone),
+    }
+}
+
+/// Converts a timespan literal into milliseconds, `None` when they don't fit.
+fn parse_timespan(pair: pest::iterators::Pair<Rule>) -> Option<i64> {
+    let literal = pair.as_str();
+    let unit = pair.into_inner().next().unwrap().as_str();
+    let amount: f64 = literal[..literal.len() - unit.len()].parse().unwrap();
+
+    let scale = match unit {
+        "d" => 86_400_000.0,
+        "h" => 3_600_000.0,
+        "m" => 60_000.0,
+        "s" => 1_000.0,
+        _ => 1.0,
+    };
+
+    let millis = (amount * scale).round();
+    (i64::MIN as f64..i64::MAX as f64)
+        .contains(&millis)
+        .then_some(millis as i64)
+}
+
+/// Strips the surrounding quotes from a string literal and resolves its escape sequences.
+fn parse_string_literal(literal: &str) -> String {
+    let mut value = String::with_capacity(literal.len());
+    let mut chars = literal[1..literal.len() - 1].chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('b') => value.push('\u{0008}'),
+            Some('t') => value.push('\t'),
+            Some('n') => value.push('\n'),
+            Some('f') => value.push('\u{000C}'),
+            Some('r') => value.push('\r'),
+            Some(digits @ ('u' | 'U')) => {
+                // Without enough hex digits it's an escaped letter, like the grammar reads it
+                let len = if digits == 'u' { 4 } else { 8 };
+                let hex: String = chars.clone().take(len).collect();
+                if hex.len() == len && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    let code = u32::from_str_radix(&hex, 16).unwrap();
+                    value.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                    chars.nth(len - 1);
+                } else {
+                    value.push(digits);
+                }
+            }
+            // An escaped line break continues the literal on the next line.
+            Some('\r') => {
+                chars.next_if_eq(&'\n');
+            }
+            Some('\n') | None => {}
+            Some(other) => value.push(other),
+        }
+    }
+
+    value
+}
+
 #[cfg(test)]
 pub mod tests {
     use crate::parser::KlangParser;
     use crate::parser::Rule;
     use pest::Parser;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use proptest::sample::select;
 
     use super::*;
+    use crate::visit::clear_spans;
 
     fn get_inner_pair(mut iterator: Pairs<Rule>) -> Pairs<Rule> {
         assert_eq!(iterator.len(), 1);
@@ -350,4 +815,881 @@ pub mod tests {
 
         assert!(result.is_ok());
     }
+
+    /// Parses a script with its spans cleared, so it compares equal to hand built nodes.
+    fn parse_ast(source: &str) -> std::result::Result<Vec<Node>, pest::error::Error<Rule>> {
+        let mut ast = parse_query(source)?;
+        ast.iter_mut().for_each(clear_spans);
+        Ok(ast)
+    }
+
+    fn parse_expr(source: &str) -> Node {
+        let mut result = KlangParser::parse(Rule::BinaryExpr, source).unwrap();
+        let mut node = parse_binary_expr(result.next().unwrap());
+        clear_spans(&mut node);
+        node
+    }
+
+    fn identity(value: &str) -> Node {
+        Node::Identity(IdentityValue {
+            value: String::from(value),
+            alias: Option::None,
+            span: Span::default(),
+        })
+    }
+
+    fn int(value: i64) -> Node {
+        Node::Scalar(ScalarValue::Int(value))
+    }
+
+    fn binary(left: Node, op: BinaryOp, right: Node) -> Node {
+        Node::BinaryExpr(BinaryExpr {
+            left: Box::new(left),
+            op,
+            right: Box::new(right),
+            span: Span::default(),
+        })
+    }
+
+    fn unary(op: UnaryOp, expr: Node) -> Node {
+        Node::UnaryExpr(UnaryExpr {
+            op,
+            expr: Box::new(expr),
+            span: Span::default(),
+        })
+    }
+
+    #[test]
+    fn arithmetic_precedence_ok() {
+        assert_eq!(
+            parse_expr("1 + 2 * 3 - 4"),
+            binary(
+                binary(
+                    int(1),
+                    BinaryOp::Add,
+                    binary(int(2), BinaryOp::Multiply, int(3))
+                ),
+                BinaryOp::Subtract,
+                int(4)
+            )
+        );
+    }
+
+    #[test]
+    fn arithmetic_grouped_ok() {
+        assert_eq!(
+            parse_expr("(1 + 2) % 3"),
+            binary(
+                binary(int(1), BinaryOp::Add, int(2)),
+                BinaryOp::Modulo,
+                int(3)
+            )
+        );
+    }
+
+    #[test]
+    fn exponentiate_right_associative_ok() {
+        assert_eq!(
+            parse_expr("2 ^ 3 ^ 2"),
+            binary(
+                int(2),
+                BinaryOp::Exponentiate,
+                binary(int(3), BinaryOp::Exponentiate, int(2))
+            )
+        );
+    }
+
+    #[test]
+    fn comparison_binds_tighter_than_logical_ok() {
+        assert_eq!(
+            parse_expr("a = 1 or b = 2 and c <= 3"),
+            binary(
+                binary(identity("a"), BinaryOp::Eq, int(1)),
+                BinaryOp::Or,
+                binary(
+                    binary(identity("b"), BinaryOp::Eq, int(2)),
+                    BinaryOp::And,
+                    binary(identity("c"), BinaryOp::LtEq, int(3))
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn arithmetic_binds_tighter_than_comparison_ok() {
+        assert_eq!(
+            parse_expr("x * 2 > y + 1"),
+            binary(
+                binary(identity("x"), BinaryOp::Multiply, int(2)),
+                BinaryOp::Gt,
+                binary(identity("y"), BinaryOp::Add, int(1))
+            )
+        );
+    }
+
+    #[test]
+    fn logical_operands_need_not_be_comparisons_ok() {
+        assert_eq!(
+            parse_expr("active and not deleted"),
+            binary(
+                identity("active"),
+                BinaryOp::And,
+                unary(UnaryOp::Not, identity("deleted"))
+            )
+        );
+    }
+
+    #[test]
+    fn not_applies_to_comparison_ok() {
+        assert_eq!(
+            parse_expr("not x = 1"),
+            unary(UnaryOp::Not, binary(identity("x"), BinaryOp::Eq, int(1)))
+        );
+    }
+
+    #[test]
+    fn keyword_prefixed_identifier_ok() {
+        assert_eq!(
+            parse_expr("notes = nullable"),
+            binary(identity("notes"), BinaryOp::Eq, identity("nullable"))
+        );
+    }
+
+    #[test]
+    fn unary_minus_ok() {
+        assert_eq!(parse_expr("-7"), int(-7));
+        assert_eq!(parse_expr("-(7)"), unary(UnaryOp::Negate, int(7)));
+        assert_eq!(
+            parse_expr("-2 ^ 2"),
+            unary(
+                UnaryOp::Negate,
+                binary(int(2), BinaryOp::Exponentiate, int(2))
+            )
+        );
+        assert_eq!(
+            parse_expr("-x ^ 2"),
+            unary(
+                UnaryOp::Negate,
+                binary(identity("x"), BinaryOp::Exponentiate, int(2))
+            )
+        );
+        assert_eq!(
+            parse_expr("x - -1"),
+            binary(identity("x"), BinaryOp::Subtract, int(-1))
+        );
+    }
+
+    #[test]
+    fn string_literal_unescaped_ok() {
+        assert_eq!(
+            parse_expr(r#"name = "a \"quoted\" \u0041""#),
+            binary(
+                identity("name"),
+                BinaryOp::Eq,
+                Node::Scalar(ScalarValue::String(String::from("a \"quoted\" A")))
+            )
+        );
+    }
+
+    #[test]
+    fn query_with_multiple_conditions_ok() {
+        let ast = parse_ast("profile | where x = 1 and y != 2 | where z").unwrap();
+
+        match ast.first().unwrap() {
+            Node::Query(query) => {
+                assert_eq!(query.operators.len(), 2);
+                assert_eq!(query.operators[1], QueryOperator::Where(identity("z")));
+            }
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+    }
+
+    fn parse_scalar_str(source: &str) -> ScalarValue {
+        let mut result = KlangParser::parse(Rule::ScalarValue, source).unwrap();
+        parse_scalar(result.next().unwrap())
+    }
+
+    #[test]
+    fn datetime_normalized_ok() {
+        assert_eq!(
+            parse_scalar_str("datetime(2024-01-01)"),
+            ScalarValue::Date(String::from("2024-01-01T00:00:00Z"))
+        );
+        assert_eq!(
+            parse_scalar_str("datetime(2024-01-01T00:00Z)"),
+            ScalarValue::Date(String::from("2024-01-01T00:00:00Z"))
+        );
+        assert_eq!(
+            parse_scalar_str("datetime(2024-01-01T10:30:15.5-05:00)"),
+            ScalarValue::Date(String::from("2024-01-01T10:30:15.5-05:00"))
+        );
+    }
+
+    #[test]
+    fn timespan_ok() {
+        assert_eq!(parse_scalar_str("5m"), ScalarValue::Timespan(300_000));
+        assert_eq!(parse_scalar_str("1d"), ScalarValue::Timespan(86_400_000));
+        assert_eq!(parse_scalar_str("1.5h"), ScalarValue::Timespan(5_400_000));
+        assert_eq!(parse_scalar_str("250ms"), ScalarValue::Timespan(250));
+        assert_eq!(
+            parse_expr("ts > -1d"),
+            binary(
+                identity("ts"),
+                BinaryOp::Gt,
+                Node::Scalar(ScalarValue::Timespan(-86_400_000))
+            )
+        );
+    }
+
+    #[test]
+    fn object_id_and_uuid_ok() {
+        assert_eq!(
+            parse_scalar_str("objectid(\"64B7F0C2A1E4D3B2C1A09F8E\")"),
+            ScalarValue::ObjectId(String::from("64b7f0c2a1e4d3b2c1a09f8e"))
+        );
+        assert_eq!(
+            parse_scalar_str("uuid(\"67e55044-10b1-426f-9247-bb680e5fe0c8\")"),
+            ScalarValue::Uuid(String::from("67e55044-10b1-426f-9247-bb680e5fe0c8"))
+        );
+    }
+
+    #[test]
+    fn composite_literals_ok() {
+        assert_eq!(
+            parse_scalar_str("[1, \"two\", []]"),
+            ScalarValue::Array(vec![
+                ScalarValue::Int(1),
+                ScalarValue::String(String::from("two")),
+                ScalarValue::Array(vec![]),
+            ])
+        );
+        assert_eq!(
+            parse_scalar_str("{ \"a\": 1, b: [null] }"),
+            ScalarValue::Object(vec![
+                (String::from("a"), ScalarValue::Int(1)),
+                (
+                    String::from("b"),
+                    ScalarValue::Array(vec![ScalarValue::Null])
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn timespan_unit_needs_boundary_ok() {
+        assert_eq!(
+            parse_expr("5 = x5m"),
+            binary(int(5), BinaryOp::Eq, identity("x5m"))
+        );
+    }
+
+    #[test]
+    fn parameters_ok() {
+        assert_eq!(
+            parse_expr("email = @email or owner = $owner_id"),
+            binary(
+                binary(
+                    identity("email"),
+                    BinaryOp::Eq,
+                    Node::Parameter(String::from("email"))
+                ),
+                BinaryOp::Or,
+                binary(
+                    identity("owner"),
+                    BinaryOp::Eq,
+                    Node::Parameter(String::from("owner_id"))
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn function_call_ok() {
+        assert_eq!(
+            parse_expr("ts > ago(1d)"),
+            binary(
+                identity("ts"),
+                BinaryOp::Gt,
+                Node::FunctionCall(FunctionCall {
+                    name: String::from("ago"),
+                    args: vec![Node::Scalar(ScalarValue::Timespan(86_400_000))],
+                    span: Span::default(),
+                })
+            )
+        );
+    }
+
+    #[test]
+    fn let_statements_ok() {
+        let ast = parse_ast(
+            "let threshold = 100;
+             let recent = events | where ts > ago(1d);
+             recent | where score > threshold",
+        )
+        .unwrap();
+
+        assert_eq!(ast.len(), 3);
+        assert_eq!(
+            ast[0],
+            Node::Let(LetExpr {
+                name: String::from("threshold"),
+                value: Box::new(int(100)),
+                span: Span::default(),
+            })
+        );
+
+        match &ast[1] {
+            Node::Let(binding) => {
+                assert_eq!(binding.name, "recent");
+                assert!(matches!(*binding.value, Node::Query(_)));
+            }
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+
+        match &ast[2] {
+            Node::Query(query) => assert_eq!(query.source, QueryExpr::table("recent").source),
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+    }
+
+    #[test]
+    fn let_scalar_identity_is_query_ok() {
+        let ast = parse_ast("let copy = events; let limit = events + 1;").unwrap();
+
+        assert!(matches!(&ast[0], Node::Let(binding) if matches!(*binding.value, Node::Query(_))));
+        assert!(
+            matches!(&ast[1], Node::Let(binding) if matches!(*binding.value, Node::BinaryExpr(_)))
+        );
+    }
+
+    #[test]
+    fn transaction_statements_ok() {
+        let ast = parse_ast(
+            "BEGIN; savepoint s1; events | take 1; rollback to s1; rollback to savepoint s2;
+             rollback; commit",
+        )
+        .unwrap();
+
+        assert_eq!(
+            ast.iter()
+                .filter_map(|node| match node {
+                    Node::Transaction(statement) => Some(statement.clone()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+            [
+                TransactionStmt::Begin,
+                TransactionStmt::Savepoint(String::from("s1")),
+                TransactionStmt::RollbackTo(String::from("s1")),
+                TransactionStmt::RollbackTo(String::from("s2")),
+                TransactionStmt::Rollback,
+                TransactionStmt::Commit,
+            ]
+        );
+        assert!(matches!(&ast[2], Node::Query(_)));
+
+        // Collections named like the keywords are still queried
+        match &parse_ast("commit | take 1").unwrap()[0] {
+            Node::Query(query) => assert_eq!(query.source, QueryExpr::table("commit").source),
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+        assert!(matches!(
+            &parse_ast("savepoint").unwrap()[0],
+            Node::Query(_)
+        ));
+        assert!(parse_ast("begin | take 1; rollback to").is_err());
+    }
+
+    #[test]
+    fn let_prefixed_table_ok() {
+        let ast = parse_ast("letters | where x = 1").unwrap();
+
+        match &ast[0] {
+            Node::Query(query) => assert_eq!(query.source, QueryExpr::table("letters").source),
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+    }
+
+    #[test]
+    fn union_source_ok() {
+        let ast = parse_ast("union withsource=src events_2023, events_*, (archive | where x = 1)")
+            .unwrap();
+
+        let mut archive = QueryExpr::table("archive");
+        archive.operators.push(QueryOperator::Where(binary(
+            identity("x"),
+            BinaryOp::Eq,
+            int(1),
+        )));
+
+        assert_eq!(
+            ast[0],
+            Node::Query(QueryExpr {
+                source: QuerySource::Union(UnionExpr {
+                    with_source: Some(String::from("src")),
+                    operands: vec![
+                        QueryExpr::table("events_2023"),
+                        QueryExpr::table("events_*"),
+                        archive,
+                    ],
+                }),
+                operators: Vec::new(),
+                order: Vec::new(),
+                span: Span::default(),
+            })
+        );
+    }
+
+    #[test]
+    fn set_operator_clauses_ok() {
+        let ast = parse_ast("a | where x | union b | intersect c, d | except e").unwrap();
+
+        match &ast[0] {
+            Node::Query(query) => assert_eq!(
+                query.operators,
+                vec![
+                    QueryOperator::Where(identity("x")),
+                    QueryOperator::Union(UnionExpr {
+                        with_source: None,
+                        operands: vec![QueryExpr::table("b")],
+                    }),
+                    QueryOperator::Intersect(vec![QueryExpr::table("c"), QueryExpr::table("d")]),
+                    QueryOperator::Except(vec![QueryExpr::table("e")]),
+                ]
+            ),
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+    }
+
+    #[test]
+    fn union_named_table_ok() {
+        let ast = parse_ast("unions | where x").unwrap();
+
+        match &ast[0] {
+            Node::Query(query) => assert_eq!(query.source, QueryExpr::table("unions").source),
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+    }
+
+    #[test]
+    fn distinct_clause_ok() {
+        let ast = parse_ast("events | distinct a, b | where a > 1").unwrap();
+
+        match &ast[0] {
+            Node::Query(query) => assert_eq!(
+                query.operators,
+                vec![
+                    QueryOperator::Distinct(vec![
+                        identity("a").try_into().unwrap(),
+                        identity("b").try_into().unwrap(),
+                    ]),
+                    QueryOperator::Where(binary(identity("a"), BinaryOp::Gt, int(1))),
+                ]
+            ),
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+    }
+
+    #[test]
+    fn serialize_and_extend_ok() {
+        let ast = parse_ast(
+            "events | serialize partition by user order by ts desc, id
+                    | extend rn = row_number(), delta = ts - prev(ts, 1)",
+        )
+        .unwrap();
+
+        let call = |name: &str, args: Vec<Node>| {
+            Node::FunctionCall(FunctionCall {
+                name: String::from(name),
+                args,
+                span: Span::default(),
+            })
+        };
+
+        match &ast[0] {
+            Node::Query(query) => assert_eq!(
+                query.operators,
+                vec![
+                    QueryOperator::Serialize(WindowExpr {
+                        partition_by: vec![identity("user").try_into().unwrap()],
+                        order_by: vec![
+                            OrderByClause {
+                                identity: identity("ts").try_into().unwrap(),
+                                direction: OrderByDirection::Desc,
+                            },
+                            OrderByClause {
+                                identity: identity("id").try_into().unwrap(),
+                                direction: OrderByDirection::Asc,
+                            },
+                        ],
+                    }),
+                    QueryOperator::Extend(vec![
+                        Assignment {
+                            name: String::from("rn"),
+                            value: call("row_number", vec![]),
+                            span: Span::default(),
+                        },
+                        Assignment {
+                            name: String::from("delta"),
+                            value: binary(
+                                identity("ts"),
+                                BinaryOp::Subtract,
+                                call("prev", vec![identity("ts"), int(1)])
+                            ),
+                            span: Span::default(),
+                        },
+                    ]),
+                ]
+            ),
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+    }
+
+    #[test]
+    fn serialize_without_window_ok() {
+        let ast = parse_ast("events | serialize | where x").unwrap();
+
+        match &ast[0] {
+            Node::Query(query) => assert_eq!(
+                query.operators[0],
+                QueryOperator::Serialize(WindowExpr {
+                    partition_by: Vec::new(),
+                    order_by: Vec::new(),
+                })
+            ),
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+    }
+
+    #[test]
+    fn in_list_ok() {
+        assert_eq!(
+            parse_expr("x + 1 !in (1, 2) and y"),
+            binary(
+                Node::In(InExpr {
+                    expr: Box::new(binary(identity("x"), BinaryOp::Add, int(1))),
+                    negated: true,
+                    set: InSet::List(vec![int(1), int(2)]),
+                    span: Span::default(),
+                }),
+                BinaryOp::And,
+                identity("y")
+            )
+        );
+        assert_eq!(
+            parse_expr("not x not in (a)"),
+            unary(
+                UnaryOp::Not,
+                Node::In(InExpr {
+                    expr: Box::new(identity("x")),
+                    negated: true,
+                    set: InSet::List(vec![identity("a")]),
+                    span: Span::default(),
+                })
+            )
+        );
+    }
+
+    #[test]
+    fn in_subquery_ok() {
+        let ast = parse_ast(
+            r#"orders | where customerId in (customers | where tier == "gold" | project _id)"#,
+        )
+        .unwrap();
+
+        let mut customers = QueryExpr::table("customers");
+        customers.operators = vec![
+            QueryOperator::Where(binary(
+                identity("tier"),
+                BinaryOp::Eq,
+                Node::Scalar(ScalarValue::String(String::from("gold"))),
+            )),
+            QueryOperator::Project(vec![Assignment {
+                name: String::from("_id"),
+                value: identity("_id"),
+                span: Span::default(),
+            }]),
+        ];
+
+        match &ast[0] {
+            Node::Query(query) => assert_eq!(
+                query.operators,
+                vec![QueryOperator::Where(Node::In(InExpr {
+                    expr: Box::new(identity("customerId")),
+                    negated: false,
+                    set: InSet::Query(customers),
+                    span: Span::default(),
+                }))]
+            ),
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+    }
+
+    #[test]
+    fn to_scalar_ok() {
+        let mut limits = QueryExpr::table("limits");
+        limits.operators = vec![QueryOperator::Project(vec![Assignment {
+            name: String::from("max"),
+            value: identity("max"),
+            span: Span::default(),
+        }])];
+
+        assert_eq!(
+            parse_expr("amount > toscalar(limits | project max)"),
+            binary(
+                identity("amount"),
+                BinaryOp::Gt,
+                Node::ToScalar(Box::new(limits))
+            )
+        );
+    }
+
+    #[test]
+    fn take_and_summarize_ok() {
+        let ast = parse_ast(
+            "events | summarize total = sum(amount), count(), max(ts) + 1 by user, paid = amount > 0 | take 5",
+        )
+        .unwrap();
+
+        let call = |name: &str, args: Vec<Node>| {
+            Node::FunctionCall(FunctionCall {
+                name: String::from(name),
+                args,
+                span: Span::default(),
+            })
+        };
+        let assignment = |name: &str, value: Node| Assignment {
+            name: String::from(name),
+            value,
+            span: Span::default(),
+        };
+
+        match &ast[0] {
+            Node::Query(query) => assert_eq!(
+                query.operators,
+                vec![
+                    QueryOperator::Summarize(SummarizeExpr {
+                        aggregates: vec![
+                            assignment("total", call("sum", vec![identity("amount")])),
+                            assignment("count_", call("count", vec![])),
+                            assignment(
+                                "Column3",
+                                binary(call("max", vec![identity("ts")]), BinaryOp::Add, int(1))
+                            ),
+                        ],
+                        by: vec![
+                            assignment("user", identity("user")),
+                            assignment("paid", binary(identity("amount"), BinaryOp::Gt, int(0))),
+                        ],
+                    }),
+                    QueryOperator::Take(5),
+                ]
+            ),
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+    }
+
+    #[test]
+    fn distinct_star_and_limit_ok() {
+        let ast = parse_ast("events | distinct * | limit 3").unwrap();
+
+        match &ast[0] {
+            Node::Query(query) => assert_eq!(
+                query.operators,
+                vec![QueryOperator::Distinct(vec![]), QueryOperator::Take(3)]
+            ),
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+    }
+
+    #[test]
+    fn for_update_ok() {
+        let ast = parse_ast("counters | where name == \"a\" | FOR  Update").unwrap();
+
+        match &ast[0] {
+            Node::Query(query) => assert_eq!(query.operators[1], QueryOperator::ForUpdate),
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+        assert!(parse_ast("counters | for updates").is_err());
+        assert!(parse_ast("counters | for").is_err());
+    }
+
+    #[test]
+    fn spans_ok() {
+        let source = "let x = 1;\nevents | where -a * (b + 1) > f(c) and d !in (1, 2)";
+        let ast = parse_query(source).unwrap();
+        let text = |span: Option<Span>| {
+            let span = span.unwrap();
+            &source[span.start..span.end]
+        };
+
+        assert_eq!(text(ast[0].span()), "let x = 1");
+        assert_eq!(
+            text(ast[1].span()),
+            "events | where -a * (b + 1) > f(c) and d !in (1, 2)"
+        );
+
+        let filter = match &ast[1] {
+            Node::Query(query) => match &query.operators[0] {
+                QueryOperator::Where(filter) => filter,
+                invalid => panic!("Invalid operator: {:?}", invalid),
+            },
+            invalid => panic!("Invalid node: {:?}", invalid),
+        };
+        let (left, right) = match filter {
+            Node::BinaryExpr(and) => (&*and.left, &*and.right),
+            invalid => panic!("Invalid node: {:?}", invalid),
+        };
+
+        assert_eq!(text(filter.span()), "-a * (b + 1) > f(c) and d !in (1, 2)");
+        assert_eq!(text(left.span()), "-a * (b + 1) > f(c)");
+        assert_eq!(text(right.span()), "d !in (1, 2)");
+
+        match left {
+            Node::BinaryExpr(gt) => {
+                assert_eq!(text(gt.left.span()), "-a * (b + 1)");
+                assert_eq!(text(gt.right.span()), "f(c)");
+            }
+            invalid => panic!("Invalid node: {:?}", invalid),
+        }
+    }
+
+    #[test]
+    fn out_of_range_literals() {
+        for source in [
+            "t | where a == 99999999999999999999",
+            "t | where a == 9223372036854775808",
+            "t | where a == [-9223372036854775809]",
+            "t | where a == - 9223372036854775808",
+            "t | where a == 1e999",
+            "t | where a > ago(99999999999999999999d)",
+            "t | take 18446744073709551616",
+            "t | limit -1",
+        ] {
+            assert!(parse_ast(source).is_err(), "{}", source);
+        }
+
+        assert!(parse_ast("t | where a == [-9223372036854775808]").is_ok());
+        assert!(parse_ast("t | where a == -9223372036854775808 | where -a < 1").is_ok());
+        assert!(parse_ast("t | where a == -9223372036854775808 ^ 2").is_err());
+        assert_eq!(
+            parse_expr("a == -9223372036854775808"),
+            binary(
+                identity("a"),
+                BinaryOp::Eq,
+                Node::Scalar(ScalarValue::Int(i64::MIN))
+            )
+        );
+        assert!(parse_ast("t | take 18446744073709551615").is_ok());
+    }
+
+    #[test]
+    fn short_unicode_escapes() {
+        let value = |source: &str| match parse_ast(source).unwrap().pop() {
+            Some(Node::Let(let_expr)) => *let_expr.value,
+            invalid => panic!("Invalid node: {:?}", invalid),
+        };
+
+        assert_eq!(
+            value(r#"let s = "\uZZZZ\u+123\U0041""#),
+            Node::Scalar(ScalarValue::String(String::from("uZZZZu+123U0041")))
+        );
+        assert_eq!(
+            value(r#"let s = "\u00e9\U0001F600""#),
+            Node::Scalar(ScalarValue::String(String::from("é😀")))
+        );
+    }
+
+    /// Pieces of queries, valid and not, that random inputs are put together from.
+    const TOKENS: &[&str] = &[
+        "events",
+        "t",
+        "b.c",
+        "x->y",
+        "|",
+        "where",
+        "project",
+        "extend",
+        "distinct",
+        "serialize",
+        "summarize",
+        "take",
+        "for",
+        "update",
+        "limit",
+        "union",
+        "intersect",
+        "except",
+        "withsource",
+        "=",
+        "==",
+        "!=",
+        "<",
+        ">=",
+        "and",
+        "or",
+        "not",
+        "in",
+        "!in",
+        "(",
+        ")",
+        "[",
+        "]",
+        "{",
+        "}",
+        ":",
+        ",",
+        ";",
+        "let",
+        "begin",
+        "commit",
+        "rollback",
+        "savepoint",
+        "to",
+        "by",
+        "partition",
+        "order",
+        "asc",
+        "desc",
+        "*",
+        "+",
+        "-",
+        "/",
+        "%",
+        "^",
+        "1",
+        "-1",
+        "1.5",
+        "1e5",
+        "99999999999999999999",
+        "-9223372036854775808",
+        "1d",
+        "99999999999999999999d",
+        "\"s\"",
+        "\"\\uZZZZ\"",
+        "\"\\U0041\"",
+        "\"\\\"",
+        "datetime(2024-13-45)",
+        "objectid(\"64b7f0c2a1e4d3b2c1a09f8e\")",
+        "uuid(\"x\")",
+        "null",
+        "count()",
+        "row_number()",
+        "toscalar",
+        "@p",
+        "--c\n",
+        "\n",
+    ];
+
+    proptest! {
+        #[test]
+        fn parses_any_text(source in "\\PC{0,64}") {
+            let _ = parse_ast(&source);
+        }
+
+        #[test]
+        fn parses_token_soup(tokens in vec(select(TOKENS), 1..16)) {
+            let _ = parse_ast(&tokens.join(" "));
+        }
+    }
 }
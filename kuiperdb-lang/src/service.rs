@@ -0,0 +1,699 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+//! Editor services for query text: diagnostics, completions and hover descriptions. They work
+//! on text that is being typed, so every statement is parsed on its own and a broken statement
+//! doesn't hide the problems of the others. Offsets are byte offsets into the source.
+
+use crate::analyzer::{describe_type, Analyzer, Diagnostic, Schema, Severity};
+use crate::ast::{
+    Assignment, BinaryExpr, FunctionCall, IdentityValue, InExpr, LetExpr, Node, QueryExpr, Span,
+    UnaryExpr,
+};
+use crate::catalog::Catalog;
+use crate::functions::{FunctionKind, FUNCTIONS};
+use crate::parser::parse_query;
+use crate::visit::{self, VisitorMut};
+use pest::error::InputLocation;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CompletionKind {
+    Collection,
+    Field,
+    Variable,
+    Operator,
+    Function,
+    Keyword,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    pub detail: String,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Hover {
+    pub span: Span,
+    pub contents: String,
+}
+
+const OPERATORS: &[(&str, &str)] = &[
+    ("where", "Keeps the documents matching a filter"),
+    (
+        "project",
+        "Selects and computes the fields of the documents",
+    ),
+    ("extend", "Adds computed fields to the documents"),
+    ("distinct", "Removes duplicate documents"),
+    ("serialize", "Orders the documents for window functions"),
+    ("summarize", "Groups the documents and computes aggregates"),
+    ("take", "Keeps the first documents"),
+    (
+        "for update",
+        "Locks the documents until the transaction completes",
+    ),
+    ("union", "Appends the documents of other tables"),
+    ("intersect", "Keeps the documents found in other tables"),
+    ("except", "Removes the documents found in other tables"),
+];
+
+/// Calls `f` with the offset of every character of the text that is code, rather than part of
+/// a string or comment. Returns whether the text ends inside a string or comment.
+fn scan(text: &str, mut f: impl FnMut(usize, char)) -> bool {
+    enum State {
+        Code,
+        String,
+        Escape,
+        Comment,
+    }
+
+    let mut state = State::Code;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        state = match state {
+            State::Code => match (c, chars.peek()) {
+                ('"', _) => State::String,
+                ('#', _) | ('/', Some((_, '/'))) | ('-', Some((_, '-'))) => State::Comment,
+                _ => {
+                    f(i, c);
+                    State::Code
+                }
+            },
+            State::String => match c {
+                '\\' => State::Escape,
+                '"' => State::Code,
+                _ => State::String,
+            },
+            State::Escape => State::String,
+            State::Comment if c == '\n' => State::Code,
+            State::Comment => State::Comment,
+        };
+    }
+
+    !matches!(state, State::Code)
+}
+
+/// Splits a script at the semicolons between statements, skipping statements that are empty.
+fn statements(source: &str) -> Vec<(usize, &str)> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut empty = true;
+
+    scan(source, |i, c| match c {
+        ';' => {
+            if !empty {
+                statements.push((start, &source[start..i]));
+            }
+            start = i + 1;
+            empty = true;
+        }
+        c if !c.is_whitespace() => empty = false,
+        _ => {}
+    });
+    if !empty {
+        statements.push((start, &source[start..]));
+    }
+
+    statements
+}
+
+/// Moves spans by the offset of the statement they were parsed from.
+struct Shift(usize);
+
+impl Shift {
+    fn shift(&self, span: &mut Span) {
+        span.start += self.0;
+        span.end += self.0;
+    }
+}
+
+impl VisitorMut for Shift {
+    fn visit_query_mut(&mut self, query: &mut QueryExpr) {
+        self.shift(&mut query.span);
+        visit::walk_query_mut(self, query)
+    }
+
+    fn visit_assignment_mut(&mut self, assignment: &mut Assignment) {
+        self.shift(&mut assignment.span);
+        visit::walk_assignment_mut(self, assignment)
+    }
+
+    fn visit_identity_mut(&mut self, identity: &mut IdentityValue) {
+        self.shift(&mut identity.span);
+    }
+
+    fn visit_binary_expr_mut(&mut self, binary: &mut BinaryExpr) {
+        self.shift(&mut binary.span);
+        visit::walk_binary_expr_mut(self, binary)
+    }
+
+    fn visit_unary_expr_mut(&mut self, unary: &mut UnaryExpr) {
+        self.shift(&mut unary.span);
+        visit::walk_unary_expr_mut(self, unary)
+    }
+
+    fn visit_function_call_mut(&mut self, call: &mut FunctionCall) {
+        self.shift(&mut call.span);
+        visit::walk_function_call_mut(self, call)
+    }
+
+    fn visit_in_mut(&mut self, in_expr: &mut InExpr) {
+        self.shift(&mut in_expr.span);
+        visit::walk_in_mut(self, in_expr)
+    }
+
+    fn visit_let_mut(&mut self, let_expr: &mut LetExpr) {
+        self.shift(&mut let_expr.span);
+        visit::walk_let_mut(self, let_expr)
+    }
+}
+
+/// Parses and analyzes the statements that start before `end`, returning the analyzer with the
+/// bindings of those statements and the syntax errors.
+fn analyze<'a>(source: &str, end: usize, catalog: &'a Catalog) -> (Analyzer<'a>, Vec<Diagnostic>) {
+    let mut analyzer = Analyzer::new(Some(catalog));
+    let mut errors = Vec::new();
+
+    for (start, text) in statements(source) {
+        if start >= end {
+            break;
+        }
+
+        match parse_query(text) {
+            Ok(nodes) => {
+                for mut node in nodes {
+                    Shift(start).visit_node_mut(&mut node);
+                    analyzer.statement(&node);
+                }
+            }
+            Err(error) => {
+                let (from, to) = match error.location {
+                    InputLocation::Pos(position) => (position, position),
+                    InputLocation::Span(span) => span,
+                };
+                errors.push(Diagnostic {
+                    span: Span::new(start + from, start + to),
+                    severity: Severity::Error,
+                    message: error.variant.message().into_owned(),
+                });
+            }
+        }
+    }
+
+    (analyzer, errors)
+}
+
+/// The syntax errors and the problems the analyzer finds, in source order.
+pub fn diagnostics(source: &str, catalog: &Catalog) -> Vec<Diagnostic> {
+    let (analyzer, mut diagnostics) = analyze(source, usize::MAX, catalog);
+    diagnostics.extend(analyzer.diagnostics);
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+    diagnostics
+}
+
+/// Describes the collection, field, binding or function call at an offset.
+pub fn hover(source: &str, offset: usize, catalog: &Catalog) -> Option<Hover> {
+    let (analyzer, _) = analyze(source, usize::MAX, catalog);
+
+    analyzer
+        .symbols
+        .into_iter()
+        .filter(|symbol| symbol.span.start <= offset && offset < symbol.span.end)
+        .min_by_key(|symbol| symbol.span.end - symbol.span.start)
+        .map(|symbol| Hover {
+            span: symbol.span,
+            contents: symbol.description,
+        })
+}
+
+/// A query nested in parentheses, or the statement itself, around the completed offset.
+struct Level {
+    start: usize,
+    pipes: Vec<usize>,
+
+    /// Whether the parentheses hold a query, rather than an expression.
+    query: bool,
+}
+
+fn ends_with_keyword(text: &str, keywords: &[&str]) -> bool {
+    let text = text.trim_end().to_ascii_lowercase();
+    keywords.iter().any(|keyword| {
+        text.strip_suffix(keyword)
+            .is_some_and(|rest| !rest.ends_with(|c: char| c.is_ascii_alphanumeric() || c == '_'))
+    })
+}
+
+/// The first word of a clause, lower case.
+fn first_word(clause: &str) -> String {
+    clause
+        .trim_start()
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+/// Whether a set operator clause, `union withsource=s a, b,`, expects another table.
+fn expects_table(clause: &str) -> bool {
+    let rest = clause.trim_start();
+    let rest = rest[first_word(rest).len()..].trim_start();
+    let rest = match rest.get(..10) {
+        Some(keyword) if keyword.eq_ignore_ascii_case("withsource") => {
+            match rest[10..].trim_start().strip_prefix('=') {
+                Some(name) => name
+                    .trim_start()
+                    .trim_start_matches(|c: char| c.is_ascii_alphanumeric() || c == '_'),
+                None => return false,
+            }
+        }
+        _ => rest,
+    };
+
+    rest.trim().is_empty() || rest.trim_end().ends_with(',')
+}
+
+/// Completes the word at an offset from the collections of the catalog, the fields of the
+/// documents flowing into the clause, operators and functions.
+pub fn completions(source: &str, offset: usize, catalog: &Catalog) -> Vec<Completion> {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &source[..offset];
+    let bytes = source.as_bytes();
+
+    // Find the query around the offset, pipes of `||` aren't operators
+    let mut levels = vec![Level {
+        start: 0,
+        pipes: Vec::new(),
+        query: true,
+    }];
+    let in_literal = scan(before, |i, c| match c {
+        ';' => {
+            levels = vec![Level {
+                start: i + 1,
+                pipes: Vec::new(),
+                query: true,
+            }];
+        }
+        '(' => {
+            let query = {
+                let outer = levels.last().unwrap();
+                let head = &before[outer.start..i];
+                let clause = &head[outer.pipes.last().map_or(0, |pipe| pipe + 1 - outer.start)..];
+                ends_with_keyword(head, &["union", "intersect", "except", "in", "toscalar"])
+                    || (matches!(
+                        first_word(clause).as_str(),
+                        "union" | "intersect" | "except"
+                    ) && head.trim_end().ends_with(','))
+            };
+            levels.push(Level {
+                start: i + 1,
+                pipes: Vec::new(),
+                query,
+            });
+        }
+        ')' if levels.len() > 1 => {
+            levels.pop();
+        }
+        '|' if bytes.get(i + 1) != Some(&b'|') && (i == 0 || bytes[i - 1] != b'|') => {
+            levels.last_mut().unwrap().pipes.push(i);
+        }
+        _ => {}
+    });
+    if in_literal {
+        return Vec::new();
+    }
+
+    let word_start = before
+        .trim_end_matches(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        .len();
+    let prefix = before[word_start..].to_ascii_lowercase();
+
+    // Expressions in parentheses complete like the query around them
+    let level = levels.iter().rposition(|level| level.query).unwrap_or(0);
+    let (expression, level) = (level + 1 < levels.len(), &levels[level]);
+
+    // A statement binding a name completes its value, its `=` comes before any pipe
+    let mut start = level.start;
+    let mut binding = false;
+    if levels[0].start == level.start && first_word(&before[start..]) == "let" {
+        let head = level.pipes.first().map_or(before.len(), |pipe| *pipe);
+        if let Some(equals) = before[start..head].find('=') {
+            start += equals + 1;
+            binding = true;
+        }
+    }
+    let clause_start = level.pipes.last().map_or(start, |pipe| pipe + 1);
+    let clause = &before[clause_start.min(word_start)..word_start];
+
+    // Bindings come from the statements before the completed one
+    let (analyzer, _) = analyze(source, levels[0].start, catalog);
+    let mut completions = Vec::new();
+
+    let tables = |completions: &mut Vec<Completion>| {
+        for collection in &catalog.collections {
+            completions.push(Completion {
+                label: collection.name.clone(),
+                kind: CompletionKind::Collection,
+                detail: format!("collection, {} fields", collection.fields.len()),
+            });
+        }
+        for name in analyzer.tables.keys() {
+            completions.push(Completion {
+                label: name.clone(),
+                kind: CompletionKind::Variable,
+                detail: String::from("query"),
+            });
+        }
+    };
+    let scalars = |completions: &mut Vec<Completion>| {
+        for (name, ty) in &analyzer.scalars {
+            completions.push(Completion {
+                label: name.clone(),
+                kind: CompletionKind::Variable,
+                detail: describe_type(*ty),
+            });
+        }
+    };
+    let functions = |completions: &mut Vec<Completion>, kind: Option<FunctionKind>| {
+        for function in FUNCTIONS {
+            if function.kind == FunctionKind::Scalar || Some(function.kind) == kind {
+                completions.push(Completion {
+                    label: function.name.to_owned(),
+                    kind: CompletionKind::Function,
+                    detail: function.signature.to_owned(),
+                });
+            }
+        }
+    };
+    let keywords = |completions: &mut Vec<Completion>, keywords: &[&str]| {
+        for keyword in keywords {
+            completions.push(Completion {
+                label: (*keyword).to_owned(),
+                kind: CompletionKind::Keyword,
+                detail: String::from("keyword"),
+            });
+        }
+    };
+
+    if level.pipes.is_empty() {
+        // The source of a query, or the value of a binding
+        if !expression && clause.trim().is_empty() {
+            tables(&mut completions);
+            if binding {
+                scalars(&mut completions);
+                functions(&mut completions, None);
+            } else if levels.len() == 1 && start == level.start {
+                keywords(
+                    &mut completions,
+                    &["let", "union", "begin", "commit", "rollback", "savepoint"],
+                );
+            }
+        } else if first_word(clause) == "union" && expects_table(clause) {
+            tables(&mut completions);
+        } else if binding {
+            scalars(&mut completions);
+            functions(&mut completions, None);
+        }
+    } else if clause.trim().is_empty() && !expression {
+        for (name, description) in OPERATORS {
+            completions.push(Completion {
+                label: (*name).to_owned(),
+                kind: CompletionKind::Operator,
+                detail: (*description).to_owned(),
+            });
+        }
+    } else {
+        let operator = first_word(clause);
+        match operator.as_str() {
+            "union" | "intersect" | "except" if !expression => {
+                if expects_table(clause) {
+                    tables(&mut completions);
+                }
+            }
+            "take" | "limit" | "for" => {}
+            "serialize" if !expression && ends_with_keyword(clause, &["serialize"]) => {
+                keywords(&mut completions, &["partition by", "order by"]);
+            }
+            _ => {
+                // The documents the clause reads come from the query before its pipe,
+                // operators that don't parse yet are left out
+                let mut analyzer = analyzer;
+                let schema = level
+                    .pipes
+                    .iter()
+                    .rev()
+                    .find_map(|pipe| match parse_query(&source[start..*pipe]) {
+                        Ok(nodes) => match nodes.as_slice() {
+                            [Node::Query(query)] => Some(analyzer.query(query)),
+                            _ => None,
+                        },
+                        Err(_) => None,
+                    })
+                    .unwrap_or_else(Schema::open);
+
+                for (name, ty) in &schema.fields {
+                    completions.push(Completion {
+                        label: name.clone(),
+                        kind: CompletionKind::Field,
+                        detail: describe_type(*ty),
+                    });
+                }
+                for (name, ty) in &analyzer.scalars {
+                    completions.push(Completion {
+                        label: name.clone(),
+                        kind: CompletionKind::Variable,
+                        detail: describe_type(*ty),
+                    });
+                }
+                match operator.as_str() {
+                    "distinct" | "serialize" => {}
+                    "summarize" => functions(&mut completions, Some(FunctionKind::Aggregate)),
+                    "extend" => functions(&mut completions, Some(FunctionKind::Window)),
+                    _ => functions(&mut completions, None),
+                }
+            }
+        }
+    }
+
+    let mut seen = std::collections::HashSet::new();
+    completions.retain(|completion| {
+        completion.label.to_ascii_lowercase().starts_with(&prefix)
+            && seen.insert(completion.label.clone())
+    });
+    completions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::{CollectionInfo, FieldInfo, ValueType};
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use proptest::sample::{select, Index};
+
+    fn catalog() -> Catalog {
+        let field = |name: &str, ty: ValueType| FieldInfo {
+            name: name.to_owned(),
+            types: vec![ty],
+        };
+
+        Catalog {
+            collections: vec![
+                CollectionInfo {
+                    name: String::from("events"),
+                    fields: vec![
+                        field("user", ValueType::String),
+                        field("amount", ValueType::Int),
+                    ],
+                    sampled: 2,
+                },
+                CollectionInfo {
+                    name: String::from("users"),
+                    fields: vec![
+                        field("name", ValueType::String),
+                        field("email", ValueType::String),
+                    ],
+                    sampled: 2,
+                },
+            ],
+        }
+    }
+
+    /// Completes at the `$` of the source.
+    fn labels(source: &str) -> Vec<String> {
+        let offset = source.find('$').unwrap();
+        let source = source.replace('$', "");
+        completions(&source, offset, &catalog())
+            .into_iter()
+            .map(|completion| completion.label)
+            .collect()
+    }
+
+    #[test]
+    fn completes_by_context() {
+        assert_eq!(
+            labels("$"),
+            [
+                "events",
+                "users",
+                "let",
+                "union",
+                "begin",
+                "commit",
+                "rollback",
+                "savepoint"
+            ]
+        );
+        assert_eq!(labels("ev$"), ["events"]);
+        assert_eq!(
+            labels("events | $"),
+            OPERATORS.iter().map(|(name, _)| *name).collect::<Vec<_>>()
+        );
+        assert_eq!(labels("events | su$"), ["summarize"]);
+        assert_eq!(
+            labels("events | where a$"),
+            ["amount", "ago", "array_concat"]
+        );
+        assert_eq!(labels("events | project u$"), ["user"]);
+        assert_eq!(labels("events | project n = user | where n$"), ["n", "now"]);
+        assert_eq!(labels("events | project n = user | distinct $"), ["n"]);
+        assert_eq!(labels("users | union e$"), ["events"]);
+        assert_eq!(
+            labels("users | union withsource=s events, $"),
+            ["events", "users"]
+        );
+        assert_eq!(labels("events | where user in (us$"), ["users"]);
+        assert_eq!(
+            labels("events | where user in (users | where e$"),
+            ["email"]
+        );
+        assert_eq!(labels("events | summarize c$"), ["coalesce", "count"]);
+        assert_eq!(labels("events | where isnull(u$"), ["user"]);
+        assert_eq!(labels("events | serialize $"), ["partition by", "order by"]);
+        assert_eq!(labels("events | where user == \"u$"), Vec::<String>::new());
+        assert_eq!(
+            labels("let t = users; let n = 1; t | where n$"),
+            ["name", "n", "now"]
+        );
+        assert_eq!(labels("let n = 1; let m = n$"), ["n", "now"]);
+        // A binding's `=` isn't looked for after its pipes
+        assert_eq!(labels("let events | where user == n$"), ["now"]);
+    }
+
+    #[test]
+    fn diagnostics_per_statement() {
+        let source = "events | where amount > \"x\";\nevents | where;\nusers | project nope";
+        let found: Vec<(Severity, &str)> = diagnostics(source, &catalog())
+            .iter()
+            .map(|diagnostic| {
+                (
+                    diagnostic.severity,
+                    &source[diagnostic.span.start..diagnostic.span.end],
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            found,
+            [
+                (Severity::Error, "amount > \"x\""),
+                (Severity::Error, ""),
+                (Severity::Warning, "nope"),
+            ]
+        );
+    }
+
+    #[test]
+    fn hovers_innermost() {
+        let source = "let n = 1; events | where amount > n and isnull(user)";
+        let at = |text: &str| {
+            hover(source, source.find(text).unwrap(), &catalog()).map(|hover| hover.contents)
+        };
+
+        assert_eq!(at("amount").unwrap(), "field amount: int");
+        assert_eq!(at("n and").unwrap(), "let n: int");
+        assert_eq!(at("user)").unwrap(), "field user: string");
+        assert!(at("isnull").unwrap().starts_with("isnull(value)"));
+        assert!(at("events")
+            .unwrap()
+            .starts_with("collection events: fields user: string"));
+        assert_eq!(at("where"), None);
+    }
+
+    const TOKENS: &[&str] = &[
+        "events",
+        "users",
+        "user",
+        "amount",
+        "let",
+        "x",
+        "=",
+        ";",
+        "|",
+        "||",
+        "(",
+        ")",
+        ",",
+        "where",
+        "project",
+        "extend",
+        "summarize",
+        "by",
+        "union",
+        "withsource",
+        "intersect",
+        "except",
+        "take",
+        "distinct",
+        "serialize",
+        "sort",
+        "toscalar",
+        "in",
+        "!in",
+        "==",
+        "-",
+        "+",
+        "*",
+        "^",
+        "and",
+        "not",
+        "1",
+        "-9223372036854775808",
+        "1.5",
+        "1d",
+        "\"s\"",
+        "\"é\"",
+        "count()",
+        "isnull(",
+        "row_number()",
+        "@p",
+        "--c\n",
+        "\n",
+        "ü",
+    ];
+
+    proptest! {
+        // Editors call the services with whatever is being typed
+        #[test]
+        fn serves_any_text(source in "\\PC{0,64}", offset in any::<Index>()) {
+            let offset = offset.index(source.len() + 1);
+            let _ = diagnostics(&source, &catalog());
+            let _ = hover(&source, offset, &catalog());
+            let _ = completions(&source, offset, &catalog());
+        }
+
+        #[test]
+        fn serves_token_soup(tokens in vec(select(TOKENS), 1..16), offset in any::<Index>()) {
+            let source = tokens.join(" ");
+            let offset = offset.index(source.len() + 1);
+            let _ = diagnostics(&source, &catalog());
+            let _ = hover(&source, offset, &catalog());
+            let _ = completions(&source, offset, &catalog());
+        }
+    }
+}
@@ -0,0 +1,915 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+//! A SQL front-end. Statements are parsed with `sqlparser` and translated into the same AST the
+//! query language produces, so both are planned and executed the same way. `sqlparser` doesn't
+//! keep source positions, the nodes it produces have empty spans.
+
+use sqlparser::ast as sql;
+use sqlparser::dialect::GenericDialect;
+use sqlparser::parser::{Parser, ParserError};
+
+use crate::ast::{
+    Assignment, BinaryExpr, BinaryOp, DeleteExpr, FunctionCall, IdentityValue, InExpr, InSet,
+    InsertExpr, Node, OrderByClause, OrderByDirection, QueryExpr, QueryOperator, QuerySource,
+    ScalarValue, Span, SummarizeExpr, TransactionStmt, UnaryExpr, UnaryOp, UnionExpr, UpdateExpr,
+    WindowExpr,
+};
+
+type Result<T> = std::result::Result<T, ParserError>;
+
+/// Parses a script of SQL statements into the query language AST.
+pub fn parse_sql(source: &str) -> Result<Vec<Node>> {
+    Parser::parse_sql(&GenericDialect {}, source)?
+        .iter()
+        .map(statement)
+        .collect()
+}
+
+fn unsupported<T>(what: impl std::fmt::Display) -> Result<T> {
+    Err(ParserError::ParserError(format!(
+        "Unsupported SQL: {}",
+        what
+    )))
+}
+
+/// The names visible to the expressions of a single SELECT.
+#[derive(Default)]
+struct Scope {
+    /// Table names and aliases a column may be qualified with
+    qualifiers: Vec<String>,
+    /// The GROUP BY keys when the SELECT aggregates, as their SQL text and field
+    groups: Option<Vec<(String, Assignment)>>,
+    /// The aggregates used so far, named after their SQL text
+    aggregates: Vec<Assignment>,
+}
+
+impl Scope {
+    fn row(&self) -> Scope {
+        Scope {
+            qualifiers: self.qualifiers.clone(),
+            ..Scope::default()
+        }
+    }
+}
+
+fn statement(statement: &sql::Statement) -> Result<Node> {
+    match statement {
+        sql::Statement::Query(query_expr) => Ok(Node::Query(query(query_expr)?)),
+        sql::Statement::Insert {
+            table_name,
+            columns,
+            source,
+            ..
+        } => {
+            let rows = match &source.body {
+                sql::SetExpr::Values(values) if source.order_by.is_empty() => &values.0,
+                _ => return unsupported("INSERT from a query"),
+            };
+            if columns.is_empty() {
+                return unsupported("INSERT without a column list");
+            }
+
+            let mut scope = Scope::default();
+            let rows = rows
+                .iter()
+                .map(|row| {
+                    if row.len() != columns.len() {
+                        return Err(ParserError::ParserError(format!(
+                            "INSERT has {} columns but {} values",
+                            columns.len(),
+                            row.len()
+                        )));
+                    }
+                    columns
+                        .iter()
+                        .zip(row)
+                        .map(|(column, value)| {
+                            Ok(Assignment {
+                                name: column.value.clone(),
+                                value: expr(value, &mut scope)?,
+                                span: Span::default(),
+                            })
+                        })
+                        .collect()
+                })
+                .collect::<Result<_>>()?;
+
+            Ok(Node::Insert(InsertExpr {
+                table: table(table_name, None),
+                rows,
+            }))
+        }
+        sql::Statement::Update {
+            table_name,
+            assignments,
+            selection,
+        } => {
+            let mut scope = table_scope(table_name, None);
+            let assignments = assignments
+                .iter()
+                .map(|assignment| {
+                    Ok(Assignment {
+                        name: assignment.id.value.clone(),
+                        value: expr(&assignment.value, &mut scope)?,
+                        span: Span::default(),
+                    })
+                })
+                .collect::<Result<_>>()?;
+
+            Ok(Node::Update(UpdateExpr {
+                table: table(table_name, None),
+                assignments,
+                filter: filter(selection, &mut scope)?,
+            }))
+        }
+        sql::Statement::Delete {
+            table_name,
+            selection,
+        } => {
+            let mut scope = table_scope(table_name, None);
+            Ok(Node::Delete(DeleteExpr {
+                table: table(table_name, None),
+                filter: filter(selection, &mut scope)?,
+            }))
+        }
+        sql::Statement::StartTransaction { modes } if modes.is_empty() => {
+            Ok(Node::Transaction(TransactionStmt::Begin))
+        }
+        sql::Statement::Commit { chain: false } => Ok(Node::Transaction(TransactionStmt::Commit)),
+        sql::Statement::Rollback { chain: false } => {
+            Ok(Node::Transaction(TransactionStmt::Rollback))
+        }
+        statement => unsupported(statement),
+    }
+}
+
+fn table(name: &sql::ObjectName, alias: Option<&sql::TableAlias>) -> IdentityValue {
+    IdentityValue {
+        value: name
+            .0
+            .last()
+            .map(|ident| ident.value.clone())
+            .unwrap_or_default(),
+        alias: alias.map(|alias| alias.name.value.clone()),
+        span: Span::default(),
+    }
+}
+
+fn table_scope(name: &sql::ObjectName, alias: Option<&sql::TableAlias>) -> Scope {
+    let table = table(name, alias);
+    Scope {
+        qualifiers: table.alias.into_iter().chain([table.value]).collect(),
+        ..Scope::default()
+    }
+}
+
+fn filter(selection: &Option<sql::Expr>, scope: &mut Scope) -> Result<Option<Box<Node>>> {
+    selection
+        .as_ref()
+        .map(|selection| Ok(Box::new(expr(selection, scope)?)))
+        .transpose()
+}
+
+fn query(query_expr: &sql::Query) -> Result<QueryExpr> {
+    if query_expr.with.is_some() {
+        return unsupported("WITH");
+    }
+    if query_expr.offset.is_some() || query_expr.fetch.is_some() {
+        return unsupported("OFFSET and FETCH");
+    }
+
+    let mut result = match &query_expr.body {
+        sql::SetExpr::Select(body) => select(body, &query_expr.order_by)?,
+        body => {
+            // The columns of a set operation are only known by name
+            let mut result = set_expr(body)?;
+            if !query_expr.order_by.is_empty() {
+                let mut scope = Scope::default();
+                let order_by = query_expr
+                    .order_by
+                    .iter()
+                    .map(|order| match expr(&order.expr, &mut scope)? {
+                        Node::Identity(identity) => order_by(identity, order),
+                        _ => unsupported(format!("ORDER BY {}", order.expr)),
+                    })
+                    .collect::<Result<_>>()?;
+                result.operators.push(serialize(order_by));
+            }
+            result
+        }
+    };
+
+    if let Some(limit) = &query_expr.limit {
+        let count = match limit {
+            sql::Expr::Value(sql::Value::Number(count, _)) => count.parse().ok(),
+            _ => None,
+        };
+        match count {
+            Some(count) => result.operators.push(QueryOperator::Take(count)),
+            None => return unsupported(format!("LIMIT {}", limit)),
+        }
+    }
+
+    Ok(result)
+}
+
+fn set_expr(body: &sql::SetExpr) -> Result<QueryExpr> {
+    match body {
+        sql::SetExpr::Select(body) => select(body, &[]),
+        sql::SetExpr::Query(body) => query(body),
+        sql::SetExpr::SetOperation {
+            op,
+            all,
+            left,
+            right,
+        } => {
+            let mut result = set_expr(left)?;
+            let right = set_expr(right)?;
+
+            result.operators.push(match op {
+                sql::SetOperator::Union => QueryOperator::Union(UnionExpr {
+                    with_source: None,
+                    operands: vec![right],
+                }),
+                sql::SetOperator::Intersect => QueryOperator::Intersect(vec![right]),
+                sql::SetOperator::Except => QueryOperator::Except(vec![right]),
+            });
+            // Without ALL the result is a set, duplicate documents are removed
+            if !all {
+                result.operators.push(QueryOperator::Distinct(Vec::new()));
+            }
+
+            Ok(result)
+        }
+        body => unsupported(body),
+    }
+}
+
+fn select(body: &sql::Select, order: &[sql::OrderByExpr]) -> Result<QueryExpr> {
+    if body.top.is_some()
+        || !body.lateral_views.is_empty()
+        || !body.cluster_by.is_empty()
+        || !body.distribute_by.is_empty()
+        || !body.sort_by.is_empty()
+    {
+        return unsupported(body);
+    }
+
+    let (source, mut scope) = match body.from.as_slice() {
+        [from] if from.joins.is_empty() => match &from.relation {
+            sql::TableFactor::Table { name, alias, .. } => (
+                QuerySource::Table(table(name, alias.as_ref())),
+                table_scope(name, alias.as_ref()),
+            ),
+            // A derived table is read as the union of a single query
+            sql::TableFactor::Derived {
+                subquery, alias, ..
+            } => (
+                QuerySource::Union(UnionExpr {
+                    with_source: None,
+                    operands: vec![query(subquery)?],
+                }),
+                Scope {
+                    qualifiers: alias.iter().map(|alias| alias.name.value.clone()).collect(),
+                    ..Scope::default()
+                },
+            ),
+            relation => return unsupported(relation),
+        },
+        [] => return unsupported("SELECT without FROM"),
+        _ => return unsupported("joins"),
+    };
+
+    let mut result = QueryExpr {
+        source,
+        operators: Vec::new(),
+        order: Vec::new(),
+        span: Span::default(),
+    };
+
+    if let Some(selection) = &body.selection {
+        result
+            .operators
+            .push(QueryOperator::Where(expr(selection, &mut scope)?));
+    }
+
+    let aggregated = !body.group_by.is_empty()
+        || body.having.as_ref().is_some_and(has_aggregate)
+        || body.projection.iter().any(|item| match item {
+            sql::SelectItem::UnnamedExpr(e) | sql::SelectItem::ExprWithAlias { expr: e, .. } => {
+                has_aggregate(e)
+            }
+            _ => false,
+        });
+
+    if aggregated {
+        let groups = body
+            .group_by
+            .iter()
+            .map(|group| {
+                let value = expr(group, &mut scope)?;
+                let name = match &value {
+                    Node::Identity(identity) => identity.value.clone(),
+                    _ => group.to_string(),
+                };
+                Ok((
+                    group.to_string(),
+                    Assignment {
+                        name,
+                        value,
+                        span: Span::default(),
+                    },
+                ))
+            })
+            .collect::<Result<_>>()?;
+        scope.groups = Some(groups);
+    }
+
+    // Every output field with the SQL text it was selected with
+    let mut outputs = Vec::new();
+    for item in &body.projection {
+        let (text, name, value) = match item {
+            sql::SelectItem::UnnamedExpr(e) => {
+                let value = expr(e, &mut scope)?;
+                let name = match e {
+                    sql::Expr::Identifier(ident) => ident.value.clone(),
+                    sql::Expr::CompoundIdentifier(idents) => idents.last().unwrap().value.clone(),
+                    e => e.to_string(),
+                };
+                (e.to_string(), name, value)
+            }
+            sql::SelectItem::ExprWithAlias { expr: e, alias } => {
+                (e.to_string(), alias.value.clone(), expr(e, &mut scope)?)
+            }
+            sql::SelectItem::Wildcard if body.projection.len() == 1 && !aggregated => {
+                break;
+            }
+            item => return unsupported(item),
+        };
+        outputs.push((
+            text,
+            Assignment {
+                name,
+                value,
+                span: Span::default(),
+            },
+        ));
+    }
+
+    let having = body
+        .having
+        .as_ref()
+        .map(|having| expr(having, &mut scope))
+        .transpose()?;
+
+    if let Some(groups) = scope.groups.take() {
+        result
+            .operators
+            .push(QueryOperator::Summarize(SummarizeExpr {
+                aggregates: std::mem::take(&mut scope.aggregates),
+                by: groups.into_iter().map(|(_, group)| group).collect(),
+            }));
+    }
+    if let Some(having) = having {
+        result.operators.push(QueryOperator::Where(having));
+    }
+
+    // Sort on the output when every key is selected, otherwise on the input before projecting
+    let selected: Option<Vec<OrderByClause>> = order
+        .iter()
+        .map(|order| {
+            let name = match &order.expr {
+                sql::Expr::Identifier(ident) => Some(&ident.value),
+                sql::Expr::CompoundIdentifier(idents) => idents.last().map(|ident| &ident.value),
+                _ => None,
+            };
+            outputs
+                .iter()
+                .find(|(text, output)| {
+                    *text == order.expr.to_string() || Some(&output.name) == name
+                })
+                .map(|(_, output)| order_by(identity(&output.name), order))
+        })
+        .collect::<Option<_>>()
+        .transpose()?;
+
+    let sort_after = match (selected, outputs.is_empty()) {
+        (Some(order_by), false) => Some(order_by),
+        (_, true) | (None, false) => {
+            if !order.is_empty() {
+                if aggregated || body.distinct {
+                    return unsupported("ORDER BY on columns that aren't selected");
+                }
+                let order_by = order
+                    .iter()
+                    .map(|order| match expr(&order.expr, &mut scope)? {
+                        Node::Identity(identity) => order_by(identity, order),
+                        _ => unsupported(format!("ORDER BY {}", order.expr)),
+                    })
+                    .collect::<Result<_>>()?;
+                result.operators.push(serialize(order_by));
+            }
+            None
+        }
+    };
+
+    if !outputs.is_empty() {
+        let fields: Vec<Assignment> = outputs.into_iter().map(|(_, output)| output).collect();
+        if body.distinct {
+            let distinct = fields.iter().map(|field| identity(&field.name));
+            let distinct = QueryOperator::Distinct(distinct.collect());
+            result.operators.push(QueryOperator::Project(fields));
+            result.operators.push(distinct);
+        } else {
+            result.operators.push(QueryOperator::Project(fields));
+        }
+    } else if body.distinct {
+        result.operators.push(QueryOperator::Distinct(Vec::new()));
+    }
+
+    if let Some(order_by) = sort_after {
+        if !order_by.is_empty() {
+            result.operators.push(serialize(order_by));
+        }
+    }
+
+    Ok(result)
+}
+
+fn serialize(order_by: Vec<OrderByClause>) -> QueryOperator {
+    QueryOperator::Serialize(WindowExpr {
+        partition_by: Vec::new(),
+        order_by,
+    })
+}
+
+fn order_by(identity: IdentityValue, order: &sql::OrderByExpr) -> Result<OrderByClause> {
+    if order.nulls_first.is_some() {
+        return unsupported("NULLS FIRST and NULLS LAST");
+    }
+
+    Ok(OrderByClause {
+        identity,
+        direction: match order.asc {
+            Some(false) => OrderByDirection::Desc,
+            _ => OrderByDirection::Asc,
+        },
+    })
+}
+
+fn identity(name: &str) -> IdentityValue {
+    IdentityValue {
+        value: name.to_owned(),
+        alias: None,
+        span: Span::default(),
+    }
+}
+
+fn is_aggregate(name: &sql::ObjectName) -> bool {
+    let name = name.to_string().to_lowercase();
+    matches!(name.as_str(), "count" | "sum" | "min" | "max" | "avg")
+}
+
+/// Whether an expression uses an aggregate function outside of a subquery.
+fn has_aggregate(e: &sql::Expr) -> bool {
+    match e {
+        sql::Expr::Function(function) => {
+            is_aggregate(&function.name)
+                || function.args.iter().any(|arg| match arg {
+                    sql::FunctionArg::Named { arg, .. } | sql::FunctionArg::Unnamed(arg) => {
+                        has_aggregate(arg)
+                    }
+                })
+        }
+        sql::Expr::BinaryOp { left, right, .. } => has_aggregate(left) || has_aggregate(right),
+        sql::Expr::UnaryOp { expr, .. }
+        | sql::Expr::Nested(expr)
+        | sql::Expr::IsNull(expr)
+        | sql::Expr::IsNotNull(expr)
+        | sql::Expr::InSubquery { expr, .. } => has_aggregate(expr),
+        sql::Expr::InList { expr, list, .. } => {
+            has_aggregate(expr) || list.iter().any(has_aggregate)
+        }
+        sql::Expr::Between {
+            expr, low, high, ..
+        } => has_aggregate(expr) || has_aggregate(low) || has_aggregate(high),
+        _ => false,
+    }
+}
+
+fn expr(e: &sql::Expr, scope: &mut Scope) -> Result<Node> {
+    // Grouped expressions are read from the field summarize gave them
+    if let Some(groups) = &scope.groups {
+        let text = e.to_string();
+        if let Some((_, group)) = groups.iter().find(|(group, _)| *group == text) {
+            return Ok(Node::Identity(identity(&group.name)));
+        }
+    }
+
+    Ok(match e {
+        sql::Expr::Identifier(ident) => match ident.value.strip_prefix('@') {
+            Some(name) if ident.quote_style.is_none() => Node::Parameter(name.to_owned()),
+            _ => column(std::slice::from_ref(ident), scope)?,
+        },
+        sql::Expr::CompoundIdentifier(idents) => column(idents, scope)?,
+        sql::Expr::Value(value) => Node::Scalar(scalar(value)?),
+        sql::Expr::Nested(e) => expr(e, scope)?,
+        sql::Expr::UnaryOp { op, expr: e } => match (op, expr(e, scope)?) {
+            (sql::UnaryOperator::Plus, e) => e,
+            (sql::UnaryOperator::Minus, Node::Scalar(ScalarValue::Int(value))) => {
+                Node::Scalar(ScalarValue::Int(-value))
+            }
+            (sql::UnaryOperator::Minus, Node::Scalar(ScalarValue::Decimal(value))) => {
+                Node::Scalar(ScalarValue::Decimal(-value))
+            }
+            (sql::UnaryOperator::Minus, e) => unary(UnaryOp::Negate, e),
+            (sql::UnaryOperator::Not, e) => unary(UnaryOp::Not, e),
+            (op, _) => return unsupported(op),
+        },
+        sql::Expr::BinaryOp { left, op, right } => {
+            let op = match op {
+                sql::BinaryOperator::Plus => BinaryOp::Add,
+                sql::BinaryOperator::Minus => BinaryOp::Subtract,
+                sql::BinaryOperator::Multiply => BinaryOp::Multiply,
+                sql::BinaryOperator::Divide => BinaryOp::Divide,
+                sql::BinaryOperator::Modulo => BinaryOp::Modulo,
+                sql::BinaryOperator::Gt => BinaryOp::Gt,
+                sql::BinaryOperator::Lt => BinaryOp::Lt,
+                sql::BinaryOperator::GtEq => BinaryOp::GtEq,
+                sql::BinaryOperator::LtEq => BinaryOp::LtEq,
+                sql::BinaryOperator::Eq => BinaryOp::Eq,
+                sql::BinaryOperator::NotEq => BinaryOp::Ne,
+                sql::BinaryOperator::And => BinaryOp::And,
+                sql::BinaryOperator::Or => BinaryOp::Or,
+                sql::BinaryOperator::Like => BinaryOp::Like,
+                sql::BinaryOperator::NotLike => {
+                    let like = binary(expr(left, scope)?, BinaryOp::Like, expr(right, scope)?);
+                    return Ok(unary(UnaryOp::Not, like));
+                }
+                op => return unsupported(op),
+            };
+            binary(expr(left, scope)?, op, expr(right, scope)?)
+        }
+        sql::Expr::IsNull(e) => call("isnull", vec![expr(e, scope)?]),
+        sql::Expr::IsNotNull(e) => call("isnotnull", vec![expr(e, scope)?]),
+        sql::Expr::Between {
+            expr: e,
+            negated,
+            low,
+            high,
+        } => {
+            let value = expr(e, scope)?;
+            let between = binary(
+                binary(value.clone(), BinaryOp::GtEq, expr(low, scope)?),
+                BinaryOp::And,
+                binary(value, BinaryOp::LtEq, expr(high, scope)?),
+            );
+            match negated {
+                true => unary(UnaryOp::Not, between),
+                false => between,
+            }
+        }
+        sql::Expr::InList {
+            expr: e,
+            list,
+            negated,
+        } => Node::In(InExpr {
+            expr: Box::new(expr(e, scope)?),
+            negated: *negated,
+            set: InSet::List(
+                list.iter()
+                    .map(|value| expr(value, scope))
+                    .collect::<Result<_>>()?,
+            ),
+            span: Span::default(),
+        }),
+        sql::Expr::InSubquery {
+            expr: e,
+            subquery,
+            negated,
+        } => Node::In(InExpr {
+            expr: Box::new(expr(e, scope)?),
+            negated: *negated,
+            set: InSet::Query(query(subquery)?),
+            span: Span::default(),
+        }),
+        sql::Expr::Subquery(subquery) => Node::ToScalar(Box::new(query(subquery)?)),
+        sql::Expr::Function(function) => {
+            if function.over.is_some() || function.distinct {
+                return unsupported(e);
+            }
+
+            let name = function.name.to_string().to_lowercase();
+            let aggregate = is_aggregate(&function.name);
+            // The arguments of an aggregate are read from the rows of the group
+            let mut row = scope.row();
+            let args_scope = match aggregate {
+                true => &mut row,
+                false => &mut *scope,
+            };
+
+            let mut args = Vec::new();
+            for arg in &function.args {
+                match arg {
+                    // COUNT(*) counts every row
+                    sql::FunctionArg::Unnamed(sql::Expr::Wildcard) if name == "count" => {}
+                    sql::FunctionArg::Unnamed(arg) => args.push(expr(arg, args_scope)?),
+                    arg => return unsupported(arg),
+                }
+            }
+
+            if !aggregate {
+                return Ok(call(&name, args));
+            }
+            if scope.groups.is_none() {
+                return unsupported(format!("{} outside of SELECT and HAVING", e));
+            }
+
+            let text = e.to_string();
+            if !scope
+                .aggregates
+                .iter()
+                .any(|aggregate| aggregate.name == text)
+            {
+                scope.aggregates.push(Assignment {
+                    name: text.clone(),
+                    value: call(&name, args),
+                    span: Span::default(),
+                });
+            }
+            Node::Identity(identity(&text))
+        }
+        e => return unsupported(e),
+    })
+}
+
+/// A column reference, the table name or alias it may be qualified with is dropped.
+fn column(idents: &[sql::Ident], scope: &Scope) -> Result<Node> {
+    let idents = match idents {
+        [qualifier, rest @ ..]
+            if !rest.is_empty()
+                && scope
+                    .qualifiers
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(&qualifier.value)) =>
+        {
+            rest
+        }
+        idents => idents,
+    };
+
+    let name = idents
+        .iter()
+        .map(|ident| ident.value.as_str())
+        .collect::<Vec<_>>()
+        .join(".");
+    let field = Node::Identity(identity(&name));
+
+    match &scope.groups {
+        Some(groups) => match groups.iter().find(|(_, group)| group.value == field) {
+            Some((_, group)) => Ok(Node::Identity(identity(&group.name))),
+            None => Err(ParserError::ParserError(format!(
+                "Column {} must appear in GROUP BY or be used in an aggregate function",
+                name
+            ))),
+        },
+        None => Ok(field),
+    }
+}
+
+fn scalar(value: &sql::Value) -> Result<ScalarValue> {
+    Ok(match value {
+        sql::Value::Number(number, _) => match number.parse() {
+            Ok(int) => ScalarValue::Int(int),
+            Err(_) => match number.parse() {
+                Ok(decimal) => ScalarValue::Decimal(decimal),
+                Err(_) => return unsupported(number),
+            },
+        },
+        sql::Value::SingleQuotedString(value)
+        | sql::Value::NationalStringLiteral(value)
+        | sql::Value::DoubleQuotedString(value) => ScalarValue::String(value.clone()),
+        sql::Value::Boolean(value) => ScalarValue::Boolean(*value),
+        sql::Value::Null => ScalarValue::Null,
+        value => return unsupported(value),
+    })
+}
+
+fn binary(left: Node, op: BinaryOp, right: Node) -> Node {
+    Node::BinaryExpr(BinaryExpr {
+        op,
+        left: Box::new(left),
+        right: Box::new(right),
+        span: Span::default(),
+    })
+}
+
+fn unary(op: UnaryOp, expr: Node) -> Node {
+    Node::UnaryExpr(UnaryExpr {
+        op,
+        expr: Box::new(expr),
+        span: Span::default(),
+    })
+}
+
+fn call(name: &str, args: Vec<Node>) -> Node {
+    Node::FunctionCall(FunctionCall {
+        name: name.to_owned(),
+        args,
+        span: Span::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Node {
+        parse_sql(source).unwrap().pop().unwrap()
+    }
+
+    fn field(name: &str) -> Node {
+        Node::Identity(identity(name))
+    }
+
+    fn int(value: i64) -> Node {
+        Node::Scalar(ScalarValue::Int(value))
+    }
+
+    fn assignment(name: &str, value: Node) -> Assignment {
+        Assignment {
+            name: name.to_owned(),
+            value,
+            span: Span::default(),
+        }
+    }
+
+    fn asc(name: &str) -> OrderByClause {
+        OrderByClause {
+            identity: identity(name),
+            direction: OrderByDirection::Asc,
+        }
+    }
+
+    #[test]
+    fn select_where_order_limit() {
+        let node = parse(
+            "SELECT o.name, total * 2 AS double FROM orders o WHERE o.total > @min ORDER BY total LIMIT 10",
+        );
+
+        let mut expected = QueryExpr::table("orders");
+        expected.source = QuerySource::Table(IdentityValue {
+            value: String::from("orders"),
+            alias: Some(String::from("o")),
+            span: Span::default(),
+        });
+        expected.operators = vec![
+            QueryOperator::Where(binary(
+                field("total"),
+                BinaryOp::Gt,
+                Node::Parameter(String::from("min")),
+            )),
+            // total isn't selected, so the documents are sorted before projecting
+            serialize(vec![asc("total")]),
+            QueryOperator::Project(vec![
+                assignment("name", field("name")),
+                assignment("double", binary(field("total"), BinaryOp::Multiply, int(2))),
+            ]),
+            QueryOperator::Take(10),
+        ];
+
+        assert_eq!(node, Node::Query(expected));
+    }
+
+    #[test]
+    fn group_by_having() {
+        let node = parse(
+            "SELECT user, COUNT(*) AS n, SUM(amount) FROM events \
+             GROUP BY user HAVING COUNT(*) > 1 ORDER BY n DESC",
+        );
+
+        let mut expected = QueryExpr::table("events");
+        expected.operators = vec![
+            QueryOperator::Summarize(SummarizeExpr {
+                aggregates: vec![
+                    assignment("COUNT(*)", call("count", vec![])),
+                    assignment("SUM(amount)", call("sum", vec![field("amount")])),
+                ],
+                by: vec![assignment("user", field("user"))],
+            }),
+            QueryOperator::Where(binary(field("COUNT(*)"), BinaryOp::Gt, int(1))),
+            QueryOperator::Project(vec![
+                assignment("user", field("user")),
+                assignment("n", field("COUNT(*)")),
+                assignment("SUM(amount)", field("SUM(amount)")),
+            ]),
+            serialize(vec![OrderByClause {
+                identity: identity("n"),
+                direction: OrderByDirection::Desc,
+            }]),
+        ];
+
+        assert_eq!(node, Node::Query(expected));
+    }
+
+    #[test]
+    fn ungrouped_column_is_rejected() {
+        assert!(parse_sql("SELECT user, amount, COUNT(*) FROM events GROUP BY user").is_err());
+        assert!(parse_sql("SELECT * FROM events WHERE COUNT(*) > 1").is_err());
+    }
+
+    #[test]
+    fn set_operations_and_predicates() {
+        let node = parse(
+            "SELECT id FROM a WHERE x IS NULL AND y NOT IN (SELECT id FROM c) \
+             UNION SELECT id FROM b",
+        );
+
+        let mut c = QueryExpr::table("c");
+        c.operators = vec![QueryOperator::Project(vec![assignment("id", field("id"))])];
+        let mut b = QueryExpr::table("b");
+        b.operators = c.operators.clone();
+
+        let mut expected = QueryExpr::table("a");
+        expected.operators = vec![
+            QueryOperator::Where(binary(
+                call("isnull", vec![field("x")]),
+                BinaryOp::And,
+                Node::In(InExpr {
+                    expr: Box::new(field("y")),
+                    negated: true,
+                    set: InSet::Query(c),
+                    span: Span::default(),
+                }),
+            )),
+            QueryOperator::Project(vec![assignment("id", field("id"))]),
+            QueryOperator::Union(UnionExpr {
+                with_source: None,
+                operands: vec![b],
+            }),
+            QueryOperator::Distinct(vec![]),
+        ];
+
+        assert_eq!(node, Node::Query(expected));
+    }
+
+    #[test]
+    fn insert_update_delete() {
+        let statements = parse_sql(
+            "INSERT INTO users (name, age) VALUES ('ann', 31), ('bob', -2); \
+             UPDATE users SET age = age + 1 WHERE users.name = 'ann'; \
+             DELETE FROM users WHERE age BETWEEN 0 AND 17",
+        )
+        .unwrap();
+
+        let string = |value: &str| Node::Scalar(ScalarValue::String(value.to_owned()));
+        assert_eq!(
+            statements,
+            vec![
+                Node::Insert(InsertExpr {
+                    table: identity("users"),
+                    rows: vec![
+                        vec![
+                            assignment("name", string("ann")),
+                            assignment("age", int(31))
+                        ],
+                        vec![
+                            assignment("name", string("bob")),
+                            assignment("age", int(-2))
+                        ],
+                    ],
+                }),
+                Node::Update(UpdateExpr {
+                    table: identity("users"),
+                    assignments: vec![assignment(
+                        "age",
+                        binary(field("age"), BinaryOp::Add, int(1))
+                    )],
+                    filter: Some(Box::new(binary(field("name"), BinaryOp::Eq, string("ann")))),
+                }),
+                Node::Delete(DeleteExpr {
+                    table: identity("users"),
+                    filter: Some(Box::new(binary(
+                        binary(field("age"), BinaryOp::GtEq, int(0)),
+                        BinaryOp::And,
+                        binary(field("age"), BinaryOp::LtEq, int(17)),
+                    ))),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn transactions() {
+        let statements =
+            parse_sql("BEGIN; DELETE FROM users; ROLLBACK; START TRANSACTION; COMMIT").unwrap();
+
+        assert_eq!(statements[0], Node::Transaction(TransactionStmt::Begin));
+        assert_eq!(statements[2], Node::Transaction(TransactionStmt::Rollback));
+        assert_eq!(statements[3], Node::Transaction(TransactionStmt::Begin));
+        assert_eq!(statements[4], Node::Transaction(TransactionStmt::Commit));
+        assert!(parse_sql("COMMIT AND CHAIN").is_err());
+    }
+}
@@ -61,10 +61,48 @@ fn number_signed_int() {
     parses_ok(Rule::Int, "-7000");
 }
 
-// #[test]
-// fn scalar_date_ok() {
-//     parses_ok(Rule::scalar, "2023-04-05");
-// }
+#[test]
+fn scalar_date_ok() {
+    parses_ok(Rule::ScalarValue, "datetime(2023-04-05)");
+}
+
+#[test]
+fn scalar_datetime_with_zone_ok() {
+    parses_ok(Rule::ScalarValue, "datetime(2023-04-05T10:30:00.25+02:00)");
+}
+
+#[test]
+fn scalar_timespan_ok() {
+    parses_ok(Rule::ScalarValue, "90s");
+}
+
+#[test]
+fn scalar_object_id_ok() {
+    parses_ok(Rule::ScalarValue, "objectid(\"64b7f0c2a1e4d3b2c1a09f8e\")");
+}
+
+#[test]
+fn scalar_object_id_too_short() {
+    parses_not_ok(Rule::ObjectId, "objectid(\"64b7f0c2\")");
+}
+
+#[test]
+fn scalar_uuid_ok() {
+    parses_ok(
+        Rule::ScalarValue,
+        "uuid(\"67e55044-10b1-426f-9247-bb680e5fe0c8\")",
+    );
+}
+
+#[test]
+fn scalar_array_ok() {
+    parses_ok(Rule::ScalarValue, "[1, -2.5, \"three\", [true]]");
+}
+
+#[test]
+fn scalar_object_ok() {
+    parses_ok(Rule::ScalarValue, "{ \"a\": 1, b: { c: null } }");
+}
 
 // #[test]
 // fn scalar_weird_value() {
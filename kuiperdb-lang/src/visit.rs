@@ -0,0 +1,579 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+//! Traversal of the AST. `Visitor` walks a tree by reference and `VisitorMut` walks it mutably,
+//! so nodes can be rewritten in place. Every method defaults to the matching `walk_*` function,
+//! which visits the children of the node in source order. An override that still wants the
+//! children visited calls the `walk_*` function itself.
+
+use crate::ast::{
+    Assignment, BinaryExpr, DeleteExpr, FunctionCall, IdentityValue, InExpr, InSet, InsertExpr,
+    LetExpr, Node, OrderByClause, QueryExpr, QueryOperator, QuerySource, ScalarValue, Span,
+    SummarizeExpr, UnaryExpr, UnionExpr, UpdateExpr, WindowExpr,
+};
+
+/// Visits the nodes of an AST by reference.
+pub trait Visitor {
+    fn visit_node(&mut self, node: &Node) {
+        walk_node(self, node)
+    }
+
+    fn visit_query(&mut self, query: &QueryExpr) {
+        walk_query(self, query)
+    }
+
+    fn visit_union(&mut self, union: &UnionExpr) {
+        walk_union(self, union)
+    }
+
+    fn visit_operator(&mut self, operator: &QueryOperator) {
+        walk_operator(self, operator)
+    }
+
+    fn visit_window(&mut self, window: &WindowExpr) {
+        walk_window(self, window)
+    }
+
+    fn visit_summarize(&mut self, summarize: &SummarizeExpr) {
+        walk_summarize(self, summarize)
+    }
+
+    fn visit_order_by(&mut self, clause: &OrderByClause) {
+        walk_order_by(self, clause)
+    }
+
+    fn visit_assignment(&mut self, assignment: &Assignment) {
+        walk_assignment(self, assignment)
+    }
+
+    /// Field paths and table names.
+    fn visit_identity(&mut self, _identity: &IdentityValue) {}
+
+    fn visit_scalar(&mut self, _scalar: &ScalarValue) {}
+
+    fn visit_parameter(&mut self, _name: &str) {}
+
+    fn visit_binary_expr(&mut self, binary: &BinaryExpr) {
+        walk_binary_expr(self, binary)
+    }
+
+    fn visit_unary_expr(&mut self, unary: &UnaryExpr) {
+        walk_unary_expr(self, unary)
+    }
+
+    fn visit_function_call(&mut self, call: &FunctionCall) {
+        walk_function_call(self, call)
+    }
+
+    fn visit_in(&mut self, in_expr: &InExpr) {
+        walk_in(self, in_expr)
+    }
+
+    fn visit_let(&mut self, let_expr: &LetExpr) {
+        walk_let(self, let_expr)
+    }
+
+    fn visit_insert(&mut self, insert: &InsertExpr) {
+        walk_insert(self, insert)
+    }
+
+    fn visit_update(&mut self, update: &UpdateExpr) {
+        walk_update(self, update)
+    }
+
+    fn visit_delete(&mut self, delete: &DeleteExpr) {
+        walk_delete(self, delete)
+    }
+}
+
+pub fn walk_node<V: Visitor + ?Sized>(visitor: &mut V, node: &Node) {
+    match node {
+        Node::Identity(identity) => visitor.visit_identity(identity),
+        Node::Scalar(scalar) => visitor.visit_scalar(scalar),
+        Node::BinaryExpr(binary) => visitor.visit_binary_expr(binary),
+        Node::UnaryExpr(unary) => visitor.visit_unary_expr(unary),
+        Node::Parameter(name) => visitor.visit_parameter(name),
+        Node::FunctionCall(call) => visitor.visit_function_call(call),
+        Node::In(in_expr) => visitor.visit_in(in_expr),
+        Node::ToScalar(query) => visitor.visit_query(query),
+        Node::Query(query) => visitor.visit_query(query),
+        Node::Let(let_expr) => visitor.visit_let(let_expr),
+        Node::Insert(insert) => visitor.visit_insert(insert),
+        Node::Update(update) => visitor.visit_update(update),
+        Node::Delete(delete) => visitor.visit_delete(delete),
+        Node::Transaction(_) => {}
+    }
+}
+
+pub fn walk_query<V: Visitor + ?Sized>(visitor: &mut V, query: &QueryExpr) {
+    match &query.source {
+        QuerySource::Table(table) => visitor.visit_identity(table),
+        QuerySource::Union(union) => visitor.visit_union(union),
+    }
+
+    for operator in &query.operators {
+        visitor.visit_operator(operator);
+    }
+
+    for clause in &query.order {
+        visitor.visit_order_by(clause);
+    }
+}
+
+pub fn walk_union<V: Visitor + ?Sized>(visitor: &mut V, union: &UnionExpr) {
+    for operand in &union.operands {
+        visitor.visit_query(operand);
+    }
+}
+
+pub fn walk_operator<V: Visitor + ?Sized>(visitor: &mut V, operator: &QueryOperator) {
+    match operator {
+        QueryOperator::Where(filter) => visitor.visit_node(filter),
+        QueryOperator::Project(assignments) | QueryOperator::Extend(assignments) => {
+            for assignment in assignments {
+                visitor.visit_assignment(assignment);
+            }
+        }
+        QueryOperator::Distinct(fields) => {
+            for field in fields {
+                visitor.visit_identity(field);
+            }
+        }
+        QueryOperator::Serialize(window) => visitor.visit_window(window),
+        QueryOperator::Union(union) => visitor.visit_union(union),
+        QueryOperator::Intersect(operands) | QueryOperator::Except(operands) => {
+            for operand in operands {
+                visitor.visit_query(operand);
+            }
+        }
+        QueryOperator::Take(_) | QueryOperator::ForUpdate => {}
+        QueryOperator::Summarize(summarize) => visitor.visit_summarize(summarize),
+    }
+}
+
+pub fn walk_window<V: Visitor + ?Sized>(visitor: &mut V, window: &WindowExpr) {
+    for field in &window.partition_by {
+        visitor.visit_identity(field);
+    }
+
+    for clause in &window.order_by {
+        visitor.visit_order_by(clause);
+    }
+}
+
+pub fn walk_summarize<V: Visitor + ?Sized>(visitor: &mut V, summarize: &SummarizeExpr) {
+    for assignment in summarize.aggregates.iter().chain(&summarize.by) {
+        visitor.visit_assignment(assignment);
+    }
+}
+
+pub fn walk_order_by<V: Visitor + ?Sized>(visitor: &mut V, clause: &OrderByClause) {
+    visitor.visit_identity(&clause.identity);
+}
+
+pub fn walk_assignment<V: Visitor + ?Sized>(visitor: &mut V, assignment: &Assignment) {
+    visitor.visit_node(&assignment.value);
+}
+
+pub fn walk_binary_expr<V: Visitor + ?Sized>(visitor: &mut V, binary: &BinaryExpr) {
+    visitor.visit_node(&binary.left);
+    visitor.visit_node(&binary.right);
+}
+
+pub fn walk_unary_expr<V: Visitor + ?Sized>(visitor: &mut V, unary: &UnaryExpr) {
+    visitor.visit_node(&unary.expr);
+}
+
+pub fn walk_function_call<V: Visitor + ?Sized>(visitor: &mut V, call: &FunctionCall) {
+    for arg in &call.args {
+        visitor.visit_node(arg);
+    }
+}
+
+pub fn walk_in<V: Visitor + ?Sized>(visitor: &mut V, in_expr: &InExpr) {
+    visitor.visit_node(&in_expr.expr);
+
+    match &in_expr.set {
+        InSet::List(values) => {
+            for value in values {
+                visitor.visit_node(value);
+            }
+        }
+        InSet::Query(query) => visitor.visit_query(query),
+    }
+}
+
+pub fn walk_let<V: Visitor + ?Sized>(visitor: &mut V, let_expr: &LetExpr) {
+    visitor.visit_node(&let_expr.value);
+}
+
+pub fn walk_insert<V: Visitor + ?Sized>(visitor: &mut V, insert: &InsertExpr) {
+    visitor.visit_identity(&insert.table);
+
+    for row in &insert.rows {
+        for assignment in row {
+            visitor.visit_assignment(assignment);
+        }
+    }
+}
+
+pub fn walk_update<V: Visitor + ?Sized>(visitor: &mut V, update: &UpdateExpr) {
+    visitor.visit_identity(&update.table);
+
+    for assignment in &update.assignments {
+        visitor.visit_assignment(assignment);
+    }
+
+    if let Some(filter) = &update.filter {
+        visitor.visit_node(filter);
+    }
+}
+
+pub fn walk_delete<V: Visitor + ?Sized>(visitor: &mut V, delete: &DeleteExpr) {
+    visitor.visit_identity(&delete.table);
+
+    if let Some(filter) = &delete.filter {
+        visitor.visit_node(filter);
+    }
+}
+
+/// Visits the nodes of an AST mutably, overriding `visit_node` allows replacing whole nodes.
+pub trait VisitorMut {
+    fn visit_node_mut(&mut self, node: &mut Node) {
+        walk_node_mut(self, node)
+    }
+
+    fn visit_query_mut(&mut self, query: &mut QueryExpr) {
+        walk_query_mut(self, query)
+    }
+
+    fn visit_union_mut(&mut self, union: &mut UnionExpr) {
+        walk_union_mut(self, union)
+    }
+
+    fn visit_operator_mut(&mut self, operator: &mut QueryOperator) {
+        walk_operator_mut(self, operator)
+    }
+
+    fn visit_window_mut(&mut self, window: &mut WindowExpr) {
+        walk_window_mut(self, window)
+    }
+
+    fn visit_summarize_mut(&mut self, summarize: &mut SummarizeExpr) {
+        walk_summarize_mut(self, summarize)
+    }
+
+    fn visit_order_by_mut(&mut self, clause: &mut OrderByClause) {
+        walk_order_by_mut(self, clause)
+    }
+
+    fn visit_assignment_mut(&mut self, assignment: &mut Assignment) {
+        walk_assignment_mut(self, assignment)
+    }
+
+    /// Field paths and table names.
+    fn visit_identity_mut(&mut self, _identity: &mut IdentityValue) {}
+
+    fn visit_scalar_mut(&mut self, _scalar: &mut ScalarValue) {}
+
+    fn visit_parameter_mut(&mut self, _name: &mut String) {}
+
+    fn visit_binary_expr_mut(&mut self, binary: &mut BinaryExpr) {
+        walk_binary_expr_mut(self, binary)
+    }
+
+    fn visit_unary_expr_mut(&mut self, unary: &mut UnaryExpr) {
+        walk_unary_expr_mut(self, unary)
+    }
+
+    fn visit_function_call_mut(&mut self, call: &mut FunctionCall) {
+        walk_function_call_mut(self, call)
+    }
+
+    fn visit_in_mut(&mut self, in_expr: &mut InExpr) {
+        walk_in_mut(self, in_expr)
+    }
+
+    fn visit_let_mut(&mut self, let_expr: &mut LetExpr) {
+        walk_let_mut(self, let_expr)
+    }
+
+    fn visit_insert_mut(&mut self, insert: &mut InsertExpr) {
+        walk_insert_mut(self, insert)
+    }
+
+    fn visit_update_mut(&mut self, update: &mut UpdateExpr) {
+        walk_update_mut(self, update)
+    }
+
+    fn visit_delete_mut(&mut self, delete: &mut DeleteExpr) {
+        walk_delete_mut(self, delete)
+    }
+}
+
+pub fn walk_node_mut<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut Node) {
+    match node {
+        Node::Identity(identity) => visitor.visit_identity_mut(identity),
+        Node::Scalar(scalar) => visitor.visit_scalar_mut(scalar),
+        Node::BinaryExpr(binary) => visitor.visit_binary_expr_mut(binary),
+        Node::UnaryExpr(unary) => visitor.visit_unary_expr_mut(unary),
+        Node::Parameter(name) => visitor.visit_parameter_mut(name),
+        Node::FunctionCall(call) => visitor.visit_function_call_mut(call),
+        Node::In(in_expr) => visitor.visit_in_mut(in_expr),
+        Node::ToScalar(query) => visitor.visit_query_mut(query),
+        Node::Query(query) => visitor.visit_query_mut(query),
+        Node::Let(let_expr) => visitor.visit_let_mut(let_expr),
+        Node::Insert(insert) => visitor.visit_insert_mut(insert),
+        Node::Update(update) => visitor.visit_update_mut(update),
+        Node::Delete(delete) => visitor.visit_delete_mut(delete),
+        Node::Transaction(_) => {}
+    }
+}
+
+pub fn walk_query_mut<V: VisitorMut + ?Sized>(visitor: &mut V, query: &mut QueryExpr) {
+    match &mut query.source {
+        QuerySource::Table(table) => visitor.visit_identity_mut(table),
+        QuerySource::Union(union) => visitor.visit_union_mut(union),
+    }
+
+    for operator in &mut query.operators {
+        visitor.visit_operator_mut(operator);
+    }
+
+    for clause in &mut query.order {
+        visitor.visit_order_by_mut(clause);
+    }
+}
+
+pub fn walk_union_mut<V: VisitorMut + ?Sized>(visitor: &mut V, union: &mut UnionExpr) {
+    for operand in &mut union.operands {
+        visitor.visit_query_mut(operand);
+    }
+}
+
+pub fn walk_operator_mut<V: VisitorMut + ?Sized>(visitor: &mut V, operator: &mut QueryOperator) {
+    match operator {
+        QueryOperator::Where(filter) => visitor.visit_node_mut(filter),
+        QueryOperator::Project(assignments) | QueryOperator::Extend(assignments) => {
+            for assignment in assignments {
+                visitor.visit_assignment_mut(assignment);
+            }
+        }
+        QueryOperator::Distinct(fields) => {
+            for field in fields {
+                visitor.visit_identity_mut(field);
+            }
+        }
+        QueryOperator::Serialize(window) => visitor.visit_window_mut(window),
+        QueryOperator::Union(union) => visitor.visit_union_mut(union),
+        QueryOperator::Intersect(operands) | QueryOperator::Except(operands) => {
+            for operand in operands {
+                visitor.visit_query_mut(operand);
+            }
+        }
+        QueryOperator::Take(_) | QueryOperator::ForUpdate => {}
+        QueryOperator::Summarize(summarize) => visitor.visit_summarize_mut(summarize),
+    }
+}
+
+pub fn walk_window_mut<V: VisitorMut + ?Sized>(visitor: &mut V, window: &mut WindowExpr) {
+    for field in &mut window.partition_by {
+        visitor.visit_identity_mut(field);
+    }
+
+    for clause in &mut window.order_by {
+        visitor.visit_order_by_mut(clause);
+    }
+}
+
+pub fn walk_summarize_mut<V: VisitorMut + ?Sized>(visitor: &mut V, summarize: &mut SummarizeExpr) {
+    for assignment in summarize.aggregates.iter_mut().chain(&mut summarize.by) {
+        visitor.visit_assignment_mut(assignment);
+    }
+}
+
+pub fn walk_order_by_mut<V: VisitorMut + ?Sized>(visitor: &mut V, clause: &mut OrderByClause) {
+    visitor.visit_identity_mut(&mut clause.identity);
+}
+
+pub fn walk_assignment_mut<V: VisitorMut + ?Sized>(visitor: &mut V, assignment: &mut Assignment) {
+    visitor.visit_node_mut(&mut assignment.value);
+}
+
+pub fn walk_binary_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, binary: &mut BinaryExpr) {
+    visitor.visit_node_mut(&mut binary.left);
+    visitor.visit_node_mut(&mut binary.right);
+}
+
+pub fn walk_unary_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, unary: &mut UnaryExpr) {
+    visitor.visit_node_mut(&mut unary.expr);
+}
+
+pub fn walk_function_call_mut<V: VisitorMut + ?Sized>(visitor: &mut V, call: &mut FunctionCall) {
+    for arg in &mut call.args {
+        visitor.visit_node_mut(arg);
+    }
+}
+
+pub fn walk_in_mut<V: VisitorMut + ?Sized>(visitor: &mut V, in_expr: &mut InExpr) {
+    visitor.visit_node_mut(&mut in_expr.expr);
+
+    match &mut in_expr.set {
+        InSet::List(values) => {
+            for value in values {
+                visitor.visit_node_mut(value);
+            }
+        }
+        InSet::Query(query) => visitor.visit_query_mut(query),
+    }
+}
+
+pub fn walk_let_mut<V: VisitorMut + ?Sized>(visitor: &mut V, let_expr: &mut LetExpr) {
+    visitor.visit_node_mut(&mut let_expr.value);
+}
+
+pub fn walk_insert_mut<V: VisitorMut + ?Sized>(visitor: &mut V, insert: &mut InsertExpr) {
+    visitor.visit_identity_mut(&mut insert.table);
+
+    for row in &mut insert.rows {
+        for assignment in row {
+            visitor.visit_assignment_mut(assignment);
+        }
+    }
+}
+
+pub fn walk_update_mut<V: VisitorMut + ?Sized>(visitor: &mut V, update: &mut UpdateExpr) {
+    visitor.visit_identity_mut(&mut update.table);
+
+    for assignment in &mut update.assignments {
+        visitor.visit_assignment_mut(assignment);
+    }
+
+    if let Some(filter) = &mut update.filter {
+        visitor.visit_node_mut(filter);
+    }
+}
+
+pub fn walk_delete_mut<V: VisitorMut + ?Sized>(visitor: &mut V, delete: &mut DeleteExpr) {
+    visitor.visit_identity_mut(&mut delete.table);
+
+    if let Some(filter) = &mut delete.filter {
+        visitor.visit_node_mut(filter);
+    }
+}
+
+/// Resets the spans of a tree, so it compares equal to a hand built one whatever text it was
+/// parsed from.
+pub fn clear_spans(node: &mut Node) {
+    struct ClearSpans;
+
+    impl VisitorMut for ClearSpans {
+        fn visit_query_mut(&mut self, query: &mut QueryExpr) {
+            query.span = Span::default();
+            walk_query_mut(self, query)
+        }
+
+        fn visit_assignment_mut(&mut self, assignment: &mut Assignment) {
+            assignment.span = Span::default();
+            walk_assignment_mut(self, assignment)
+        }
+
+        fn visit_identity_mut(&mut self, identity: &mut IdentityValue) {
+            identity.span = Span::default();
+        }
+
+        fn visit_binary_expr_mut(&mut self, binary: &mut BinaryExpr) {
+            binary.span = Span::default();
+            walk_binary_expr_mut(self, binary)
+        }
+
+        fn visit_unary_expr_mut(&mut self, unary: &mut UnaryExpr) {
+            unary.span = Span::default();
+            walk_unary_expr_mut(self, unary)
+        }
+
+        fn visit_function_call_mut(&mut self, call: &mut FunctionCall) {
+            call.span = Span::default();
+            walk_function_call_mut(self, call)
+        }
+
+        fn visit_in_mut(&mut self, in_expr: &mut InExpr) {
+            in_expr.span = Span::default();
+            walk_in_mut(self, in_expr)
+        }
+
+        fn visit_let_mut(&mut self, let_expr: &mut LetExpr) {
+            let_expr.span = Span::default();
+            walk_let_mut(self, let_expr)
+        }
+    }
+
+    ClearSpans.visit_node_mut(node);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_query;
+
+    /// Records every field path and table name with the text its span covers.
+    struct Identities<'a> {
+        source: &'a str,
+        found: Vec<(String, &'a str)>,
+    }
+
+    impl Visitor for Identities<'_> {
+        fn visit_identity(&mut self, identity: &IdentityValue) {
+            let Span { start, end } = identity.span;
+            self.found
+                .push((identity.value.clone(), &self.source[start..end]));
+        }
+    }
+
+    #[test]
+    fn visits_in_source_order() {
+        let source = "t | where a > 1 and b in (u | project c) | extend d = f(e) | distinct g";
+        let mut visitor = Identities {
+            source,
+            found: Vec::new(),
+        };
+
+        for node in parse_query(source).unwrap() {
+            visitor.visit_node(&node);
+        }
+
+        let names: Vec<_> = visitor
+            .found
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, ["t", "a", "b", "u", "c", "e", "g"]);
+        assert!(visitor.found.iter().all(|(name, text)| name == text));
+    }
+
+    /// Replaces every parameter with a literal.
+    struct Bind;
+
+    impl VisitorMut for Bind {
+        fn visit_node_mut(&mut self, node: &mut Node) {
+            match node {
+                Node::Parameter(_) => *node = Node::Scalar(ScalarValue::Int(7)),
+                node => walk_node_mut(self, node),
+            }
+        }
+    }
+
+    #[test]
+    fn rewrites_nodes() {
+        let mut ast = parse_query("t | where a == @p or toscalar(u | where b > @q) == 1").unwrap();
+        let mut expected =
+            parse_query("t | where a == 7 or toscalar(u | where b > 7) == 1").unwrap();
+        for node in ast.iter_mut().chain(&mut expected) {
+            Bind.visit_node_mut(node);
+            clear_spans(node);
+        }
+
+        assert_eq!(ast, expected);
+    }
+}
@@ -0,0 +1,381 @@
+//--------------------------------------------------------------------------
+// (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
+//--------------------------------------------------------------------------
+
+//! A language server for kuiper queries over stdin and stdout. Collections and fields are read
+//! from a catalog file, like the gateway serves at `/catalog`:
+//!
+//!     kuiperdb-lsp --catalog catalog.json
+
+use std::collections::HashMap;
+use std::error::Error;
+
+use kuiperdb_lang::analyzer::Severity;
+use kuiperdb_lang::ast::Span;
+use kuiperdb_lang::catalog::Catalog;
+use kuiperdb_lang::service::{self, CompletionKind};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, RequestId, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as NotificationType, PublishDiagnostics,
+};
+use lsp_types::request::{Completion, HoverRequest, Request as RequestType};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    Diagnostic, DiagnosticSeverity, Hover, HoverContents, HoverParams, HoverProviderCapability,
+    MarkupContent, MarkupKind, Position, PublishDiagnosticsParams, Range, ServerCapabilities,
+    TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Converts a position, in UTF-16 code units as the protocol counts them, into a byte offset.
+fn offset(text: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(end) => line_start += end + 1,
+            None => return text.len(),
+        }
+    }
+
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= position.character as usize || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+
+    text.len()
+}
+
+fn position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |end| end + 1);
+
+    Position::new(
+        before.matches('\n').count() as u32,
+        before[line_start..].encode_utf16().count() as u32,
+    )
+}
+
+fn range(text: &str, span: Span) -> Range {
+    Range::new(position(text, span.start), position(text, span.end))
+}
+
+/// The params of a notification. A client sending malformed ones mustn't stop the server, the
+/// notification is logged and skipped.
+fn notification_params<N: NotificationType>(notification: Notification) -> Option<N::Params> {
+    match notification.extract(N::METHOD) {
+        Ok(params) => Some(params),
+        Err(err) => {
+            eprintln!("Skipped a notification: {}", err);
+            None
+        }
+    }
+}
+
+fn invalid_params(id: RequestId, err: serde_json::Error) -> Response {
+    Response::new_err(
+        id,
+        ErrorCode::InvalidParams as i32,
+        format!("Invalid params: {}", err),
+    )
+}
+
+fn publish_diagnostics(
+    connection: &Connection,
+    catalog: &Catalog,
+    uri: Url,
+    text: &str,
+) -> Result<()> {
+    let diagnostics = service::diagnostics(text, catalog)
+        .into_iter()
+        .map(|diagnostic| Diagnostic {
+            range: range(text, diagnostic.span),
+            severity: Some(match diagnostic.severity {
+                Severity::Error => DiagnosticSeverity::ERROR,
+                Severity::Warning => DiagnosticSeverity::WARNING,
+            }),
+            source: Some(String::from("kuiperdb")),
+            message: diagnostic.message,
+            ..Default::default()
+        })
+        .collect();
+
+    let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+    connection
+        .sender
+        .send(Message::Notification(Notification::new(
+            PublishDiagnostics::METHOD.to_owned(),
+            params,
+        )))?;
+
+    Ok(())
+}
+
+fn handle_request(
+    request: Request,
+    documents: &HashMap<Url, String>,
+    catalog: &Catalog,
+) -> Response {
+    match request.method.as_str() {
+        Completion::METHOD => {
+            let params: CompletionParams = match serde_json::from_value(request.params) {
+                Ok(params) => params,
+                Err(err) => return invalid_params(request.id, err),
+            };
+            let position = params.text_document_position;
+            let text = documents
+                .get(&position.text_document.uri)
+                .map_or("", String::as_str);
+            let offset = offset(text, position.position);
+
+            let items: Vec<CompletionItem> = service::completions(text, offset, catalog)
+                .into_iter()
+                .map(|completion| CompletionItem {
+                    kind: Some(match completion.kind {
+                        CompletionKind::Collection => CompletionItemKind::CLASS,
+                        CompletionKind::Field => CompletionItemKind::FIELD,
+                        CompletionKind::Variable => CompletionItemKind::VARIABLE,
+                        CompletionKind::Operator => CompletionItemKind::OPERATOR,
+                        CompletionKind::Function => CompletionItemKind::FUNCTION,
+                        CompletionKind::Keyword => CompletionItemKind::KEYWORD,
+                    }),
+                    label: completion.label,
+                    detail: Some(completion.detail),
+                    ..Default::default()
+                })
+                .collect();
+
+            Response::new_ok(request.id, CompletionResponse::Array(items))
+        }
+        HoverRequest::METHOD => {
+            let params: HoverParams = match serde_json::from_value(request.params) {
+                Ok(params) => params,
+                Err(err) => return invalid_params(request.id, err),
+            };
+            let position = params.text_document_position_params;
+            let text = documents
+                .get(&position.text_document.uri)
+                .map_or("", String::as_str);
+            let offset = offset(text, position.position);
+
+            let hover = service::hover(text, offset, catalog).map(|hover| Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::PlainText,
+                    value: hover.contents,
+                }),
+                range: Some(range(text, hover.span)),
+            });
+
+            Response::new_ok(request.id, hover)
+        }
+        method => Response::new_err(
+            request.id,
+            ErrorCode::MethodNotFound as i32,
+            format!("Unsupported request {}", method),
+        ),
+    }
+}
+
+fn run(connection: &Connection, catalog: &Catalog) -> Result<()> {
+    let mut documents: HashMap<Url, String> = HashMap::new();
+
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+
+                let response = handle_request(request, &documents, catalog);
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(notification) => match notification.method.as_str() {
+                DidOpenTextDocument::METHOD => {
+                    if let Some(params) = notification_params::<DidOpenTextDocument>(notification) {
+                        let document = params.text_document;
+                        publish_diagnostics(
+                            connection,
+                            catalog,
+                            document.uri.clone(),
+                            &document.text,
+                        )?;
+                        documents.insert(document.uri, document.text);
+                    }
+                }
+                DidChangeTextDocument::METHOD => {
+                    // Documents are synced in full, the last change holds the whole text
+                    if let Some(params) = notification_params::<DidChangeTextDocument>(notification)
+                    {
+                        if let Some(change) = params.content_changes.into_iter().last() {
+                            let uri = params.text_document.uri;
+                            publish_diagnostics(connection, catalog, uri.clone(), &change.text)?;
+                            documents.insert(uri, change.text);
+                        }
+                    }
+                }
+                DidCloseTextDocument::METHOD => {
+                    if let Some(params) = notification_params::<DidCloseTextDocument>(notification)
+                    {
+                        let uri = params.text_document.uri;
+                        documents.remove(&uri);
+                        publish_diagnostics(connection, catalog, uri, "")?;
+                    }
+                }
+                _ => {}
+            },
+            Message::Response(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let catalog = match args.iter().position(|arg| arg == "--catalog") {
+        Some(i) => {
+            let path = args.get(i + 1).ok_or("--catalog expects a path")?;
+            serde_json::from_str(&std::fs::read_to_string(path)?)?
+        }
+        None => Catalog::default(),
+    };
+
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = serde_json::to_value(ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![String::from("|"), String::from("(")]),
+            ..Default::default()
+        }),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        ..Default::default()
+    })?;
+    connection.initialize(capabilities)?;
+
+    run(&connection, &catalog)?;
+    drop(connection);
+    io_threads.join()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuiperdb_lang::catalog::{CollectionInfo, FieldInfo, ValueType};
+    use lsp_types::{
+        DidCloseTextDocumentParams, DidOpenTextDocumentParams, TextDocumentIdentifier,
+        TextDocumentItem,
+    };
+    use serde_json::json;
+    use std::thread::{self, JoinHandle};
+
+    /// Runs a server on a thread, it stops once its client is dropped
+    fn serve() -> (Connection, JoinHandle<Result<()>>) {
+        let (server, client) = Connection::memory();
+        let catalog = Catalog {
+            collections: vec![CollectionInfo {
+                name: String::from("events"),
+                fields: vec![FieldInfo {
+                    name: String::from("amount"),
+                    types: vec![ValueType::Int],
+                }],
+                sampled: 1,
+            }],
+        };
+
+        (client, thread::spawn(move || run(&server, &catalog)))
+    }
+
+    fn open(client: &Connection, uri: &Url, text: &str) {
+        let params = DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(
+                uri.clone(),
+                String::from("kuiper"),
+                1,
+                text.into(),
+            ),
+        };
+        let notification = Notification::new(DidOpenTextDocument::METHOD.to_owned(), params);
+        client.sender.send(notification.into()).unwrap();
+    }
+
+    fn published(client: &Connection) -> PublishDiagnosticsParams {
+        match client.receiver.recv().unwrap() {
+            Message::Notification(notification) => {
+                notification.extract(PublishDiagnostics::METHOD).unwrap()
+            }
+            invalid => panic!("Invalid message: {:?}", invalid),
+        }
+    }
+
+    #[test]
+    fn diagnostics_of_open_documents() {
+        let (client, server) = serve();
+        let uri = Url::parse("file:///query.kql").unwrap();
+
+        open(&client, &uri, "events\n| where nope > 1");
+        let params = published(&client);
+        assert_eq!(params.uri, uri);
+        let found: Vec<(&str, Range)> = params
+            .diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.message.as_str(), diagnostic.range))
+            .collect();
+        assert_eq!(
+            found,
+            [(
+                "Unknown field nope",
+                Range::new(Position::new(1, 8), Position::new(1, 12))
+            )]
+        );
+
+        // Closing a document clears its diagnostics
+        let params = DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+        };
+        let notification = Notification::new(DidCloseTextDocument::METHOD.to_owned(), params);
+        client.sender.send(notification.into()).unwrap();
+        assert!(published(&client).diagnostics.is_empty());
+
+        drop(client);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn malformed_params() {
+        let (client, server) = serve();
+        let uri = Url::parse("file:///query.kql").unwrap();
+
+        // The notification is skipped, the server keeps running
+        let notification = Notification::new(
+            DidOpenTextDocument::METHOD.to_owned(),
+            json!({ "textDocument": 1 }),
+        );
+        client.sender.send(notification.into()).unwrap();
+
+        // The request is answered with an error
+        let request = Request::new(RequestId::from(1), Completion::METHOD.to_owned(), json!([]));
+        client.sender.send(request.into()).unwrap();
+        match client.receiver.recv().unwrap() {
+            Message::Response(response) => {
+                assert_eq!(response.id, RequestId::from(1));
+                assert_eq!(
+                    response.error.unwrap().code,
+                    ErrorCode::InvalidParams as i32
+                );
+            }
+            invalid => panic!("Invalid message: {:?}", invalid),
+        }
+
+        open(&client, &uri, "events | where amount > 1");
+        assert!(published(&client).diagnostics.is_empty());
+
+        drop(client);
+        server.join().unwrap().unwrap();
+    }
+}
@@ -2,11 +2,12 @@
 // (C) Copyright Travis Sharp <travis@darkspace.dev>.  All rights reserved.
 //--------------------------------------------------------------------------
 
-use kuiperdb_core::storage::rocksdb::Datastore;
+use kuiperdb_core::storage::rocksdb::{Datastore, DatastoreConfig};
 use kuiperdb_engine::{
     execution::{self, Executor, QueryPlan},
     plan::CollectionScan,
 };
+use std::io::Read;
 use std::time::Instant;
 
 // https://stackoverflow.com/questions/61699050/how-can-i-make-my-rust-code-run-faster-in-parallel
@@ -22,6 +23,7 @@ async fn test_basic_query(collection: &str, ex: &Executor) {
         .execute_select(QueryPlan {
             schema: String::from("default"),
             collection: String::from(collection),
+            filter: Option::None,
         })
         .await
         .expect("Failed to execute query.");
@@ -88,11 +90,47 @@ async fn test_builk_insert(collection: &str, ex: &Executor, count: u32) {
     println!("Elapsed: {}s - {} records.", seconds, count);
 }
 
+/// Reads a query from stdin and writes it back in its normalized form, `kuiper fmt < query.kql`
+fn format_stdin() {
+    let mut source = String::new();
+    std::io::stdin()
+        .read_to_string(&mut source)
+        .expect("Failed to read the query.");
+
+    let ast = match kuiperdb_lang::parser::parse_query(&source) {
+        Ok(ast) => ast,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+
+    match kuiperdb_lang::format::format_query(&ast) {
+        Ok(text) => println!("{}", text),
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
+    if std::env::args().nth(1).as_deref() == Some("fmt") {
+        format_stdin();
+        return;
+    }
+
     let mut command: String = String::from("");
 
-    let ds = Datastore::new(".\\dbs\\vuln_db").await.unwrap();
+    let config = match DatastoreConfig::load() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    let ds = Datastore::open(config).await.unwrap();
     let ex = Executor::new(ds);
 
     // ex.create_collection(String::from("test_collection"))